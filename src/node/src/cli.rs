@@ -1,4 +1,4 @@
-use sc_cli::RunCmd;
+use sc_cli::{BlockNumberOrHash, CliConfiguration, DatabaseParams, PruningParams, RunCmd, SharedParams};
 
 #[derive(Debug, clap::Parser)]
 pub struct Cli {
@@ -36,7 +36,44 @@ pub enum Subcommand {
 	/// Revert the chain to a previous state.
 	Revert(sc_cli::RevertCmd),
 
+	/// Check that the indexes of the graph database agree with each other.
+	CheckIndexes(CheckIndexesCmd),
+
 	/// The custom benchmark subcommand benchmarking runtime pallets.
 	#[clap(name = "benchmark", about = "Benchmark runtime pallets.")]
 	Benchmark(frame_benchmarking_cli::BenchmarkCmd),
 }
+
+/// The `check-indexes` command: checks the indexes of the graph database in the state of a block.
+#[derive(Debug, clap::Parser)]
+pub struct CheckIndexesCmd {
+	/// Block hash or number, the best block if not given.
+	#[clap(value_name = "HASH or NUMBER")]
+	pub at: Option<BlockNumberOrHash>,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub shared_params: SharedParams,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub pruning_params: PruningParams,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub database_params: DatabaseParams,
+}
+
+impl CliConfiguration for CheckIndexesCmd {
+	fn shared_params(&self) -> &SharedParams {
+		&self.shared_params
+	}
+
+	fn pruning_params(&self) -> Option<&PruningParams> {
+		Some(&self.pruning_params)
+	}
+
+	fn database_params(&self) -> Option<&DatabaseParams> {
+		Some(&self.database_params)
+	}
+}
@@ -6,6 +6,10 @@ use crate::{
 use node_template_runtime::Block;
 use sc_cli::{ChainSpec, RuntimeVersion, SubstrateCli};
 use sc_service::PartialComponents;
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::generic::BlockId;
+use subgraph_runtime_api::SubGraphApi;
 
 impl SubstrateCli for Cli {
 	fn impl_name() -> String {
@@ -98,6 +102,32 @@ pub fn run() -> sc_cli::Result<()> {
 				Ok((cmd.run(client, backend), task_manager))
 			})
 		},
+		Some(Subcommand::CheckIndexes(cmd)) => {
+			let runner = cli.create_runner(cmd)?;
+			runner.sync_run(|config| {
+				let PartialComponents { client, .. } = service::new_partial(&config)?;
+				let at = match &cmd.at {
+					Some(at) => at.parse::<Block>().map_err(sc_cli::Error::Input)?,
+					None => BlockId::Hash(client.info().best_hash),
+				};
+				let found = client
+					.runtime_api()
+					.check_indexes(&at)
+					.map_err(|e| sc_cli::Error::Application(Box::new(e)))?;
+				for inconsistency in found.iter() {
+					println!("{}", inconsistency);
+				}
+				if found.is_empty() {
+					println!("The indexes of the graph database are consistent");
+					Ok(())
+				} else {
+					Err(sc_cli::Error::Input(format!(
+						"{} inconsistencies found, the `repair_indexes` extrinsic rebuilds the secondary indexes",
+						found.len()
+					)))
+				}
+			})
+		},
 		Some(Subcommand::Benchmark(cmd)) =>
 			if cfg!(feature = "runtime-benchmarks") {
 				let runner = cli.create_runner(cmd)?;
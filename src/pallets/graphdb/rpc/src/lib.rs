@@ -14,6 +14,18 @@ use subgraph_runtime_api::SubGraphApi as SubGraphRuntimeApi;
 pub trait SubGraphApi<BlockHash> {
     #[rpc(name = "sparql_query")]
     fn query(&self, query: String, at: Option<BlockHash>) -> Result<String>;
+    #[rpc(name = "sparql_queryRdfs")]
+    fn query_rdfs(&self, query: String, at: Option<BlockHash>) -> Result<String>;
+    #[rpc(name = "sparql_querySameAs")]
+    fn query_same_as(&self, query: String, at: Option<BlockHash>) -> Result<String>;
+    #[rpc(name = "sparql_explain")]
+    fn explain(&self, query: String, at: Option<BlockHash>) -> Result<String>;
+    #[rpc(name = "sparql_profile")]
+    fn profile(&self, query: String, at: Option<BlockHash>) -> Result<String>;
+    #[rpc(name = "sparql_validateShapes")]
+    fn validate_shapes(&self, at: Option<BlockHash>) -> Result<String>;
+    #[rpc(name = "sparql_graphRoot")]
+    fn graph_root(&self, graph_name: String, at: Option<BlockHash>) -> Result<Option<Vec<u8>>>;
 }
 
 pub struct SubGraph<C, M> {
@@ -50,4 +62,99 @@ impl<C, Block> SubGraphApi<<Block as BlockT>::Hash> for SubGraph<C, Block>
             data: Some(format!("{:?}", e).into()),
         })
     }
-}
+    fn query_rdfs(&self, query: String, at: Option<<Block as BlockT>::Hash>) -> Result<String> {
+        let api = self.client.runtime_api();
+        let at = BlockId::hash(at.unwrap_or_else(||
+            // If the block hash is not supplied assume the best block.
+            self.client.info().best_hash)
+        );
+
+        let runtime_api_result = api.query_rdfs(&at, query);
+        runtime_api_result.map_err(|e| RpcError {
+            code: ErrorCode::ServerError(1001),
+            message: "Sparql query error".into(),
+            data: Some(format!("{:?}", e).into()),
+        })
+    }
+    fn query_same_as(&self, query: String, at: Option<<Block as BlockT>::Hash>) -> Result<String> {
+        let api = self.client.runtime_api();
+        let at = BlockId::hash(at.unwrap_or_else(||
+            // If the block hash is not supplied assume the best block.
+            self.client.info().best_hash)
+        );
+
+        let runtime_api_result = api.query_same_as(&at, query);
+        runtime_api_result.map_err(|e| RpcError {
+            code: ErrorCode::ServerError(1001),
+            message: "Sparql query error".into(),
+            data: Some(format!("{:?}", e).into()),
+        })
+    }
+    fn explain(&self, query: String, at: Option<<Block as BlockT>::Hash>) -> Result<String> {
+        let api = self.client.runtime_api();
+        let at = BlockId::hash(at.unwrap_or_else(||
+            // If the block hash is not supplied assume the best block.
+            self.client.info().best_hash)
+        );
+
+        let runtime_api_result = api.explain(&at, query);
+        runtime_api_result.map_err(|e| RpcError {
+            code: ErrorCode::ServerError(1001),
+            message: "Sparql explain error".into(),
+            data: Some(format!("{:?}", e).into()),
+        })?.map_err(|e| RpcError {
+            code: ErrorCode::InvalidParams,
+            message: "Sparql explain error".into(),
+            data: Some(e.into()),
+        })
+    }
+
+    fn profile(&self, query: String, at: Option<<Block as BlockT>::Hash>) -> Result<String> {
+        let api = self.client.runtime_api();
+        let at = BlockId::hash(at.unwrap_or_else(||
+            // If the block hash is not supplied assume the best block.
+            self.client.info().best_hash)
+        );
+
+        let runtime_api_result = api.profile(&at, query);
+        runtime_api_result.map_err(|e| RpcError {
+            code: ErrorCode::ServerError(1001),
+            message: "Sparql profile error".into(),
+            data: Some(format!("{:?}", e).into()),
+        })?.map_err(|e| RpcError {
+            code: ErrorCode::InvalidParams,
+            message: "Sparql profile error".into(),
+            data: Some(e.into()),
+        })
+    }
+
+    fn validate_shapes(&self, at: Option<<Block as BlockT>::Hash>) -> Result<String> {
+        let api = self.client.runtime_api();
+        let at = BlockId::hash(at.unwrap_or_else(||
+            // If the block hash is not supplied assume the best block.
+            self.client.info().best_hash)
+        );
+
+        let runtime_api_result = api.validate_shapes(&at);
+        runtime_api_result.map_err(|e| RpcError {
+            code: ErrorCode::ServerError(1001),
+            message: "SHACL validation error".into(),
+            data: Some(format!("{:?}", e).into()),
+        })
+    }
+
+    fn graph_root(&self, graph_name: String, at: Option<<Block as BlockT>::Hash>) -> Result<Option<Vec<u8>>> {
+        let api = self.client.runtime_api();
+        let at = BlockId::hash(at.unwrap_or_else(||
+            // If the block hash is not supplied assume the best block.
+            self.client.info().best_hash)
+        );
+
+        let runtime_api_result = api.graph_root(&at, graph_name);
+        runtime_api_result.map_err(|e| RpcError {
+            code: ErrorCode::ServerError(1001),
+            message: "Sparql graph root error".into(),
+            data: Some(format!("{:?}", e).into()),
+        })
+    }
+}
\ No newline at end of file
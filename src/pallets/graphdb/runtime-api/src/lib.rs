@@ -5,5 +5,12 @@
 sp_api::decl_runtime_apis! {
     pub trait SubGraphApi {
         fn query(query: String) -> String;
+        fn query_rdfs(query: String) -> String;
+        fn query_same_as(query: String) -> String;
+        fn explain(query: String) -> Result<String, String>;
+        fn profile(query: String) -> Result<String, String>;
+        fn validate_shapes() -> String;
+        fn graph_root(graph_name: String) -> Option<Vec<u8>>;
+        fn check_indexes() -> Vec<String>;
     }
 }
\ No newline at end of file
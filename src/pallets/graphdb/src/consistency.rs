@@ -0,0 +1,968 @@
+//! Consistency of the indexes of the store.
+//!
+//! Each quad is written to the `SPO` index of its graph and, independently, to the `POS`, `OSP`, ordered
+//! literal, full-text, spatial, vector, `owl:sameAs` and term graph indexes: `check_indexes` verifies that they
+//! agree with each other, with the quad counts of `GraphNameStore`, `DefaultGraphQuadCountStore` and
+//! `QuadCountStore` and with the strings of `Id2StrStore` and the terms of `Id2TermStore`. `RepairIndexes`
+//! rebuilds the secondary indexes from the `SPO` ones, the primary indexes, in batches across blocks.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use frame_support::pallet_prelude::*;
+use frame_support::storage::child::KillStorageResult;
+
+use crate::{
+    count_term_graphs, decode_term_id, Config, DefaultGraphQuadCountStore, DefaultOspStore, DefaultPosStore,
+    DefaultSpoStore, GraphGenerationStore, GraphNameStore, GraphPurgeStore, Id2StrStore, Id2TermStore,
+    IndexRepairCursorStore, OrderedLiteralStore, QuadCountStore, SameAsMemberStore, SameAsStore, SpatialIndexStore,
+    StoreFamily, StrRefCountStore, TermGraphStore, TermRefCountStore, TextIndexStore, VectorEdgeStore,
+    VectorEntryStore, VectorNodeStore,
+};
+use crate::error::StoreError;
+use crate::hnsw;
+use crate::model::vector::Vector;
+use crate::same_as;
+use crate::spatial::{self, CellKey};
+use crate::text::{self, TokenKey};
+use crate::graph_trie::{GraphTrie, TAG_ORDERED_LITERAL, TAG_OSP, TAG_POS, TAG_SPO};
+use crate::store::codec::{EncodedQuad, EncodedTerm, OrderedKey, TermId};
+
+/// A disagreement between the maps of the store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// A quad `(s, p, o)` of the `SPO` index of a graph (the default graph if `None`) has no entry in another index
+    MissingEntry { index: &'static str, graph: Option<TermId>, quad: [TermId; 3] },
+    /// An entry of an index has no quad `(s, p, o)` in the `SPO` index of its graph, or not with this ordered key
+    DanglingEntry { index: &'static str, graph: Option<TermId>, quad: [TermId; 3] },
+    /// The quad count of a graph name differs from the number of quads of its graph
+    GraphCount { graph: TermId, stored: u64, counted: u64 },
+    /// The quad count of the default graph differs from the number of its quads
+    DefaultGraphCount { stored: u64, counted: u64 },
+    /// The quad count of the store differs from the number of quads of all the graphs
+    QuadCount { stored: u64, counted: u64 },
+    /// The quad count of a node of the vector index differs from the number of quads with its predicate and vector
+    VectorNodeCount { predicate: TermId, object: TermId, stored: u32, counted: u32 },
+    /// A link or an entry point of the vector index of a predicate is not a node of the index
+    DanglingVectorNode { predicate: TermId, object: TermId },
+    /// The canonical representative of a node in `SameAsStore` is not the one of its `owl:sameAs` links,
+    /// `None` if the node is alone in its class
+    SameAsClass { term: TermId, stored: Option<TermId>, counted: Option<TermId> },
+    /// `SameAsMemberStore` lists a member of a class which is not (if `stored`), or does not list one which is
+    SameAsMember { class: TermId, member: TermId, stored: bool },
+    /// The number of quads of a named graph using a term differs from the one of `TermGraphStore`
+    TermGraphCount { term: TermId, graph: TermId, stored: u64, counted: u64 },
+    /// A term id of an index or of a graph name cannot be decoded
+    InvalidTerm { id: TermId, error: String },
+    /// A string referenced by a term is missing or is not valid UTF-8
+    InvalidStr { hash: u128, error: String },
+    /// The reference count of a string differs from the number of terms referencing it
+    StrRefCount { hash: u128, stored: u64, counted: u64 },
+    /// A string of `Id2StrStore` is not referenced by any term
+    UnreferencedStr { hash: u128 },
+    /// The reference count of a term of `Id2TermStore` differs from the number of quads and graph names using it
+    TermRefCount { hash: u128, stored: u64, counted: u64 },
+    /// A term of `Id2TermStore` is not used by any quad or graph name
+    UnreferencedTerm { hash: u128 },
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingEntry { index, graph, quad } => {
+                write!(f, "{} has no entry for the quad {}", index, QuadIds(graph, quad))
+            }
+            Self::DanglingEntry { index, graph, quad } => {
+                write!(f, "{} has an entry for the quad {} which is not stored", index, QuadIds(graph, quad))
+            }
+            Self::GraphCount { graph, stored, counted } => write!(
+                f,
+                "The graph {} counts {} quads but has {}",
+                hex::encode(graph), stored, counted
+            ),
+            Self::DefaultGraphCount { stored, counted } => {
+                write!(f, "The default graph counts {} quads but has {}", stored, counted)
+            }
+            Self::QuadCount { stored, counted } => write!(f, "The store counts {} quads but has {}", stored, counted),
+            Self::VectorNodeCount { predicate, object, stored, counted } => write!(
+                f,
+                "The vector {} of {} counts {} quads but has {}",
+                hex::encode(object), hex::encode(predicate), stored, counted
+            ),
+            Self::DanglingVectorNode { predicate, object } => write!(
+                f,
+                "The vector {} of {} is linked but is not stored",
+                hex::encode(object), hex::encode(predicate)
+            ),
+            Self::SameAsClass { term, stored, counted } => write!(
+                f,
+                "The node {} is in the owl:sameAs class {} but its links put it in {}",
+                hex::encode(term), class_id(stored), class_id(counted)
+            ),
+            Self::SameAsMember { class, member, stored: true } => write!(
+                f,
+                "The owl:sameAs class {} lists {} which is not its member",
+                hex::encode(class), hex::encode(member)
+            ),
+            Self::SameAsMember { class, member, stored: false } => write!(
+                f,
+                "The owl:sameAs class {} does not list its member {}",
+                hex::encode(class), hex::encode(member)
+            ),
+            Self::TermGraphCount { term, graph, stored, counted } => write!(
+                f,
+                "The term {} counts {} quads of the graph {} but has {}",
+                hex::encode(term), stored, hex::encode(graph), counted
+            ),
+            Self::InvalidTerm { id, error } => write!(f, "The term {} cannot be decoded: {}", hex::encode(id), error),
+            Self::InvalidStr { hash, error } => write!(f, "The string {:032x} cannot be read: {}", hash, error),
+            Self::StrRefCount { hash, stored, counted } => write!(
+                f,
+                "The string {:032x} counts {} references but has {}",
+                hash, stored, counted
+            ),
+            Self::UnreferencedStr { hash } => write!(f, "The string {:032x} is not referenced", hash),
+            Self::TermRefCount { hash, stored, counted } => write!(
+                f,
+                "The term {:032x} counts {} references but has {}",
+                hash, stored, counted
+            ),
+            Self::UnreferencedTerm { hash } => write!(f, "The term {:032x} is not referenced", hash),
+        }
+    }
+}
+
+/// The id of an `owl:sameAs` class in hexadecimal, `none` for a node alone in its class
+fn class_id(class: &Option<TermId>) -> String {
+    class.map_or_else(|| "none".to_owned(), hex::encode)
+}
+
+/// The ids of a quad, displayed in hexadecimal
+struct QuadIds<'a>(&'a Option<TermId>, &'a [TermId; 3]);
+
+impl fmt::Display for QuadIds<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [s, p, o] = self.1;
+        write!(f, "({} {} {}", hex::encode(s), hex::encode(p), hex::encode(o))?;
+        match self.0 {
+            Some(g) => write!(f, " {})", hex::encode(g)),
+            None => write!(f, ") of the default graph"),
+        }
+    }
+}
+
+/// Checks that all the indexes maintained (see `Config::QuadIndexes`) have the same quads as the `SPO` ones,
+/// that the graph names and the quad counts count the quads of their graphs and that the strings and the terms of
+/// `Id2TermStore` are counted by the quads and the graph names referencing them.
+///
+/// The whole store is read and the references to the strings are counted in memory: this is meant for
+/// `try-runtime` and offline checks, not for the extrinsics.
+pub fn check_indexes<T: Config>() -> Vec<Inconsistency> {
+    let mut check = IndexCheck::<T>::default();
+    check.default_graph();
+    check.same_as_classes();
+    check.named_graphs();
+    check.term_graphs();
+    check.quad_count();
+    check.strs();
+    check.terms();
+    check.found
+}
+
+struct IndexCheck<T> {
+    found: Vec<Inconsistency>,
+    /// Number of quads of the `SPO` indexes read so far
+    quads: u64,
+    /// The terms decoded so far, `None` if the id cannot be decoded
+    terms: BTreeMap<TermId, Option<EncodedTerm>>,
+    /// Number of references to each string, counted from the quads and the graph names
+    str_refs: BTreeMap<u128, u64>,
+    /// Number of references to each term of `Id2TermStore`, counted from the quads and the graph names
+    term_refs: BTreeMap<u128, u64>,
+    /// Number of quads of each named graph using each term, by term and graph
+    term_graphs: BTreeMap<(TermId, TermId), u64>,
+    /// The `owl:sameAs` links between two distinct nodes of the default graph
+    same_as_links: Vec<(TermId, TermId)>,
+    /// Whether the terms read so far are vectors of the vector index
+    vectors: BTreeMap<TermId, bool>,
+    _p: PhantomData<T>,
+}
+
+impl<T> Default for IndexCheck<T> {
+    fn default() -> Self {
+        Self {
+            found: Vec::new(),
+            quads: 0,
+            terms: BTreeMap::new(),
+            str_refs: BTreeMap::new(),
+            term_refs: BTreeMap::new(),
+            term_graphs: BTreeMap::new(),
+            same_as_links: Vec::new(),
+            vectors: BTreeMap::new(),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<T: Config> IndexCheck<T> {
+    fn default_graph(&mut self) {
+        let pos = StoreFamily::DefaultPos.is_enabled::<T>();
+        let osp = StoreFamily::DefaultOsp.is_enabled::<T>();
+        let spatial = StoreFamily::Spatial.is_enabled::<T>();
+        let vector = StoreFamily::Vector.is_enabled::<T>();
+        let text = StoreFamily::Text.is_enabled::<T>();
+        let same_as = same_as::same_as::<T>().term_id();
+        let mut vector_quads = BTreeMap::new();
+        let mut counted = 0;
+        for (s, p, o) in <DefaultSpoStore<T>>::iter_keys() {
+            let quad = [s, p, o];
+            if p == same_as && s != o && self.is_node(&s) && self.is_node(&o) {
+                self.same_as_links.push((s, o));
+            }
+            if pos && !<DefaultPosStore<T>>::contains_key((p, o, s)) {
+                self.found.push(missing(StoreFamily::DefaultPos, None, quad));
+            }
+            if osp && !<DefaultOspStore<T>>::contains_key((o, s, p)) {
+                self.found.push(missing(StoreFamily::DefaultOsp, None, quad));
+            }
+            if let Some(key) = self.ordered_key(&o) {
+                if !<OrderedLiteralStore<T>>::contains_key((p, key, o, s)) {
+                    self.found.push(missing(StoreFamily::OrderedLiteral, None, quad));
+                }
+            }
+            if spatial {
+                if let Some(key) = self.cell_key(&o) {
+                    if !<SpatialIndexStore<T>>::contains_key((key, o, s, p)) {
+                        self.found.push(missing(StoreFamily::Spatial, None, quad));
+                    }
+                }
+            }
+            if text {
+                for key in self.token_keys(&o) {
+                    if !<TextIndexStore<T>>::contains_key((key, o, s, p)) {
+                        self.found.push(missing(StoreFamily::Text, None, quad));
+                        break;
+                    }
+                }
+            }
+            if vector && self.is_vector(&o) {
+                *vector_quads.entry((p, o)).or_insert(0) += 1;
+            }
+            self.count_refs(&quad);
+            counted += 1;
+        }
+        let stored = <DefaultGraphQuadCountStore<T>>::get();
+        if counted != stored {
+            self.found.push(Inconsistency::DefaultGraphCount { stored, counted });
+        }
+        self.quads += counted;
+
+        if pos {
+            for (p, o, s) in <DefaultPosStore<T>>::iter_keys() {
+                if !<DefaultSpoStore<T>>::contains_key((s, p, o)) {
+                    self.found.push(dangling(StoreFamily::DefaultPos, None, [s, p, o]));
+                }
+            }
+        }
+        if osp {
+            for (o, s, p) in <DefaultOspStore<T>>::iter_keys() {
+                if !<DefaultSpoStore<T>>::contains_key((s, p, o)) {
+                    self.found.push(dangling(StoreFamily::DefaultOsp, None, [s, p, o]));
+                }
+            }
+        }
+        for (p, key, o, s) in <OrderedLiteralStore<T>>::iter_keys() {
+            if !<DefaultSpoStore<T>>::contains_key((s, p, o)) || self.ordered_key(&o) != Some(key) {
+                self.found.push(dangling(StoreFamily::OrderedLiteral, None, [s, p, o]));
+            }
+        }
+        if spatial {
+            for (key, o, s, p) in <SpatialIndexStore<T>>::iter_keys() {
+                if !<DefaultSpoStore<T>>::contains_key((s, p, o)) || self.cell_key(&o) != Some(key) {
+                    self.found.push(dangling(StoreFamily::Spatial, None, [s, p, o]));
+                }
+            }
+        }
+        if text {
+            for (key, o, s, p) in <TextIndexStore<T>>::iter_keys() {
+                if !<DefaultSpoStore<T>>::contains_key((s, p, o)) || !self.token_keys(&o).contains(&key) {
+                    self.found.push(dangling(StoreFamily::Text, None, [s, p, o]));
+                }
+            }
+        }
+        if vector {
+            self.vector_graphs(vector_quads);
+        }
+    }
+
+    /// Checks that `SameAsStore` maps the nodes linked by `owl:sameAs` to the lowest term id of their class,
+    /// and that `SameAsMemberStore` lists the members of each class
+    fn same_as_classes(&mut self) {
+        // union-find on the linked nodes, the root of a class is its lowest term id
+        let mut parents = BTreeMap::<TermId, TermId>::new();
+        fn root(parents: &BTreeMap<TermId, TermId>, mut id: TermId) -> TermId {
+            while let Some(parent) = parents.get(&id).filter(|parent| **parent != id) {
+                id = *parent;
+            }
+            id
+        }
+        for (a, b) in std::mem::take(&mut self.same_as_links) {
+            let (a, b) = (root(&parents, a), root(&parents, b));
+            parents.insert(a.min(b), a.min(b));
+            parents.insert(a.max(b), a.min(b));
+        }
+        let classes: BTreeMap<TermId, TermId> = parents.keys().map(|id| (*id, root(&parents, *id))).collect();
+
+        let mut counted = classes.clone();
+        for (term, class) in <SameAsStore<T>>::iter() {
+            let counted = counted.remove(&term);
+            if counted != Some(class) {
+                self.found.push(Inconsistency::SameAsClass { term, stored: Some(class), counted });
+            }
+        }
+        for (term, class) in counted {
+            self.found.push(Inconsistency::SameAsClass { term, stored: None, counted: Some(class) });
+        }
+        for (class, member) in <SameAsMemberStore<T>>::iter_keys() {
+            if classes.get(&member) != Some(&class) {
+                self.found.push(Inconsistency::SameAsMember { class, member, stored: true });
+            }
+        }
+        for (member, class) in classes {
+            if !<SameAsMemberStore<T>>::contains_key((class, member)) {
+                self.found.push(Inconsistency::SameAsMember { class, member, stored: false });
+            }
+        }
+    }
+
+    /// Checks that the nodes of the vector index count the quads with their vector `vector_quads`, by predicate and
+    /// vector, and that their links are to nodes
+    fn vector_graphs(&mut self, mut vector_quads: BTreeMap<(TermId, TermId), u32>) {
+        for ((predicate, object), node) in <VectorNodeStore<T>>::iter() {
+            let counted = vector_quads.remove(&(predicate, object)).unwrap_or(0);
+            if node.quads != counted {
+                self.found.push(Inconsistency::VectorNodeCount { predicate, object, stored: node.quads, counted });
+            }
+        }
+        for ((predicate, object), counted) in vector_quads {
+            self.found.push(Inconsistency::VectorNodeCount { predicate, object, stored: 0, counted });
+        }
+        let mut linked = BTreeSet::new();
+        for ((predicate, object, _), links) in <VectorEdgeStore<T>>::iter() {
+            linked.insert((predicate, object));
+            linked.extend(links.into_iter().map(|link| (predicate, link)));
+        }
+        linked.extend(<VectorEntryStore<T>>::iter().map(|((predicate, _), object)| (predicate, object)));
+        for (predicate, object) in linked {
+            if !<VectorNodeStore<T>>::contains_key((predicate, object)) {
+                self.found.push(Inconsistency::DanglingVectorNode { predicate, object });
+            }
+        }
+    }
+
+    fn named_graphs(&mut self) {
+        let pos = StoreFamily::Gpos.is_enabled::<T>();
+        let osp = StoreFamily::Gosp.is_enabled::<T>();
+        for (g, stored) in <GraphNameStore<T>>::iter() {
+            self.count_refs(&[g]);
+            let graph = Some(g);
+            let trie = GraphTrie::of::<T>(&g);
+            let mut counted = 0;
+            for (s, p, o) in trie.triples_with_prefix(TAG_SPO, &[]) {
+                let quad = [s, p, o];
+                if pos && !trie.contains_triple(TAG_POS, (p, o, s)) {
+                    self.found.push(missing(StoreFamily::Gpos, graph, quad));
+                }
+                if osp && !trie.contains_triple(TAG_OSP, (o, s, p)) {
+                    self.found.push(missing(StoreFamily::Gosp, graph, quad));
+                }
+                if let Some(key) = self.ordered_key(&o) {
+                    if !trie.contains_ordered_literal(p, key, o, s) {
+                        self.found.push(missing(StoreFamily::OrderedLiteral, graph, quad));
+                    }
+                }
+                self.count_refs(&quad);
+                let mut terms = quad;
+                terms.sort_unstable();
+                for (i, term) in terms.iter().enumerate() {
+                    if i == 0 || terms[i - 1] != *term {
+                        *self.term_graphs.entry((*term, g)).or_default() += 1;
+                    }
+                }
+                counted += 1;
+            }
+            if counted != stored {
+                self.found.push(Inconsistency::GraphCount { graph: g, stored, counted });
+            }
+            self.quads += counted;
+
+            if pos {
+                for (p, o, s) in trie.triples_with_prefix(TAG_POS, &[]) {
+                    if !trie.contains_triple(TAG_SPO, (s, p, o)) {
+                        self.found.push(dangling(StoreFamily::Gpos, graph, [s, p, o]));
+                    }
+                }
+            }
+            if osp {
+                for (o, s, p) in trie.triples_with_prefix(TAG_OSP, &[]) {
+                    if !trie.contains_triple(TAG_SPO, (s, p, o)) {
+                        self.found.push(dangling(StoreFamily::Gosp, graph, [s, p, o]));
+                    }
+                }
+            }
+            for (p, key, o, s) in trie.ordered_literal_entries() {
+                if !trie.contains_triple(TAG_SPO, (s, p, o)) || self.ordered_key(&o) != Some(key) {
+                    self.found.push(dangling(StoreFamily::OrderedLiteral, graph, [s, p, o]));
+                }
+            }
+        }
+        // the quads of the dropped graphs reference their strings until they are purged
+        for ((g, generation), release) in <GraphPurgeStore<T>>::iter() {
+            if release {
+                for (s, p, o) in GraphTrie::with_generation(&g, generation).triples_with_prefix(TAG_SPO, &[]) {
+                    self.count_refs(&[s, p, o]);
+                }
+            }
+        }
+    }
+
+    /// Checks that `TermGraphStore` counts the quads of the current generation of each named graph using each
+    /// term, the entries of the dropped generations are removed with their quads
+    fn term_graphs(&mut self) {
+        let mut counted = std::mem::take(&mut self.term_graphs);
+        for ((term, graph, generation), stored) in <TermGraphStore<T>>::iter() {
+            if generation != <GraphGenerationStore<T>>::get(graph) {
+                if <GraphPurgeStore<T>>::contains_key((graph, generation)) {
+                    continue;
+                }
+                self.found.push(Inconsistency::TermGraphCount { term, graph, stored, counted: 0 });
+                continue;
+            }
+            let counted = counted.remove(&(term, graph)).unwrap_or(0);
+            if stored != counted {
+                self.found.push(Inconsistency::TermGraphCount { term, graph, stored, counted });
+            }
+        }
+        for ((term, graph), counted) in counted {
+            self.found.push(Inconsistency::TermGraphCount { term, graph, stored: 0, counted });
+        }
+    }
+
+    fn quad_count(&mut self) {
+        let stored = <QuadCountStore<T>>::get();
+        if stored != self.quads {
+            self.found.push(Inconsistency::QuadCount { stored, counted: self.quads });
+        }
+    }
+
+    fn strs(&mut self) {
+        for hash in self.str_refs.keys() {
+            let error = match <Id2StrStore<T>>::get(hash) {
+                None => StoreError::MissingStr { hash: *hash },
+                Some(value) if std::str::from_utf8(&value).is_err() => StoreError::InvalidUtf8 { hash: *hash },
+                Some(_) => continue,
+            };
+            self.found.push(Inconsistency::InvalidStr { hash: *hash, error: error.to_string() });
+        }
+        let mut stored_refs = BTreeMap::new();
+        for (hash, stored) in <StrRefCountStore<T>>::iter() {
+            stored_refs.insert(hash, stored);
+        }
+        for (hash, counted) in self.str_refs.iter() {
+            let stored = stored_refs.remove(hash).unwrap_or(0);
+            if stored != *counted {
+                self.found.push(Inconsistency::StrRefCount { hash: *hash, stored, counted: *counted });
+            }
+        }
+        for (hash, stored) in stored_refs {
+            self.found.push(Inconsistency::StrRefCount { hash, stored, counted: 0 });
+        }
+        for hash in <Id2StrStore<T>>::iter_keys() {
+            if !self.str_refs.contains_key(&hash) {
+                self.found.push(Inconsistency::UnreferencedStr { hash });
+            }
+        }
+    }
+
+    fn terms(&mut self) {
+        let mut stored_refs = BTreeMap::new();
+        for (hash, stored) in <TermRefCountStore<T>>::iter() {
+            stored_refs.insert(hash, stored);
+        }
+        for (hash, counted) in self.term_refs.iter() {
+            let stored = stored_refs.remove(hash).unwrap_or(0);
+            if stored != *counted {
+                self.found.push(Inconsistency::TermRefCount { hash: *hash, stored, counted: *counted });
+            }
+        }
+        for (hash, stored) in stored_refs {
+            self.found.push(Inconsistency::TermRefCount { hash, stored, counted: 0 });
+        }
+        for hash in <Id2TermStore<T>>::iter_keys() {
+            if !self.term_refs.contains_key(&hash) {
+                self.found.push(Inconsistency::UnreferencedTerm { hash });
+            }
+        }
+    }
+
+    /// Counts the references of the terms `ids` to their strings and to their encoding
+    fn count_refs(&mut self, ids: &[TermId]) {
+        for id in ids {
+            if let Some(term) = self.term(id) {
+                let str_refs = &mut self.str_refs;
+                term.on_each_id(|hash| *str_refs.entry(*hash).or_default() += 1);
+                if let (_, Some((hash, _))) = term.to_term_id() {
+                    *self.term_refs.entry(*hash).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    /// Whether `id` is a node (not a literal) of an `owl:sameAs` link
+    fn is_node(&mut self, id: &TermId) -> bool {
+        matches!(self.term(id), Some(term) if !term.is_literal())
+    }
+
+    fn ordered_key(&mut self, id: &TermId) -> Option<OrderedKey> {
+        self.term(id)?.to_ordered_key()
+    }
+
+    /// The spatial cell of the geometry `id`, a geometry whose string is missing is reported by `strs`
+    fn cell_key(&mut self, id: &TermId) -> Option<CellKey> {
+        spatial::object_cell_key::<T>(self.term(id)?).ok()?
+    }
+
+    /// The keys of the tokens of the string literal `id`, a literal whose string is missing is reported by `strs`
+    fn token_keys(&mut self, id: &TermId) -> BTreeSet<TokenKey> {
+        match self.term(id) {
+            Some(term) => text::object_token_keys::<T>(term).unwrap_or_default(),
+            None => BTreeSet::new(),
+        }
+    }
+
+    /// Whether `id` is a vector of the vector index, a vector whose string is missing is reported by `strs`
+    fn is_vector(&mut self, id: &TermId) -> bool {
+        if let Some(is_vector) = self.vectors.get(id) {
+            return *is_vector;
+        }
+        let is_vector = match self.term(id) {
+            Some(term) => matches!(hnsw::object_vector::<T>(term), Ok(Some(vector)) if vector.norm() > 0.),
+            None => false,
+        };
+        self.vectors.insert(*id, is_vector);
+        is_vector
+    }
+
+    /// The term of `id`, an id that cannot be decoded is reported once
+    fn term(&mut self, id: &TermId) -> Option<EncodedTerm> {
+        if let Some(term) = self.terms.get(id) {
+            return *term;
+        }
+        let term = match decode_term_id::<T>(id) {
+            Ok(term) => Some(term),
+            Err(error) => {
+                self.found.push(Inconsistency::InvalidTerm { id: *id, error: error.to_string() });
+                None
+            }
+        };
+        self.terms.insert(*id, term);
+        term
+    }
+}
+
+/// A step of the repair of the secondary indexes, in the order they are done
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub enum IndexRepairStep {
+    /// Removes the entries of `DefaultPosStore`, `DefaultOspStore`, `OrderedLiteralStore`, `TextIndexStore`,
+    /// `SpatialIndexStore`, of the vector index, of the `owl:sameAs` classes and of `TermGraphStore`
+    ClearDefaultGraph,
+    /// Writes them again from `DefaultSpoStore`
+    DefaultGraph,
+    /// Removes the `POS`, `OSP` and ordered literal entries of a named graph
+    ClearGraph(TermId),
+    /// Writes them and the entries of the graph in `TermGraphStore` again from the `SPO` index of the graph,
+    /// with the number of quads read so far
+    Graph(TermId, u64),
+}
+
+/// Progress of the repair of the secondary indexes: the current step and the key of the last quad it read
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub struct IndexRepairCursor {
+    pub step: IndexRepairStep,
+    /// Raw storage key, or key of the child trie of the graph
+    pub after: Option<Vec<u8>>,
+}
+
+/// Rebuilds the secondary indexes from the `SPO` ones, `Config::IndexRepairBatchSize` entries at the start
+/// of each block.
+///
+/// The secondary entries of each graph are removed, then written again for each quad of its `SPO` index,
+/// and the quad counts of the graphs and of the store are set to the number of quads read. The statistics and the string
+/// references are kept as they are. The store can neither be queried nor updated until the repair is done.
+pub struct RepairIndexes<T>(PhantomData<T>);
+
+impl<T: Config> RepairIndexes<T> {
+    /// Starts the repair, from the first step if it was already ongoing
+    pub fn start() {
+        <IndexRepairCursorStore<T>>::put(IndexRepairCursor { step: IndexRepairStep::ClearDefaultGraph, after: None });
+    }
+
+    /// Repairs the next batch of entries, if the repair is ongoing (called by `on_initialize`)
+    pub fn repair_batch() -> Weight {
+        let mut cursor = match <IndexRepairCursorStore<T>>::get() {
+            Some(cursor) => cursor,
+            None => return T::DbWeight::get().reads(1),
+        };
+        let mut batch = IndexRepairBatch::<T>::new(T::IndexRepairBatchSize::get());
+        loop {
+            // a step clearing entries may use the whole batch
+            if batch.remaining == 0 || !batch.run_step(&mut cursor) {
+                <IndexRepairCursorStore<T>>::put(cursor);
+                break;
+            }
+            match batch.next_step(&cursor.step) {
+                Some(step) => cursor = IndexRepairCursor { step, after: None },
+                None => {
+                    <IndexRepairCursorStore<T>>::kill();
+                    frame_support::log::info!(target: "runtime::graphdb", "the secondary indexes are rebuilt");
+                    break;
+                }
+            }
+        }
+
+        // estimate: a quad reads the term of its object and writes up to three entries, with the accesses of
+        // its tokens and its vector
+        T::DbWeight::get().reads_writes(
+            2 + batch.entries as u64 * 2 + batch.index_reads,
+            1 + batch.removed as u64 + batch.entries as u64 * 3 + batch.index_writes,
+        )
+    }
+}
+
+/// The entries repaired in a block
+struct IndexRepairBatch<T> {
+    /// Number of entries that may still be read or removed
+    remaining: u32,
+    entries: u32,
+    removed: u32,
+    /// Storage reads and writes of the full-text, spatial and vector indexes
+    index_reads: u64,
+    index_writes: u64,
+    _p: PhantomData<T>,
+}
+
+impl<T: Config> IndexRepairBatch<T> {
+    fn new(size: u32) -> Self {
+        Self { remaining: size.max(1), entries: 0, removed: 0, index_reads: 0, index_writes: 0, _p: PhantomData }
+    }
+
+    /// Repairs the entries of the step after the cursor, returns `true` once the step is done.
+    ///
+    /// The quads are read before the entries are written, as the storage must not change while it is iterated.
+    fn run_step(&mut self, cursor: &mut IndexRepairCursor) -> bool {
+        let limit = self.remaining as usize;
+        let after = cursor.after.take();
+        let read = match &mut cursor.step {
+            IndexRepairStep::ClearDefaultGraph => {
+                return self.clear(|limit| <DefaultPosStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <DefaultOspStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <OrderedLiteralStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <TextIndexStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <SpatialIndexStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <VectorNodeStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <VectorEdgeStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <VectorEntryStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <SameAsStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <SameAsMemberStore<T>>::remove_all(Some(limit)))
+                    && self.clear(|limit| <TermGraphStore<T>>::remove_all(Some(limit)));
+            }
+            IndexRepairStep::ClearGraph(g) => {
+                let trie = GraphTrie::of::<T>(g);
+                return self.clear(|limit| trie.clear_index(TAG_POS, limit))
+                    && self.clear(|limit| trie.clear_index(TAG_OSP, limit))
+                    && self.clear(|limit| trie.clear_index(TAG_ORDERED_LITERAL, limit));
+            }
+            IndexRepairStep::DefaultGraph => {
+                let entries: Vec<(TermId, TermId, TermId)> = match after {
+                    Some(after) => <DefaultSpoStore<T>>::iter_from(after).map(|(key, _)| key).take(limit).collect(),
+                    None => {
+                        <QuadCountStore<T>>::put(0);
+                        <DefaultGraphQuadCountStore<T>>::put(0);
+                        <DefaultSpoStore<T>>::iter_keys().take(limit).collect()
+                    }
+                };
+                for (s, p, o) in entries.iter().copied() {
+                    if StoreFamily::DefaultPos.is_enabled::<T>() {
+                        <DefaultPosStore<T>>::insert((p, o, s), ());
+                    }
+                    if StoreFamily::DefaultOsp.is_enabled::<T>() {
+                        <DefaultOspStore<T>>::insert((o, s, p), ());
+                    }
+                    if let Some(key) = ordered_key::<T>(&o) {
+                        <OrderedLiteralStore<T>>::insert((p, key, o, s), ());
+                    }
+                    if StoreFamily::Text.is_enabled::<T>() {
+                        let (keys, reads) = token_keys::<T>(&o);
+                        for key in keys.iter() {
+                            <TextIndexStore<T>>::insert((*key, o, s, p), ());
+                        }
+                        self.index_reads += reads;
+                        self.index_writes += keys.len() as u64;
+                    }
+                    if StoreFamily::Spatial.is_enabled::<T>() {
+                        if let (Some(key), reads) = cell_key::<T>(&o) {
+                            <SpatialIndexStore<T>>::insert((key, o, s, p), ());
+                            self.index_reads += reads;
+                            self.index_writes += 1;
+                        }
+                    }
+                    if StoreFamily::Vector.is_enabled::<T>() {
+                        if let Some(vector) = object_vector::<T>(&o) {
+                            let (reads, writes) = hnsw::insert::<T>(p, o, &vector);
+                            self.index_reads += reads;
+                            self.index_writes += writes;
+                        }
+                    }
+                    if p == same_as::same_as::<T>().term_id() {
+                        if let Some(link) = same_as_link::<T>([s, p, o]) {
+                            same_as::link::<T>(&link);
+                        }
+                    }
+                }
+                <DefaultGraphQuadCountStore<T>>::mutate(|count| *count += entries.len() as u64);
+                <QuadCountStore<T>>::mutate(|count| *count += entries.len() as u64);
+                cursor.after = entries.last().map(|key| <DefaultSpoStore<T>>::hashed_key_for(*key));
+                entries.len()
+            }
+            IndexRepairStep::Graph(g, count) => {
+                let trie = GraphTrie::of::<T>(g);
+                let generation = <GraphGenerationStore<T>>::get(*g);
+                let entries: Vec<_> = trie.triples_after(TAG_SPO, after).take(limit).collect();
+                for &(_, (s, p, o)) in entries.iter() {
+                    count_term_graphs::<T>(*g, generation, [s, p, o], true);
+                    if StoreFamily::Gpos.is_enabled::<T>() {
+                        trie.insert_triple(TAG_POS, (p, o, s));
+                    }
+                    if StoreFamily::Gosp.is_enabled::<T>() {
+                        trie.insert_triple(TAG_OSP, (o, s, p));
+                    }
+                    if let Some(key) = ordered_key::<T>(&o) {
+                        trie.insert_ordered_literal(p, key, o, s);
+                    }
+                }
+                *count += entries.len() as u64;
+                <QuadCountStore<T>>::mutate(|total| *total += entries.len() as u64);
+                if entries.len() < limit {
+                    <GraphNameStore<T>>::insert(*g, *count);
+                }
+                cursor.after = entries.last().map(|(key, _)| key.clone());
+                entries.len()
+            }
+        };
+        self.entries += read as u32;
+        self.remaining -= read as u32;
+        read < limit
+    }
+
+    /// The step following `step`, `None` once the last named graph is done
+    fn next_step(&self, step: &IndexRepairStep) -> Option<IndexRepairStep> {
+        let next_graph = |after: Option<&TermId>| {
+            let g = match after {
+                Some(g) => <GraphNameStore<T>>::iter_keys_from(<GraphNameStore<T>>::hashed_key_for(g)).next(),
+                None => <GraphNameStore<T>>::iter_keys().next(),
+            }?;
+            Some(IndexRepairStep::ClearGraph(g))
+        };
+        match step {
+            IndexRepairStep::ClearDefaultGraph => Some(IndexRepairStep::DefaultGraph),
+            IndexRepairStep::DefaultGraph => next_graph(None),
+            IndexRepairStep::ClearGraph(g) => Some(IndexRepairStep::Graph(*g, 0)),
+            IndexRepairStep::Graph(g, _) => next_graph(Some(g)),
+        }
+    }
+
+    /// Removes up to the remaining number of entries with `remove`, returns `true` once none is left
+    fn clear(&mut self, remove: impl FnOnce(u32) -> KillStorageResult) -> bool {
+        let (done, removed) = match remove(self.remaining) {
+            KillStorageResult::AllRemoved(removed) => (true, removed),
+            KillStorageResult::SomeRemaining(removed) => (false, removed),
+        };
+        self.removed += removed;
+        self.remaining = self.remaining.saturating_sub(removed);
+        done
+    }
+}
+
+fn missing(family: StoreFamily, graph: Option<TermId>, quad: [TermId; 3]) -> Inconsistency {
+    Inconsistency::MissingEntry { index: family.name(), graph, quad }
+}
+
+fn dangling(family: StoreFamily, graph: Option<TermId>, quad: [TermId; 3]) -> Inconsistency {
+    Inconsistency::DanglingEntry { index: family.name(), graph, quad }
+}
+
+/// The ordered key of the object `o`, `None` if it is not an ordered literal or cannot be decoded
+fn ordered_key<T: Config>(o: &TermId) -> Option<OrderedKey> {
+    match decode_term_id::<T>(o) {
+        Ok(object) => object.to_ordered_key(),
+        Err(error) => {
+            frame_support::log::warn!(target: "runtime::graphdb", "skipped during the index repair: {}", error);
+            None
+        }
+    }
+}
+
+/// The keys of the tokens of the object `o` and the reads of its text, none if it is not a string literal or
+/// cannot be decoded
+fn token_keys<T: Config>(o: &TermId) -> (BTreeSet<TokenKey>, u64) {
+    let keys = decode_term_id::<T>(o)
+        .and_then(|object| Ok((text::object_token_keys::<T>(object)?, text::object_reads(object))));
+    match keys {
+        Ok(keys) => keys,
+        Err(error) => {
+            frame_support::log::warn!(target: "runtime::graphdb", "skipped during the index repair: {}", error);
+            (BTreeSet::new(), 0)
+        }
+    }
+}
+
+/// The spatial cell of the object `o` and the reads of its text, `None` if it is not a geometry or cannot be
+/// decoded
+fn cell_key<T: Config>(o: &TermId) -> (Option<CellKey>, u64) {
+    let key = decode_term_id::<T>(o)
+        .and_then(|object| Ok((spatial::object_cell_key::<T>(object)?, spatial::object_reads(object))));
+    match key {
+        Ok(key) => key,
+        Err(error) => {
+            frame_support::log::warn!(target: "runtime::graphdb", "skipped during the index repair: {}", error);
+            (None, 0)
+        }
+    }
+}
+
+/// The `owl:sameAs` quad `(s, p, o)` of the default graph, `None` if it does not link two nodes or cannot be decoded
+fn same_as_link<T: Config>([s, p, o]: [TermId; 3]) -> Option<EncodedQuad> {
+    let mut terms = Vec::with_capacity(3);
+    for id in [s, p, o] {
+        match decode_term_id::<T>(&id) {
+            Ok(term) => terms.push(term),
+            Err(error) => {
+                frame_support::log::warn!(target: "runtime::graphdb", "skipped during the index repair: {}", error);
+                return None;
+            }
+        }
+    }
+    let quad = EncodedQuad::new(terms[0], terms[1], terms[2], EncodedTerm::DefaultGraph);
+    Some(quad).filter(same_as::is_link::<T>)
+}
+
+/// The vector of the object `o`, `None` if it is not a vector or cannot be decoded
+fn object_vector<T: Config>(o: &TermId) -> Option<Vector> {
+    match decode_term_id::<T>(o).and_then(hnsw::object_vector::<T>) {
+        Ok(vector) => vector,
+        Err(error) => {
+            frame_support::log::warn!(target: "runtime::graphdb", "skipped during the index repair: {}", error);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frame_support::{assert_noop, assert_ok};
+
+    use super::*;
+    use crate::{CodecVersionStore, Error};
+    use crate::mock::{new_test_ext, Graphdb, Origin, Test};
+
+    /// Runs the batches of the ongoing repair until it is done, returns the number of blocks it took
+    fn run_repair() -> u32 {
+        let mut blocks = 0;
+        while <IndexRepairCursorStore<Test>>::exists() {
+            RepairIndexes::<Test>::repair_batch();
+            blocks += 1;
+            assert!(blocks < 1000, "the repair does not end");
+        }
+        blocks
+    }
+
+    #[test]
+    fn missing_entries_are_found_and_repaired() {
+        new_test_ext().execute_with(|| {
+            let update = "INSERT DATA { :a :p 1, 2 . :b :q :a . GRAPH :g { :a :p 3 . :c :r 4 } }";
+            Graphdb::sparql_update(Origin::signed(1), update.into()).unwrap();
+            assert_eq!(check_indexes::<Test>(), vec![]);
+            let (s, p, o) = <DefaultSpoStore<Test>>::iter_keys().next().unwrap();
+            <DefaultPosStore<Test>>::remove((p, o, s));
+            let g = <GraphNameStore<Test>>::iter_keys().next().unwrap();
+            let trie = GraphTrie::of::<Test>(&g);
+            let (_, (gs, gp, go)) = trie.triples_after(TAG_SPO, None).next().unwrap();
+            trie.remove_triple(TAG_OSP, (go, gs, gp));
+            <GraphNameStore<Test>>::insert(g, 7);
+            <QuadCountStore<Test>>::put(1);
+
+            let found = check_indexes::<Test>();
+            assert!(found.contains(&missing(StoreFamily::DefaultPos, None, [s, p, o])));
+            assert!(found.contains(&missing(StoreFamily::Gosp, Some(g), [gs, gp, go])));
+            assert!(found.contains(&Inconsistency::GraphCount { graph: g, stored: 7, counted: 2 }));
+            assert!(found.contains(&Inconsistency::QuadCount { stored: 1, counted: 5 }));
+
+            assert_ok!(Graphdb::repair_indexes(Origin::root()));
+            // the store is not available until the indexes are rebuilt
+            assert!(Graphdb::sparql_update(Origin::signed(1), "INSERT DATA { :d :p 5 }".into()).is_err());
+            assert!(run_repair() > 1);
+            assert_eq!(check_indexes::<Test>(), vec![]);
+            Graphdb::sparql_update(Origin::signed(1), "INSERT DATA { :d :p 5 }".into()).unwrap();
+        });
+    }
+
+    #[test]
+    fn dangling_entries_are_found_and_removed() {
+        new_test_ext().execute_with(|| {
+            let update = "INSERT DATA { :a :p 1 . GRAPH :g { :a :p 2 } }";
+            Graphdb::sparql_update(Origin::signed(1), update.into()).unwrap();
+            let (s, p, o) = <DefaultSpoStore<Test>>::iter_keys().next().unwrap();
+            <DefaultOspStore<Test>>::insert((s, o, p), ());
+            let g = <GraphNameStore<Test>>::iter_keys().next().unwrap();
+            GraphTrie::of::<Test>(&g).insert_triple(TAG_POS, (o, s, p));
+
+            let found = check_indexes::<Test>();
+            assert!(found.contains(&dangling(StoreFamily::DefaultOsp, None, [o, p, s])));
+            assert!(found.contains(&dangling(StoreFamily::Gpos, Some(g), [p, o, s])));
+
+            RepairIndexes::<Test>::start();
+            run_repair();
+            assert_eq!(check_indexes::<Test>(), vec![]);
+        });
+    }
+
+    #[test]
+    fn same_as_classes_are_checked_and_rebuilt() {
+        new_test_ext().execute_with(|| {
+            let update = "INSERT DATA { :a owl:sameAs :b . :b owl:sameAs :c . :d owl:sameAs :e }";
+            Graphdb::sparql_update(Origin::signed(1), update.into()).unwrap();
+            assert_eq!(check_indexes::<Test>(), vec![]);
+            let (member, class) = <SameAsStore<Test>>::iter().find(|(member, class)| member != class).unwrap();
+            <SameAsStore<Test>>::remove(member);
+            <SameAsMemberStore<Test>>::insert((member, class), ());
+
+            let found = check_indexes::<Test>();
+            assert!(found.contains(&Inconsistency::SameAsClass { term: member, stored: None, counted: Some(class) }));
+            assert!(found.contains(&Inconsistency::SameAsMember { class: member, member: class, stored: true }));
+
+            RepairIndexes::<Test>::start();
+            run_repair();
+            assert_eq!(check_indexes::<Test>(), vec![]);
+            assert_eq!(<SameAsStore<Test>>::iter().count(), 5);
+        });
+    }
+
+    #[test]
+    fn only_root_repairs_the_indexes() {
+        new_test_ext().execute_with(|| {
+            assert_noop!(Graphdb::repair_indexes(Origin::signed(1)), DispatchError::BadOrigin);
+            <CodecVersionStore<Test>>::put(1);
+            assert_noop!(Graphdb::repair_indexes(Origin::root()), Error::<Test>::ReEncodingOngoing);
+        });
+    }
+}
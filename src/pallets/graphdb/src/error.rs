@@ -1,24 +1,79 @@
-use std::convert::Infallible;
 use std::error::Error;
-use std::io;
+use std::{fmt, io};
 
 // use sp_std::convert::Infallible;
 // use sp_std::vec::Vec;
 
-/// Traits that allows unwrapping only infallible results
-pub(crate) trait UnwrapInfallible {
-    type Value;
+/// An error of the graph storage
+#[derive(Debug)]
+pub enum StoreError {
+    /// A string or a term encoding has the same hash as a different one of the dictionary
+    StrHashCollision { hash: u128 },
+    /// A value is longer than `Config::MaxValueLength`
+    ValueTooLong { length: usize, max: u32 },
+    /// Stored bytes are not a valid term encoding
+    CorruptedTerm { msg: String },
+    /// A stored string is not valid UTF-8
+    InvalidUtf8 { hash: u128 },
+    /// A string or a term encoding referenced by the indexes is not stored
+    MissingStr { hash: u128 },
+    /// The keys are written with an older version of the term encoding and are being re-encoded
+    OutdatedEncoding { stored: u8, current: u8 },
+    /// The secondary indexes are being rebuilt from the primary ones
+    IndexRepairOngoing,
+    /// The inference rules still construct new triples after `Config::MaxRuleRounds` rounds
+    RuleRoundsExceeded { max: u32 },
+    /// The evaluation did more work than the weight declared by its extrinsic
+    WeightExhausted { limit: u64 },
+    /// The store is being migrated to the current storage layout in batches
+    MigrationOngoing,
+    /// A signed update writes a graph only written by root and by the inference (see `crate::Access`)
+    ProtectedGraph { graph: String },
+}
 
-    fn unwrap_infallible(self) -> Self::Value;
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StrHashCollision { hash } => {
+                write!(f, "The hash {:032x} is already used by another string or term", hash)
+            }
+            Self::ValueTooLong { length, max } => {
+                write!(f, "The value of {} bytes is longer than the maximum of {} bytes", length, max)
+            }
+            Self::CorruptedTerm { msg } => write!(f, "Corrupted term encoding: {}", msg),
+            Self::InvalidUtf8 { hash } => write!(f, "The string {:032x} is not valid UTF-8", hash),
+            Self::MissingStr { hash } => write!(f, "The string {:032x} is not in the store", hash),
+            Self::OutdatedEncoding { stored, current } => write!(
+                f,
+                "The keys are written with the term encoding {} and are being re-encoded with the encoding {}",
+                stored, current
+            ),
+            Self::IndexRepairOngoing => write!(f, "The secondary indexes are being rebuilt"),
+            Self::RuleRoundsExceeded { max } => {
+                write!(f, "The inference rules do not reach a fixpoint in {} rounds", max)
+            }
+            Self::WeightExhausted { limit } => write!(f, "The evaluation exceeds the weight limit of {}", limit),
+            Self::MigrationOngoing => write!(f, "The store is being migrated"),
+            Self::ProtectedGraph { graph } => write!(f, "The graph <{}> is only written by root", graph),
+        }
+    }
 }
 
-impl<T> UnwrapInfallible for Result<T, Infallible> {
-    type Value = T;
+impl Error for StoreError {}
 
-    fn unwrap_infallible(self) -> T {
-        match self {
-            Ok(value) => value,
-            Err(error) => match error {},
+impl From<StoreError> for io::Error {
+    fn from(error: StoreError) -> Self {
+        match error {
+            StoreError::ValueTooLong { .. } | StoreError::ProtectedGraph { .. } => invalid_input_error(error),
+            StoreError::StrHashCollision { .. }
+            | StoreError::CorruptedTerm { .. }
+            | StoreError::InvalidUtf8 { .. }
+            | StoreError::MissingStr { .. }
+            | StoreError::OutdatedEncoding { .. }
+            | StoreError::IndexRepairOngoing
+            | StoreError::RuleRoundsExceeded { .. }
+            | StoreError::WeightExhausted { .. }
+            | StoreError::MigrationOngoing => invalid_data_error(error),
         }
     }
 }
@@ -0,0 +1,330 @@
+//! User-defined SPARQL functions: WebAssembly modules registered on chain under the IRI of the function
+//! (see `Pallet::register_function`), called by the queries and the updates like the built-in functions.
+//!
+//! A module exports its `memory`, `alloc(len: i32) -> i32`, which returns the address of `len` free bytes, and
+//! `evaluate(address: i32, len: i32) -> i64`. The arguments of a call are written to the bytes allocated by
+//! `alloc`, in N-Triples and separated by new lines, then `evaluate` returns the address of the value of the call
+//! in its high 32 bits and its length in its low 32 bits. The value is a term in N-Triples, a negative result or
+//! a value that is not a term is an error.
+//!
+//! The calls are deterministic so that the updates calling them replay the same on all the nodes: a module has no
+//! imports and no start function, the float instructions are rejected (their NaN bits depend on the platform), and
+//! each call runs in a fresh instance with at most `MAX_MEMORY_PAGES` pages of memory. The calls of a query or an
+//! update share `Config::MaxFunctionFuel` units of fuel, and the fuel they burn is charged to its meter at
+//! `FUEL_WEIGHT` a unit. A call running out of fuel or memory is an error.
+//!
+//! A module reserves a deposit of its account, by byte, until it is removed (see `Pallet::remove_function`). Its
+//! registration is weighed by its size, as it is compiled and validated, and so is its lookup by a query or an
+//! update. The calls exceeding the weight of the meter fail the evaluation.
+
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use std::str::{self, FromStr};
+
+use frame_support::pallet_prelude::*;
+use frame_support::sp_runtime::traits::Saturating;
+use wasmi::core::ValueType;
+use wasmi::{Engine, ExternType, Instance, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::meter::Meter;
+use crate::model::{NamedNode, Term};
+use crate::sparql::{CustomFunction, EvaluationError};
+use crate::{BalanceOf, Config, FunctionStore};
+
+/// Most pages of 64 KiB of the memory of a call
+const MAX_MEMORY_PAGES: usize = 16;
+
+/// Weight of a unit of fuel, about an instruction of the interpreter
+pub(crate) const FUEL_WEIGHT: Weight = 5_000;
+
+/// Weight of compiling and validating a byte of a module
+const BYTE_WEIGHT: Weight = 500_000;
+
+/// Weight of `register_function` with a module of `len` bytes
+pub(crate) fn register_weight<T: Config>(len: usize) -> Weight {
+    let len = (len as Weight).min(T::MaxFunctionSize::get().into());
+    (10_000 as Weight)
+        .saturating_add(T::DbWeight::get().reads_writes(1, 1))
+        .saturating_add(BYTE_WEIGHT.saturating_mul(len))
+}
+
+/// The deposit reserved for a module of `len` bytes
+pub(crate) fn deposit<T: Config>(len: usize) -> BalanceOf<T> {
+    let len = BalanceOf::<T>::from(u32::try_from(len).unwrap_or(u32::MAX));
+    T::FunctionDeposit::get().saturating_add(T::FunctionByteDeposit::get().saturating_mul(len))
+}
+
+/// The engine of the modules: fuel metering and no float instructions
+fn engine() -> Engine {
+    let mut config = wasmi::Config::default();
+    config.consume_fuel(true).floats(false);
+    Engine::new(&config)
+}
+
+/// Compiles a module and checks its exports, `None` if it is not a valid function
+fn compile(engine: &Engine, code: &[u8]) -> Option<Module> {
+    let module = Module::new(engine, code).ok()?;
+    if module.imports().len() > 0 {
+        return None;
+    }
+    let is_func = |name: &str, params: &[ValueType], results: &[ValueType]| match module.get_export(name) {
+        Some(ExternType::Func(func)) => func.params() == params && func.results() == results,
+        _ => false,
+    };
+    let valid = matches!(module.get_export("memory"), Some(ExternType::Memory(_)))
+        && is_func("alloc", &[ValueType::I32], &[ValueType::I32])
+        && is_func("evaluate", &[ValueType::I32, ValueType::I32], &[ValueType::I64]);
+    if valid {
+        Some(module)
+    } else {
+        None
+    }
+}
+
+/// Whether `code` is a valid function module, its instances having no start function
+pub(crate) fn is_valid(code: &[u8]) -> bool {
+    let engine = engine();
+    match compile(&engine, code) {
+        Some(module) => instantiate(&engine, &module).is_some(),
+        None => false,
+    }
+}
+
+fn instantiate(engine: &Engine, module: &Module) -> Option<(Store<StoreLimits>, Instance)> {
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(MAX_MEMORY_PAGES * 65536)
+        .instances(1)
+        .build();
+    let mut store = Store::new(engine, limits);
+    store.limiter(|limits| limits);
+    let instance = Linker::new(engine)
+        .instantiate(&mut store, module)
+        .ok()?
+        .ensure_no_start(&mut store)
+        .ok()?;
+    Some((store, instance))
+}
+
+/// Calls the function of `module` with `arguments` and `fuel` units of fuel, returns its value, `None` if the call
+/// is an error, and the fuel it burnt
+fn call(engine: &Engine, module: &Module, fuel: u64, arguments: &[Term]) -> (Option<Term>, u64) {
+    let (mut store, instance) = match instantiate(engine, module) {
+        Some(instance) => instance,
+        None => return (None, 0),
+    };
+    let value = evaluate(&mut store, &instance, fuel, arguments);
+    (value, store.fuel_consumed().unwrap_or(fuel))
+}
+
+fn evaluate(store: &mut Store<StoreLimits>, instance: &Instance, fuel: u64, arguments: &[Term]) -> Option<Term> {
+    store.add_fuel(fuel).ok()?;
+    let memory = instance.get_memory(&*store, "memory")?;
+    let alloc = instance.get_typed_func::<i32, i32>(&*store, "alloc").ok()?;
+    let evaluate = instance.get_typed_func::<(i32, i32), i64>(&*store, "evaluate").ok()?;
+
+    let input = arguments
+        .iter()
+        .map(|argument| argument.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let len = i32::try_from(input.len()).ok()?;
+    let address = alloc.call(&mut *store, len).ok()?;
+    memory
+        .write(&mut *store, usize::try_from(address).ok()?, input.as_bytes())
+        .ok()?;
+    let result = u64::try_from(evaluate.call(&mut *store, (address, len)).ok()?).ok()?;
+
+    let start = usize::try_from(result >> 32).ok()?;
+    let end = start.checked_add(usize::try_from(result & 0xFFFF_FFFF).ok()?)?;
+    let output = memory.data(&*store).get(start..end)?;
+    Term::from_str(str::from_utf8(output).ok()?).ok()
+}
+
+/// Looks up the registered functions for a query or an update: their calls burn the fuel left in `fuel`, which
+/// is charged to `meter`
+pub(crate) fn resolver<T: Config>(
+    meter: Rc<Meter>,
+    fuel: Rc<Cell<u64>>,
+) -> impl Fn(&NamedNode) -> Result<Option<CustomFunction>, EvaluationError> {
+    move |name: &NamedNode| resolve::<T>(name, meter.clone(), fuel.clone())
+}
+
+/// The function registered under `name`, compiled for the calls of one query. Reading and compiling the module
+/// are charged to `meter` like its registration.
+fn resolve<T: Config>(
+    name: &NamedNode,
+    meter: Rc<Meter>,
+    fuel: Rc<Cell<u64>>,
+) -> Result<Option<CustomFunction>, EvaluationError> {
+    let key = match BoundedVec::try_from(name.as_str().as_bytes().to_vec()) {
+        Ok(key) => key,
+        Err(_) => return Ok(None),
+    };
+    meter.charge::<T>(1, 0)?;
+    let code = match <FunctionStore<T>>::get(key) {
+        Some((_, code, _)) => code,
+        None => return Ok(None),
+    };
+    meter.charge_weight::<T>(BYTE_WEIGHT.saturating_mul(code.len() as Weight))?;
+    let engine = engine();
+    let module = match compile(&engine, &code) {
+        Some(module) => module,
+        None => return Ok(None),
+    };
+    Ok(Some(Rc::new(move |arguments: &[Term]| {
+        let (value, burnt) = call(&engine, &module, fuel.get(), arguments);
+        fuel.set(fuel.get().saturating_sub(burnt));
+        // a call exhausting the weight of the update fails it
+        meter.charge_weight::<T>(burnt.saturating_mul(FUEL_WEIGHT))?;
+        Ok(value)
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use frame_support::traits::ReservableCurrency;
+    use frame_support::weights::GetDispatchInfo;
+    use frame_support::{assert_noop, assert_ok};
+
+    use super::*;
+    use crate::mock::{new_test_ext, Balances, Graphdb, Origin, Test};
+    use crate::model::Literal;
+    use crate::sparql::{Query, QueryResults};
+    use crate::{Access, Call, Error, GraphStore, Pallet};
+
+    const ECHO: &str = r#"(module
+        (memory (export "memory") 1)
+        (func (export "alloc") (param i32) (result i32) i32.const 1024)
+        (func (export "evaluate") (param i32 i32) (result i64)
+            local.get 0 i64.extend_i32_u i64.const 32 i64.shl local.get 1 i64.extend_i32_u i64.or))"#;
+
+    fn module(body: &str) -> Vec<u8> {
+        wat::parse_str(format!(
+            r#"(module {} (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0))"#,
+            body
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn calls_are_metered_and_deterministic() {
+        let engine = engine();
+        let echo = compile(&engine, &wat::parse_str(ECHO).unwrap()).unwrap();
+        let argument = Term::from(Literal::new_simple_literal("a \"b\"\nc"));
+        assert_eq!(call(&engine, &echo, 1000, std::slice::from_ref(&argument)).0, Some(argument));
+        assert_eq!(call(&engine, &echo, 1000, &[]).0, None);
+        assert_eq!(call(&engine, &echo, 0, &[Term::from(Literal::from(1))]).0, None);
+
+        let endless = module(r#"(func (export "evaluate") (param i32 i32) (result i64) (loop (br 0)) i64.const 0)"#);
+        assert!(is_valid(&endless));
+        let endless = compile(&engine, &endless).unwrap();
+        let (value, burnt) = call(&engine, &endless, 100_000, &[]);
+        assert_eq!(value, None);
+        assert!(burnt > 90_000 && burnt <= 100_000);
+
+        // the floats, the imports and the start functions are rejected, as the modules without the exports
+        assert!(!is_valid(&module(
+            r#"(func (export "evaluate") (param i32 i32) (result i64) f64.const 1 i64.trunc_f64_s)"#
+        )));
+        assert!(!is_valid(&module(
+            r#"(import "env" "now" (func (result i64))) (func (export "evaluate") (param i32 i32) (result i64) call 0)"#
+        )));
+        assert!(!is_valid(&module(
+            r#"(func $s) (start $s) (func (export "evaluate") (param i32 i32) (result i64) i64.const 0)"#
+        )));
+        assert!(!is_valid(&module(r#"(func (export "evaluate") (param i32) (result i64) i64.const 0)"#)));
+        assert!(!is_valid(b"\0asm"));
+    }
+
+    const ENDLESS: &str = r#"(func (export "evaluate") (param i32 i32) (result i64) (loop (br 0)) i64.const 0)"#;
+
+    fn register(account: u64, name: &str, code: Vec<u8>) -> DispatchResult {
+        Graphdb::register_function(Origin::signed(account), format!("http://example.com/{}", name).into_bytes(), code)
+    }
+
+    /// Executes a signed update, returns its weight
+    fn update(update: &str) -> Weight {
+        let info = Graphdb::sparql_update(Origin::signed(1), update.as_bytes().to_vec()).unwrap();
+        info.actual_weight.unwrap()
+    }
+
+    #[test]
+    fn a_function_reserves_a_deposit_until_it_is_removed() {
+        new_test_ext().execute_with(|| {
+            let echo = wat::parse_str(ECHO).unwrap();
+            let endless = module(ENDLESS);
+            assert_ok!(register(1, "f", echo.clone()));
+            assert_eq!(Balances::reserved_balance(1), 100 + echo.len() as u64);
+            // the deposit of a replaced function follows the size of its module
+            assert_ok!(register(1, "f", endless.clone()));
+            assert_eq!(Balances::reserved_balance(1), 100 + endless.len() as u64);
+            assert_noop!(register(2, "f", echo.clone()), Error::<Test>::NotFunctionOwner);
+            assert!(register(3, "g", echo).is_err());
+
+            let f = b"http://example.com/f".to_vec();
+            assert_noop!(Graphdb::remove_function(Origin::signed(2), f.clone()), Error::<Test>::NotFunctionOwner);
+            assert_ok!(Graphdb::remove_function(Origin::signed(1), f.clone()));
+            assert_eq!(Balances::reserved_balance(1), 0);
+            assert_noop!(Graphdb::remove_function(Origin::signed(1), f), Error::<Test>::UnknownFunction);
+        });
+    }
+
+    #[test]
+    fn the_registration_is_weighed_by_the_size_of_the_module() {
+        let weight = |len: usize| {
+            let call = Call::<Test>::register_function { iri: b"http://example.com/f".to_vec(), code: vec![0; len] };
+            call.get_dispatch_info().weight
+        };
+        assert_eq!(weight(1024) - weight(0), 1024 * BYTE_WEIGHT);
+        // a module larger than the largest one fails before being compiled
+        assert_eq!(weight(1 << 20), weight(64 * 1024));
+    }
+
+    #[test]
+    fn the_calls_of_an_update_share_its_fuel() {
+        new_test_ext().execute_with(|| {
+            assert_ok!(register(1, "echo", wat::parse_str(ECHO).unwrap()));
+            assert_ok!(register(1, "endless", module(ENDLESS)));
+
+            let echoed = update("INSERT { :r :echo ?w } WHERE { BIND(<http://example.com/echo>(1) AS ?w) }");
+            assert!(Pallet::<Test>::execute_query("ASK { :r :echo 1 }").contains("true"));
+            // the fuel burnt is charged to the update
+            let once = update("INSERT { :r :loop ?v } WHERE { BIND(<http://example.com/endless>(1) AS ?v) }");
+            assert!(once > echoed + 90_000 * FUEL_WEIGHT);
+            // the first call burns the fuel of the update, the next ones fail
+            let sparql = "INSERT { :s :echo ?w } WHERE { VALUES ?x { 1 2 3 } \
+                BIND(<http://example.com/endless>(?x) AS ?v) BIND(<http://example.com/echo>(?x) AS ?w) }";
+            let thrice = update(sparql);
+            assert!(thrice < once + echoed);
+            assert!(Pallet::<Test>::execute_query("ASK { :s :echo ?w }").contains("false"));
+        });
+    }
+
+    #[test]
+    fn the_module_and_the_calls_exhausting_the_weight_fail_the_query() {
+        new_test_ext().execute_with(|| {
+            let endless = module(ENDLESS);
+            let compiled = <Test as frame_system::Config>::DbWeight::get().reads(1)
+                + BYTE_WEIGHT * endless.len() as Weight;
+            assert_ok!(register(1, "endless", endless));
+            let sparql = "SELECT ?v WHERE { BIND(<http://example.com/endless>(1) AS ?v) }";
+            let query = || Query::parse(sparql, None).unwrap();
+
+            // reading and compiling the module are charged before the evaluation
+            let store = GraphStore::<Test>::with_weight_limit(compiled - 1, Access::Root);
+            assert!(store.query(query()).is_err());
+            assert!(store.meter.is_exhausted());
+
+            // the fuel burnt by a call is an error of the evaluation, not an unbound value
+            let limit = compiled + <Test as frame_system::Config>::DbWeight::get().reads(10) + 10_000 * FUEL_WEIGHT;
+            let store = GraphStore::<Test>::with_weight_limit(limit, Access::Root);
+            match store.query(query()).unwrap() {
+                QueryResults::Solutions(solutions) => assert!(solutions.collect::<Result<Vec<_>, _>>().is_err()),
+                _ => unreachable!(),
+            }
+            assert!(store.meter.is_exhausted());
+        });
+    }
+}
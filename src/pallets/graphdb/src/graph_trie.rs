@@ -0,0 +1,235 @@
+//! Storage of the named graphs: each graph has its own child trie.
+//!
+//! The root of a trie commits to the content of its graph only. A graph is dropped by starting a new generation
+//! of its trie (see `GraphGenerationStore`): the trie of the older generation is removed in batches across blocks
+//! (see `crate::purge`), so that dropping a graph does not depend on its size.
+//! The keys are a tag followed by fixed-width term ids (see `TermId`):
+//! - the `SPO`, `POS` and `OSP` indexes of the quads of the graph, with empty values
+//! - the ordered literal index: `p`, the ordered key of `o`, `o` and `s`, with empty values
+//! - the cardinality statistics of the graph, counted in the global statistics too
+
+use frame_support::sp_runtime::StateVersion;
+use frame_support::storage::child::{self, ChildInfo, KillStorageResult};
+
+use crate::{Config, GraphGenerationStore};
+use crate::store::codec::{OrderedKey, TermId, ORDERED_KEY_SIZE, TERM_ID_SIZE};
+
+const CHILD_STORAGE_KEY_PREFIX: &[u8] = b"graphdb:graph:";
+
+pub(crate) const TAG_SPO: u8 = 0;
+pub(crate) const TAG_POS: u8 = 1;
+pub(crate) const TAG_OSP: u8 = 2;
+pub(crate) const TAG_ORDERED_LITERAL: u8 = 3;
+const TAG_PREDICATE_COUNT: u8 = 4;
+const TAG_PREDICATE_CLASS_COUNT: u8 = 5;
+
+/// The child trie of a named graph
+pub(crate) struct GraphTrie {
+    info: ChildInfo,
+}
+
+impl GraphTrie {
+    /// The trie of the current generation of the named graph `graph_name`
+    pub fn of<T: Config>(graph_name: &TermId) -> Self {
+        Self::with_generation(graph_name, <GraphGenerationStore<T>>::get(graph_name))
+    }
+
+    /// The trie of a generation of a named graph, the first generation is keyed by the graph name only
+    pub fn with_generation(graph_name: &TermId, generation: u32) -> Self {
+        let mut storage_key = CHILD_STORAGE_KEY_PREFIX.to_vec();
+        storage_key.extend_from_slice(graph_name);
+        if generation > 0 {
+            storage_key.extend_from_slice(&generation.to_le_bytes());
+        }
+        Self { info: ChildInfo::new_default_from_vec(storage_key) }
+    }
+
+    /// Root hash of the trie, it changes with any quad of the graph
+    pub fn root(&self, version: StateVersion) -> Vec<u8> {
+        child::root(&self.info, version)
+    }
+
+    /// Removes up to `limit` keys of the trie
+    pub fn kill(&self, limit: u32) -> KillStorageResult {
+        child::kill_storage(&self.info, Some(limit))
+    }
+
+    /// Removes up to `limit` keys of the index `tag`
+    pub fn clear_index(&self, tag: u8, limit: u32) -> KillStorageResult {
+        sp_io::default_child_storage::clear_prefix(self.info.storage_key(), &[tag], Some(limit))
+    }
+
+    pub fn insert_triple(&self, tag: u8, triple: (TermId, TermId, TermId)) {
+        child::put_raw(&self.info, &triple_key(tag, triple), &[]);
+    }
+
+    pub fn remove_triple(&self, tag: u8, triple: (TermId, TermId, TermId)) {
+        child::kill(&self.info, &triple_key(tag, triple));
+    }
+
+    pub fn contains_triple(&self, tag: u8, triple: (TermId, TermId, TermId)) -> bool {
+        child::exists(&self.info, &triple_key(tag, triple))
+    }
+
+    /// Triples of an index starting with `prefix`, in the order of the index
+    pub fn triples_with_prefix(&self, tag: u8, prefix: &[TermId]) -> impl Iterator<Item = (TermId, TermId, TermId)> {
+        let mut start = vec![tag];
+        for id in prefix {
+            start.extend_from_slice(id);
+        }
+        self.keys_from(start.clone(), start).map(|key| triple_at(&key))
+    }
+
+    /// Triples of an index after the key `after` (from the first one if `None`), with their key
+    pub fn triples_after(&self, tag: u8, after: Option<Vec<u8>>) -> impl Iterator<Item = (Vec<u8>, (TermId, TermId, TermId))> {
+        self.keys_from(vec![tag], after.unwrap_or_else(|| vec![tag])).map(|key| {
+            let triple = triple_at(&key);
+            (key, triple)
+        })
+    }
+
+    pub fn insert_ordered_literal(&self, p: TermId, key: OrderedKey, o: TermId, s: TermId) {
+        child::put_raw(&self.info, &ordered_literal_key(p, key, o, s), &[]);
+    }
+
+    pub fn remove_ordered_literal(&self, p: TermId, key: OrderedKey, o: TermId, s: TermId) {
+        child::kill(&self.info, &ordered_literal_key(p, key, o, s));
+    }
+
+    pub fn contains_ordered_literal(&self, p: TermId, key: OrderedKey, o: TermId, s: TermId) -> bool {
+        child::exists(&self.info, &ordered_literal_key(p, key, o, s))
+    }
+
+    /// All the entries `(p, ordered key of o, o, s)` of the ordered literal index
+    pub fn ordered_literal_entries(&self) -> impl Iterator<Item = (TermId, OrderedKey, TermId, TermId)> {
+        self.keys_from(vec![TAG_ORDERED_LITERAL], vec![TAG_ORDERED_LITERAL]).map(|key| {
+            let mut ordered_key = [0; ORDERED_KEY_SIZE];
+            ordered_key.copy_from_slice(&key[1 + TERM_ID_SIZE..1 + TERM_ID_SIZE + ORDERED_KEY_SIZE]);
+            let o = 1 + TERM_ID_SIZE + ORDERED_KEY_SIZE;
+            (term_id_at(&key, 1), ordered_key, term_id_at(&key, o), term_id_at(&key, o + TERM_ID_SIZE))
+        })
+    }
+
+    /// `(o, s)` of the ordered literal index for `p`, from the key `lower` to the key `upper` (both included)
+    pub fn ordered_literals(&self, p: TermId, lower: OrderedKey, upper: OrderedKey) -> impl Iterator<Item = (TermId, TermId)> {
+        let mut prefix = vec![TAG_ORDERED_LITERAL];
+        prefix.extend_from_slice(&p);
+        let mut start = prefix.clone();
+        start.extend_from_slice(&lower);
+        let key_start = prefix.len();
+        self.keys_from(prefix, start)
+            .take_while(move |key| key[key_start..key_start + ORDERED_KEY_SIZE] <= upper[..])
+            .map(move |key| {
+                let o = key_start + ORDERED_KEY_SIZE;
+                (term_id_at(&key, o), term_id_at(&key, o + TERM_ID_SIZE))
+            })
+    }
+
+    /// Applies `update` to the number of quads of the graph with the predicate `p`
+    pub fn mutate_predicate_count(&self, p: TermId, update: impl FnOnce(&mut Option<u64>)) {
+        self.mutate_count(TAG_PREDICATE_COUNT, &[p], update);
+    }
+
+    /// Applies `update` to the number of quads of the graph with the predicate `p` whose subject has the class `class`
+    pub fn mutate_predicate_class_count(&self, p: TermId, class: TermId, update: impl FnOnce(&mut Option<u64>)) {
+        self.mutate_count(TAG_PREDICATE_CLASS_COUNT, &[p, class], update);
+    }
+
+    /// Number of quads of the graph with the predicate `p`
+    pub fn predicate_count(&self, p: TermId) -> u64 {
+        let mut key = vec![TAG_PREDICATE_COUNT];
+        key.extend_from_slice(&p);
+        child::get::<u64>(&self.info, &key).unwrap_or_default()
+    }
+
+    /// The statistics of the graph: `(p, count)`
+    pub fn predicate_counts(&self) -> impl Iterator<Item = (TermId, u64)> + '_ {
+        self.predicate_counts_after(None).map(|(_, p, count)| (p, count))
+    }
+
+    /// The statistics of the graph: `(p, class, count)`
+    pub fn predicate_class_counts(&self) -> impl Iterator<Item = (TermId, TermId, u64)> + '_ {
+        self.predicate_class_counts_after(None).map(|(_, (p, class), count)| (p, class, count))
+    }
+
+    /// The statistics `(p, count)` after the key `after` (from the first one if `None`), with their key
+    pub fn predicate_counts_after(&self, after: Option<Vec<u8>>) -> impl Iterator<Item = (Vec<u8>, TermId, u64)> + '_ {
+        self.counts(TAG_PREDICATE_COUNT, after).map(|(key, count)| {
+            let p = term_id_at(&key, 1);
+            (key, p, count)
+        })
+    }
+
+    /// The statistics `(p, class, count)` after the key `after` (from the first one if `None`), with their key
+    pub fn predicate_class_counts_after(
+        &self,
+        after: Option<Vec<u8>>,
+    ) -> impl Iterator<Item = (Vec<u8>, (TermId, TermId), u64)> + '_ {
+        self.counts(TAG_PREDICATE_CLASS_COUNT, after).map(|(key, count)| {
+            let ids = (term_id_at(&key, 1), term_id_at(&key, 1 + TERM_ID_SIZE));
+            (key, ids, count)
+        })
+    }
+
+    fn mutate_count(&self, tag: u8, ids: &[TermId], update: impl FnOnce(&mut Option<u64>)) {
+        let mut key = vec![tag];
+        for id in ids {
+            key.extend_from_slice(id);
+        }
+        let mut count = child::get::<u64>(&self.info, &key);
+        update(&mut count);
+        match count {
+            Some(count) => child::put(&self.info, &key, &count),
+            None => child::kill(&self.info, &key),
+        }
+    }
+
+    fn counts(&self, tag: u8, after: Option<Vec<u8>>) -> impl Iterator<Item = (Vec<u8>, u64)> + '_ {
+        self.keys_from(vec![tag], after.unwrap_or_else(|| vec![tag])).map(move |key| {
+            let count = child::get::<u64>(&self.info, &key).unwrap_or_default();
+            (key, count)
+        })
+    }
+
+    /// The keys starting with `prefix` after `start`, read lazily
+    fn keys_from(&self, prefix: Vec<u8>, start: Vec<u8>) -> impl Iterator<Item = Vec<u8>> {
+        let storage_key = self.info.storage_key().to_vec();
+        let mut last = start;
+        core::iter::from_fn(move || {
+            let key = sp_io::default_child_storage::next_key(&storage_key, &last)
+                .filter(|key| key.starts_with(&prefix))?;
+            last = key.clone();
+            Some(key)
+        })
+    }
+}
+
+fn triple_key(tag: u8, triple: (TermId, TermId, TermId)) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + 3 * TERM_ID_SIZE);
+    key.push(tag);
+    key.extend_from_slice(&triple.0);
+    key.extend_from_slice(&triple.1);
+    key.extend_from_slice(&triple.2);
+    key
+}
+
+fn ordered_literal_key(p: TermId, ordered_key: OrderedKey, o: TermId, s: TermId) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + ORDERED_KEY_SIZE + 3 * TERM_ID_SIZE);
+    key.push(TAG_ORDERED_LITERAL);
+    key.extend_from_slice(&p);
+    key.extend_from_slice(&ordered_key);
+    key.extend_from_slice(&o);
+    key.extend_from_slice(&s);
+    key
+}
+
+fn triple_at(key: &[u8]) -> (TermId, TermId, TermId) {
+    (term_id_at(key, 1), term_id_at(key, 1 + TERM_ID_SIZE), term_id_at(key, 1 + 2 * TERM_ID_SIZE))
+}
+
+fn term_id_at(key: &[u8], start: usize) -> TermId {
+    let mut id = [0; TERM_ID_SIZE];
+    id.copy_from_slice(&key[start..start + TERM_ID_SIZE]);
+    id
+}
+
@@ -0,0 +1,493 @@
+//! Approximate nearest-neighbour index of the `vec:floatVector` embeddings of the default graph.
+//!
+//! The vector objects of each predicate are the nodes of a hierarchical navigable small world graph (HNSW): each
+//! node is in the layers `0` to its level, and is linked in each of them to up to `M` of its closest nodes (`M0` in
+//! the layer `0`). A lookup walks greedily from the entry point, a node of the highest level, down to the layer
+//! `0` where it keeps the `EF_SEARCH` closest nodes found. The vectors are compared with the cosine distance, the
+//! vectors of different dimensions are in separate graphs and the zero vectors are not indexed.
+//!
+//! `VectorNodeStore` has the nodes of each predicate with their level and their vector scaled to unit length,
+//! `VectorEdgeStore` their links in each layer and `VectorEntryStore` the entry point of each graph. They are
+//! kept up to date with the index entries of the quads when `QuadIndexes::VECTOR` is maintained: a node counts the
+//! quads with its predicate and vector, and is removed with the last one, its neighbours being linked to each other.
+//! The level of a node is drawn from the hash of its object so that all the nodes replay the same insertions.
+//!
+//! The queries read it with the `vec:nearest` property function (see `crate::sparql::plan_builder`):
+//! `(?profile ?distance) vec:nearest (:embedding "[0.1, 0.8, 0.3]"^^vec:floatVector 5)` binds the subjects of
+//! the 5 quads whose `:embedding` vector is the closest to the query, and their distance. The vectors of the named
+//! graphs are not indexed, they are scanned.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use frame_support::pallet_prelude::*;
+
+use crate::{Config, Id2StrStore, VectorEdgeStore, VectorEntryStore, VectorNodeStore};
+use crate::error::StoreError;
+use crate::meter::Accesses;
+use crate::model::vector::Vector;
+use crate::store::codec::{EncodedQuad, EncodedTerm, TermId};
+use crate::store::model::StrHash;
+
+/// The property function of the nearest neighbour search
+pub(crate) const NEAREST: &str = "http://relationlabs.ai/vector/nearest";
+
+/// Most links of a node in the layers above the layer `0`
+const M: usize = 16;
+
+/// Most links of a node in the layer `0`
+const M0: usize = 2 * M;
+
+/// Number of closest nodes kept while linking a new node
+const EF_CONSTRUCTION: usize = 64;
+
+/// Least number of closest nodes kept by a lookup
+const EF_SEARCH: usize = 64;
+
+/// Highest level of a node
+const MAX_LEVEL: u8 = 16;
+
+/// A vector object of the index
+#[derive(Encode, Decode, Clone, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct VectorNode {
+    /// The highest layer of the node
+    pub level: u8,
+    /// Number of quads of the default graph with the predicate and the vector
+    pub quads: u32,
+    /// The bits of the components of the vector scaled to unit length
+    pub unit: Vec<u32>,
+}
+
+/// A node found by a lookup: the bits of its distance to the query, which sort as the distance, and its object
+type Candidate = (u64, TermId);
+
+/// Adds the vector object of a quad inserted in the default graph, returns the reads and writes it did
+pub(crate) fn index<T: Config>(quad: &EncodedQuad) -> Result<Accesses, StoreError> {
+    if quad.graph_name.is_default_graph() {
+        if let Some(vector) = object_vector::<T>(quad.object)? {
+            return Ok(insert::<T>(quad.predicate.term_id(), quad.object.term_id(), &vector));
+        }
+    }
+    Ok((0, 0))
+}
+
+/// Removes the vector object of a quad removed from the default graph, returns the reads and writes it did
+pub(crate) fn unindex<T: Config>(quad: &EncodedQuad) -> Result<Accesses, StoreError> {
+    if quad.graph_name.is_default_graph() {
+        return Ok(remove::<T>(quad.predicate.term_id(), quad.object.term_id()));
+    }
+    Ok((0, 0))
+}
+
+/// The vector of a term, `None` if the term is not a vector
+pub(crate) fn object_vector<T: Config>(object: EncodedTerm) -> Result<Option<Vector>, StoreError> {
+    let text = match object {
+        EncodedTerm::SmallVectorLiteral(value) => String::from(value),
+        EncodedTerm::BigVectorLiteral { value_id } => {
+            let value = <Id2StrStore<T>>::get(*value_id).ok_or(StoreError::MissingStr { hash: *value_id })?;
+            String::from_utf8(value.into_inner()).map_err(|_| StoreError::InvalidUtf8 { hash: *value_id })?
+        }
+        _ => return Ok(None),
+    };
+    // the literals are encoded as vectors only when they are valid
+    Ok(text.parse().ok())
+}
+
+/// Counts a quad with the vector `object` and `predicate`, the vector is linked to the graph by the first one.
+/// Returns the reads and writes it did: up to `EF_CONSTRUCTION` nodes and their links in each layer of the node,
+/// and the links of its neighbours.
+pub(crate) fn insert<T: Config>(predicate: TermId, object: TermId, vector: &Vector) -> Accesses {
+    if <VectorNodeStore<T>>::contains_key((predicate, object)) {
+        <VectorNodeStore<T>>::mutate((predicate, object), |node| {
+            if let Some(node) = node {
+                node.quads += 1;
+            }
+        });
+        return (2, 1);
+    }
+    let unit = match unit_vector(vector) {
+        Some(unit) => unit,
+        None => return (1, 0),
+    };
+    let level = level(&object);
+    let dimensions = unit.len() as u32;
+    let mut graph = Graph::<T>::new(predicate);
+    graph.reads += 2;
+    graph.put_node(object, VectorNode { level, quads: 1, unit: unit.iter().map(|c| c.to_bits()).collect() });
+
+    let entry = match <VectorEntryStore<T>>::get((predicate, dimensions)) {
+        Some(entry) => entry,
+        None => {
+            <VectorEntryStore<T>>::insert((predicate, dimensions), object);
+            return (graph.reads, graph.writes + 1);
+        }
+    };
+    let (entry_level, entry_distance) = match (graph.level(&entry), graph.distance(&unit, &entry)) {
+        (Some(entry_level), Some(entry_distance)) => (entry_level, entry_distance),
+        // the entry point is not a node of the graph anymore
+        _ => {
+            <VectorEntryStore<T>>::insert((predicate, dimensions), object);
+            return (graph.reads, graph.writes + 1);
+        }
+    };
+    let mut closest = vec![(entry_distance, entry)];
+    for layer in (level.saturating_add(1)..=entry_level).rev() {
+        closest = graph.search_layer(&unit, &closest, 1, layer);
+    }
+    for layer in (0..=level.min(entry_level)).rev() {
+        closest = graph.search_layer(&unit, &closest, EF_CONSTRUCTION, layer);
+        let neighbours: Vec<TermId> = closest
+            .iter()
+            .map(|(_, neighbour)| *neighbour)
+            .filter(|neighbour| *neighbour != object)
+            .take(max_links(layer))
+            .collect();
+        for neighbour in neighbours.iter() {
+            graph.link(&object, neighbour, layer);
+        }
+    }
+    if level > entry_level {
+        <VectorEntryStore<T>>::insert((predicate, dimensions), object);
+        graph.writes += 1;
+    }
+    (graph.reads, graph.writes)
+}
+
+/// Uncounts a quad with the vector `object` and `predicate`, the vector is removed from the graph with the last one.
+/// Returns the reads and writes it did: the links of its neighbours in each layer of the node.
+pub(crate) fn remove<T: Config>(predicate: TermId, object: TermId) -> Accesses {
+    let node = match <VectorNodeStore<T>>::get((predicate, object)) {
+        Some(node) => node,
+        None => return (1, 0),
+    };
+    if node.quads > 1 {
+        <VectorNodeStore<T>>::insert((predicate, object), VectorNode { quads: node.quads - 1, ..node });
+        return (1, 1);
+    }
+    <VectorNodeStore<T>>::remove((predicate, object));
+    let mut graph = Graph::<T>::new(predicate);
+    graph.reads += 1;
+    graph.writes += 1;
+    graph.nodes.insert(object, None);
+    // the neighbours in the highest layer where the node has some
+    let mut top_neighbours = Vec::new();
+    for layer in 0..=node.level {
+        let neighbours = <VectorEdgeStore<T>>::take((predicate, object, layer));
+        graph.reads += 1;
+        graph.writes += 1;
+        for neighbour in neighbours.iter() {
+            graph.unlink(neighbour, &object, layer);
+        }
+        // the neighbours losing a link are linked to each other instead
+        for (i, neighbour) in neighbours.iter().enumerate() {
+            for other in neighbours[i + 1..].iter() {
+                graph.link(neighbour, other, layer);
+            }
+        }
+        if !neighbours.is_empty() {
+            top_neighbours = neighbours;
+        }
+    }
+
+    let dimensions = node.unit.len() as u32;
+    graph.reads += 1;
+    if <VectorEntryStore<T>>::get((predicate, dimensions)) == Some(object) {
+        let mut entry = top_neighbours
+            .iter()
+            .filter_map(|neighbour| Some((graph.level(neighbour)?, *neighbour)))
+            .max();
+        if entry.is_none() {
+            // a node linked to no other node: the graph may have other nodes
+            for (object, node) in <VectorNodeStore<T>>::iter_prefix((predicate,)) {
+                graph.reads += 1;
+                if node.unit.len() as u32 == dimensions {
+                    entry = entry.max(Some((node.level, object)));
+                }
+            }
+        }
+        match entry {
+            Some((_, entry)) => <VectorEntryStore<T>>::insert((predicate, dimensions), entry),
+            None => <VectorEntryStore<T>>::remove((predicate, dimensions)),
+        }
+        graph.writes += 1;
+    }
+    (graph.reads, graph.writes)
+}
+
+/// The objects of the `k` vectors with `predicate` the closest to `query` found by a lookup, the closest first
+pub(crate) fn nearest<T: Config>(predicate: TermId, query: &Vector, k: usize) -> Vec<TermId> {
+    let unit = match unit_vector(query) {
+        Some(unit) => unit,
+        None => return Vec::new(),
+    };
+    let mut graph = Graph::<T>::new(predicate);
+    let entry = match <VectorEntryStore<T>>::get((predicate, unit.len() as u32)) {
+        Some(entry) => entry,
+        None => return Vec::new(),
+    };
+    let (entry_level, entry_distance) = match (graph.level(&entry), graph.distance(&unit, &entry)) {
+        (Some(entry_level), Some(entry_distance)) => (entry_level, entry_distance),
+        _ => return Vec::new(),
+    };
+    let mut closest = vec![(entry_distance, entry)];
+    for layer in (1..=entry_level).rev() {
+        closest = graph.search_layer(&unit, &closest, 1, layer);
+    }
+    graph
+        .search_layer(&unit, &closest, EF_SEARCH.max(k), 0)
+        .into_iter()
+        .take(k)
+        .map(|(_, object)| object)
+        .collect()
+}
+
+/// The nodes and the links of the graph of a predicate, the nodes read are cached
+struct Graph<T> {
+    predicate: TermId,
+    /// `None` if the object is not a node
+    nodes: BTreeMap<TermId, Option<VectorNode>>,
+    /// Number of storage reads and writes done
+    reads: u64,
+    writes: u64,
+    _p: PhantomData<T>,
+}
+
+impl<T: Config> Graph<T> {
+    fn new(predicate: TermId) -> Self {
+        Self { predicate, nodes: BTreeMap::new(), reads: 0, writes: 0, _p: PhantomData }
+    }
+
+    fn node(&mut self, object: &TermId) -> Option<&VectorNode> {
+        let (predicate, reads) = (self.predicate, &mut self.reads);
+        self.nodes
+            .entry(*object)
+            .or_insert_with(|| {
+                *reads += 1;
+                <VectorNodeStore<T>>::get((predicate, *object))
+            })
+            .as_ref()
+    }
+
+    fn put_node(&mut self, object: TermId, node: VectorNode) {
+        <VectorNodeStore<T>>::insert((self.predicate, object), &node);
+        self.writes += 1;
+        self.nodes.insert(object, Some(node));
+    }
+
+    fn level(&mut self, object: &TermId) -> Option<u8> {
+        Some(self.node(object)?.level)
+    }
+
+    /// The cosine distance of the node `object` to the unit vector `query`, ordered by its bits
+    fn distance(&mut self, query: &[f32], object: &TermId) -> Option<u64> {
+        let node = self.node(object)?;
+        if node.unit.len() != query.len() {
+            return None;
+        }
+        let dot: f64 = node.unit.iter().zip(query).map(|(a, b)| f64::from(f32::from_bits(*a)) * f64::from(*b)).sum();
+        Some((1. - dot).clamp(0., 2.).to_bits())
+    }
+
+    fn links(&mut self, object: &TermId, layer: u8) -> Vec<TermId> {
+        self.reads += 1;
+        <VectorEdgeStore<T>>::get((self.predicate, *object, layer))
+    }
+
+    /// Links two nodes in a layer, unless one of them keeps closer links.
+    /// The links go both ways so that a removed node is unlinked from all the nodes linked to it.
+    fn link(&mut self, object: &TermId, other: &TermId, layer: u8) {
+        if object == other || self.node(object).is_none() || self.node(other).is_none() {
+            return;
+        }
+        let mut links = self.links(object, layer);
+        if links.contains(other) {
+            return;
+        }
+        links.push(*other);
+        if self.put_links(object, layer, links) {
+            let mut links = self.links(other, layer);
+            links.push(*object);
+            self.put_links(other, layer, links);
+        }
+    }
+
+    /// Removes the link of a node to another in a layer
+    fn unlink(&mut self, object: &TermId, other: &TermId, layer: u8) {
+        let mut links = self.links(object, layer);
+        if let Some(i) = links.iter().position(|link| link == other) {
+            links.remove(i);
+            <VectorEdgeStore<T>>::insert((self.predicate, *object, layer), links);
+            self.writes += 1;
+        }
+    }
+
+    /// Writes the links of a node in a layer, keeping the closest ones if there are too many: the dropped nodes
+    /// are unlinked from the node. Returns whether the last link was kept.
+    fn put_links(&mut self, object: &TermId, layer: u8, mut links: Vec<TermId>) -> bool {
+        let last = links.last().copied();
+        let mut dropped = Vec::new();
+        if links.len() > max_links(layer) {
+            if let Some(node) = self.node(object) {
+                let unit: Vec<f32> = node.unit.iter().map(|c| f32::from_bits(*c)).collect();
+                let mut candidates: Vec<Candidate> = links
+                    .iter()
+                    .filter_map(|link| Some((self.distance(&unit, link)?, *link)))
+                    .collect();
+                candidates.sort_unstable();
+                links = candidates.into_iter().map(|(_, link)| link).collect();
+            }
+            dropped = links.split_off(max_links(layer).min(links.len()));
+        }
+        for link in dropped.iter() {
+            self.unlink(link, object, layer);
+        }
+        let kept = last.map(|last| links.contains(&last)).unwrap_or(true);
+        <VectorEdgeStore<T>>::insert((self.predicate, *object, layer), links);
+        self.writes += 1;
+        kept
+    }
+
+    /// The `ef` closest nodes of a layer to `query` found from the nodes `entry`, the closest first
+    fn search_layer(&mut self, query: &[f32], entry: &[Candidate], ef: usize, layer: u8) -> Vec<Candidate> {
+        let mut visited: BTreeSet<TermId> = entry.iter().map(|(_, object)| *object).collect();
+        let mut candidates: BTreeSet<Candidate> = entry.iter().copied().collect();
+        let mut found = candidates.clone();
+        while let Some(closest) = candidates.iter().next().copied() {
+            candidates.remove(&closest);
+            if found.len() >= ef && closest.0 > furthest(&found) {
+                break;
+            }
+            for neighbour in self.links(&closest.1, layer) {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                // the links to the removed nodes are skipped
+                let distance = match self.distance(query, &neighbour) {
+                    Some(distance) => distance,
+                    None => continue,
+                };
+                if found.len() < ef || distance < furthest(&found) {
+                    candidates.insert((distance, neighbour));
+                    found.insert((distance, neighbour));
+                    if found.len() > ef {
+                        let furthest = found.iter().next_back().copied();
+                        found.remove(&furthest.unwrap());
+                    }
+                }
+            }
+        }
+        found.into_iter().collect()
+    }
+}
+
+/// The distance of the furthest candidate
+fn furthest(found: &BTreeSet<Candidate>) -> u64 {
+    found.iter().next_back().map(|(distance, _)| *distance).unwrap_or(u64::MAX)
+}
+
+fn max_links(layer: u8) -> usize {
+    if layer == 0 {
+        M0
+    } else {
+        M
+    }
+}
+
+/// The vector scaled to unit length, `None` for the zero vectors
+fn unit_vector(vector: &Vector) -> Option<Vec<f32>> {
+    let norm = vector.norm();
+    if norm == 0. {
+        return None;
+    }
+    Some(vector.components().iter().map(|c| (f64::from(*c) / norm) as f32).collect())
+}
+
+/// The level of a node, drawn from the hash of its object with the probability `M^-level` to be at least `level`
+fn level(object: &TermId) -> u8 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&StrHash::from_bytes(object).to_be_bytes()[..8]);
+    // uniform in ]0, 1]
+    let uniform = (u64::from_be_bytes(bytes) >> 11) as f64 / (1_u64 << 53) as f64 + 1. / (1_u64 << 54) as f64;
+    (-uniform.ln() / (M as f64).ln()).min(f64::from(MAX_LEVEL)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consistency::RepairIndexes;
+    use crate::mock::{new_test_ext, Graphdb, Origin, Test};
+    use crate::{IndexRepairCursorStore, Pallet};
+
+    /// An update inserting `count` vectors of the predicate `:embedding` from `first`, returns its weight
+    fn insert_vectors(first: u32, count: u32) -> Weight {
+        let quads: Vec<String> = (first..first + count)
+            .map(|i| {
+                let (x, y) = (f64::from(i).cos(), f64::from(i).sin());
+                format!(":v{} :embedding \"[{}, {}, {}]\"^^vec:floatVector .", i, x, y, i % 7)
+            })
+            .collect();
+        let update = format!("INSERT DATA {{ {} }}", quads.join(" ")).into_bytes();
+        Graphdb::sparql_update(Origin::signed(1), update).unwrap().actual_weight.unwrap()
+    }
+
+    #[test]
+    fn levels_are_geometric() {
+        let levels: Vec<u8> = (0..4096_u32)
+            .map(|i| {
+                let mut object = TermId::default();
+                object[..4].copy_from_slice(&i.to_be_bytes());
+                level(&object)
+            })
+            .collect();
+        let above = |level: u8| levels.iter().filter(|l| **l >= level).count();
+        // about 4096 / 16 and 4096 / 256
+        assert!((150..400).contains(&above(1)));
+        assert!((5..40).contains(&above(2)));
+        assert!(levels.iter().all(|level| *level <= MAX_LEVEL));
+    }
+
+    #[test]
+    fn an_insertion_is_charged_for_the_nodes_it_reads_and_links() {
+        new_test_ext().execute_with(|| {
+            let first = insert_vectors(0, 1);
+            for first in (1..121).step_by(20) {
+                insert_vectors(first, 20);
+            }
+            let linked = insert_vectors(121, 1);
+            // the new node is compared with the closest nodes and linked to them
+            let writes = <Test as frame_system::Config>::DbWeight::get().writes(2 * M as u64);
+            assert!(linked > first + writes, "{} <= {} + {}", linked, first, writes);
+
+            let vector = "[0.5, 0.5, 1]".parse().unwrap();
+            let (mut predicate, mut object) = (TermId::default(), TermId::default());
+            predicate[0] = 1;
+            object[0] = 2;
+            // the first node of a graph is its entry point
+            assert_eq!(insert::<Test>(predicate, object, &vector), (2, 2));
+        });
+    }
+
+    /// Repairs the indexes, returns the weight of the heaviest batch
+    fn heaviest_repair_batch() -> Weight {
+        RepairIndexes::<Test>::start();
+        let mut heaviest = 0;
+        while <IndexRepairCursorStore<Test>>::exists() {
+            heaviest = heaviest.max(RepairIndexes::<Test>::repair_batch());
+        }
+        heaviest
+    }
+
+    #[test]
+    fn the_repair_is_charged_for_the_vector_index() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a :p 1, 2, 3 }").unwrap();
+            let without_vectors = heaviest_repair_batch();
+
+            Pallet::<Test>::execute_update("DELETE DATA { :a :p 1, 2, 3 }").unwrap();
+            for first in (0..60).step_by(20) {
+                insert_vectors(first, 20);
+            }
+            assert!(heaviest_repair_batch() > 2 * without_vectors);
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+}
@@ -2,29 +2,50 @@
 
 use pallet_timestamp as timestamp;
 
-use std::convert::{Infallible, TryFrom, TryInto};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
 use std::io::{BufRead, BufReader, Write};
-use std::vec::IntoIter;
+use std::iter::{empty, once};
+use std::rc::Rc;
 
 use frame_support::pallet_prelude::*;
-
-use crate::error::UnwrapInfallible;
+use frame_support::StorageHasher;
+use frame_support::codec::FullCodec;
+use frame_support::dispatch::{DispatchErrorWithPostInfo, WithPostDispatchInfo};
+use frame_support::storage::StoragePrefixedMap;
+use frame_support::traits::Currency;
+use sp_io::KillStorageResult;
+
+use crate::error::StoreError;
+use crate::graph_trie::{GraphTrie, TAG_OSP, TAG_POS, TAG_SPO};
+use crate::meter::{Meter, QUAD_WRITE_READS, QUAD_WRITE_WRITES};
 use crate::io::{DatasetFormat, DatasetParser, GraphFormat, GraphParser};
 use crate::model::*;
+use crate::model::geo::Envelope;
+use crate::model::vector::Vector;
+use crate::model::vocab::rdf;
+use crate::reasoner::BaseChanges;
+use crate::rules::QuadChanges;
+use crate::shacl::ShapeChanges;
+use crate::text::TextQuery;
 use crate::sparql::{
     EvaluationError,
     Query,
-    QueryOptions,
     QueryResults,
     QueryResultsFormat,
     Update,
-    UpdateOptions,
 };
 use crate::store::{
+    GraphNameShape,
+    QuadPatternShape,
     ReadableEncodedStore,
     WritableEncodedStore,
+    EncodedObjectRange,
     model::StrHash,
-    codec::{EncodedQuad, EncodedTerm},
+    namespace::Namespaces,
+    small_string::SmallString,
+    codec::{EncodedQuad, EncodedTerm, OrderedKey, TermId, CODEC_VERSION, ordered_key_type_range},
     numeric_encoder::{
         Decoder,
         ReadEncoder,
@@ -37,31 +58,76 @@ use crate::store::{
 };
 
 pub use pallet::*;
+pub use crate::model::{Literal, NamedNode, Term};
+pub use crate::sparql::{EntailmentRegime, EqualityMode, QueryOptions, UpdateOptions};
+
+pub mod consistency;
+pub mod migrations;
+
+#[cfg(test)]
+mod mock;
 
 mod error;
+mod functions;
+mod graph_trie;
+mod hnsw;
 mod io;
+mod meter;
 mod model;
 mod sparql;
 mod store;
 mod ogm;
+mod purge;
+mod reasoner;
+mod rules;
+mod same_as;
+mod shacl;
+mod spatial;
+mod text;
 
 type IoError = std::io::Error;
 
+/// The balance of the deposits of the user-defined functions
+pub type BalanceOf<T> =
+    <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+
 const PREFIX: &str = "
       prefix : <http://relationlabs.ai/entity/>
       prefix rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#>
       prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#>
       prefix xsd: <http://www.w3.org/2001/XMLSchema#>
+      prefix owl: <http://www.w3.org/2002/07/owl#>
+      prefix rule: <http://relationlabs.ai/rule/>
+      prefix sh: <http://www.w3.org/ns/shacl#>
+      prefix text: <http://relationlabs.ai/text/>
+      prefix geo: <http://www.opengis.net/ont/geosparql#>
+      prefix geof: <http://www.opengis.net/def/function/geosparql/>
+      prefix uom: <http://www.opengis.net/def/uom/OGC/1.0/>
+      prefix vec: <http://relationlabs.ai/vector/>
 ";
 const GRAPH_NAME_ACL: &str = "http://relationlabs.ai/acl/";
 
 #[frame_support::pallet]
 pub mod pallet {
     use frame_support::pallet_prelude::*;
+    use frame_support::sp_runtime::traits::Zero;
     use frame_support::storage::Key;
+    use frame_support::traits::ReservableCurrency;
+    use frame_support::transactional;
     use frame_system::pallet_prelude::*;
+    use std::convert::TryFrom;
 
     use super::timestamp;
+    use crate::{Access, BalanceOf, GraphStore, QuadIndexes};
+    use crate::consistency::{IndexRepairCursor, RepairIndexes};
+    use crate::functions;
+    use crate::purge;
+    use crate::model::NamedNode;
+    use crate::migrations::{self, ReEncodingCursor};
+    use crate::store::codec::TermId;
+    use crate::hnsw::VectorNode;
+    use crate::spatial::CellKey;
+    use crate::text::TokenKey;
 
     /// Configure the pallet by specifying the parameters and types on which it depends.
     #[pallet::config]
@@ -72,10 +138,89 @@ pub mod pallet {
         /// The maximum length a name may be.
         #[pallet::constant]
         type MaxValueLength: Get<u32>;
+
+        /// The quad indexes maintained on writes, `DefaultSpo` and `Gspo` are always maintained.
+        ///
+        /// The lookups without a matching index scan the mandatory ones. Enabling an index on a chain
+        /// with data requires the index to be rebuilt.
+        #[pallet::constant]
+        type QuadIndexes: Get<QuadIndexes>;
+
+        /// The namespaces of the runtime: the named nodes they start with are stored as a prefix id
+        /// and a local name, after the built-in rdf, rdfs, xsd and owl ones (see `Namespaces`).
+        ///
+        /// Up to 128 namespaces are used. Changing them on a chain with data requires the store to be rebuilt.
+        type Namespaces: Get<&'static [&'static str]>;
+
+        /// Number of index keys re-encoded at the start of each block while the keys of an older
+        /// term encoding are upgraded (see `migrations::ReEncodeTerms`).
+        type ReEncodingBatchSize: Get<u32>;
+
+        /// Number of index entries removed or written at the start of each block while the secondary
+        /// indexes are rebuilt (see `repair_indexes`).
+        type IndexRepairBatchSize: Get<u32>;
+
+        /// Number of keys of the dropped named graphs removed at the start of each block (see `crate::purge`).
+        type GraphPurgeBatchSize: Get<u32>;
+
+        /// The named graph the OWL 2 RL consequences of the default graph are materialized into after each
+        /// update (see `reasoner`), the queries read it with the default graph. `None` disables the reasoner.
+        ///
+        /// Enabling it on a chain with data only infers the consequences of the quads written afterwards.
+        type InferredGraph: Get<Option<&'static str>>;
+
+        /// The named graph of the user-defined inference rules, evaluated after each update (see `rules`).
+        /// `None` disables the rules.
+        ///
+        /// Enabling it on a chain with data only evaluates the rules once they or the quads change.
+        type RulesGraph: Get<Option<&'static str>>;
+
+        /// The named graph the triples constructed by the rules are written to, the queries read it with
+        /// the default graph.
+        type DerivedGraph: Get<&'static str>;
+
+        /// Maximum number of rounds of rule evaluation after an update, an update whose rules construct
+        /// new triples after the last round fails.
+        type MaxRuleRounds: Get<u32>;
+
+        /// The named graph of the SHACL shapes the data is validated against after each `sparql_update`
+        /// (see `shacl`). `None` disables the validation. Only root writes it (see `root_sparql_update`).
+        ///
+        /// Enabling it on a chain with data only validates the focus nodes written afterwards.
+        type ShapesGraph: Get<Option<&'static str>>;
+
+        /// The largest WebAssembly module of a user-defined function, in bytes (see `register_function`).
+        #[pallet::constant]
+        type MaxFunctionSize: Get<u32>;
+
+        /// The fuel of the calls to the user-defined functions of a query or an update, about one unit per
+        /// executed instruction. The calls share it, a call running out of fuel is an error.
+        #[pallet::constant]
+        type MaxFunctionFuel: Get<u64>;
+
+        /// The currency the deposits of the user-defined functions are reserved in.
+        type Currency: ReservableCurrency<Self::AccountId>;
+
+        /// The deposit reserved for a user-defined function, returned when it is removed.
+        #[pallet::constant]
+        type FunctionDeposit: Get<BalanceOf<Self>>;
+
+        /// The deposit reserved for each byte of the module of a user-defined function.
+        #[pallet::constant]
+        type FunctionByteDeposit: Get<BalanceOf<Self>>;
+
+        /// The weight declared by `sparql_update`: an update doing more work fails, the weight it did not
+        /// use is refunded (see `crate::meter`).
+        #[pallet::constant]
+        type MaxUpdateWeight: Get<Weight>;
     }
 
+    /// The current storage version, older layouts are upgraded by `crate::migrations`.
+    const STORAGE_VERSION: StorageVersion = StorageVersion::new(9);
+
     #[pallet::pallet]
     #[pallet::generate_store(pub (super) trait Store)]
+    #[pallet::storage_version(STORAGE_VERSION)]
     pub struct Pallet<T>(PhantomData<T>);
 
     // ID to Str
@@ -83,10 +228,79 @@ pub mod pallet {
     #[pallet::getter(fn id2str)]
     pub type Id2StrStore<T: Config> = StorageMap<_, Blake2_128Concat, u128, BoundedVec<u8, T::MaxValueLength>>;
 
-    // Graph names (only store name as key, value is useless)
+    // Number of references to each string of Id2StrStore by the quads and the graph names,
+    // a string is removed with its last reference
+    #[pallet::storage]
+    #[pallet::getter(fn str_ref_count)]
+    pub type StrRefCountStore<T: Config> = StorageMap<_, Blake2_128Concat, u128, u64>;
+
+    // Terms whose encoding does not fit in a term id, by hash of the encoding (see `TermId`)
+    #[pallet::storage]
+    #[pallet::getter(fn id2term)]
+    pub type Id2TermStore<T: Config> = StorageMap<_, Blake2_128Concat, u128, BoundedVec<u8, T::MaxValueLength>>;
+
+    // Number of references to each term of Id2TermStore by the quads and the graph names,
+    // a term is removed with its last reference
+    #[pallet::storage]
+    #[pallet::getter(fn term_ref_count)]
+    pub type TermRefCountStore<T: Config> = StorageMap<_, Blake2_128Concat, u128, u64>;
+
+    // Graph names with the number of quads of the graph (a named graph may be empty)
     #[pallet::storage]
     #[pallet::getter(fn graphs)]
-    pub type GraphNameStore<T: Config> = StorageMap<_, Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>, bool>;
+    pub type GraphNameStore<T: Config> = StorageMap<_, Blake2_128Concat, TermId, u64, ValueQuery>;
+
+    // Generation of the child trie of each named graph (see `GraphTrie::of`), incremented when the graph is
+    // dropped or cleared: the tries of the older generations are removed by `crate::purge`
+    #[pallet::storage]
+    pub type GraphGenerationStore<T: Config> = StorageMap<_, Blake2_128Concat, TermId, u32, ValueQuery>;
+
+    // Tries of the older generations of the named graphs still to be removed, and whether their quads still
+    // reference their strings (not once the store is cleared)
+    #[pallet::storage]
+    pub type GraphPurgeStore<T: Config> = StorageMap<_, Blake2_128Concat, (TermId, u32), bool, OptionQuery>;
+
+    // Named graphs using each term as a subject, a predicate or an object, with the generation of their trie and
+    // the number of their quads using it, so that a lookup with a bound term and without a graph only reads
+    // the graphs of the term
+    #[pallet::storage]
+    pub type TermGraphStore<T: Config> = StorageNMap<
+        _,
+        (
+            Key<Blake2_128Concat, TermId>, // term
+            Key<Identity, TermId>, // g (graph name)
+            Key<Identity, u32>, // generation of the trie of g
+        ),
+        u64,
+        OptionQuery,
+    >;
+
+    // Number of quads of the store, all graphs included
+    #[pallet::storage]
+    #[pallet::getter(fn quad_count)]
+    pub type QuadCountStore<T: Config> = StorageValue<_, u64, ValueQuery>;
+
+    // Number of quads of the default graph, the named graphs are counted by GraphNameStore
+    #[pallet::storage]
+    #[pallet::getter(fn default_graph_quad_count)]
+    pub type DefaultGraphQuadCountStore<T: Config> = StorageValue<_, u64, ValueQuery>;
+
+    // Version of the term encoding of the keys (see `CODEC_VERSION`), older while they are re-encoded.
+    // Zero for an empty store, the first update sets it.
+    #[pallet::storage]
+    #[pallet::getter(fn codec_version)]
+    pub type CodecVersionStore<T: Config> = StorageValue<_, u8, ValueQuery>;
+
+    // Progress of the re-encoding of the keys written with an older term encoding (see `migrations::ReEncodeTerms`)
+    #[pallet::storage]
+    pub type ReEncodingCursorStore<T: Config> = StorageValue<_, ReEncodingCursor, OptionQuery>;
+
+    // Progress of the rebuild of the secondary indexes (see `consistency::RepairIndexes`)
+    #[pallet::storage]
+    pub type IndexRepairCursorStore<T: Config> = StorageValue<_, IndexRepairCursor, OptionQuery>;
+
+    // Quad indexes: keys are fixed-width term ids (see `TermId`), only the first one is hashed so that
+    // the entries sharing a first term are stored together and sorted by the remaining terms.
 
     // Default GraphStore
     #[pallet::storage]
@@ -94,13 +308,12 @@ pub mod pallet {
     pub type DefaultSpoStore<T: Config> = StorageNMap<
         _,
         (
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // s (subject)
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // p (predicate)
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // o (object)
+            Key<Blake2_128Concat, TermId>, // s (subject)
+            Key<Identity, TermId>, // p (predicate)
+            Key<Identity, TermId>, // o (object)
         ),
-        //BoundedVec<u8, T::MaxValueLength>, // value TODO: value is useless, just use a fixed bool or u8?
-        bool,
-        OptionQuery, // ValueQuery
+        (),
+        OptionQuery,
     >;
 
     #[pallet::storage]
@@ -108,11 +321,11 @@ pub mod pallet {
     pub type DefaultPosStore<T: Config> = StorageNMap<
         _,
         (
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // p
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // o
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // s
+            Key<Blake2_128Concat, TermId>, // p
+            Key<Identity, TermId>, // o
+            Key<Identity, TermId>, // s
         ),
-        bool,
+        (),
         OptionQuery,
     >;
 
@@ -121,100 +334,155 @@ pub mod pallet {
     pub type DefaultOspStore<T: Config> = StorageNMap<
         _,
         (
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // o
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // s
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // p
+            Key<Blake2_128Concat, TermId>, // o
+            Key<Identity, TermId>, // s
+            Key<Identity, TermId>, // p
         ),
-        bool,
+        (),
         OptionQuery,
     >;
 
-    // Named GraphStore
+    // Named GraphStore: the indexes of each named graph are in its own child trie (see `GraphTrie`)
+
+    // Cardinality statistics (used by the query planner to order joins)
+    // Number of quads per predicate, all graphs included
+    #[pallet::storage]
+    #[pallet::getter(fn predicate_count)]
+    pub type PredicateCountStore<T: Config> = StorageMap<_, Blake2_128Concat, TermId, u64, OptionQuery>;
+
+    // Number of quads per predicate in the default graph
+    #[pallet::storage]
+    #[pallet::getter(fn default_graph_predicate_count)]
+    pub type DefaultGraphPredicateCountStore<T: Config> = StorageMap<_, Blake2_128Concat, TermId, u64, OptionQuery>;
+
+    // Number of quads per (predicate, class): a quad is counted for each class its subject has in the same graph.
+    // A rdf:type quad is only counted for its object, so (rdf:type, class) is the number of instances of the class.
+    #[pallet::storage]
+    #[pallet::getter(fn predicate_class_count)]
+    pub type PredicateClassCountStore<T: Config> = StorageNMap<
+        _,
+        (
+            Key<Blake2_128Concat, TermId>, // p
+            Key<Identity, TermId>, // class
+        ),
+        u64,
+        OptionQuery,
+    >;
 
+    // Ordered literal index: numeric and date/time objects of the default graph sorted by value within a predicate,
+    // the order-preserving key (see `EncodedTerm::to_ordered_key`) is stored as is so that range filters become range scans.
+    // Each named graph has the same index in its child trie.
     #[pallet::storage]
-    #[pallet::getter(fn gspo)]
-    pub type GspoStore<T: Config> = StorageNMap<
+    #[pallet::getter(fn ordered_literal)]
+    pub type OrderedLiteralStore<T: Config> = StorageNMap<
         _,
         (
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // g (graph)
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // s (subject)
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // p (predicate)
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // o (object)
+            Key<Blake2_128Concat, TermId>, // p
+            Key<Identity, [u8; 9]>, // ordered key of o
+            Key<Identity, TermId>, // o
+            Key<Identity, TermId>, // s
         ),
-        bool,
+        (),
         OptionQuery,
     >;
 
+    // Equivalence classes of the owl:sameAs links of the default graph (see `crate::same_as`):
+    // the canonical representative of each member of a class with several members
+    #[pallet::storage]
+    #[pallet::getter(fn same_as)]
+    pub type SameAsStore<T: Config> = StorageMap<_, Blake2_128Concat, TermId, TermId, OptionQuery>;
+
+    // The members of each class, by canonical representative
     #[pallet::storage]
-    #[pallet::getter(fn gpos)]
-    pub type GposStore<T: Config> = StorageNMap<
+    pub type SameAsMemberStore<T: Config> = StorageNMap<
         _,
         (
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // g
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // p
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // o
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // s
+            Key<Blake2_128Concat, TermId>, // canonical representative
+            Key<Identity, TermId>, // member
         ),
-        bool,
+        (),
         OptionQuery,
     >;
 
+    // Full-text index: the quads of the default graph by token of their string literal object (see `crate::text`),
+    // maintained when `QuadIndexes::TEXT` is enabled
     #[pallet::storage]
-    #[pallet::getter(fn gosp)]
-    pub type GospStore<T: Config> = StorageNMap<
+    pub type TextIndexStore<T: Config> = StorageNMap<
         _,
         (
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // g
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // o
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // s
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // p
+            Key<Blake2_128Concat, TokenKey>, // token of o
+            Key<Identity, TermId>, // o
+            Key<Identity, TermId>, // s
+            Key<Identity, TermId>, // p
         ),
-        bool,
+        (),
         OptionQuery,
     >;
 
+    // Spatial index: the quads of the default graph by quadtree cell of their geometry object (see `crate::spatial`),
+    // maintained when `QuadIndexes::SPATIAL` is enabled. The cell key is stored as is so that the cells inside a
+    // cell are scanned together.
     #[pallet::storage]
-    #[pallet::getter(fn spog)]
-    pub type SpogStore<T: Config> = StorageNMap<
+    pub type SpatialIndexStore<T: Config> = StorageNMap<
         _,
         (
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // s
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // p
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // o
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // g
+            Key<Identity, CellKey>, // cell of o
+            Key<Identity, TermId>, // o
+            Key<Identity, TermId>, // s
+            Key<Identity, TermId>, // p
         ),
-        bool,
+        (),
         OptionQuery,
     >;
 
+    // Vector index: the nodes of the nearest neighbour graph of the vector objects of each predicate of the default
+    // graph (see `crate::hnsw`), maintained when `QuadIndexes::VECTOR` is enabled
     #[pallet::storage]
-    #[pallet::getter(fn posg)]
-    pub type PosgStore<T: Config> = StorageNMap<
+    pub type VectorNodeStore<T: Config> = StorageNMap<
         _,
         (
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // p
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // o
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // s
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // g
+            Key<Identity, TermId>, // p
+            Key<Identity, TermId>, // o
         ),
-        bool,
+        VectorNode,
         OptionQuery,
     >;
 
+    // The links of the nodes in each layer of their graph
+    #[pallet::storage]
+    pub type VectorEdgeStore<T: Config> = StorageNMap<
+        _,
+        (
+            Key<Identity, TermId>, // p
+            Key<Identity, TermId>, // o
+            Key<Identity, u8>, // layer
+        ),
+        Vec<TermId>,
+        ValueQuery,
+    >;
+
+    // The entry point of the graph of the vectors of each predicate and number of dimensions
     #[pallet::storage]
-    #[pallet::getter(fn ospg)]
-    pub type OspgStore<T: Config> = StorageNMap<
+    pub type VectorEntryStore<T: Config> = StorageNMap<
         _,
         (
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // o
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // s
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // p
-            Key<Blake2_128Concat, BoundedVec<u8, T::MaxValueLength>>, // g
+            Key<Identity, TermId>, // p
+            Key<Identity, u32>, // dimensions
         ),
-        bool,
+        TermId,
         OptionQuery,
     >;
 
+    // User-defined functions: the WebAssembly module of each function IRI (see `crate::functions`), with the
+    // account that registered it and the deposit it reserved
+    #[pallet::storage]
+    pub type FunctionStore<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        BoundedVec<u8, T::MaxValueLength>,
+        (T::AccountId, BoundedVec<u8, T::MaxFunctionSize>, BalanceOf<T>),
+    >;
+
     // Pallets use events to inform users when important changes are made.
     // https://docs.substrate.io/v3/runtime/events-and-errors
     #[pallet::event]
@@ -225,6 +493,18 @@ pub mod pallet {
 
         /// parameters. [who]
         DataUpdate(T::AccountId),
+
+        /// The secondary indexes are being rebuilt from the primary ones.
+        IndexRepairStarted,
+
+        /// Root executed a sparql update.
+        RootDataUpdate,
+
+        /// A user-defined function was registered. [who, iri]
+        FunctionRegistered(T::AccountId, Vec<u8>),
+
+        /// A user-defined function was removed and its deposit returned. [who, iri]
+        FunctionRemoved(T::AccountId, Vec<u8>),
     }
 
     // Errors inform users that something went wrong.
@@ -234,6 +514,34 @@ pub mod pallet {
         NoneValue,
         /// Errors should have helpful documentation associated with them.
         StorageOverflow,
+        /// The keys of the store are being re-encoded with the current term encoding, the store is
+        /// available again once the re-encoding is done.
+        ReEncodingOngoing,
+        /// The secondary indexes are being rebuilt, the store is available again once the repair is done.
+        IndexRepairOngoing,
+        /// The update does not conform to the shapes of `Config::ShapesGraph` and is reverted, the
+        /// validation report is logged.
+        ShapesViolated,
+        /// The update is not valid UTF-8 or does not parse.
+        InvalidUpdate,
+        /// The evaluation of the update failed (e.g. a value too long or a string hash collision) and is
+        /// reverted, the error is logged.
+        UpdateFailed,
+        /// The update does more work than `Config::MaxUpdateWeight` and is reverted.
+        UpdateTooHeavy,
+        /// The IRI of a user-defined function is not valid, or its module is too large or does not have the
+        /// exports of a function (see `crate::functions`).
+        InvalidFunction,
+        /// The user-defined function was registered by another account.
+        NotFunctionOwner,
+        /// No user-defined function is registered under the IRI.
+        UnknownFunction,
+        /// The update of a signed account writes a graph only written by root and by the inference
+        /// (see `root_sparql_update`), and is reverted.
+        ProtectedGraph,
+        /// The inference rules still construct new triples after `Config::MaxRuleRounds` rounds of
+        /// evaluation, the update is reverted.
+        RuleRoundsExceeded,
     }
 
     // You can implement the [`Hooks`] trait to define some logic
@@ -245,8 +553,22 @@ pub mod pallet {
         //
         // This function must return the weight consumed by `on_initialize` and `on_finalize`.
         fn on_initialize(_n: T::BlockNumber) -> Weight {
-            // Anything that needs to be done at the start of the block.
-            0
+            // the secondary indexes are rebuilt, then the keys written with an older layout or term encoding
+            // are migrated, in batches
+            if <IndexRepairCursorStore<T>>::exists() {
+                RepairIndexes::<T>::repair_batch()
+            } else if <ReEncodingCursorStore<T>>::exists() {
+                migrations::ReEncodeTerms::<T>::reencode_batch()
+            } else {
+                // then the tries of the dropped named graphs are removed
+                purge::purge_batch::<T>()
+            }
+        }
+
+        // `try_state` of the later FRAME versions: the indexes must agree once the runtime is upgraded
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            Self::do_try_state()
         }
     }
 
@@ -258,20 +580,93 @@ pub mod pallet {
         #[pallet::weight(10_000 + T::DbWeight::get().writes(1))]
         pub fn init_db(origin: OriginFor<T>) -> DispatchResult {
             let who = ensure_signed(origin)?;
+            ensure!(Self::is_encoding_current(), Error::<T>::ReEncodingOngoing);
+            ensure!(!<IndexRepairCursorStore<T>>::exists(), Error::<T>::IndexRepairOngoing);
             Self::init_data();
             Self::deposit_event(Event::DataInitialized(who));
             Ok(())
         }
 
-        /// Execute sparql update
-        #[pallet::weight(10_000 + T::DbWeight::get().writes(1))]
-        pub fn sparql_update(origin: OriginFor<T>, update: Vec<u8>) -> DispatchResult {
+        /// Execute sparql update, reverted if it fails or if the focus nodes it touched do not conform to the shapes.
+        ///
+        /// The update is charged for the storage accesses it does, up to `Config::MaxUpdateWeight`, even if it fails.
+        /// It cannot write the graphs written by the inference (see `Access`).
+        #[pallet::weight(T::MaxUpdateWeight::get())]
+        #[transactional]
+        pub fn sparql_update(origin: OriginFor<T>, update: Vec<u8>) -> DispatchResultWithPostInfo {
             let who = ensure_signed(origin)?;
-            let update = std::str::from_utf8(&update).unwrap();
-            Self::execute_update(update);
+            let actual_weight = Self::dispatch_update(update, Access::Signed)?;
             Self::deposit_event(Event::DataUpdate(who));
+            Ok(Some(actual_weight).into())
+        }
+
+        /// Rebuilds the secondary indexes from the primary ones, in batches at the start of the next blocks
+        /// (see `consistency::RepairIndexes`). The store can neither be queried nor updated until it is done.
+        #[pallet::weight(10_000 + T::DbWeight::get().writes(1))]
+        pub fn repair_indexes(origin: OriginFor<T>) -> DispatchResult {
+            ensure_root(origin)?;
+            ensure!(Self::is_encoding_current(), Error::<T>::ReEncodingOngoing);
+            RepairIndexes::<T>::start();
+            Self::deposit_event(Event::IndexRepairStarted);
             Ok(())
         }
+
+        /// Registers the WebAssembly module `code` as the user-defined function `iri`, which the queries and the
+        /// updates then call like a built-in function (see `crate::functions`). A function is only replaced by
+        /// the account that registered it.
+        ///
+        /// `Config::FunctionDeposit` and `Config::FunctionByteDeposit` for each byte of `code` are reserved
+        /// until the function is removed, the deposit of a replaced function is adjusted to the new module.
+        #[pallet::weight(functions::register_weight::<T>(code.len()))]
+        pub fn register_function(origin: OriginFor<T>, iri: Vec<u8>, code: Vec<u8>) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            let valid_iri = std::str::from_utf8(&iri).ok().and_then(|iri| NamedNode::new(iri).ok()).is_some();
+            ensure!(valid_iri, Error::<T>::InvalidFunction);
+            let key = BoundedVec::try_from(iri.clone()).map_err(|_| Error::<T>::InvalidFunction)?;
+            let code = BoundedVec::try_from(code).map_err(|_| Error::<T>::InvalidFunction)?;
+            let reserved = match <FunctionStore<T>>::get(&key) {
+                Some((owner, _, reserved)) => {
+                    ensure!(owner == who, Error::<T>::NotFunctionOwner);
+                    reserved
+                }
+                None => Zero::zero(),
+            };
+            ensure!(functions::is_valid(&code), Error::<T>::InvalidFunction);
+            let deposit = functions::deposit::<T>(code.len());
+            if deposit > reserved {
+                T::Currency::reserve(&who, deposit - reserved)?;
+            } else {
+                T::Currency::unreserve(&who, reserved - deposit);
+            }
+            <FunctionStore<T>>::insert(key, (who.clone(), code, deposit));
+            Self::deposit_event(Event::FunctionRegistered(who, iri));
+            Ok(())
+        }
+
+        /// Removes the user-defined function `iri` registered by the caller and returns its deposit. The queries
+        /// and the updates calling it then fail.
+        #[pallet::weight(10_000 + T::DbWeight::get().reads_writes(2, 2))]
+        pub fn remove_function(origin: OriginFor<T>, iri: Vec<u8>) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            let key = BoundedVec::try_from(iri.clone()).map_err(|_| Error::<T>::UnknownFunction)?;
+            let (owner, _, reserved) = <FunctionStore<T>>::get(&key).ok_or(Error::<T>::UnknownFunction)?;
+            ensure!(owner == who, Error::<T>::NotFunctionOwner);
+            T::Currency::unreserve(&who, reserved);
+            <FunctionStore<T>>::remove(key);
+            Self::deposit_event(Event::FunctionRemoved(who, iri));
+            Ok(())
+        }
+
+        /// Execute sparql update as root, like `sparql_update` but also writing the graphs written by the
+        /// inference (see `Access`).
+        #[pallet::weight(T::MaxUpdateWeight::get())]
+        #[transactional]
+        pub fn root_sparql_update(origin: OriginFor<T>, update: Vec<u8>) -> DispatchResultWithPostInfo {
+            ensure_root(origin)?;
+            let actual_weight = Self::dispatch_update(update, Access::Root)?;
+            Self::deposit_event(Event::RootDataUpdate);
+            Ok(Some(actual_weight).into())
+        }
     }
 }
 
@@ -299,7 +694,49 @@ impl<T: Config> Pallet<T> {
 
     }
 
+    /// Whether the keys of the store use the current term encoding and no migration is ongoing, they cannot
+    /// be queried nor updated while they are re-encoded or migrated
+    pub fn is_encoding_current() -> bool {
+        matches!(<CodecVersionStore<T>>::get(), 0 | CODEC_VERSION) && !<ReEncodingCursorStore<T>>::exists()
+    }
+
+    /// Checks that the indexes of the store agree with each other and with the strings, returns the
+    /// inconsistencies found (see `consistency::check_indexes`)
+    pub fn check_indexes() -> Vec<String> {
+        consistency::check_indexes::<T>().iter().map(ToString::to_string).collect()
+    }
+
+    /// Fails if the indexes of the store are inconsistent, unless they are being re-encoded or repaired
+    #[cfg(feature = "try-runtime")]
+    pub fn do_try_state() -> Result<(), &'static str> {
+        if <ReEncodingCursorStore<T>>::exists() || <IndexRepairCursorStore<T>>::exists() {
+            return Ok(());
+        }
+        let found = consistency::check_indexes::<T>();
+        for inconsistency in found.iter() {
+            frame_support::log::error!(target: "runtime::graphdb", "{}", inconsistency);
+        }
+        ensure!(found.is_empty(), "the indexes of the store are inconsistent");
+        Ok(())
+    }
+
     pub fn execute_query<S: AsRef<str>>(query: S) -> String {
+        Self::execute_query_with_entailment(query, EntailmentRegime::Simple)
+    }
+
+    /// Executes a query whose basic graph patterns also match the quads entailed with `entailment`
+    pub fn execute_query_with_entailment<S: AsRef<str>>(query: S, entailment: EntailmentRegime) -> String {
+        Self::execute_query_with_options(query, QueryOptions::default().with_entailment(entailment))
+    }
+
+    /// Executes a query whose basic graph patterns compare the terms with `equality`
+    pub fn execute_query_with_equality<S: AsRef<str>>(query: S, equality: EqualityMode) -> String {
+        Self::execute_query_with_options(query, QueryOptions::default().with_equality(equality))
+    }
+
+    /// Executes a query with some options, e.g. the custom functions registered with
+    /// [`QueryOptions::with_custom_function`]
+    pub fn execute_query_with_options<S: AsRef<str>>(query: S, options: QueryOptions) -> String {
         let now = <timestamp::Pallet<T>>::get();
         println!("now: {:?}", now);
         let graph_store = GraphStore::<T>::new();
@@ -308,37 +745,196 @@ impl<T: Config> Pallet<T> {
               {}
             ", PREFIX, query.as_ref());
         println!("sparql_query: {:?}", sparql);
-        let query = Query::parse(&sparql, None).unwrap();
-        let query_result = graph_store.query(query).unwrap();
+        let query = Query::parse_with_options(&sparql, None, &options).unwrap();
+        let query_result = graph_store.query_with_options(query, options).unwrap();
         let mut buffer = Vec::default();
         query_result.write(&mut buffer, QueryResultsFormat::Json).unwrap();
         String::from_utf8_lossy(&buffer[..]).to_string()
     }
 
-    pub fn execute_update<S: AsRef<str>>(update: S) {
+    /// Returns the evaluation plan of a query, or the error of a query that does not parse or fails
+    pub fn explain_query<S: AsRef<str>>(query: S) -> Result<String, String> {
+        Self::explain(query, false)
+    }
+
+    /// Evaluates a query and returns its plan annotated with rows and storage reads per node
+    pub fn profile_query<S: AsRef<str>>(query: S) -> Result<String, String> {
+        Self::explain(query, true)
+    }
+
+    fn explain<S: AsRef<str>>(query: S, profile: bool) -> Result<String, String> {
+        let graph_store = GraphStore::<T>::new();
+        let sparql = format!("
+              {}
+              {}
+            ", PREFIX, query.as_ref());
+        let query = Query::parse(&sparql, None).map_err(|error| error.to_string())?;
+        graph_store.explain(query, profile).map_err(|error| error.to_string())
+    }
+
+    /// Returns the root hash of the child trie of a named graph, `None` if the graph does not exist
+    pub fn graph_root<S: AsRef<str>>(graph_name: S) -> Option<Vec<u8>> {
+        let graph_store = GraphStore::<T>::new();
+        let graph_name = NamedNode::new(graph_name.as_ref()).ok()?;
+        let graph_name = graph_store
+            .get_encoded_named_or_blank_node(graph_name.as_ref().into())
+            .ok()??;
+        let g = graph_name.term_id();
+        if <GraphNameStore<T>>::contains_key(g) {
+            Some(GraphTrie::of::<T>(&g).root(T::Version::get().state_version()))
+        } else {
+            None
+        }
+    }
+
+    pub fn execute_update<S: AsRef<str>>(update: S) -> Result<(), EvaluationError> {
+        Self::update_store(&GraphStore::<T>::new(), update)
+    }
+
+    /// Executes an update with some options, e.g. the custom functions of its query part
+    pub fn execute_update_with_options<S: AsRef<str>>(update: S, options: UpdateOptions) -> Result<(), EvaluationError> {
+        Self::update_store_with_options(&GraphStore::<T>::new(), update, options)
+    }
+
+    /// Executes an update, then validates the focus nodes it touched against the shapes of
+    /// `Config::ShapesGraph`. Returns the validation report of the violations as N-Triples, `None` if
+    /// the data conforms.
+    pub fn execute_validated_update<S: AsRef<str>>(update: S) -> Result<Option<String>, EvaluationError> {
+        Self::validated_update(&GraphStore::<T>::new(), update)
+    }
+
+    /// Executes the update of an extrinsic with the weight limit `Config::MaxUpdateWeight`, returns the weight
+    /// it used
+    fn dispatch_update(update: Vec<u8>, access: Access) -> Result<Weight, DispatchErrorWithPostInfo> {
+        ensure!(Self::is_encoding_current(), Error::<T>::ReEncodingOngoing);
+        ensure!(!<IndexRepairCursorStore<T>>::exists(), Error::<T>::IndexRepairOngoing);
+        let update = std::str::from_utf8(&update).map_err(|_| Error::<T>::InvalidUpdate)?;
+        let graph_store = GraphStore::<T>::with_weight_limit(T::MaxUpdateWeight::get(), access);
+        let result = Self::validated_update(&graph_store, update);
+        let actual_weight = graph_store.meter.weight::<T>().min(T::MaxUpdateWeight::get());
+        match result {
+            Ok(None) => Ok(actual_weight),
+            Ok(Some(report)) => {
+                frame_support::log::warn!(target: "runtime::graphdb", "the update violates the shapes:\n{}", report);
+                Err(Error::<T>::ShapesViolated.with_weight(actual_weight))
+            }
+            Err(error) => {
+                frame_support::log::warn!(target: "runtime::graphdb", "the update failed: {}", error);
+                let error = match error {
+                    EvaluationError::Parsing(_) => Error::<T>::InvalidUpdate,
+                    _ if matches!(error.store_error(), Some(StoreError::ProtectedGraph { .. })) => {
+                        Error::<T>::ProtectedGraph
+                    }
+                    _ if matches!(error.store_error(), Some(StoreError::RuleRoundsExceeded { .. })) => {
+                        Error::<T>::RuleRoundsExceeded
+                    }
+                    _ if graph_store.meter.is_exhausted() => Error::<T>::UpdateTooHeavy,
+                    _ => Error::<T>::UpdateFailed,
+                };
+                Err(error.with_weight(actual_weight))
+            }
+        }
+    }
+
+    fn validated_update<S: AsRef<str>>(graph_store: &GraphStore<T>, update: S) -> Result<Option<String>, EvaluationError> {
+        Self::update_store(graph_store, update)?;
+        let report = shacl::validate_changes(graph_store)?;
+        Ok(if report.conforms() { None } else { Some(report.to_string()) })
+    }
+
+    /// Returns the SHACL validation report of all the focus nodes of the shapes, as N-Triples
+    pub fn validate_shapes() -> String {
         let graph_store = GraphStore::<T>::new();
+        match shacl::validate_all(&graph_store) {
+            Ok(report) => report.to_string(),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    fn update_store<S: AsRef<str>>(graph_store: &GraphStore<T>, update: S) -> Result<(), EvaluationError> {
+        Self::update_store_with_options(graph_store, update, UpdateOptions::default())
+    }
+
+    fn update_store_with_options<S: AsRef<str>>(
+        graph_store: &GraphStore<T>,
+        update: S,
+        options: UpdateOptions,
+    ) -> Result<(), EvaluationError> {
         let sparql = format!("
               {}
               {}
             ", PREFIX, update.as_ref());
-        println!("sparql_update: {}", sparql);
-        let update = Update::parse(&sparql, None).unwrap();
-        let result = graph_store.update(update);
-        println!("sparql_update result: {:?}", result);
+        let update = Update::parse_with_options(&sparql, None, &options)?;
+        graph_store.update_with_options(update, options)
     }
 }
 
 //****************************
 /// Impl graph storage
 //****************************
+/// Who writes through a `GraphStore`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// The updates of `sparql_update`, which cannot write the graphs of `protected_graphs`
+    Signed,
+    /// Root, the inference and the runtime API, which write any graph
+    Root,
+}
+
+/// The named graphs only written by root and by the inference: the graph the OWL consequences are
+/// materialized into, the graph of the rules, the graph of the triples they construct and the graph of
+/// the shapes
+fn protected_graphs<T: Config>() -> Vec<&'static str> {
+    let mut graphs: Vec<_> = T::InferredGraph::get().into_iter().collect();
+    if let Some(rules_graph) = T::RulesGraph::get() {
+        graphs.push(rules_graph);
+        graphs.push(T::DerivedGraph::get());
+    }
+    graphs.extend(T::ShapesGraph::get());
+    graphs
+}
+
 #[derive(Debug, Clone)]
 struct GraphStore<T> {
+    /// Strings whose last reference was removed, deleted by `collect_strs` once the writes are done
+    released_strs: Rc<RefCell<BTreeSet<u128>>>,
+    /// Terms of `Id2TermStore` whose last reference was removed, deleted with the strings
+    released_terms: Rc<RefCell<BTreeSet<u128>>>,
+    /// Quads of the default graph written since the last update, see `reasoner::materialize`
+    base_changes: Rc<RefCell<BaseChanges>>,
+    /// Quads written since the last update, see `rules::derive`
+    rule_changes: Rc<RefCell<QuadChanges>>,
+    /// Quads written since the last validation, see `shacl::validate_changes`
+    shape_changes: Rc<RefCell<ShapeChanges>>,
+    /// The storage accesses done through the store, see `crate::meter`
+    meter: Rc<Meter>,
+    /// Who writes through the store, the inference writes as `Access::Root`
+    access: Cell<Access>,
+    /// The fuel left to the calls of the user-defined functions, see `functions::resolver`
+    fuel: Rc<Cell<u64>>,
     _p: PhantomData<T>,
 }
 
 impl<T: Config> GraphStore<T> {
     pub fn new() -> Self {
+        Self::with_meter(Meter::default(), Access::Root)
+    }
+
+    /// A store whose lookups and writes fail once their weight exceeds `limit`, written by `access`
+    pub fn with_weight_limit(limit: Weight, access: Access) -> Self {
+        Self::with_meter(Meter::with_limit(limit), access)
+    }
+
+    fn with_meter(meter: Meter, access: Access) -> Self {
         Self {
+            released_strs: Rc::default(),
+            released_terms: Rc::default(),
+            base_changes: Rc::default(),
+            rule_changes: Rc::default(),
+            shape_changes: Rc::default(),
+            meter: Rc::new(meter),
+            access: Cell::new(access),
+            fuel: Rc::new(Cell::new(T::MaxFunctionFuel::get())),
             _p: PhantomData,
         }
     }
@@ -357,9 +953,28 @@ impl<T: Config> GraphStore<T> {
         query: impl TryInto<Query, Error=impl Into<EvaluationError>>,
         options: QueryOptions,
     ) -> Result<QueryResults, EvaluationError> {
+        self.ensure_available()?;
+        let query = self.with_materialized_graphs(query.try_into().map_err(|e| e.into())?);
+        let resolver = functions::resolver::<T>(self.meter.clone(), self.fuel.clone());
+        let options = options.with_function_resolver(resolver);
         sparql::evaluate_query(self.clone(), query, options)
     }
 
+    /// Renders the evaluation plan of a [SPARQL 1.1 query](https://www.w3.org/TR/sparql11-query/).
+    ///
+    /// With `profile` the query is evaluated and the plan annotated with rows and storage reads.
+    pub fn explain(
+        &self,
+        query: impl TryInto<Query, Error=impl Into<EvaluationError>>,
+        profile: bool,
+    ) -> Result<String, EvaluationError> {
+        self.ensure_available()?;
+        let query = self.with_materialized_graphs(query.try_into().map_err(|e| e.into())?);
+        let resolver = functions::resolver::<T>(self.meter.clone(), self.fuel.clone());
+        let options = QueryOptions::default().with_function_resolver(resolver);
+        sparql::explain_query(self.clone(), query, options, profile)
+    }
+
     /// Executes a [SPARQL 1.1 update](https://www.w3.org/TR/sparql11-update/).
     pub fn update(
         &self,
@@ -374,28 +989,106 @@ impl<T: Config> GraphStore<T> {
         update: impl TryInto<Update, Error = impl Into<EvaluationError>>,
         options: UpdateOptions,
     ) -> Result<(), EvaluationError> {
-        sparql::evaluate_update(
+        self.ensure_available()?;
+        <CodecVersionStore<T>>::put(CODEC_VERSION);
+        let resolver = functions::resolver::<T>(self.meter.clone(), self.fuel.clone());
+        let options = UpdateOptions::from(options.query_options().clone().with_function_resolver(resolver));
+        let result = sparql::evaluate_update(
             self.clone(),
             &mut &*self,
             update.try_into().map_err(|e| e.into())?,
             options,
         )
+        .and_then(|()| self.infer());
+        // an update may remove the last reference to a string and then insert it again
+        self.collect_strs();
+        result
+    }
+
+    /// Materializes the OWL consequences of the quads written, then evaluates the rules on them. The inference
+    /// writes the protected graphs whoever wrote the quads.
+    fn infer(&self) -> Result<(), EvaluationError> {
+        let access = self.access.replace(Access::Root);
+        let result = reasoner::materialize(self).map_err(EvaluationError::from).and_then(|()| rules::derive(self));
+        self.access.set(access);
+        result
+    }
+
+    /// Adds the inferred graph and the derived graph to the default graph of a query without a dataset
+    fn with_materialized_graphs(&self, mut query: Query) -> Query {
+        let default_graph = rules::query_default_graph::<T>();
+        if default_graph.len() > 1 && query.dataset().is_default_dataset() {
+            query.dataset_mut().set_default_graph(default_graph);
+        }
+        query
+    }
+
+    /// Fails while the keys are written with an older term encoding (see `CODEC_VERSION`): the terms
+    /// of a query or an update would not match them, while the store is migrated and while the secondary
+    /// indexes are rebuilt
+    fn ensure_available(&self) -> Result<(), StoreError> {
+        match <CodecVersionStore<T>>::get() {
+            0 | CODEC_VERSION => (),
+            stored => return Err(StoreError::OutdatedEncoding { stored, current: CODEC_VERSION }),
+        }
+        if <ReEncodingCursorStore<T>>::exists() {
+            return Err(StoreError::MigrationOngoing);
+        }
+        if <IndexRepairCursorStore<T>>::exists() {
+            return Err(StoreError::IndexRepairOngoing);
+        }
+        Ok(())
+    }
+
+    /// Fails if the store is written by a signed update and `graph_name` is one of the `protected_graphs`
+    fn ensure_writable(&self, graph_name: EncodedTerm) -> Result<(), StoreError> {
+        if self.access.get() == Access::Root || graph_name.is_default_graph() {
+            return Ok(());
+        }
+        for graph in protected_graphs::<T>() {
+            if self.get_encoded_named_node(NamedNodeRef::new_unchecked(graph))? == Some(graph_name) {
+                return Err(StoreError::ProtectedGraph { graph: graph.to_owned() });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the store contains a quad, the read is charged to the meter
+    fn contains_encoded_quad_metered(&self, quad: &EncodedQuad) -> Result<bool, StoreError> {
+        self.meter.charge::<T>(1, 0)?;
+        Ok(self.contains_encoded_quad(quad))
+    }
+
+    /// Deletes the released strings and terms that are still not referenced
+    fn collect_strs(&self) {
+        for id in std::mem::take(&mut *self.released_strs.borrow_mut()) {
+            if !<StrRefCountStore<T>>::contains_key(id) {
+                <Id2StrStore<T>>::remove(id);
+            }
+        }
+        for hash in std::mem::take(&mut *self.released_terms.borrow_mut()) {
+            if !<TermRefCountStore<T>>::contains_key(hash) {
+                <Id2TermStore<T>>::remove(hash);
+            }
+        }
     }
 
     /// Adds a quad to this store.
     #[allow(clippy::needless_pass_by_value)]
-    pub fn insert_quad(&self, quad: impl Into<Quad>) {
+    pub fn insert_quad(&self, quad: impl Into<Quad>) -> Result<(), StoreError> {
         let mut this = self;
-        let quad = this.encode_quad(quad.into().as_ref()).unwrap_infallible();
-        this.insert_encoded(&quad).unwrap_infallible();
+        let quad = this.encode_quad(quad.into().as_ref())?;
+        this.insert_encoded(&quad)
     }
 
     /// Removes a quad from this store.
-    pub fn remove_quad<'a>(&self, quad: impl Into<QuadRef<'a>>){
-        if let Ok(Some(quad))= self.get_encoded_quad(quad.into()){
+    pub fn remove_quad<'a>(&self, quad: impl Into<QuadRef<'a>>) -> Result<(), StoreError> {
+        if let Some(quad) = self.get_encoded_quad(quad.into())? {
             let mut this = self;
-            this.remove_encoded(&quad).unwrap_infallible();
+            this.remove_encoded(&quad)?;
+            self.collect_strs();
         }
+        Ok(())
     }
 
     /// Loads a graph (e.g. triples)from reader into the store.
@@ -406,9 +1099,14 @@ impl<T: Config> GraphStore<T> {
         to_graph_name: impl Into<GraphNameRef<'a>>,
         base_iri: Option<&str>,
     ) -> Result<(), IoError> {
+        self.ensure_available()?;
+        <CodecVersionStore<T>>::put(CODEC_VERSION);
         let mut store = self;
         store::load_graph(&mut store, reader, format, to_graph_name.into(), base_iri)?;
-        Ok(())
+        self.infer().map_err(|error| match error {
+            EvaluationError::Io(error) => error,
+            error => IoError::new(std::io::ErrorKind::InvalidData, error),
+        })
     }
 
     /// Dumps a store graph into a writer.
@@ -418,8 +1116,8 @@ impl<T: Config> GraphStore<T> {
         format: GraphFormat,
         from_graph_name: impl Into<GraphNameRef<'a>>,
     ) -> Result<(), IoError> {
-        let iter=self.quads_for_pattern(None, None, None, Some(from_graph_name.into()))
-            .map(|q| Ok(q.into()));
+        let iter = self.quads_for_pattern(None, None, None, Some(from_graph_name.into()))?
+            .map(|q| Ok(q?.into()));
         store::dump_graph(iter, writer, format)
     }
 
@@ -435,53 +1133,45 @@ impl<T: Config> GraphStore<T> {
     pub fn contains_named_graph<'a>(
         &self,
         graph_name: impl Into<NamedOrBlankNodeRef<'a>>,
-    ) -> bool {
-        if let Some(graph_name) = self
-            .get_encoded_named_or_blank_node(graph_name.into()).unwrap_infallible()
-        {
-            self.contains_encoded_named_graph(graph_name).unwrap_infallible()
+    ) -> Result<bool, StoreError> {
+        if let Some(graph_name) = self.get_encoded_named_or_blank_node(graph_name.into())? {
+            self.contains_encoded_named_graph(graph_name)
         } else {
-            false
+            Ok(false)
         }
     }
 
     /// Inserts a graph into this store
-    pub fn insert_named_graph(&self, graph_name: impl Into<NamedOrBlankNode>) {
+    pub fn insert_named_graph(&self, graph_name: impl Into<NamedOrBlankNode>) -> Result<(), StoreError> {
         let mut this = self;
-        let graph_name = this
-            .encode_named_or_blank_node(graph_name.into().as_ref())
-            .unwrap_infallible();
+        let graph_name = this.encode_named_or_blank_node(graph_name.into().as_ref())?;
         this.insert_encoded_named_graph(graph_name)
-            .unwrap_infallible()
     }
 
     /// Removes a graph from this store.
-    pub fn remove_named_graph<'a>(&self, graph_name: impl Into<NamedOrBlankNodeRef<'a>>) {
-        if let Some(graph_name) = self
-            .get_encoded_named_or_blank_node(graph_name.into())
-            .unwrap_infallible()
-        {
+    pub fn remove_named_graph<'a>(&self, graph_name: impl Into<NamedOrBlankNodeRef<'a>>) -> Result<(), StoreError> {
+        if let Some(graph_name) = self.get_encoded_named_or_blank_node(graph_name.into())? {
             let mut this = self;
-            this.remove_encoded_named_graph(graph_name)
-                .unwrap_infallible()
+            this.remove_encoded_named_graph(graph_name)?;
+            self.collect_strs();
         }
+        Ok(())
     }
 
     /// Clears a graph from this store.
-    pub fn clear_graph<'a>(&self, graph_name: impl Into<GraphNameRef<'a>>) {
-        if let Some(graph_name) = self
-            .get_encoded_graph_name(graph_name.into())
-            .unwrap_infallible()
-        {
+    pub fn clear_graph<'a>(&self, graph_name: impl Into<GraphNameRef<'a>>) -> Result<(), StoreError> {
+        if let Some(graph_name) = self.get_encoded_graph_name(graph_name.into())? {
             let mut this = self;
-            this.clear_encoded_graph(graph_name).unwrap_infallible()
+            this.clear_encoded_graph(graph_name)?;
+            self.collect_strs();
         }
+        Ok(())
     }
 
     /// Clears the store.
-    pub fn clear_all(&self) {
+    pub fn clear_all(&self) -> Result<(), StoreError> {
         let mut this = self;
-        this.clear().unwrap_infallible()
+        this.clear()
     }
 
     /// Retrieves quads with a filter on each quad component (used by dump_graph & ReadableEncodedStore)
@@ -491,19 +1181,19 @@ impl<T: Config> GraphStore<T> {
         predicate: Option<NamedNodeRef<'_>>,
         object: Option<TermRef<'_>>,
         graph_name: Option<GraphNameRef<'_>>,
-    ) -> QuadIter<T>
+    ) -> Result<QuadIter<T>, StoreError>
     {
-        if let Some((subject, predicate, object, graph_name)) =
-        store::get_encoded_quad_pattern(self, subject, predicate, object, graph_name).unwrap_infallible()
+        Ok(if let Some((subject, predicate, object, graph_name)) =
+        store::get_encoded_quad_pattern(self, subject, predicate, object, graph_name)?
         {
             let iter = self.encoded_quads_for_pattern(subject, predicate, object, graph_name);
             QuadIter::new(iter, self.clone())
         } else {
             QuadIter::empty()
-        }
+        })
     }
 
-    /// Query quads for the given pattern
+    /// Query quads for the given pattern, the lookup and each quad read are charged to the meter
     fn encoded_quads_for_pattern(
         &self,
         subject: Option<EncodedTerm>,
@@ -512,6 +1202,47 @@ impl<T: Config> GraphStore<T> {
         graph_name: Option<EncodedTerm>,
     ) -> EncodedQuadsIter
     {
+        self.metered(self.lookup_quads(subject, predicate, object, graph_name))
+    }
+
+    /// Charges a read to the meter for a lookup and for each quad it yields, the quads after the limit are errors
+    fn metered(&self, quads: EncodedQuadsIter) -> EncodedQuadsIter {
+        if let Err(error) = self.meter.charge::<T>(1, 0) {
+            return EncodedQuadsIter::new(EncodedQuadIter::from_results(once(Err(error))));
+        }
+        let meter = self.meter.clone();
+        EncodedQuadsIter::new(EncodedQuadIter::from_results(
+            quads.map(move |quad| quad.and_then(|quad| meter.charge::<T>(1, 0).map(|()| quad))),
+        ))
+    }
+
+    fn lookup_quads(
+        &self,
+        subject: Option<EncodedTerm>,
+        predicate: Option<EncodedTerm>,
+        object: Option<EncodedTerm>,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter
+    {
+        let shape = QuadPatternShape {
+            subject: subject.is_some(),
+            predicate: predicate.is_some(),
+            object: object.is_some(),
+            graph_name: match graph_name {
+                None => GraphNameShape::Any,
+                Some(graph_name) if graph_name.is_default_graph() => GraphNameShape::DefaultGraph,
+                Some(_) => GraphNameShape::NamedGraph,
+            },
+        };
+        if !StoreFamily::for_pattern(shape).iter().all(|family| family.is_enabled::<T>()) {
+            return match graph_name {
+                Some(graph_name) => self.scan_quads_for_pattern(subject, predicate, object, graph_name),
+                // the default graph and each named graph, with their own index
+                None => self.quads_in_all_graphs(subject.or(object).or(predicate), move |store, graph_name| {
+                    store.lookup_quads(subject, predicate, object, Some(graph_name))
+                }),
+            };
+        }
         match subject {
             Some(subject) => match predicate {
                 // spog, spo, spg, sp
@@ -588,6 +1319,46 @@ impl<T: Config> GraphStore<T> {
         }
     }
 
+    // Fallback when the index of a pattern is not maintained: prefix scan of DefaultSpo or of the Gspo index of the graph
+    fn scan_quads_for_pattern(
+        &self,
+        subject: Option<EncodedTerm>,
+        predicate: Option<EncodedTerm>,
+        object: Option<EncodedTerm>,
+        graph_name: EncodedTerm,
+    ) -> EncodedQuadsIter {
+        let s = subject.map(EncodedTerm::term_id);
+        let p = predicate.map(EncodedTerm::term_id);
+        let o = object.map(EncodedTerm::term_id);
+
+        let keys: Box<dyn Iterator<Item = (TermId, TermId, TermId)>> = if graph_name.is_default_graph() {
+            match (s, p) {
+                (Some(s), Some(p)) => Box::new(<DefaultSpoStore<T>>::iter_key_prefix((s, p, ))
+                    .map(move |o| (s, p, o))),
+                (Some(s), None) => Box::new(<DefaultSpoStore<T>>::iter_key_prefix((s, ))
+                    .map(move |(p, o)| (s, p, o))),
+                (None, _) => Box::new(<DefaultSpoStore<T>>::iter_keys()),
+            }
+        } else {
+            let trie = GraphTrie::of::<T>(&graph_name.term_id());
+            match (s, p) {
+                (Some(s), Some(p)) => Box::new(trie.triples_with_prefix(TAG_SPO, &[s, p])),
+                (Some(s), None) => Box::new(trie.triples_with_prefix(TAG_SPO, &[s])),
+                (None, _) => Box::new(trie.triples_with_prefix(TAG_SPO, &[])),
+            }
+        };
+        // the ids are compared before decoding, which may read the storage
+        let iter = keys
+            .filter(move |(_, key_p, key_o)| p.map_or(true, |p| p == *key_p) && o.map_or(true, |o| o == *key_o))
+            .map(move |(s, p, o)| {
+                let subject = decode_term_id::<T>(&s)?;
+                let predicate = decode_term_id::<T>(&p)?;
+                let object = decode_term_id::<T>(&o)?;
+                Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+            });
+        EncodedQuadsIter::new(EncodedQuadIter::from_results(iter))
+    }
+
     // Step1 pattern: spog
     fn quads_for_subject_predicate_object_graph(
         &self,
@@ -602,19 +1373,18 @@ impl<T: Config> GraphStore<T> {
         } else {
             vec![]
         };
-        EncodedQuadsIter::new(EncodedQuadIter { iter: quads.into_iter() })
+        EncodedQuadsIter::new(EncodedQuadIter::new(quads.into_iter()))
     }
 
     // Check quad(s,p,o,g)
     fn contains_encoded_quad(&self, quad: &EncodedQuad) -> bool {
-        let s = quad.subject.to_bounded_vec();
-        let p = quad.predicate.to_bounded_vec();
-        let o = quad.object.to_bounded_vec();
+        let s = quad.subject.term_id();
+        let p = quad.predicate.term_id();
+        let o = quad.object.term_id();
         if quad.graph_name.is_default_graph() {
             <DefaultSpoStore<T>>::contains_key((s, p, o))
         } else {
-            let g = quad.graph_name.to_bounded_vec();
-            <SpogStore<T>>::contains_key((s, p, o, g))
+            GraphTrie::of::<T>(&quad.graph_name.term_id()).contains_triple(TAG_SPO, (s, p, o))
         }
     }
 
@@ -625,21 +1395,9 @@ impl<T: Config> GraphStore<T> {
         predicate: EncodedTerm,
         object: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let s = subject.to_bounded_vec();
-        let p = predicate.to_bounded_vec();
-        let o = object.to_bounded_vec();
-
-        let default_graph = vec![EncodedQuad::new(subject, predicate, object, EncodedTerm::DefaultGraph)];
-        let named_graph_iter = <SpogStore<T>>::iter_key_prefix((s, p, o, ))
-            .map(|g| { // v is a BoundedVec of graph name
-                let graph_name = EncodedTerm::from_bytes(g.as_ref());
-                EncodedQuad::new(subject, predicate, object, graph_name)
-            }).collect::<Vec<_>>().into_iter();
-
-        EncodedQuadsIter::pair(
-            EncodedQuadIter { iter: default_graph.into_iter() },
-            EncodedQuadIter { iter: named_graph_iter },
-        )
+        self.quads_in_all_graphs(Some(subject), move |store, graph_name| {
+            store.quads_for_subject_predicate_object_graph(subject, predicate, object, graph_name)
+        })
     }
 
     // Step3 pattern: spg
@@ -649,24 +1407,24 @@ impl<T: Config> GraphStore<T> {
         predicate: EncodedTerm,
         graph_name: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let s = subject.to_bounded_vec();
-        let p = predicate.to_bounded_vec();
+        let s = subject.term_id();
+        let p = predicate.term_id();
 
         let iter = if graph_name.is_default_graph() {
-            <DefaultSpoStore<T>>::iter_key_prefix((s, p, ))
-                .map(|o| {
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            EncodedQuadIter::from_results(<DefaultSpoStore<T>>::iter_key_prefix((s, p, ))
+                .map(move |o| {
+                    let object = decode_term_id::<T>(&o)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         } else {
-            let g = graph_name.to_bounded_vec();
-            <GspoStore<T>>::iter_key_prefix((g, s, p, ))
-                .map(|o| {
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            let g = graph_name.term_id();
+            EncodedQuadIter::from_results(GraphTrie::of::<T>(&g).triples_with_prefix(TAG_SPO, &[s, p])
+                .map(move |(_, _, o)| {
+                    let object = decode_term_id::<T>(&o)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         };
-        EncodedQuadsIter::new(EncodedQuadIter { iter })
+        EncodedQuadsIter::new(iter)
     }
 
     // Step4 pattern: sp
@@ -675,28 +1433,9 @@ impl<T: Config> GraphStore<T> {
         subject: EncodedTerm,
         predicate: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let s = subject.to_bounded_vec();
-        let p = predicate.to_bounded_vec();
-
-        let default_graph_iter =
-            <DefaultSpoStore<T>>::iter_key_prefix((s.clone(), p.clone(), ))
-                .map(|o| {
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, EncodedTerm::DefaultGraph)
-                }).collect::<Vec<_>>().into_iter();
-
-        let named_graph_iter =
-            <SpogStore<T>>::iter_key_prefix((s, p, ))
-                .map(|(o, g)| {
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    let graph_name = EncodedTerm::from_bytes(g.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter();
-
-        EncodedQuadsIter::pair(
-            EncodedQuadIter { iter: default_graph_iter },
-            EncodedQuadIter { iter: named_graph_iter },
-        )
+        self.quads_in_all_graphs(Some(subject), move |store, graph_name| {
+            store.quads_for_subject_predicate_graph(subject, predicate, graph_name)
+        })
     }
 
     // Step5 pattern: sog
@@ -706,24 +1445,24 @@ impl<T: Config> GraphStore<T> {
         object: EncodedTerm,
         graph_name: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let s = subject.to_bounded_vec();
-        let o = object.to_bounded_vec();
+        let s = subject.term_id();
+        let o = object.term_id();
 
         let iter = if graph_name.is_default_graph() {
-            <DefaultOspStore<T>>::iter_key_prefix((o, s, ))
-                .map(|p| {
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            EncodedQuadIter::from_results(<DefaultOspStore<T>>::iter_key_prefix((o, s, ))
+                .map(move |p| {
+                    let predicate = decode_term_id::<T>(&p)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         } else {
-            let g = graph_name.to_bounded_vec();
-            <GospStore<T>>::iter_key_prefix((g, o, s, ))
-                .map(|p| {
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            let g = graph_name.term_id();
+            EncodedQuadIter::from_results(GraphTrie::of::<T>(&g).triples_with_prefix(TAG_OSP, &[o, s])
+                .map(move |(_, _, p)| {
+                    let predicate = decode_term_id::<T>(&p)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         };
-        EncodedQuadsIter::new(EncodedQuadIter { iter })
+        EncodedQuadsIter::new(iter)
     }
 
     // Step6 pattern: so
@@ -732,27 +1471,9 @@ impl<T: Config> GraphStore<T> {
         subject: EncodedTerm,
         object: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let s = subject.to_bounded_vec();
-        let o = object.to_bounded_vec();
-        let default_graph_iter =
-            <DefaultOspStore<T>>::iter_key_prefix((o.clone(), s.clone(), ))
-                .map(|p| {
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    EncodedQuad::new(subject, predicate, object, EncodedTerm::DefaultGraph)
-                }).collect::<Vec<_>>().into_iter();
-
-        let named_graph_iter =
-            <OspgStore<T>>::iter_key_prefix((o, s, ))
-                .map(|(p, g)| {
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let graph_name = EncodedTerm::from_bytes(g.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter();
-
-        EncodedQuadsIter::pair(
-            EncodedQuadIter { iter: default_graph_iter },
-            EncodedQuadIter { iter: named_graph_iter },
-        )
+        self.quads_in_all_graphs(Some(subject), move |store, graph_name| {
+            store.quads_for_subject_object_graph(subject, object, graph_name)
+        })
     }
 
     // Step7 pattern: sg
@@ -761,25 +1482,25 @@ impl<T: Config> GraphStore<T> {
         subject: EncodedTerm,
         graph_name: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let s = subject.to_bounded_vec();
+        let s = subject.term_id();
 
         let iter = if graph_name.is_default_graph() {
-            <DefaultSpoStore<T>>::iter_key_prefix((s, ))
-                .map(|(p, o)| {
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            EncodedQuadIter::from_results(<DefaultSpoStore<T>>::iter_key_prefix((s, ))
+                .map(move |(p, o)| {
+                    let predicate = decode_term_id::<T>(&p)?;
+                    let object = decode_term_id::<T>(&o)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         } else {
-            let g = graph_name.to_bounded_vec();
-            <GspoStore<T>>::iter_key_prefix((g, s, ))
-                .map(|(p, o)| {
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            let g = graph_name.term_id();
+            EncodedQuadIter::from_results(GraphTrie::of::<T>(&g).triples_with_prefix(TAG_SPO, &[s])
+                .map(move |(_, p, o)| {
+                    let predicate = decode_term_id::<T>(&p)?;
+                    let object = decode_term_id::<T>(&o)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         };
-        EncodedQuadsIter::new(EncodedQuadIter { iter })
+        EncodedQuadsIter::new(iter)
     }
 
     // Step8 pattern: s
@@ -787,28 +1508,9 @@ impl<T: Config> GraphStore<T> {
         &self,
         subject: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let s = subject.to_bounded_vec();
-        let default_graph_iter =
-            <DefaultSpoStore<T>>::iter_key_prefix((s.clone(), ))
-                .map(|(p, o)| {
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, EncodedTerm::DefaultGraph)
-                }).collect::<Vec<_>>().into_iter();
-
-        let named_graph_iter =
-            <SpogStore<T>>::iter_key_prefix((s, ))
-                .map(|(p, o, g)| {
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    let graph_name = EncodedTerm::from_bytes(g.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter();
-
-        EncodedQuadsIter::pair(
-            EncodedQuadIter { iter: default_graph_iter },
-            EncodedQuadIter { iter: named_graph_iter },
-        )
+        self.quads_in_all_graphs(Some(subject), move |store, graph_name| {
+            store.quads_for_subject_graph(subject, graph_name)
+        })
     }
 
     // Step9 pattern: pog
@@ -818,24 +1520,24 @@ impl<T: Config> GraphStore<T> {
         object: EncodedTerm,
         graph_name: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let p = predicate.to_bounded_vec();
-        let o = object.to_bounded_vec();
+        let p = predicate.term_id();
+        let o = object.term_id();
 
         let iter = if graph_name.is_default_graph() {
-            <DefaultPosStore<T>>::iter_key_prefix((p, o, ))
-                .map(|s| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            EncodedQuadIter::from_results(<DefaultPosStore<T>>::iter_key_prefix((p, o, ))
+                .map(move |s| {
+                    let subject = decode_term_id::<T>(&s)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         } else {
-            let g = graph_name.to_bounded_vec();
-            <GposStore<T>>::iter_key_prefix((g, p, o, ))
-                .map(|s| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            let g = graph_name.term_id();
+            EncodedQuadIter::from_results(GraphTrie::of::<T>(&g).triples_with_prefix(TAG_POS, &[p, o])
+                .map(move |(_, _, s)| {
+                    let subject = decode_term_id::<T>(&s)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         };
-        EncodedQuadsIter::new(EncodedQuadIter { iter })
+        EncodedQuadsIter::new(iter)
     }
 
     // Step10 pattern: po
@@ -844,28 +1546,9 @@ impl<T: Config> GraphStore<T> {
         predicate: EncodedTerm,
         object: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let p = predicate.to_bounded_vec();
-        let o = object.to_bounded_vec();
-
-        let default_graph_iter =
-            <DefaultPosStore<T>>::iter_key_prefix((p.clone(), o.clone(), ))
-                .map(|s| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    EncodedQuad::new(subject, predicate, object, EncodedTerm::DefaultGraph)
-                }).collect::<Vec<_>>().into_iter();
-
-        let named_graph_iter =
-            <PosgStore<T>>::iter_key_prefix((p, o, ))
-                .map(|(s, g)| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let graph_name = EncodedTerm::from_bytes(g.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter();
-
-        EncodedQuadsIter::pair(
-            EncodedQuadIter { iter: default_graph_iter },
-            EncodedQuadIter { iter: named_graph_iter },
-        )
+        self.quads_in_all_graphs(Some(object), move |store, graph_name| {
+            store.quads_for_predicate_object_graph(predicate, object, graph_name)
+        })
     }
 
     // Step11 pattern: pg
@@ -874,25 +1557,25 @@ impl<T: Config> GraphStore<T> {
         predicate: EncodedTerm,
         graph_name: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let p = predicate.to_bounded_vec();
+        let p = predicate.term_id();
 
         let iter = if graph_name.is_default_graph() {
-            <DefaultPosStore<T>>::iter_key_prefix((p, ))
-                .map(|(o, s)| {
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            EncodedQuadIter::from_results(<DefaultPosStore<T>>::iter_key_prefix((p, ))
+                .map(move |(o, s)| {
+                    let object = decode_term_id::<T>(&o)?;
+                    let subject = decode_term_id::<T>(&s)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         } else {
-            let g = graph_name.to_bounded_vec();
-            <GposStore<T>>::iter_key_prefix((g, p, ))
-                .map(|(o, s)| {
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            let g = graph_name.term_id();
+            EncodedQuadIter::from_results(GraphTrie::of::<T>(&g).triples_with_prefix(TAG_POS, &[p])
+                .map(move |(_, o, s)| {
+                    let object = decode_term_id::<T>(&o)?;
+                    let subject = decode_term_id::<T>(&s)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         };
-        EncodedQuadsIter::new(EncodedQuadIter { iter })
+        EncodedQuadsIter::new(iter)
     }
 
     // Step12 pattern: p
@@ -900,29 +1583,9 @@ impl<T: Config> GraphStore<T> {
         &self,
         predicate: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let p = predicate.to_bounded_vec();
-
-        let default_graph_iter =
-            <DefaultPosStore<T>>::iter_key_prefix((p.clone(), ))
-                .map(|(o, s)| {
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    EncodedQuad::new(subject, predicate, object, EncodedTerm::DefaultGraph)
-                }).collect::<Vec<_>>().into_iter();
-
-        let named_graph_iter =
-            <PosgStore<T>>::iter_key_prefix((p, ))
-                .map(|(o, s, g)| {
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let graph_name = EncodedTerm::from_bytes(g.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter();
-
-        EncodedQuadsIter::pair(
-            EncodedQuadIter { iter: default_graph_iter },
-            EncodedQuadIter { iter: named_graph_iter },
-        )
+        self.quads_in_all_graphs(Some(predicate), move |store, graph_name| {
+            store.quads_for_predicate_graph(predicate, graph_name)
+        })
     }
 
     // Step13 pattern: og
@@ -931,25 +1594,25 @@ impl<T: Config> GraphStore<T> {
         object: EncodedTerm,
         graph_name: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let o = object.to_bounded_vec();
+        let o = object.term_id();
 
         let iter = if graph_name.is_default_graph() {
-            <DefaultOspStore<T>>::iter_key_prefix((o, ))
-                .map(|(s, p)| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            EncodedQuadIter::from_results(<DefaultOspStore<T>>::iter_key_prefix((o, ))
+                .map(move |(s, p)| {
+                    let subject = decode_term_id::<T>(&s)?;
+                    let predicate = decode_term_id::<T>(&p)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         } else {
-            let g = graph_name.to_bounded_vec();
-            <GospStore<T>>::iter_key_prefix((g, o, ))
-                .map(|(s, p)| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+            let g = graph_name.term_id();
+            EncodedQuadIter::from_results(GraphTrie::of::<T>(&g).triples_with_prefix(TAG_OSP, &[o])
+                .map(move |(_, s, p)| {
+                    let subject = decode_term_id::<T>(&s)?;
+                    let predicate = decode_term_id::<T>(&p)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
         };
-        EncodedQuadsIter::new(EncodedQuadIter { iter })
+        EncodedQuadsIter::new(iter)
     }
 
     // Step14 pattern: o
@@ -957,83 +1620,212 @@ impl<T: Config> GraphStore<T> {
         &self,
         object: EncodedTerm,
     ) -> EncodedQuadsIter {
-        let o = object.to_bounded_vec();
-
-        let default_graph_iter =
-            <DefaultOspStore<T>>::iter_key_prefix((o.clone(), ))
-                .map(|(s, p)| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    EncodedQuad::new(subject, predicate, object, EncodedTerm::DefaultGraph)
-                }).collect::<Vec<_>>().into_iter();
-
-        let named_graph_iter =
-            <OspgStore<T>>::iter_key_prefix((o, ))
-                .map(|(s, p, g)| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let graph_name = EncodedTerm::from_bytes(g.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter();
-
-        EncodedQuadsIter::pair(
-            EncodedQuadIter { iter: default_graph_iter },
-            EncodedQuadIter { iter: named_graph_iter },
-        )
+        self.quads_in_all_graphs(Some(object), move |store, graph_name| {
+            store.quads_for_object_graph(object, graph_name)
+        })
     }
 
-    // Step15 pattern: g
-    fn quads_for_graph(&self, graph_name: EncodedTerm) -> EncodedQuadsIter {
-        let iter = if graph_name.is_default_graph() {
-            <DefaultSpoStore<T>>::iter_keys()
-                .map(|(s, p, o)| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
-        } else {
-            let g = graph_name.to_bounded_vec();
-            <GspoStore<T>>::iter_key_prefix((g, ))
-                .map(|(s, p, o)| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter()
+    // Range pattern: p with an object value between bounds, read from the ordered literal index
+    fn quads_for_predicate_object_range(
+        &self,
+        predicate: EncodedTerm,
+        range: &EncodedObjectRange<StrHash>,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter {
+        let lower = range.lower.map(move |lower| lower.to_ordered_key_bound(false));
+        let upper = range.upper.map(move |upper| upper.to_ordered_key_bound(true));
+        let (lower, upper) = match (lower, upper) {
+            (Some(None), _) | (_, Some(None)) | (None, None) => {
+                // not an ordered type: the caller checks the values
+                return self.lookup_quads(None, Some(predicate), None, graph_name);
+            }
+            (Some(Some(lower)), Some(Some(upper))) if lower[0] != upper[0] => {
+                // numbers and dates are not comparable
+                return EncodedQuadsIter::new(EncodedQuadIter::new(empty()));
+            }
+            (Some(Some(lower)), Some(Some(upper))) => (lower, upper),
+            (Some(Some(lower)), None) => (lower, ordered_key_type_range(lower).1),
+            (None, Some(Some(upper))) => (ordered_key_type_range(upper).0, upper),
+        };
+
+        let graph_name = match graph_name {
+            Some(graph_name) => graph_name,
+            None => {
+                return self.quads_in_all_graphs(Some(predicate), move |store, graph_name| {
+                    store.quads_for_predicate_object_key_range(predicate, lower, upper, graph_name)
+                })
+            }
         };
-        EncodedQuadsIter::new(EncodedQuadIter { iter })
+        self.quads_for_predicate_object_key_range(predicate, lower, upper, graph_name)
+    }
+
+    fn quads_for_predicate_object_key_range(
+        &self,
+        predicate: EncodedTerm,
+        lower: OrderedKey,
+        upper: OrderedKey,
+        graph_name: EncodedTerm,
+    ) -> EncodedQuadsIter {
+        let p = predicate.term_id();
+        let iter = if graph_name.is_default_graph() {
+            let mut start_key = <OrderedLiteralStore<T>>::final_prefix().to_vec();
+            start_key.extend_from_slice(&Blake2_128Concat::hash(&p.encode()));
+            start_key.extend_from_slice(&lower);
+            EncodedQuadIter::from_results(<OrderedLiteralStore<T>>::iter_from(start_key)
+                .map(move |(key, _)| key)
+                .take_while(move |(key_p, key, ..)| *key_p == p && *key <= upper)
+                .map(move |(_, _, o, s)| {
+                    let subject = decode_term_id::<T>(&s)?;
+                    let object = decode_term_id::<T>(&o)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
+        } else {
+            let g = graph_name.term_id();
+            EncodedQuadIter::from_results(GraphTrie::of::<T>(&g).ordered_literals(p, lower, upper)
+                .map(move |(o, s)| {
+                    let subject = decode_term_id::<T>(&s)?;
+                    let object = decode_term_id::<T>(&o)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
+        };
+        EncodedQuadsIter::new(iter)
+    }
+
+    // Step15 pattern: g
+    fn quads_for_graph(&self, graph_name: EncodedTerm) -> EncodedQuadsIter {
+        let iter = if graph_name.is_default_graph() {
+            EncodedQuadIter::from_results(<DefaultSpoStore<T>>::iter_keys()
+                .map(move |(s, p, o)| {
+                    let subject = decode_term_id::<T>(&s)?;
+                    let predicate = decode_term_id::<T>(&p)?;
+                    let object = decode_term_id::<T>(&o)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
+        } else {
+            let g = graph_name.term_id();
+            EncodedQuadIter::from_results(GraphTrie::of::<T>(&g).triples_with_prefix(TAG_SPO, &[])
+                .map(move |(s, p, o)| {
+                    let subject = decode_term_id::<T>(&s)?;
+                    let predicate = decode_term_id::<T>(&p)?;
+                    let object = decode_term_id::<T>(&o)?;
+                    Ok(EncodedQuad::new(subject, predicate, object, graph_name))
+                }))
+        };
+        EncodedQuadsIter::new(iter)
     }
 
     // Step16 pattern: none (query all)
     fn quads(&self) -> EncodedQuadsIter {
-        let default_graph_iter =
-            <DefaultSpoStore<T>>::iter_keys()
-                .map(|(s, p, o)| {
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, EncodedTerm::DefaultGraph)
-                }).collect::<Vec<_>>().into_iter();
-
-        let named_graph_iter =
-            <GspoStore<T>>::iter_keys()
-                .map(|(g, s, p, o)| {
-                    let graph_name = EncodedTerm::from_bytes(g.as_ref());
-                    let subject = EncodedTerm::from_bytes(s.as_ref());
-                    let predicate = EncodedTerm::from_bytes(p.as_ref());
-                    let object = EncodedTerm::from_bytes(o.as_ref());
-                    EncodedQuad::new(subject, predicate, object, graph_name)
-                }).collect::<Vec<_>>().into_iter();
+        self.quads_in_all_graphs(None, |store, graph_name| store.quads_for_graph(graph_name))
+    }
 
+    fn text_quads(
+        &self,
+        predicate: Option<EncodedTerm>,
+        query: &TextQuery,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter {
+        match graph_name {
+            Some(graph_name) if graph_name.is_default_graph() && StoreFamily::Text.is_enabled::<T>() => {
+                EncodedQuadsIter::new(EncodedQuadIter::from_results(text::quads::<T>(query, predicate)))
+            }
+            // the literals of the named graphs are not indexed
+            Some(graph_name) => self.lookup_quads(None, predicate, None, Some(graph_name)),
+            None => {
+                let query = query.clone();
+                self.quads_in_all_graphs(predicate, move |store, graph_name| {
+                    store.text_quads(predicate, &query, Some(graph_name))
+                })
+            }
+        }
+    }
+
+    fn area_quads(
+        &self,
+        predicate: Option<EncodedTerm>,
+        area: &Envelope,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter {
+        match graph_name {
+            Some(graph_name) if graph_name.is_default_graph() && StoreFamily::Spatial.is_enabled::<T>() => {
+                EncodedQuadsIter::new(EncodedQuadIter::from_results(spatial::quads::<T>(area, predicate)))
+            }
+            // the geometries of the named graphs are not indexed
+            Some(graph_name) => self.lookup_quads(None, predicate, None, Some(graph_name)),
+            None => {
+                let area = *area;
+                self.quads_in_all_graphs(predicate, move |store, graph_name| {
+                    store.area_quads(predicate, &area, Some(graph_name))
+                })
+            }
+        }
+    }
+
+    fn vector_quads(
+        &self,
+        predicate: EncodedTerm,
+        query: &Vector,
+        k: usize,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter {
+        match graph_name {
+            Some(graph_name) if graph_name.is_default_graph() && StoreFamily::Vector.is_enabled::<T>() => {
+                // the quads of the nearest vectors
+                let store = self.clone();
+                let quads = hnsw::nearest::<T>(predicate.term_id(), query, k).into_iter().flat_map(move |o| {
+                    let quads: Box<dyn Iterator<Item = Result<EncodedQuad, StoreError>>> =
+                        match decode_term_id::<T>(&o) {
+                            Ok(object) => Box::new(store.lookup_quads(
+                                None,
+                                Some(predicate),
+                                Some(object),
+                                Some(graph_name),
+                            )),
+                            Err(error) => Box::new(once(Err(error))),
+                        };
+                    quads
+                });
+                EncodedQuadsIter::new(EncodedQuadIter::from_results(quads))
+            }
+            // the vectors of the named graphs are not indexed
+            Some(graph_name) => self.lookup_quads(None, Some(predicate), None, Some(graph_name)),
+            None => {
+                let query = query.clone();
+                self.quads_in_all_graphs(Some(predicate), move |store, graph_name| {
+                    store.vector_quads(predicate, &query, k, Some(graph_name))
+                })
+            }
+        }
+    }
+
+    /// The quads of the default graph then of each named graph, `lookup` reads one graph. With a `term` of
+    /// the pattern, only the named graphs with a quad using it are read (see `TermGraphStore`).
+    fn quads_in_all_graphs(
+        &self,
+        term: Option<EncodedTerm>,
+        lookup: impl Fn(&Self, EncodedTerm) -> EncodedQuadsIter + 'static,
+    ) -> EncodedQuadsIter {
+        let default_graph_iter = lookup(self, EncodedTerm::DefaultGraph);
+        let store = self.clone();
+        let graphs: Box<dyn Iterator<Item = TermId>> = match term {
+            Some(term) => Box::new(<TermGraphStore<T>>::iter_key_prefix((term.term_id(),))
+                .filter(|(g, generation)| *generation == <GraphGenerationStore<T>>::get(g))
+                .map(|(g, _)| g)),
+            None => Box::new(<GraphNameStore<T>>::iter_keys()),
+        };
+        let named_graph_iter = graphs
+            .flat_map(move |g| match decode_term_id::<T>(&g) {
+                Ok(graph_name) => lookup(&store, graph_name),
+                Err(error) => EncodedQuadsIter::new(EncodedQuadIter::from_results(once(Err(error)))),
+            });
         EncodedQuadsIter::pair(
-            EncodedQuadIter { iter: default_graph_iter },
-            EncodedQuadIter { iter: named_graph_iter },
+            EncodedQuadIter::from_results(default_graph_iter),
+            EncodedQuadIter::from_results(named_graph_iter),
         )
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum StoreFamily {
     DefaultSpo,
     //TODO rename to Dspo , same as sled impl, or my prefer: dspo(default), spog (named graph)
@@ -1042,30 +1834,146 @@ enum StoreFamily {
     Gspo,
     Gpos,
     Gosp,
-    Spog,
-    Posg,
-    Ospg,
+    OrderedLiteral,
+    Text,
+    Spatial,
+    Vector,
+}
+
+impl StoreFamily {
+    /// Index families read by `encoded_quads_for_pattern` for a lookup of the given shape
+    fn for_pattern(shape: QuadPatternShape) -> Vec<StoreFamily> {
+        use StoreFamily::*;
+        // (default graph, named graph): without a graph name each graph is read with its own index
+        let (default, named) = match (shape.subject, shape.predicate, shape.object) {
+            (true, _, false) | (true, true, true) => (DefaultSpo, Gspo),
+            (_, false, true) => (DefaultOsp, Gosp),
+            (false, true, _) => (DefaultPos, Gpos),
+            (false, false, false) => (DefaultSpo, Gspo),
+        };
+        match shape.graph_name {
+            GraphNameShape::DefaultGraph => vec![default],
+            GraphNameShape::NamedGraph => vec![named],
+            GraphNameShape::Bound | GraphNameShape::Any => vec![default, named],
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            StoreFamily::DefaultSpo => "DefaultSpo",
+            StoreFamily::DefaultPos => "DefaultPos",
+            StoreFamily::DefaultOsp => "DefaultOsp",
+            StoreFamily::Gspo => "Gspo",
+            StoreFamily::Gpos => "Gpos",
+            StoreFamily::Gosp => "Gosp",
+            StoreFamily::OrderedLiteral => "OrderedLiteral",
+            StoreFamily::Text => "Text",
+            StoreFamily::Spatial => "Spatial",
+            StoreFamily::Vector => "Vector",
+        }
+    }
+
+    /// Same as `for_pattern`, with the indexes not maintained replaced by the ones scanned instead
+    fn for_lookup<T: Config>(shape: QuadPatternShape) -> Vec<StoreFamily> {
+        Self::for_pattern(shape)
+            .into_iter()
+            .map(|family| if family.is_enabled::<T>() { family } else { family.mandatory() })
+            .collect()
+    }
+
+    fn is_enabled<T: Config>(self) -> bool {
+        T::QuadIndexes::get().contains(self)
+    }
+
+    /// The index always maintained for the same graphs, scanned when `self` is not maintained
+    fn mandatory(self) -> StoreFamily {
+        match self {
+            StoreFamily::DefaultSpo
+            | StoreFamily::DefaultPos
+            | StoreFamily::DefaultOsp
+            | StoreFamily::Spatial
+            | StoreFamily::Vector
+            | StoreFamily::Text => StoreFamily::DefaultSpo,
+            _ => StoreFamily::Gspo,
+        }
+    }
+
+    fn flag(self) -> u16 {
+        match self {
+            StoreFamily::DefaultSpo => QuadIndexes::DEFAULT_SPO.0,
+            StoreFamily::DefaultPos => QuadIndexes::DEFAULT_POS.0,
+            StoreFamily::DefaultOsp => QuadIndexes::DEFAULT_OSP.0,
+            StoreFamily::Gspo => QuadIndexes::GSPO.0,
+            StoreFamily::Gpos => QuadIndexes::GPOS.0,
+            StoreFamily::Gosp => QuadIndexes::GOSP.0,
+            StoreFamily::Spatial => QuadIndexes::SPATIAL.0,
+            StoreFamily::Vector => QuadIndexes::VECTOR.0,
+            StoreFamily::Text => QuadIndexes::TEXT.0,
+            StoreFamily::OrderedLiteral => QuadIndexes::REQUIRED.0,
+        }
+    }
+}
+
+/// A set of quad indexes, see `Config::QuadIndexes`.
+///
+/// The sets are combined with `with`, e.g. `QuadIndexes::DEFAULT_POS.with(QuadIndexes::GPOS)`.
+///
+/// The `SPOG`, `POSG` and `OSPG` indexes of the named graphs were removed with the child tries of the graphs
+/// (storage version 2): a lookup without a graph reads the graphs of a bound term from `TermGraphStore` instead.
+/// Their bits `1 << 6`, `1 << 7` and `1 << 8` are not used anymore.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+pub struct QuadIndexes(u16);
+
+impl QuadIndexes {
+    pub const DEFAULT_SPO: Self = Self(1);
+    pub const DEFAULT_POS: Self = Self(1 << 1);
+    pub const DEFAULT_OSP: Self = Self(1 << 2);
+    pub const GSPO: Self = Self(1 << 3);
+    pub const GPOS: Self = Self(1 << 4);
+    pub const GOSP: Self = Self(1 << 5);
+    /// The spatial index of the geometries of the default graph, see `crate::spatial`
+    pub const SPATIAL: Self = Self(1 << 9);
+    /// The nearest neighbour index of the vectors of the default graph, see `crate::hnsw`
+    pub const VECTOR: Self = Self(1 << 10);
+    /// The full-text index of the string literals of the default graph, see `crate::text`
+    pub const TEXT: Self = Self(1 << 11);
+    /// The indexes always maintained
+    pub const REQUIRED: Self = Self::DEFAULT_SPO.with(Self::GSPO);
+    /// All the quad indexes, without the secondary `SPATIAL`, `VECTOR` and `TEXT` ones
+    pub const ALL: Self = Self((1 << 6) - 1);
+
+    pub const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    fn contains(self, family: StoreFamily) -> bool {
+        self.with(Self::REQUIRED).0 & family.flag() != 0
+    }
 }
 
 //****************************
 // ID/STR Mapping Store
 //****************************
 
-impl<T> StrEncodingAware for GraphStore<T> {
-    type Error = Infallible;
+impl<T: Config> StrEncodingAware for GraphStore<T> {
+    type Error = StoreError;
     type StrId = StrHash;
+
+    fn namespaces(&self) -> Namespaces {
+        Namespaces::new(T::Namespaces::get())
+    }
 }
 
 impl<T: Config> StrLookup for GraphStore<T> {
-    fn get_str(&self, id: StrHash) -> Result<Option<String>, Infallible> {
+    fn get_str(&self, id: StrHash) -> Result<Option<String>, StoreError> {
         Ok(if let Some(value) = <Id2StrStore<T>>::get(*id) {
-            Some(String::from_utf8(value.into_inner()).unwrap()) //TODO check unwrap
+            Some(String::from_utf8(value.into_inner()).map_err(|_| StoreError::InvalidUtf8 { hash: *id })?)
         } else {
             None
         })
     }
 
-    fn get_str_id(&self, value: &str) -> Result<Option<StrHash>, Infallible> {
+    fn get_str_id(&self, value: &str) -> Result<Option<StrHash>, StoreError> {
         let id = StrHash::new(value);
         Ok(if <Id2StrStore<T>>::contains_key(*id) {
             Some(id)
@@ -1076,14 +1984,65 @@ impl<T: Config> StrLookup for GraphStore<T> {
 }
 
 impl<'a, T: Config> StrContainer for &'a GraphStore<T> {
-    fn insert_str(&mut self, value: &str) -> Result<StrHash, Infallible> {
+    fn insert_str(&mut self, value: &str) -> Result<StrHash, StoreError> {
         let key = StrHash::new(value);
-        let value = BoundedVec::try_from(value.as_bytes().to_vec()).unwrap(); //TODO check unwrap
-        <Id2StrStore<T>>::insert(*key, value);
+        // the references are counted by the index entries, see `retain_strs`
+        match <Id2StrStore<T>>::get(*key) {
+            Some(stored) if stored[..] == *value.as_bytes() => (),
+            Some(_) => return Err(StoreError::StrHashCollision { hash: *key }),
+            None => {
+                <Id2StrStore<T>>::insert(*key, to_bounded_vec::<T>(value.as_bytes().to_vec())?);
+            }
+        }
         Ok(key)
     }
 }
 
+/// Bounds a value to `Config::MaxValueLength`
+fn to_bounded_vec<T: Config>(value: Vec<u8>) -> Result<BoundedVec<u8, T::MaxValueLength>, StoreError> {
+    let length = value.len();
+    BoundedVec::try_from(value).map_err(|_| StoreError::ValueTooLong { length, max: T::MaxValueLength::get() })
+}
+
+/// Counts a reference to each string of `term` and to its encoding if it is stored in `Id2TermStore`, from a quad
+/// or a graph name (the graph name is counted once, by its `GraphNameStore` entry)
+fn retain_strs<T: Config>(term: EncodedTerm) {
+    term.on_each_id(|id| <StrRefCountStore<T>>::mutate(*id, |count| *count = Some(count.unwrap_or(0) + 1)));
+    if let (_, Some((hash, _))) = term.to_term_id() {
+        <TermRefCountStore<T>>::mutate(*hash, |count| *count = Some(count.unwrap_or(0) + 1));
+    }
+}
+
+/// Removes a reference to each string of `term` and to its encoding, the strings and the encoding left without
+/// reference are released to `store`
+fn release_strs<T: Config>(store: &GraphStore<T>, term: EncodedTerm) {
+    term.on_each_id(|id| {
+        <StrRefCountStore<T>>::mutate(*id, |count| {
+            *count = count.map(|count| count.saturating_sub(1)).filter(|count| *count > 0);
+            if count.is_none() {
+                store.released_strs.borrow_mut().insert(*id);
+            }
+        })
+    });
+    if let (_, Some((hash, _))) = term.to_term_id() {
+        <TermRefCountStore<T>>::mutate(*hash, |count| {
+            *count = count.map(|count| count.saturating_sub(1)).filter(|count| *count > 0);
+            if count.is_none() {
+                store.released_terms.borrow_mut().insert(*hash);
+            }
+        });
+    }
+}
+
+/// Decodes a term id read from an index, the terms it references are in `Id2TermStore`
+fn decode_term_id<T: Config>(id: &TermId) -> Result<EncodedTerm, StoreError> {
+    EncodedTerm::from_term_id(id, |hash| {
+        <Id2TermStore<T>>::get(*hash)
+            .map(BoundedVec::into_inner)
+            .ok_or(StoreError::MissingStr { hash: *hash })
+    })
+}
+
 //****************************
 // READ Store
 //****************************
@@ -1101,18 +2060,178 @@ impl<'a, T: Config> ReadableEncodedStore for GraphStore<T> {
         self.encoded_quads_for_pattern(subject, predicate, object, graph_name)
     }
 
+    fn encoded_quads_for_equal_pattern(
+        &self,
+        subject: Option<EncodedTerm>,
+        predicate: Option<EncodedTerm>,
+        object: Option<EncodedTerm>,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter {
+        let equal_terms = |term: Option<EncodedTerm>| match term {
+            Some(term) => same_as::equal_terms::<T>(term).map(|terms| terms.into_iter().map(Some).collect()),
+            None => Ok(vec![None]),
+        };
+        let (subjects, predicates, objects): (Vec<_>, Vec<_>, Vec<_>) =
+            match (equal_terms(subject), equal_terms(predicate), equal_terms(object)) {
+                (Ok(subjects), Ok(predicates), Ok(objects)) => (subjects, predicates, objects),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                    return EncodedQuadsIter::new(EncodedQuadIter::from_results(once(Err(e))));
+                }
+            };
+        let mut iters = Vec::with_capacity(subjects.len() * predicates.len() * objects.len());
+        for subject in subjects.iter() {
+            for predicate in predicates.iter() {
+                for object in objects.iter() {
+                    iters.push(self.encoded_quads_for_pattern(*subject, *predicate, *object, graph_name));
+                }
+            }
+        }
+        // a quad with a member of a class is found again with the other members
+        let mut canonical_terms = HashMap::new();
+        let mut merged_quads = HashSet::new();
+        let iter = iters.into_iter().flatten().filter_map(move |quad| {
+            match quad.and_then(|quad| canonical_quad::<T>(quad, &mut canonical_terms)) {
+                Ok((quad, true)) if !merged_quads.insert(quad) => None,
+                result => Some(result.map(|(quad, _)| quad)),
+            }
+        });
+        EncodedQuadsIter::new(EncodedQuadIter::from_results(iter))
+    }
+
     fn encoded_named_graphs(&self) -> Self::GraphsIter {
         let iter = <GraphNameStore<T>>::iter_keys()
-            .map(|graph_name| {
-                EncodedTerm::from_bytes(graph_name.as_ref())
-            }).collect::<Vec<_>>().into_iter();
-        EncodedGraphNameIter { iter }
+            .map(|graph_name| decode_term_id::<T>(&graph_name));
+        EncodedGraphNameIter { iter: Box::new(iter) }
     }
 
-    fn contains_encoded_named_graph(&self, graph_name: EncodedTerm) -> Result<bool, Infallible> {
-        let g = graph_name.to_bounded_vec();
+    fn contains_encoded_named_graph(&self, graph_name: EncodedTerm) -> Result<bool, StoreError> {
+        let g = graph_name.term_id();
         Ok(<GraphNameStore<T>>::contains_key(g))
     }
+
+    fn encoded_quads_indexes_for_pattern(&self, shape: QuadPatternShape) -> Vec<&'static str> {
+        StoreFamily::for_lookup::<T>(shape).into_iter().map(StoreFamily::name).collect()
+    }
+
+    fn encoded_predicate_cardinality(&self, predicate: EncodedTerm) -> Option<u64> {
+        // unknown rather than empty without statistics
+        <PredicateCountStore<T>>::get(predicate.term_id())
+    }
+
+    fn encoded_quad_count(&self, predicate: Option<EncodedTerm>, graph_name: Option<EncodedTerm>) -> Option<u64> {
+        Some(match (predicate, graph_name) {
+            (None, None) => <QuadCountStore<T>>::get(),
+            (None, Some(graph_name)) if graph_name.is_default_graph() => <DefaultGraphQuadCountStore<T>>::get(),
+            (None, Some(graph_name)) => <GraphNameStore<T>>::get(graph_name.term_id()),
+            (Some(predicate), None) => <PredicateCountStore<T>>::get(predicate.term_id()).unwrap_or(0),
+            (Some(predicate), Some(graph_name)) if graph_name.is_default_graph() => {
+                <DefaultGraphPredicateCountStore<T>>::get(predicate.term_id()).unwrap_or(0)
+            }
+            (Some(predicate), Some(graph_name)) => {
+                if !<GraphNameStore<T>>::contains_key(graph_name.term_id()) {
+                    return Some(0);
+                }
+                GraphTrie::of::<T>(&graph_name.term_id()).predicate_count(predicate.term_id())
+            }
+        })
+    }
+
+    fn encoded_predicate_class_cardinality(
+        &self,
+        predicate: EncodedTerm,
+        class: EncodedTerm,
+    ) -> Option<u64> {
+        Some(<PredicateClassCountStore<T>>::get((predicate.term_id(), class.term_id())).unwrap_or(0))
+    }
+
+    fn encoded_quads_for_object_range(
+        &self,
+        predicate: EncodedTerm,
+        range: &EncodedObjectRange<StrHash>,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter {
+        self.metered(self.quads_for_predicate_object_range(predicate, range, graph_name))
+    }
+
+    fn encoded_object_range_indexes(&self) -> Vec<&'static str> {
+        vec![StoreFamily::OrderedLiteral.name()]
+    }
+
+    fn encoded_quads_for_text(
+        &self,
+        predicate: Option<EncodedTerm>,
+        query: &TextQuery,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter {
+        self.metered(self.text_quads(predicate, query, graph_name))
+    }
+
+    fn encoded_text_indexes(&self) -> Vec<&'static str> {
+        if StoreFamily::Text.is_enabled::<T>() {
+            vec![StoreFamily::Text.name()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn encoded_quads_for_area(
+        &self,
+        predicate: Option<EncodedTerm>,
+        area: &Envelope,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter {
+        self.metered(self.area_quads(predicate, area, graph_name))
+    }
+
+    fn encoded_spatial_indexes(&self) -> Vec<&'static str> {
+        if StoreFamily::Spatial.is_enabled::<T>() {
+            vec![StoreFamily::Spatial.name()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn encoded_quads_for_vector(
+        &self,
+        predicate: EncodedTerm,
+        query: &Vector,
+        k: usize,
+        graph_name: Option<EncodedTerm>,
+    ) -> EncodedQuadsIter {
+        self.metered(self.vector_quads(predicate, query, k, graph_name))
+    }
+
+    fn encoded_vector_indexes(&self) -> Vec<&'static str> {
+        if StoreFamily::Vector.is_enabled::<T>() {
+            vec![StoreFamily::Vector.name()]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Replaces the subject, the predicate and the object of a quad by the canonical node of their `owl:sameAs` class,
+/// returns whether one of them has a class. The canonical nodes are cached in `canonical_terms`.
+fn canonical_quad<T: Config>(
+    mut quad: EncodedQuad,
+    canonical_terms: &mut HashMap<EncodedTerm, Option<EncodedTerm>>,
+) -> Result<(EncodedQuad, bool), StoreError> {
+    let mut merged = false;
+    for term in [&mut quad.subject, &mut quad.predicate, &mut quad.object] {
+        let canonical = match canonical_terms.get(term) {
+            Some(canonical) => *canonical,
+            None => {
+                let canonical = same_as::canonical_term::<T>(*term)?;
+                canonical_terms.insert(*term, canonical);
+                canonical
+            }
+        };
+        if let Some(canonical) = canonical {
+            *term = canonical;
+            merged = true;
+        }
+    }
+    Ok((quad, merged))
 }
 
 //****************************
@@ -1140,9 +2259,9 @@ impl EncodedQuadsIter {
 }
 
 impl Iterator for EncodedQuadsIter {
-    type Item = Result<EncodedQuad, Infallible>;
+    type Item = Result<EncodedQuad, StoreError>;
 
-    fn next(&mut self) -> Option<Result<EncodedQuad, Infallible>> {
+    fn next(&mut self) -> Option<Result<EncodedQuad, StoreError>> {
         if let Some(result) = self.first.next() {
             Some(result)
         } else if let Some(second) = self.second.as_mut() {
@@ -1153,27 +2272,38 @@ impl Iterator for EncodedQuadsIter {
     }
 }
 
+/// Quads read lazily from one storage index: the storage is only read when the next quad is asked for.
 struct EncodedQuadIter {
-    iter: IntoIter<EncodedQuad>,
+    iter: Box<dyn Iterator<Item = Result<EncodedQuad, StoreError>>>,
+}
+
+impl EncodedQuadIter {
+    fn new(iter: impl Iterator<Item = EncodedQuad> + 'static) -> Self {
+        Self::from_results(iter.map(Ok))
+    }
+
+    fn from_results(iter: impl Iterator<Item = Result<EncodedQuad, StoreError>> + 'static) -> Self {
+        Self { iter: Box::new(iter) }
+    }
 }
 
 impl Iterator for EncodedQuadIter {
-    type Item = Result<EncodedQuad, Infallible>;
+    type Item = Result<EncodedQuad, StoreError>;
 
-    fn next(&mut self) -> Option<Result<EncodedQuad, Infallible>> {
-        self.iter.next().map(Ok)
+    fn next(&mut self) -> Option<Result<EncodedQuad, StoreError>> {
+        self.iter.next()
     }
 }
 
 struct EncodedGraphNameIter {
-    iter: IntoIter<EncodedTerm>,
+    iter: Box<dyn Iterator<Item = Result<EncodedTerm, StoreError>>>,
 }
 
 impl Iterator for EncodedGraphNameIter {
-    type Item = Result<EncodedTerm, Infallible>;
+    type Item = Result<EncodedTerm, StoreError>;
 
-    fn next(&mut self) -> Option<Result<EncodedTerm, Infallible>> {
-        self.iter.next().map(Ok)
+    fn next(&mut self) -> Option<Result<EncodedTerm, StoreError>> {
+        self.iter.next()
     }
 }
 
@@ -1184,14 +2314,13 @@ pub struct GraphNameIter<T: Config> {
 }
 
 impl<T: Config> Iterator for GraphNameIter<T> {
-    type Item = NamedOrBlankNode;
+    type Item = Result<NamedOrBlankNode, StoreError>;
 
-    fn next(&mut self) -> Option<NamedOrBlankNode> {
-        if let Some(Ok(encoded_term)) = self.iter.next() {
-            Some(self.store.decode_named_or_blank_node(encoded_term).unwrap())
-        } else {
-            None
-        }
+    fn next(&mut self) -> Option<Result<NamedOrBlankNode, StoreError>> {
+        Some(match self.iter.next()? {
+            Ok(encoded_term) => self.store.decode_named_or_blank_node(encoded_term).map_err(StoreError::from),
+            Err(error) => Err(error),
+        })
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -1228,18 +2357,14 @@ enum QuadIterInner<T: Config> {
 }
 
 impl<T: Config> Iterator for QuadIter<T> {
-    type Item = Quad;
+    type Item = Result<Quad, StoreError>;
 
-    fn next(&mut self) -> Option<Quad> {
+    fn next(&mut self) -> Option<Result<Quad, StoreError>> {
         match &mut self.inner {
-            QuadIterInner::Quads { iter, store } => {
-                if let Some(Ok(q)) = iter.next() {
-                    let quad = store.decode_quad(&q).unwrap();
-                    Some(quad)
-                } else {
-                    None
-                }
-            }
+            QuadIterInner::Quads { iter, store } => Some(match iter.next()? {
+                Ok(quad) => store.decode_quad(&quad).map_err(StoreError::from),
+                Err(error) => Err(error),
+            }),
             QuadIterInner::Empty => None,
         }
     }
@@ -1249,23 +2374,41 @@ impl<T: Config> Iterator for QuadIter<T> {
 // WRITE Store
 //****************************
 impl EncodedTerm {
-    fn to_bounded_vec<S: Get<u32>>(self) -> BoundedVec<u8, S> {
-        BoundedVec::try_from(self.to_bytes()).unwrap()
+    fn term_id(self) -> TermId {
+        self.to_term_id().0
     }
 }
 
+/// Stores the encoding of a term too long to be inlined in its id, fails if its hash is used by another term
+fn insert_term<T: Config>(term: EncodedTerm) -> Result<(), StoreError> {
+    if let (_, Some((hash, bytes))) = term.to_term_id() {
+        match <Id2TermStore<T>>::get(*hash) {
+            Some(stored) if stored[..] == bytes[..] => (),
+            Some(_) => return Err(StoreError::StrHashCollision { hash: *hash }),
+            None => <Id2TermStore<T>>::insert(*hash, to_bounded_vec::<T>(bytes)?),
+        }
+    }
+    Ok(())
+}
+
 fn insert_into_triple_map<T: Config>(store_family: StoreFamily, t: (EncodedTerm, EncodedTerm, EncodedTerm)) {
-    let triple_key = (t.0.to_bounded_vec(), t.1.to_bounded_vec(), t.2.to_bounded_vec());
+    if !store_family.is_enabled::<T>() {
+        return;
+    }
+    let triple_key = (t.0.term_id(), t.1.term_id(), t.2.term_id());
     match store_family {
-        StoreFamily::DefaultSpo => <DefaultSpoStore<T>>::insert(triple_key, true),
-        StoreFamily::DefaultPos => <DefaultPosStore<T>>::insert(triple_key, true),
-        StoreFamily::DefaultOsp => <DefaultOspStore<T>>::insert(triple_key, true),
+        StoreFamily::DefaultSpo => <DefaultSpoStore<T>>::insert(triple_key, ()),
+        StoreFamily::DefaultPos => <DefaultPosStore<T>>::insert(triple_key, ()),
+        StoreFamily::DefaultOsp => <DefaultOspStore<T>>::insert(triple_key, ()),
         _ => panic!("Unsupported triple StoreFamily: {:?}", store_family),
     }
 }
 
 fn remove_from_triple_map<T: Config>(store_family: StoreFamily, t: (EncodedTerm, EncodedTerm, EncodedTerm)) {
-    let triple_key = (t.0.to_bounded_vec(), t.1.to_bounded_vec(), t.2.to_bounded_vec());
+    if !store_family.is_enabled::<T>() {
+        return;
+    }
+    let triple_key = (t.0.term_id(), t.1.term_id(), t.2.term_id());
     match store_family {
         StoreFamily::DefaultSpo => <DefaultSpoStore<T>>::remove(triple_key),
         StoreFamily::DefaultPos => <DefaultPosStore<T>>::remove(triple_key),
@@ -1275,164 +2418,752 @@ fn remove_from_triple_map<T: Config>(store_family: StoreFamily, t: (EncodedTerm,
 }
 
 fn insert_into_quad_map<T: Config>(store_family: StoreFamily, t: (EncodedTerm, EncodedTerm, EncodedTerm, EncodedTerm)) {
-    let quad_key = (t.0.to_bounded_vec(), t.1.to_bounded_vec(), t.2.to_bounded_vec(), t.3.to_bounded_vec());
-    match store_family {
-        StoreFamily::Gspo => <GspoStore<T>>::insert(quad_key, true),
-        StoreFamily::Gpos => <GposStore<T>>::insert(quad_key, true),
-        StoreFamily::Gosp => <GospStore<T>>::insert(quad_key, true),
-        StoreFamily::Spog => <SpogStore<T>>::insert(quad_key, true),
-        StoreFamily::Posg => <PosgStore<T>>::insert(quad_key, true),
-        StoreFamily::Ospg => <OspgStore<T>>::insert(quad_key, true),
-        _ => panic!("Unsupported quad StoreFamily: {:?}", store_family),
+    if !store_family.is_enabled::<T>() {
+        return;
     }
+    GraphTrie::of::<T>(&t.0.term_id())
+        .insert_triple(store_family.trie_tag(), (t.1.term_id(), t.2.term_id(), t.3.term_id()));
 }
 
 fn remove_from_quad_map<T: Config>(store_family: StoreFamily, t: (EncodedTerm, EncodedTerm, EncodedTerm, EncodedTerm)) {
-    let quad_key = (t.0.to_bounded_vec(), t.1.to_bounded_vec(), t.2.to_bounded_vec(), t.3.to_bounded_vec());
-    match store_family {
-        StoreFamily::Gspo => <GspoStore<T>>::remove(quad_key),
-        StoreFamily::Gpos => <GposStore<T>>::remove(quad_key),
-        StoreFamily::Gosp => <GospStore<T>>::remove(quad_key),
-        StoreFamily::Spog => <SpogStore<T>>::remove(quad_key),
-        StoreFamily::Posg => <PosgStore<T>>::remove(quad_key),
-        StoreFamily::Ospg => <OspgStore<T>>::remove(quad_key),
-        _ => panic!("Unsupported quad StoreFamily: {:?}", store_family),
+    if !store_family.is_enabled::<T>() {
+        return;
     }
+    GraphTrie::of::<T>(&t.0.term_id())
+        .remove_triple(store_family.trie_tag(), (t.1.term_id(), t.2.term_id(), t.3.term_id()));
 }
 
-impl<'a, T: Config> WritableEncodedStore for &'a GraphStore<T> {
-    fn insert_encoded(&mut self, quad: &EncodedQuad) -> Result<(), Infallible> {
-        if quad.graph_name.is_default_graph() {
-            insert_into_triple_map::<T>(
-                StoreFamily::DefaultSpo,
-                (quad.subject, quad.predicate, quad.object),
-            );
-            insert_into_triple_map::<T>(
-                StoreFamily::DefaultPos,
-                (quad.predicate, quad.object, quad.subject),
-            );
-            insert_into_triple_map::<T>(
-                StoreFamily::DefaultOsp,
-                (quad.object, quad.subject, quad.predicate),
-            );
+impl StoreFamily {
+    /// The tag of a named graph index in the child trie of the graph
+    fn trie_tag(self) -> u8 {
+        match self {
+            StoreFamily::Gspo => TAG_SPO,
+            StoreFamily::Gpos => TAG_POS,
+            StoreFamily::Gosp => TAG_OSP,
+            _ => panic!("Unsupported quad StoreFamily: {:?}", self),
+        }
+    }
+}
+
+fn insert_ordered_literal<T: Config>(quad: &EncodedQuad, key: OrderedKey) {
+    let (p, o, s) = (quad.predicate.term_id(), quad.object.term_id(), quad.subject.term_id());
+    if quad.graph_name.is_default_graph() {
+        <OrderedLiteralStore<T>>::insert((p, key, o, s), ());
+    } else {
+        GraphTrie::of::<T>(&quad.graph_name.term_id()).insert_ordered_literal(p, key, o, s);
+    }
+}
+
+fn remove_ordered_literal<T: Config>(quad: &EncodedQuad, key: OrderedKey) {
+    let (p, o, s) = (quad.predicate.term_id(), quad.object.term_id(), quad.subject.term_id());
+    if quad.graph_name.is_default_graph() {
+        <OrderedLiteralStore<T>>::remove((p, key, o, s));
+    } else {
+        GraphTrie::of::<T>(&quad.graph_name.term_id()).remove_ordered_literal(p, key, o, s);
+    }
+}
+
+fn rdf_type<T: Config>() -> EncodedTerm {
+    // the built-in rdf namespace is the longest one for rdf:type, or a longer one of the runtime
+    let (prefix, local) = Namespaces::new(T::Namespaces::get())
+        .split(rdf::TYPE.as_str())
+        .expect("rdf:type is in the rdf namespace");
+    let local = SmallString::try_from(local).expect("the local name of rdf:type is inlined");
+    EncodedTerm::SmallPrefixedNamedNode { prefix, local }
+}
+
+/// Keeps the cardinality statistics in sync with a quad added to (or removed from) the store,
+/// the statistics of a named graph are also counted in its child trie
+fn update_statistics<T: Config>(store: &GraphStore<T>, quad: &EncodedQuad, inserted: bool) -> Result<(), StoreError> {
+    // counts dropping to zero are removed
+    let update = |count: &mut Option<u64>| {
+        let value = count.unwrap_or(0);
+        *count = if inserted {
+            Some(value + 1)
         } else {
-            insert_into_quad_map::<T>(
-                StoreFamily::Gspo,
-                (quad.graph_name, quad.subject, quad.predicate, quad.object),
-            );
-            insert_into_quad_map::<T>(
-                StoreFamily::Gpos,
-                (quad.graph_name, quad.predicate, quad.object, quad.subject),
-            );
-            insert_into_quad_map::<T>(
-                StoreFamily::Gosp,
-                (quad.graph_name, quad.object, quad.subject, quad.predicate),
-            );
-            insert_into_quad_map::<T>(
-                StoreFamily::Spog,
-                (quad.subject, quad.predicate, quad.object, quad.graph_name),
-            );
-            insert_into_quad_map::<T>(
-                StoreFamily::Posg,
-                (quad.predicate, quad.object, quad.subject, quad.graph_name),
-            );
-            insert_into_quad_map::<T>(
-                StoreFamily::Ospg,
-                (quad.object, quad.subject, quad.predicate, quad.graph_name),
-            );
+            Some(value.saturating_sub(1)).filter(|value| *value > 0)
+        };
+    };
+    let graph = Some(quad.graph_name)
+        .filter(|graph_name| !graph_name.is_default_graph())
+        .map(|graph_name| GraphTrie::of::<T>(&graph_name.term_id()));
+    let update_predicate_class = |p: TermId, class: TermId| {
+        <PredicateClassCountStore<T>>::mutate((p, class), update);
+        if let Some(graph) = &graph {
+            graph.mutate_predicate_class_count(p, class, update);
+        }
+    };
+
+    let p = quad.predicate.term_id();
+    <PredicateCountStore<T>>::mutate(p, update);
+    match &graph {
+        Some(graph) => graph.mutate_predicate_count(p, update),
+        None => <DefaultGraphPredicateCountStore<T>>::mutate(p, update),
+    }
+
+    let rdf_type = rdf_type::<T>();
+    if quad.predicate == rdf_type {
+        update_predicate_class(p, quad.object.term_id());
+        // the other quads of the subject gain (or lose) the class
+        for other in store.encoded_quads_for_pattern(Some(quad.subject), None, None, Some(quad.graph_name)) {
+            let other = other?;
+            if other.predicate != rdf_type {
+                update_predicate_class(other.predicate.term_id(), quad.object.term_id());
+            }
+        }
+    } else {
+        for class in store.encoded_quads_for_pattern(Some(quad.subject), Some(rdf_type), None, Some(quad.graph_name)) {
+            let class = class?.object;
+            update_predicate_class(p, class.term_id());
+        }
+    }
+    Ok(())
+}
+
+/// Removes the quads of the named graph `g` from the statistics and the quad count, then starts a new
+/// generation of its trie: the quads are removed from the trie of the current one by `crate::purge`.
+///
+/// Only the statistics of the graph are read, with the meter.
+fn drop_graph_quads<T: Config>(store: &GraphStore<T>, g: TermId) -> Result<(), StoreError> {
+    let count = <GraphNameStore<T>>::get(g);
+    if count == 0 {
+        return Ok(());
+    }
+    let graph = GraphTrie::of::<T>(&g);
+    for (p, count) in graph.predicate_counts() {
+        store.meter.charge::<T>(2, 1)?;
+        <PredicateCountStore<T>>::mutate(p, |value| {
+            *value = value.map(|value| value.saturating_sub(count)).filter(|value| *value > 0);
+        });
+    }
+    for (p, class, count) in graph.predicate_class_counts() {
+        store.meter.charge::<T>(2, 1)?;
+        <PredicateClassCountStore<T>>::mutate((p, class), |value| {
+            *value = value.map(|value| value.saturating_sub(count)).filter(|value| *value > 0);
+        });
+    }
+    store.meter.charge::<T>(3, 4)?;
+    <QuadCountStore<T>>::mutate(|total| *total = total.saturating_sub(count));
+    start_graph_generation::<T>(g, true);
+    Ok(())
+}
+
+/// Starts a new generation of the trie of the named graph `g`, the trie of the current one is removed by
+/// `crate::purge`. `release_strs` tells whether its quads still reference their strings.
+fn start_graph_generation<T: Config>(g: TermId, release_strs: bool) {
+    let generation = <GraphGenerationStore<T>>::get(g);
+    <GraphPurgeStore<T>>::insert((g, generation), release_strs);
+    <GraphGenerationStore<T>>::insert(g, generation + 1);
+}
 
-            // store graph name
-            <GraphNameStore<T>>::insert(quad.graph_name.to_bounded_vec(), true);
+/// Counts the quad `(s, p, o)` of the generation `generation` of the named graph `g` in `TermGraphStore`,
+/// or uncounts it. A term used twice by the quad is counted once.
+fn count_term_graphs<T: Config>(g: TermId, generation: u32, triple: [TermId; 3], inserted: bool) {
+    let mut ids = triple;
+    ids.sort_unstable();
+    for (i, id) in ids.iter().enumerate() {
+        if i > 0 && ids[i - 1] == *id {
+            continue;
         }
-        Ok(())
+        <TermGraphStore<T>>::mutate((*id, g, generation), |count| {
+            let value = count.unwrap_or(0);
+            *count = if inserted {
+                Some(value + 1)
+            } else {
+                Some(value.saturating_sub(1)).filter(|value| *value > 0)
+            };
+        });
     }
+}
 
-    fn remove_encoded(&mut self, quad: &EncodedQuad) -> Result<(), Infallible> {
-        if quad.graph_name.is_default_graph() {
-            remove_from_triple_map::<T>(
-                StoreFamily::DefaultSpo,
-                (quad.subject, quad.predicate, quad.object),
-            );
-            remove_from_triple_map::<T>(
-                StoreFamily::DefaultPos,
-                (quad.predicate, quad.object, quad.subject),
-            );
-            remove_from_triple_map::<T>(
-                StoreFamily::DefaultOsp,
-                (quad.object, quad.subject, quad.predicate),
-            );
-        } else {
-            remove_from_quad_map::<T>(
-                StoreFamily::Gspo,
-                (quad.graph_name, quad.subject, quad.predicate, quad.object),
-            );
-            remove_from_quad_map::<T>(
-                StoreFamily::Gpos,
-                (quad.graph_name, quad.predicate, quad.object, quad.subject),
-            );
-            remove_from_quad_map::<T>(
-                StoreFamily::Gosp,
-                (quad.graph_name, quad.object, quad.subject, quad.predicate),
-            );
-            remove_from_quad_map::<T>(
-                StoreFamily::Spog,
-                (quad.subject, quad.predicate, quad.object, quad.graph_name),
-            );
-            remove_from_quad_map::<T>(
-                StoreFamily::Posg,
-                (quad.predicate, quad.object, quad.subject, quad.graph_name),
-            );
-            remove_from_quad_map::<T>(
-                StoreFamily::Ospg,
-                (quad.object, quad.subject, quad.predicate, quad.graph_name),
-            );
+/// Stores the encodings of the terms of a quad, fails if one of them collides with another term.
+/// It runs before anything else of the quad is written.
+fn insert_terms<T: Config>(quad: &EncodedQuad) -> Result<(), StoreError> {
+    insert_term::<T>(quad.subject)?;
+    insert_term::<T>(quad.predicate)?;
+    insert_term::<T>(quad.object)?;
+    insert_term::<T>(quad.graph_name)
+}
+
+/// Writes the index entries of a quad and counts its references to the strings, the statistics are not updated.
+/// The terms of the quad are stored by `insert_terms` first.
+/// The accesses of the spatial, vector and full-text indexes, which depend on the object, are charged to the meter
+/// of `store`.
+fn insert_quad_entries<T: Config>(store: &GraphStore<T>, quad: &EncodedQuad) -> Result<(), StoreError> {
+    for term in [quad.subject, quad.predicate, quad.object].iter() {
+        retain_strs::<T>(*term);
+    }
+    <QuadCountStore<T>>::mutate(|count| *count += 1);
+    if quad.graph_name.is_default_graph() {
+        <DefaultGraphQuadCountStore<T>>::mutate(|count| *count += 1);
+        insert_into_triple_map::<T>(
+            StoreFamily::DefaultSpo,
+            (quad.subject, quad.predicate, quad.object),
+        );
+        insert_into_triple_map::<T>(
+            StoreFamily::DefaultPos,
+            (quad.predicate, quad.object, quad.subject),
+        );
+        insert_into_triple_map::<T>(
+            StoreFamily::DefaultOsp,
+            (quad.object, quad.subject, quad.predicate),
+        );
+    } else {
+        insert_into_quad_map::<T>(
+            StoreFamily::Gspo,
+            (quad.graph_name, quad.subject, quad.predicate, quad.object),
+        );
+        insert_into_quad_map::<T>(
+            StoreFamily::Gpos,
+            (quad.graph_name, quad.predicate, quad.object, quad.subject),
+        );
+        insert_into_quad_map::<T>(
+            StoreFamily::Gosp,
+            (quad.graph_name, quad.object, quad.subject, quad.predicate),
+        );
+
+        // store graph name with its quad count
+        let g = quad.graph_name.term_id();
+        let triple = [quad.subject.term_id(), quad.predicate.term_id(), quad.object.term_id()];
+        count_term_graphs::<T>(g, <GraphGenerationStore<T>>::get(g), triple, true);
+        if !<GraphNameStore<T>>::contains_key(g) {
+            retain_strs::<T>(quad.graph_name);
         }
-        Ok(())
+        <GraphNameStore<T>>::mutate(g, |count| *count += 1);
+    }
+    if let Some(key) = quad.object.to_ordered_key() {
+        insert_ordered_literal::<T>(quad, key);
+    }
+    if same_as::is_link::<T>(quad) {
+        same_as::link::<T>(quad);
+    }
+    if StoreFamily::Spatial.is_enabled::<T>() {
+        let (reads, writes) = spatial::index::<T>(quad)?;
+        store.meter.charge::<T>(reads, writes)?;
+    }
+    if StoreFamily::Vector.is_enabled::<T>() {
+        let (reads, writes) = hnsw::index::<T>(quad)?;
+        store.meter.charge::<T>(reads, writes)?;
+    }
+    if StoreFamily::Text.is_enabled::<T>() {
+        let (reads, writes) = text::index::<T>(quad)?;
+        store.meter.charge::<T>(reads, writes)?;
+    }
+    Ok(())
+}
+
+/// Removes the index entries of a quad and its references to the strings, the statistics are not updated.
+/// The accesses of the spatial, vector and full-text indexes are charged to the meter of `store`.
+fn remove_quad_entries<T: Config>(store: &GraphStore<T>, quad: &EncodedQuad) -> Result<(), StoreError> {
+    for term in [quad.subject, quad.predicate, quad.object].iter() {
+        release_strs::<T>(store, *term);
+    }
+    <QuadCountStore<T>>::mutate(|count| *count = count.saturating_sub(1));
+    if quad.graph_name.is_default_graph() {
+        <DefaultGraphQuadCountStore<T>>::mutate(|count| *count = count.saturating_sub(1));
+        remove_from_triple_map::<T>(
+            StoreFamily::DefaultSpo,
+            (quad.subject, quad.predicate, quad.object),
+        );
+        remove_from_triple_map::<T>(
+            StoreFamily::DefaultPos,
+            (quad.predicate, quad.object, quad.subject),
+        );
+        remove_from_triple_map::<T>(
+            StoreFamily::DefaultOsp,
+            (quad.object, quad.subject, quad.predicate),
+        );
+    } else {
+        remove_from_quad_map::<T>(
+            StoreFamily::Gspo,
+            (quad.graph_name, quad.subject, quad.predicate, quad.object),
+        );
+        remove_from_quad_map::<T>(
+            StoreFamily::Gpos,
+            (quad.graph_name, quad.predicate, quad.object, quad.subject),
+        );
+        remove_from_quad_map::<T>(
+            StoreFamily::Gosp,
+            (quad.graph_name, quad.object, quad.subject, quad.predicate),
+        );
+        let g = quad.graph_name.term_id();
+        let triple = [quad.subject.term_id(), quad.predicate.term_id(), quad.object.term_id()];
+        count_term_graphs::<T>(g, <GraphGenerationStore<T>>::get(g), triple, false);
+        <GraphNameStore<T>>::mutate(g, |count| *count = count.saturating_sub(1));
+    }
+    if let Some(key) = quad.object.to_ordered_key() {
+        remove_ordered_literal::<T>(quad, key);
+    }
+    if same_as::is_link::<T>(quad) {
+        same_as::unlink::<T>(quad);
+    }
+    if StoreFamily::Spatial.is_enabled::<T>() {
+        let (reads, writes) = spatial::unindex::<T>(quad)?;
+        store.meter.charge::<T>(reads, writes)?;
+    }
+    if StoreFamily::Vector.is_enabled::<T>() {
+        let (reads, writes) = hnsw::unindex::<T>(quad)?;
+        store.meter.charge::<T>(reads, writes)?;
+    }
+    if StoreFamily::Text.is_enabled::<T>() {
+        let (reads, writes) = text::unindex::<T>(quad)?;
+        store.meter.charge::<T>(reads, writes)?;
+    }
+    Ok(())
+}
+
+/// Removes the entries of a map, charging a write per entry to the meter of `store`: only the entries the weight
+/// left pays for are removed, a map keeping entries exhausts the meter
+fn remove_all_charged<T, M, V>(store: &GraphStore<T>) -> Result<(), StoreError>
+where
+    T: Config,
+    M: StoragePrefixedMap<V>,
+    V: FullCodec,
+{
+    let write = T::DbWeight::get().writes(1);
+    let limit = store.meter.remaining::<T>(write).map(|count| count.min(u64::from(u32::MAX)) as u32);
+    match M::remove_all(limit) {
+        KillStorageResult::AllRemoved(removed) => store.meter.charge::<T>(0, u64::from(removed)),
+        KillStorageResult::SomeRemaining(removed) => {
+            store.meter.charge::<T>(0, u64::from(removed))?;
+            Err(store.meter.exhaust())
+        }
+    }
+}
+
+impl<'a, T: Config> WritableEncodedStore for &'a GraphStore<T> {
+    fn insert_encoded(&mut self, quad: &EncodedQuad) -> Result<(), StoreError> {
+        self.meter.charge::<T>(1, 0)?;
+        self.ensure_writable(quad.graph_name)?;
+        if self.contains_encoded_quad(quad) {
+            return Ok(());
+        }
+        self.meter.charge::<T>(QUAD_WRITE_READS, QUAD_WRITE_WRITES)?;
+        if T::InferredGraph::get().is_some() {
+            self.base_changes.borrow_mut().record(quad, true);
+        }
+        if T::RulesGraph::get().is_some() {
+            self.rule_changes.borrow_mut().record(quad, true);
+        }
+        if T::ShapesGraph::get().is_some() {
+            self.shape_changes.borrow_mut().record(quad);
+        }
+        // a term colliding with another one fails before the statistics and the entries are written
+        insert_terms::<T>(quad)?;
+        update_statistics::<T>(self, quad, true)?;
+        insert_quad_entries::<T>(self, quad)
+    }
+
+    fn remove_encoded(&mut self, quad: &EncodedQuad) -> Result<(), StoreError> {
+        self.meter.charge::<T>(1, 0)?;
+        self.ensure_writable(quad.graph_name)?;
+        if !self.contains_encoded_quad(quad) {
+            return Ok(());
+        }
+        self.meter.charge::<T>(QUAD_WRITE_READS, QUAD_WRITE_WRITES)?;
+        if T::InferredGraph::get().is_some() {
+            self.base_changes.borrow_mut().record(quad, false);
+        }
+        if T::RulesGraph::get().is_some() {
+            self.rule_changes.borrow_mut().record(quad, false);
+        }
+        if T::ShapesGraph::get().is_some() {
+            self.shape_changes.borrow_mut().record(quad);
+        }
+        update_statistics::<T>(self, quad, false)?;
+        remove_quad_entries::<T>(self, quad)
     }
 
-    fn insert_encoded_named_graph(&mut self, graph_name: EncodedTerm) -> Result<(), Infallible> {
-        <GraphNameStore<T>>::insert(graph_name.to_bounded_vec(), true);
+    fn insert_encoded_named_graph(&mut self, graph_name: EncodedTerm) -> Result<(), StoreError> {
+        self.ensure_writable(graph_name)?;
+        insert_term::<T>(graph_name)?;
+        let g = graph_name.term_id();
+        if !<GraphNameStore<T>>::contains_key(g) {
+            retain_strs::<T>(graph_name);
+            <GraphNameStore<T>>::insert(g, 0);
+        }
         Ok(())
     }
 
-    fn clear_encoded_graph(&mut self, graph_name: EncodedTerm) -> Result<(), Infallible> {
+    fn clear_encoded_graph(&mut self, graph_name: EncodedTerm) -> Result<(), StoreError> {
+        self.ensure_writable(graph_name)?;
         if graph_name.is_default_graph() {
-            <DefaultSpoStore<T>>::remove_all(None);
-            <DefaultPosStore<T>>::remove_all(None);
-            <DefaultOspStore<T>>::remove_all(None);
+            // quads are removed one by one to keep the statistics up to date, they are collected first as the
+            // storage must not change while it is iterated. The reads and the removals are charged to the meter,
+            // which bounds the size of the default graph an update can clear.
+            let quads = self.encoded_quads_for_pattern(None, None, None, Some(graph_name))
+                .collect::<Result<Vec<_>, _>>()?;
+            for quad in quads {
+                self.remove_encoded(&quad)?;
+            }
         } else {
-            for quad in self.quads_for_graph(graph_name) {
-                self.remove_encoded(&quad?)?;
+            let g = graph_name.term_id();
+            if <GraphNameStore<T>>::contains_key(g) {
+                self.rule_changes.borrow_mut().record_cleared();
+                self.shape_changes.borrow_mut().record_cleared();
+                drop_graph_quads::<T>(self, g)?;
+                <GraphNameStore<T>>::insert(g, 0);
             }
         }
         Ok(())
     }
 
-    fn remove_encoded_named_graph(&mut self, graph_name: EncodedTerm) -> Result<(), Infallible> {
-        for quad in self.quads_for_graph(graph_name) {
-            self.remove_encoded(&quad?)?;
+    fn remove_encoded_named_graph(&mut self, graph_name: EncodedTerm) -> Result<(), StoreError> {
+        self.ensure_writable(graph_name)?;
+        let g = graph_name.term_id();
+        if <GraphNameStore<T>>::contains_key(g) {
+            self.rule_changes.borrow_mut().record_cleared();
+            self.shape_changes.borrow_mut().record_cleared();
+            drop_graph_quads::<T>(self, g)?;
+            // remove graph name
+            release_strs::<T>(self, graph_name);
+            <GraphNameStore<T>>::remove(g);
         }
-        // remove graph name
-        <GraphNameStore<T>>::remove(graph_name.to_bounded_vec());
         Ok(())
     }
 
     fn clear(&mut self) -> Result<(), Self::Error> {
-        // clear graph names
-        <GraphNameStore<T>>::remove_all(None);
-        // clear id/string
-        <Id2StrStore<T>>::remove_all(None);
+        // the removals are charged to the meter: an update fails instead of clearing more entries than its weight
+        // pays for. A signed update does not clear the protected graphs
+        for graph in protected_graphs::<T>() {
+            if let Some(graph_name) = self.get_encoded_named_node(NamedNodeRef::new_unchecked(graph))? {
+                if <GraphNameStore<T>>::contains_key(graph_name.term_id()) {
+                    self.ensure_writable(graph_name)?;
+                }
+            }
+        }
+        self.base_changes.borrow_mut().clear();
+        self.rule_changes.borrow_mut().clear();
+        self.shape_changes.borrow_mut().clear();
+        // clear named graphs: their tries are removed in batches, without their strings which are removed below
+        for (g, count) in <GraphNameStore<T>>::iter().collect::<Vec<_>>() {
+            self.meter.charge::<T>(1, 0)?;
+            if count > 0 {
+                self.meter.charge::<T>(1, 2)?;
+                start_graph_generation::<T>(g, false);
+            }
+        }
+        for key in <GraphPurgeStore<T>>::iter_keys().collect::<Vec<_>>() {
+            self.meter.charge::<T>(1, 1)?;
+            <GraphPurgeStore<T>>::insert(key, false);
+        }
+        remove_all_charged::<T, GraphNameStore<T>, _>(self)?;
+        remove_all_charged::<T, TermGraphStore<T>, _>(self)?;
+        // clear id/string and id/term
+        remove_all_charged::<T, Id2StrStore<T>, _>(self)?;
+        remove_all_charged::<T, StrRefCountStore<T>, _>(self)?;
+        remove_all_charged::<T, Id2TermStore<T>, _>(self)?;
+        remove_all_charged::<T, TermRefCountStore<T>, _>(self)?;
         // clear default graph
-        <DefaultSpoStore<T>>::remove_all(None);
-        <DefaultPosStore<T>>::remove_all(None);
-        <DefaultOspStore<T>>::remove_all(None);
-        // clear named graph
-        <GspoStore<T>>::remove_all(None);
-        <GposStore<T>>::remove_all(None);
-        <GospStore<T>>::remove_all(None);
-        <SpogStore<T>>::remove_all(None);
-        <PosgStore<T>>::remove_all(None);
-        <OspgStore<T>>::remove_all(None);
+        remove_all_charged::<T, DefaultSpoStore<T>, _>(self)?;
+        remove_all_charged::<T, DefaultPosStore<T>, _>(self)?;
+        remove_all_charged::<T, DefaultOspStore<T>, _>(self)?;
+        // clear statistics
+        self.meter.charge::<T>(0, 2)?;
+        <QuadCountStore<T>>::kill();
+        <DefaultGraphQuadCountStore<T>>::kill();
+        remove_all_charged::<T, PredicateCountStore<T>, _>(self)?;
+        remove_all_charged::<T, DefaultGraphPredicateCountStore<T>, _>(self)?;
+        remove_all_charged::<T, PredicateClassCountStore<T>, _>(self)?;
+        // clear ordered literal index
+        remove_all_charged::<T, OrderedLiteralStore<T>, _>(self)?;
+        // clear owl:sameAs classes
+        remove_all_charged::<T, SameAsStore<T>, _>(self)?;
+        remove_all_charged::<T, SameAsMemberStore<T>, _>(self)?;
+        // clear full-text index
+        remove_all_charged::<T, TextIndexStore<T>, _>(self)?;
+        // clear spatial index
+        remove_all_charged::<T, SpatialIndexStore<T>, _>(self)?;
+        // clear vector index
+        remove_all_charged::<T, VectorNodeStore<T>, _>(self)?;
+        remove_all_charged::<T, VectorEdgeStore<T>, _>(self)?;
+        remove_all_charged::<T, VectorEntryStore<T>, _>(self)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{new_test_ext, Graphdb, Origin, Test};
+
+    /// An update inserting `count` quads in the default graph
+    fn insert_data(from: usize, count: usize) -> Vec<u8> {
+        let triples: Vec<String> = (from..from + count).map(|i| format!(":s{} :p {} .", i, i)).collect();
+        format!("INSERT DATA {{ {} }}", triples.join(" ")).into_bytes()
+    }
+
+    #[test]
+    fn an_update_is_charged_for_its_writes() {
+        new_test_ext().execute_with(|| {
+            let small = Graphdb::sparql_update(Origin::signed(1), insert_data(0, 1)).unwrap();
+            let large = Graphdb::sparql_update(Origin::signed(1), insert_data(1, 10)).unwrap();
+            let (small, large) = (small.actual_weight.unwrap(), large.actual_weight.unwrap());
+            assert!(small < large);
+            assert!(large < <Test as Config>::MaxUpdateWeight::get());
+        });
+    }
+
+    #[test]
+    fn an_update_heavier_than_the_limit_is_reverted() {
+        new_test_ext().execute_with(|| {
+            let error = Graphdb::sparql_update(Origin::signed(1), insert_data(0, 1000)).unwrap_err();
+            assert_eq!(error.error, Error::<Test>::UpdateTooHeavy.into());
+            assert_eq!(error.post_info.actual_weight, Some(<Test as Config>::MaxUpdateWeight::get()));
+            assert_eq!(<QuadCountStore<Test>>::get(), 0);
+        });
+    }
+
+    #[test]
+    fn a_term_is_removed_with_its_last_reference() {
+        new_test_ext().execute_with(|| {
+            let quad = ":a :p \"a literal longer than a term id\"@en";
+            let update = format!("INSERT DATA {{ {} . GRAPH :g {{ {} }} }}", quad, quad);
+            Graphdb::sparql_update(Origin::signed(1), update.into_bytes()).unwrap();
+            let hashes: Vec<u128> = <Id2TermStore<Test>>::iter_keys().collect();
+            assert_eq!(hashes.len(), 1);
+            assert_eq!(<TermRefCountStore<Test>>::get(hashes[0]), Some(2));
+
+            Graphdb::sparql_update(Origin::signed(1), format!("DELETE DATA {{ {} }}", quad).into_bytes()).unwrap();
+            assert_eq!(<TermRefCountStore<Test>>::get(hashes[0]), Some(1));
+            let update = format!("DELETE DATA {{ GRAPH :g {{ {} }} }}", quad);
+            Graphdb::sparql_update(Origin::signed(1), update.into_bytes()).unwrap();
+            assert!(!<TermRefCountStore<Test>>::contains_key(hashes[0]));
+            assert!(!<Id2TermStore<Test>>::contains_key(hashes[0]));
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn a_term_colliding_with_another_is_rejected() {
+        new_test_ext().execute_with(|| {
+            let quad = ":a :p \"a literal longer than a term id\"@en";
+            Pallet::<Test>::execute_update(format!("INSERT DATA {{ {} }}", quad)).unwrap();
+            let hash = <Id2TermStore<Test>>::iter_keys().next().unwrap();
+            // another term with the same hash
+            <Id2TermStore<Test>>::insert(hash, BoundedVec::try_from(vec![1, 2, 3]).unwrap());
+
+            let update = format!("INSERT DATA {{ GRAPH :g {{ {} }} }}", quad);
+            let error = Pallet::<Test>::execute_update(update).unwrap_err();
+            assert!(error.to_string().contains(&format!("{:032x}", hash)));
+            assert_eq!(<QuadCountStore<Test>>::get(), 1);
+        });
+    }
+
+    #[test]
+    fn the_statistics_are_unchanged_by_a_rejected_insertion() {
+        new_test_ext().execute_with(|| {
+            let quad = ":a :p \"a literal longer than a term id\"@en";
+            Pallet::<Test>::execute_update(format!("INSERT DATA {{ {} }}", quad)).unwrap();
+            let hash = <Id2TermStore<Test>>::iter_keys().next().unwrap();
+            <Id2TermStore<Test>>::insert(hash, BoundedVec::try_from(vec![1, 2, 3]).unwrap());
+            let counts = || {
+                let mut predicates: Vec<_> = <PredicateCountStore<Test>>::iter().collect();
+                predicates.sort();
+                let mut classes: Vec<_> = <PredicateClassCountStore<Test>>::iter().collect();
+                classes.sort();
+                (predicates, classes, <QuadCountStore<Test>>::get(), <DefaultGraphQuadCountStore<Test>>::get())
+            };
+            let before = counts();
+
+            let update = format!("INSERT DATA {{ GRAPH :g {{ {} }} }}", quad);
+            assert!(Pallet::<Test>::execute_update(update).is_err());
+            assert_eq!(counts(), before);
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) WHERE { GRAPH ?g { ?s :p ?o } }"), 0);
+        });
+    }
+
+    #[test]
+    fn clearing_a_large_default_graph_is_bounded() {
+        new_test_ext().execute_with(|| {
+            Graphdb::sparql_update(Origin::signed(1), insert_data(0, 400)).unwrap();
+            Graphdb::sparql_update(Origin::signed(1), insert_data(400, 400)).unwrap();
+            let error = Graphdb::sparql_update(Origin::signed(1), b"CLEAR DEFAULT".to_vec()).unwrap_err();
+            assert_eq!(error.error, Error::<Test>::UpdateTooHeavy.into());
+            assert_eq!(<DefaultGraphQuadCountStore<Test>>::get(), 800);
+
+            // a smaller default graph is cleared
+            Graphdb::sparql_update(Origin::signed(1), b"DELETE { ?s :p ?o } WHERE { ?s :p ?o . FILTER(?o >= 400) }".to_vec())
+                .unwrap();
+            Graphdb::sparql_update(Origin::signed(1), b"CLEAR DEFAULT".to_vec()).unwrap();
+            assert_eq!(<DefaultGraphQuadCountStore<Test>>::get(), 0);
+        });
+    }
+
+    #[test]
+    fn clearing_everything_is_charged_for_its_removals() {
+        new_test_ext().execute_with(|| {
+            let clear_all = || Graphdb::sparql_update(Origin::signed(1), b"CLEAR ALL".to_vec());
+            Graphdb::sparql_update(Origin::signed(1), insert_data(0, 1)).unwrap();
+            let small = clear_all().unwrap().actual_weight.unwrap();
+            Graphdb::sparql_update(Origin::signed(1), insert_data(0, 20)).unwrap();
+            let large = clear_all().unwrap().actual_weight.unwrap();
+            // the SPO, POS and OSP entries of the other quads at least
+            let writes = <Test as frame_system::Config>::DbWeight::get().writes(3 * 19);
+            assert!(large >= small + writes, "{} < {} + {}", large, small, writes);
+
+            Graphdb::sparql_update(Origin::signed(1), insert_data(0, 20)).unwrap();
+            let store = GraphStore::<Test>::with_weight_limit(writes, Access::Root);
+            assert!(store.update("CLEAR ALL").is_err());
+            assert!(store.meter.is_exhausted());
+        });
+    }
+
+    #[test]
+    fn a_limited_query_reads_only_the_quads_it_returns() {
+        new_test_ext().execute_with(|| {
+            Graphdb::sparql_update(Origin::signed(1), insert_data(0, 100)).unwrap();
+            let limit = <Test as frame_system::Config>::DbWeight::get().reads(20);
+            let solutions = |query: &str| -> Result<usize, EvaluationError> {
+                let store = GraphStore::<Test>::with_weight_limit(limit, Access::Root);
+                match store.query(format!("{}{}", PREFIX, query).as_str())? {
+                    QueryResults::Solutions(solutions) => Ok(solutions.collect::<Result<Vec<_>, _>>()?.len()),
+                    _ => unreachable!(),
+                }
+            };
+            // the quads are read from the storage as the solutions are consumed
+            assert_eq!(solutions("SELECT ?s WHERE { ?s :p ?o } LIMIT 3").unwrap(), 3);
+            assert!(solutions("SELECT ?s WHERE { ?s :p ?o }").is_err());
+        });
+    }
+
+    #[test]
+    fn the_lookups_scan_the_required_indexes_when_the_others_are_disabled() {
+        new_test_ext().execute_with(|| {
+            crate::mock::QuadIndexes::set(&QuadIndexes::REQUIRED);
+            Pallet::<Test>::execute_update("INSERT DATA { :a :p :b . :b :q :c . GRAPH :g { :a :p :c . :c :q :b } }")
+                .unwrap();
+            assert_eq!(<DefaultPosStore<Test>>::iter_keys().count(), 0);
+            assert_eq!(<DefaultOspStore<Test>>::iter_keys().count(), 0);
+            let entity = |name: &str| format!("\"http://relationlabs.ai/entity/{}\"", name);
+
+            // by object and by predicate, in the default graph then in the named graph
+            let found = Pallet::<Test>::execute_query("SELECT ?s WHERE { ?s ?p :b }");
+            assert!(found.contains(&entity("a")) && !found.contains(&entity("c")));
+            let found = Pallet::<Test>::execute_query("SELECT ?s WHERE { ?s :q ?o }");
+            assert!(found.contains(&entity("b")) && !found.contains(&entity("c")));
+            let found = Pallet::<Test>::execute_query("SELECT ?s WHERE { GRAPH :g { ?s ?p :b } }");
+            assert!(found.contains(&entity("c")) && !found.contains(&entity("a")));
+            let found = Pallet::<Test>::execute_query("SELECT ?o WHERE { GRAPH ?g { ?s :p ?o } }");
+            assert!(found.contains(&entity("c")) && !found.contains(&entity("b")));
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn a_corrupted_term_fails_the_query_instead_of_panicking() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(
+                "INSERT DATA { :a :p \"a literal longer than a term id\"@en . <http://example.org/x> :q 1 }",
+            )
+            .unwrap();
+            let solutions = |query: &str| -> Result<usize, EvaluationError> {
+                let store = GraphStore::<Test>::new();
+                match store.query(format!("{}{}", PREFIX, query).as_str())? {
+                    QueryResults::Solutions(solutions) => Ok(solutions.collect::<Result<Vec<_>, _>>()?.len()),
+                    _ => unreachable!(),
+                }
+            };
+            assert_eq!(solutions("SELECT ?o WHERE { :a :p ?o }").unwrap(), 1);
+
+            // an encoding which is not a term, then a missing one
+            let hash = <Id2TermStore<Test>>::iter_keys().next().unwrap();
+            <Id2TermStore<Test>>::insert(hash, BoundedVec::try_from(vec![]).unwrap());
+            assert!(solutions("SELECT ?o WHERE { :a :p ?o }").is_err());
+            <Id2TermStore<Test>>::remove(hash);
+            assert!(solutions("SELECT ?o WHERE { :a :p ?o }").is_err());
+
+            // strings which are not UTF-8
+            assert_eq!(solutions("SELECT ?s WHERE { ?s ?p 1 }").unwrap(), 1);
+            for id in <Id2StrStore<Test>>::iter_keys().collect::<Vec<_>>() {
+                <Id2StrStore<Test>>::insert(id, BoundedVec::try_from(vec![0xff, 0xfe]).unwrap());
+            }
+            assert!(solutions("SELECT ?s WHERE { ?s ?p 1 }").is_err());
+        });
+    }
+
+    #[test]
+    fn the_custom_functions_and_aggregates_are_evaluated() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a :v 1, 2, 3 . :b :v 4 }").unwrap();
+            let integer = |term: &Term| match term {
+                Term::Literal(literal) => literal.value().parse::<i64>().ok(),
+                _ => None,
+            };
+            let double = NamedNode::new_unchecked("http://relationlabs.ai/entity/double");
+            let product = NamedNode::new_unchecked("http://relationlabs.ai/entity/product");
+            let options = QueryOptions::default()
+                .with_custom_function(double, move |arguments| {
+                    Some(Literal::from(integer(arguments.first()?)? * 2).into())
+                })
+                .with_custom_aggregate(product.clone(), move |values| {
+                    let product = values.iter().try_fold(1, |product, value| Some(product * integer(value)?))?;
+                    Some(Literal::from(product).into())
+                });
+            let values = |query: &str, options: &QueryOptions| -> Vec<(Option<Term>, Option<Term>)> {
+                let query = Query::parse_with_options(&format!("{}{}", PREFIX, query), None, options).unwrap();
+                match GraphStore::<Test>::new().query_with_options(query, options.clone()).unwrap() {
+                    QueryResults::Solutions(solutions) => solutions
+                        .map(|solution| {
+                            let solution = solution.unwrap();
+                            (solution.get("n").cloned(), solution.get("c").cloned())
+                        })
+                        .collect(),
+                    _ => unreachable!(),
+                }
+            };
+            let integers = |values: Vec<(Option<Term>, Option<Term>)>| -> Vec<(Option<i64>, Option<i64>)> {
+                values.iter().map(|(n, c)| (n.as_ref().and_then(integer), c.as_ref().and_then(integer))).collect()
+            };
+
+            let found = values("SELECT (:double(?o) AS ?n) WHERE { :b :v ?o }", &options);
+            assert_eq!(integers(found), vec![(Some(8), None)]);
+            // the built-in aggregates are still parsed next to the custom ones
+            let found = values("SELECT (:product(?o) AS ?n) (COUNT(?o) AS ?c) WHERE { :a :v ?o }", &options);
+            assert_eq!(integers(found), vec![(Some(6), Some(3))]);
+            let found = values("SELECT ?s (:product(DISTINCT ?o) AS ?n) WHERE { ?s :v ?o } GROUP BY ?s", &options);
+            let mut found = integers(found);
+            found.sort();
+            assert_eq!(found, vec![(Some(4), None), (Some(6), None)]);
+
+            // without the registration the call is an unknown function, evaluated per solution
+            let found = values("SELECT (:product(?o) AS ?n) WHERE { :a :v ?o }", &QueryOptions::default());
+            assert_eq!(integers(found), vec![(None, None); 3]);
+            let found = Pallet::<Test>::execute_query_with_options(
+                "SELECT (:product(?o) AS ?n) WHERE { :a :v ?o }",
+                QueryOptions::default()
+                    .with_custom_aggregate(product, |values| Some(Literal::from(values.len() as i64).into())),
+            );
+            assert!(found.contains("\"value\":\"3\""));
+        });
+    }
+
+    /// The count `?n` of the first solution of a query
+    fn query_count(query: &str) -> u64 {
+        let results = Pallet::<Test>::execute_query(query);
+        let value = results.split("\"value\":\"").nth(1).expect("the query has no solution");
+        value[..value.find('"').unwrap()].parse().unwrap()
+    }
+
+    #[test]
+    fn a_count_is_read_from_the_statistics() {
+        new_test_ext().execute_with(|| {
+            let update = "INSERT DATA { :a :p 1, 2, 3 . :b :q 4 . GRAPH :g { :a :p 5 . :c :p 6 . :c :r 7 } \
+                GRAPH :h { :a :p 8 } }";
+            Pallet::<Test>::execute_update(update).unwrap();
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) WHERE { ?s ?p ?o }"), 4);
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) WHERE { ?s :p ?o }"), 3);
+            assert_eq!(query_count("SELECT (COUNT(?o) AS ?n) WHERE { GRAPH :g { ?s :p ?o } }"), 2);
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) WHERE { GRAPH ?g { ?s :p ?o } }"), 3);
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) WHERE { GRAPH ?g { ?s ?p ?o } }"), 4);
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) FROM :g WHERE { ?s :p ?o }"), 2);
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) FROM :g FROM :h WHERE { ?s :p ?o }"), 3);
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) FROM NAMED :h WHERE { GRAPH ?g { ?s :p ?o } }"), 1);
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) FROM NAMED :h WHERE { GRAPH :g { ?s :p ?o } }"), 0);
+
+            // the counts of the default graph are not read from its quads
+            let p = <DefaultGraphPredicateCountStore<Test>>::iter().find(|(_, count)| *count == 3).unwrap().0;
+            <DefaultGraphPredicateCountStore<Test>>::insert(p, 42);
+            assert_eq!(query_count("SELECT (COUNT(*) AS ?n) WHERE { ?s :p ?o }"), 42);
+        });
+    }
+}
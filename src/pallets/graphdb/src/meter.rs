@@ -0,0 +1,89 @@
+//! Metering of the work done on the store by an extrinsic, so that it is charged for the work it does.
+//!
+//! The lookups count a read per quad they yield, the writes count an estimate of the entries a quad
+//! writes. Once the weight of the meter exceeds its limit, the lookups and the writes fail: an update
+//! cannot do more work than the weight its extrinsic declared.
+
+use std::cell::Cell;
+
+use frame_support::pallet_prelude::*;
+
+use crate::Config;
+use crate::error::StoreError;
+
+/// Estimate of the reads of writing or removing a quad: the quad itself, its strings and terms, the statistics
+/// and the graphs of its terms
+pub(crate) const QUAD_WRITE_READS: u64 = 14;
+/// Estimate of the writes of writing or removing a quad: up to eighteen index entries, counts and references
+pub(crate) const QUAD_WRITE_WRITES: u64 = 18;
+
+/// The storage reads and writes of an update of a secondary index, see `crate::text`, `crate::spatial` and
+/// `crate::hnsw`
+pub(crate) type Accesses = (u64, u64);
+
+/// The storage reads and writes done through a `GraphStore`, and the weight of the other work
+#[derive(Debug, Default)]
+pub(crate) struct Meter {
+    reads: Cell<u64>,
+    writes: Cell<u64>,
+    /// Weight of the work that does not access the storage
+    other: Cell<Weight>,
+    /// The weight the evaluation must not exceed, `None` for the queries of the runtime API
+    limit: Option<Weight>,
+    exhausted: Cell<bool>,
+}
+
+impl Meter {
+    pub fn with_limit(limit: Weight) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::default()
+        }
+    }
+
+    /// Counts storage accesses, fails once the limit is exceeded
+    pub fn charge<T: Config>(&self, reads: u64, writes: u64) -> Result<(), StoreError> {
+        self.reads.set(self.reads.get().saturating_add(reads));
+        self.writes.set(self.writes.get().saturating_add(writes));
+        self.check::<T>()
+    }
+
+    /// Counts the weight of work that does not access the storage, fails once the limit is exceeded
+    pub fn charge_weight<T: Config>(&self, weight: Weight) -> Result<(), StoreError> {
+        self.other.set(self.other.get().saturating_add(weight));
+        self.check::<T>()
+    }
+
+    /// The weight of the work counted so far
+    pub fn weight<T: Config>(&self) -> Weight {
+        T::DbWeight::get()
+            .reads_writes(self.reads.get(), self.writes.get())
+            .saturating_add(self.other.get())
+    }
+
+    /// How many times `weight` fits in the weight left to the evaluation, `None` without a limit
+    pub fn remaining<T: Config>(&self, weight: Weight) -> Option<u64> {
+        self.limit.map(|limit| limit.saturating_sub(self.weight::<T>()) / weight.max(1))
+    }
+
+    /// Fails the evaluation for work the weight left does not pay for
+    pub fn exhaust(&self) -> StoreError {
+        self.exhausted.set(true);
+        StoreError::WeightExhausted { limit: self.limit.unwrap_or_default() }
+    }
+
+    /// Whether the evaluation failed because it exceeded the limit
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.get()
+    }
+
+    fn check<T: Config>(&self) -> Result<(), StoreError> {
+        match self.limit {
+            Some(limit) if self.weight::<T>() > limit => {
+                self.exhausted.set(true);
+                Err(StoreError::WeightExhausted { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,1536 @@
+//! Storage migrations of the graph database pallet, to be added to the runtime `Executive`.
+//!
+//! The migrations changing the layout of the keys (`v1` and `v2`) move the quads of the older layout aside, then
+//! insert them again with the current layout and term encoding (see `LegacyLayout`), in batches across blocks.
+//!
+//! A change of the term encoding (see `CODEC_VERSION`) does not change the layout: `ReEncodeTerms`
+//! re-encodes the keys in batches across blocks instead. The statistics and the references to the strings and
+//! to the terms are then recounted from the quads, also in batches, which the migrations adding counts start on
+//! their own (see `start_recount`). The migrations adding a secondary index or the quad counts start its
+//! rebuild from the quads instead (see `RepairIndexes`): an upgrade does not read the quads itself.
+
+use std::collections::BTreeMap;
+
+use frame_support::pallet_prelude::*;
+use frame_support::storage::{storage_prefix, unhashed};
+use frame_support::traits::OnRuntimeUpgrade;
+use sp_io::KillStorageResult;
+
+use crate::{
+    count_term_graphs, decode_term_id, insert_quad_entries, insert_term, insert_terms, remove_quad_entries, retain_strs,
+    CodecVersionStore, Config, DefaultGraphPredicateCountStore, DefaultSpoStore, GraphGenerationStore, GraphNameStore,
+    GraphPurgeStore, GraphStore, Id2StrStore, Id2TermStore, Pallet, PredicateClassCountStore, PredicateCountStore,
+    ReEncodingCursorStore, StrRefCountStore, TermGraphStore, TermRefCountStore,
+};
+use crate::error::StoreError;
+use crate::graph_trie::{GraphTrie, TAG_SPO};
+use crate::meter::{QUAD_WRITE_READS, QUAD_WRITE_WRITES};
+use crate::store::WritableEncodedStore;
+use crate::store::numeric_encoder::{Decoder, WriteEncoder};
+use crate::store::codec::{EncodedQuad, EncodedTerm, TermId, CODEC_VERSION};
+
+/// The value of `result`, or `None` once the error is logged: the migrations skip the invalid items
+fn skip_invalid<V, E: Into<StoreError>>(result: Result<V, E>) -> Option<V> {
+    result.map_err(|error| warn_skipped(&error.into())).ok()
+}
+
+fn warn_skipped(error: &StoreError) {
+    frame_support::log::warn!(target: "runtime::graphdb", "skipped during the migration: {}", error);
+}
+
+/// A layout of the keys older than the current one, migrated in batches across blocks: the graph names and the
+/// quads are moved out of the maps of the current layout (see `STAGING_MAPS`), the other maps are cleared, then
+/// the moved entries are decoded and inserted again.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub enum LegacyLayout {
+    /// Version 0: the terms of the keys are SCALE encoded (see `v1`)
+    V0,
+    /// Version 1: the keys are term ids and the named graphs are stored in maps (see `v2`)
+    V1,
+}
+
+/// The maps the graph names, the quads of the default graph and the quads of the named graphs of an older layout
+/// are moved to, in the order they are inserted again
+const STAGING_MAPS: [&[u8]; 3] = [b"LegacyGraphNameStore", b"LegacyDefaultGraphStore", b"LegacyNamedGraphStore"];
+
+/// The maps written by the insertion of the quads, removed before the quads of an older layout are inserted:
+/// the indexes of the older layouts, the statistics and the secondary indexes
+const REBUILT_MAPS: [&[u8]; 26] = [
+    b"DefaultSpoStore",
+    b"DefaultPosStore",
+    b"DefaultOspStore",
+    b"GspoStore",
+    b"GposStore",
+    b"GospStore",
+    b"SpogStore",
+    b"PosgStore",
+    b"OspgStore",
+    b"GraphNameStore",
+    b"PredicateCountStore",
+    b"PredicateClassCountStore",
+    b"OrderedLiteralStore",
+    b"StrRefCountStore",
+    b"QuadCountStore",
+    b"DefaultGraphQuadCountStore",
+    b"SameAsStore",
+    b"SameAsMemberStore",
+    b"TextIndexStore",
+    b"SpatialIndexStore",
+    b"VectorNodeStore",
+    b"VectorEdgeStore",
+    b"VectorEntryStore",
+    b"TermGraphStore",
+    b"TermRefCountStore",
+    b"DefaultGraphPredicateCountStore",
+];
+
+impl LegacyLayout {
+    /// The maps of the layout holding the graph names, the quads of the default graph and the quads of the
+    /// named graphs, moved to `STAGING_MAPS`
+    fn moved_maps(self) -> [&'static [u8]; 3] {
+        match self {
+            LegacyLayout::V0 => [b"GraphNameStore", b"DefaultSpoStore", b"SpogStore"],
+            LegacyLayout::V1 => [b"GraphNameStore", b"DefaultSpoStore", b"GspoStore"],
+        }
+    }
+
+    /// Decodes the terms of a key of the layout, without the prefix of its map
+    fn decode_key<T: Config>(self, key: &[u8]) -> Result<Vec<EncodedTerm>, StoreError> {
+        match self {
+            LegacyLayout::V0 => v1::decode_v0_key(key),
+            LegacyLayout::V1 => v2::decode_v1_key::<T>(key),
+        }
+    }
+}
+
+/// Starts migrating the keys of an older layout. The keys are written with the current layout and term encoding:
+/// the storage version and the version of the term encoding are the current ones from the start, so that the
+/// later migrations do nothing, and the store is not available until the keys are migrated.
+fn start_layout_migration<T: Config>(layout: LegacyLayout) -> Weight {
+    <ReEncodingCursorStore<T>>::put(ReEncodingCursor { step: ReEncodingStep::StageLegacyKeys(layout), after: None });
+    <CodecVersionStore<T>>::put(CODEC_VERSION);
+    Pallet::<T>::current_storage_version().put::<Pallet<T>>();
+    T::DbWeight::get().reads_writes(1, 3)
+}
+
+/// The prefix of the keys of the map `name` of the pallet
+fn map_prefix<T: Config>(name: &[u8]) -> [u8; 32] {
+    storage_prefix(<Pallet<T> as PalletInfoAccess>::name().as_bytes(), name)
+}
+
+/// The first `limit` raw keys starting with `prefix`
+fn first_keys(prefix: &[u8], limit: usize) -> Vec<Vec<u8>> {
+    let mut keys = Vec::new();
+    let mut key = prefix.to_vec();
+    while keys.len() < limit {
+        match sp_io::storage::next_key(&key) {
+            Some(next) if next.starts_with(prefix) => {
+                keys.push(next.clone());
+                key = next;
+            }
+            _ => break,
+        }
+    }
+    keys
+}
+
+/// A step of the re-encoding of the keys, in the order they are done
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub enum ReEncodingStep {
+    /// The keys of `PredicateCountStore`
+    PredicateCounts,
+    /// The keys of `PredicateClassCountStore`
+    PredicateClassCounts,
+    /// The quads of the default graph, read from `DefaultSpoStore`
+    DefaultGraph,
+    /// The statistics of a named graph by predicate
+    GraphPredicateCounts(TermId),
+    /// The statistics of a named graph by predicate and class
+    GraphPredicateClassCounts(TermId),
+    /// The quads of a named graph, read from its `SPO` index
+    GraphQuads(TermId),
+    /// The statistics and the references recounted from the quads are removed
+    ClearCounts,
+    /// The quads of the default graph are counted and retain their strings and terms, read from `DefaultSpoStore`
+    CountDefaultGraph,
+    /// The statistics of a named graph, kept in its child trie, are added to the ones of the store
+    CountGraph(TermId),
+    /// The graph names and the quads of an older layout are moved to `STAGING_MAPS`
+    StageLegacyKeys(LegacyLayout),
+    /// The maps written by the insertion of the quads are removed (see `REBUILT_MAPS`)
+    ClearRebuiltMaps(LegacyLayout),
+    /// The moved graph names and quads are decoded and inserted with the current layout
+    InsertLegacyKeys(LegacyLayout),
+    /// The strings no longer referenced are removed from `Id2StrStore`
+    CollectStrs,
+    /// The quads of a named graph using each term are counted in `TermGraphStore`, and retain their strings
+    /// and terms
+    CountGraphTerms(TermId),
+    /// The graph names retain their strings and terms
+    CountGraphNames,
+    /// The terms no longer referenced are removed from `Id2TermStore`
+    CollectTerms,
+}
+
+impl ReEncodingStep {
+    fn is_recount(&self) -> bool {
+        matches!(
+            self,
+            ReEncodingStep::ClearCounts
+                | ReEncodingStep::CountDefaultGraph
+                | ReEncodingStep::CountGraph(_)
+                | ReEncodingStep::CountGraphTerms(_)
+                | ReEncodingStep::CountGraphNames
+                | ReEncodingStep::CollectStrs
+                | ReEncodingStep::CollectTerms
+        )
+    }
+}
+
+/// Recounts the statistics and the references from the quads in batches, once the steps of an ongoing
+/// re-encoding are done (or from the start if they are being recounted).
+fn start_recount<T: Config>() {
+    match <ReEncodingCursorStore<T>>::get() {
+        Some(cursor) if !cursor.step.is_recount() => (),
+        _ => <ReEncodingCursorStore<T>>::put(ReEncodingCursor { step: ReEncodingStep::ClearCounts, after: None }),
+    }
+}
+
+/// The named graph after `after` in `GraphNameStore`, the first one without `after`
+fn next_graph_name<T: Config>(after: Option<&TermId>) -> Option<TermId> {
+    match after {
+        Some(g) => <GraphNameStore<T>>::iter_keys_from(<GraphNameStore<T>>::hashed_key_for(g)).next(),
+        None => <GraphNameStore<T>>::iter_keys().next(),
+    }
+}
+
+/// Progress of the re-encoding of the keys: the current step and the key of the last entry it re-encoded
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+pub struct ReEncodingCursor {
+    pub step: ReEncodingStep,
+    /// Raw storage key, or key of the child trie of the graph
+    pub after: Option<Vec<u8>>,
+}
+
+/// Re-encodes the keys written with an older term encoding (see `CODEC_VERSION`) with the current one,
+/// `Config::ReEncodingBatchSize` keys at the start of each block.
+///
+/// The upgrade only starts the re-encoding (from the first key if it was already ongoing) and the store
+/// can neither be queried nor updated until it is done. The statistics are re-keyed first, while the strings
+/// of the older ids are still referenced by the quads, then the quads are moved with all their index entries,
+/// then the statistics and the references are recounted and the strings and terms of the older ids removed.
+/// It stays in the runtime `Executive`: it does nothing while the encoding of the keys is the current one.
+///
+/// The batches also migrate the keys of an older layout (see `LegacyLayout`), with the same cursor.
+pub struct ReEncodeTerms<T>(PhantomData<T>);
+
+impl<T: Config> OnRuntimeUpgrade for ReEncodeTerms<T> {
+    fn on_runtime_upgrade() -> Weight {
+        let version = <CodecVersionStore<T>>::get();
+        // zero for an empty store
+        if version == 0 || version >= CODEC_VERSION {
+            return T::DbWeight::get().reads(1);
+        }
+        // a key re-encoded twice is not changed
+        <ReEncodingCursorStore<T>>::put(ReEncodingCursor { step: ReEncodingStep::PredicateCounts, after: None });
+        T::DbWeight::get().reads_writes(1, 1)
+    }
+
+    #[cfg(feature = "try-runtime")]
+    fn pre_upgrade() -> Result<(), &'static str> {
+        checks::record_quad_count::<T, Self>();
+        Ok(())
+    }
+
+    #[cfg(feature = "try-runtime")]
+    fn post_upgrade() -> Result<(), &'static str> {
+        let version = <CodecVersionStore<T>>::get();
+        ensure!(
+            version == 0 || version == CODEC_VERSION || <ReEncodingCursorStore<T>>::exists(),
+            "the keys of an older term encoding are not re-encoded"
+        );
+        checks::check_upgraded::<T, Self>()
+    }
+}
+
+impl<T: Config> ReEncodeTerms<T> {
+    /// Re-encodes the next batch of keys, if the re-encoding is ongoing (called by `on_initialize`)
+    pub fn reencode_batch() -> Weight {
+        let mut cursor = match <ReEncodingCursorStore<T>>::get() {
+            Some(cursor) => cursor,
+            None => return T::DbWeight::get().reads(1),
+        };
+        let mut batch = ReEncodingBatch::<T>::new(T::ReEncodingBatchSize::get());
+        loop {
+            if !batch.run_step(&mut cursor) {
+                <ReEncodingCursorStore<T>>::put(cursor);
+                break;
+            }
+            match batch.next_step(&cursor.step) {
+                Some(step) => cursor = ReEncodingCursor { step, after: None },
+                None => {
+                    <ReEncodingCursorStore<T>>::kill();
+                    frame_support::log::info!(target: "runtime::graphdb", "the store is migrated");
+                    break;
+                }
+            }
+        }
+        // the strings and the terms released by the moved quads are removed by `CollectStrs` and `CollectTerms` once
+        // the references are recounted: the references of a store being migrated may not be counted yet
+
+        // estimate: a moved quad reads its strings, the term encodings and the statistics, and writes up to
+        // eighteen entries
+        T::DbWeight::get().reads_writes(
+            2 + batch.entries as u64 + batch.terms.len() as u64 * 2 + batch.moved as u64 * QUAD_WRITE_READS,
+            1 + batch.moved as u64 * QUAD_WRITE_WRITES + batch.written as u64,
+        )
+    }
+}
+
+/// The keys re-encoded in a block
+struct ReEncodingBatch<T> {
+    store: GraphStore<T>,
+    /// Number of entries that may still be read
+    remaining: u32,
+    entries: u32,
+    moved: u32,
+    /// Number of entries written or removed besides the moved quads: the statistics, the staged keys and the strings
+    written: u32,
+    /// The re-encoded ids: the term decoded with its older encoding and the term encoded with the current one
+    terms: BTreeMap<TermId, (EncodedTerm, EncodedTerm)>,
+}
+
+impl<T: Config> ReEncodingBatch<T> {
+    fn new(size: u32) -> Self {
+        Self {
+            store: GraphStore::new(),
+            remaining: size.max(1),
+            entries: 0,
+            moved: 0,
+            written: 0,
+            terms: BTreeMap::new(),
+        }
+    }
+
+    /// Re-encodes the entries of the step after the cursor, returns `true` once the step is done.
+    ///
+    /// The entries are read before they are re-encoded, as the storage must not change while it is iterated.
+    fn run_step(&mut self, cursor: &mut ReEncodingCursor) -> bool {
+        let limit = self.remaining as usize;
+        let after = cursor.after.take();
+        let read = match cursor.step {
+            ReEncodingStep::PredicateCounts => {
+                let entries: Vec<(TermId, u64)> = match after {
+                    Some(after) => <PredicateCountStore<T>>::iter_from(after).take(limit).collect(),
+                    None => <PredicateCountStore<T>>::iter().take(limit).collect(),
+                };
+                for (p, count) in entries.iter() {
+                    if let Some(new_p) = self.reencode_id(p) {
+                        if new_p != *p {
+                            <PredicateCountStore<T>>::remove(p);
+                            <PredicateCountStore<T>>::mutate(new_p, |value| *value = Some(value.unwrap_or(0) + count));
+                        }
+                    }
+                }
+                cursor.after = entries.last().map(|(p, _)| <PredicateCountStore<T>>::hashed_key_for(p));
+                entries.len()
+            }
+            ReEncodingStep::PredicateClassCounts => {
+                let entries: Vec<((TermId, TermId), u64)> = match after {
+                    Some(after) => <PredicateClassCountStore<T>>::iter_from(after).take(limit).collect(),
+                    None => <PredicateClassCountStore<T>>::iter().take(limit).collect(),
+                };
+                for ((p, class), count) in entries.iter() {
+                    if let (Some(new_p), Some(new_class)) = (self.reencode_id(p), self.reencode_id(class)) {
+                        if (new_p, new_class) != (*p, *class) {
+                            <PredicateClassCountStore<T>>::remove((*p, *class));
+                            <PredicateClassCountStore<T>>::mutate((new_p, new_class), |value| {
+                                *value = Some(value.unwrap_or(0) + count)
+                            });
+                        }
+                    }
+                }
+                cursor.after = entries.last().map(|(key, _)| <PredicateClassCountStore<T>>::hashed_key_for(*key));
+                entries.len()
+            }
+            ReEncodingStep::DefaultGraph => {
+                let entries: Vec<(TermId, TermId, TermId)> = match after {
+                    Some(after) => <DefaultSpoStore<T>>::iter_from(after).map(|(key, _)| key).take(limit).collect(),
+                    None => <DefaultSpoStore<T>>::iter_keys().take(limit).collect(),
+                };
+                for (s, p, o) in entries.iter() {
+                    self.move_quad(&[*s, *p, *o], EncodedTerm::DefaultGraph, EncodedTerm::DefaultGraph);
+                }
+                cursor.after = entries.last().map(|key| <DefaultSpoStore<T>>::hashed_key_for(*key));
+                entries.len()
+            }
+            ReEncodingStep::GraphPredicateCounts(g) => {
+                let (graph, target) = match self.graph_tries(&g) {
+                    Some(tries) => tries,
+                    None => return true,
+                };
+                let entries: Vec<_> = graph.predicate_counts_after(after).take(limit).collect();
+                for (_, p, count) in entries.iter() {
+                    if let Some(new_p) = self.reencode_id(p) {
+                        if new_p != *p || target.is_some() {
+                            graph.mutate_predicate_count(*p, |value| *value = None);
+                            target.as_ref().unwrap_or(&graph).mutate_predicate_count(new_p, |value| {
+                                *value = Some(value.unwrap_or(0) + count)
+                            });
+                        }
+                    }
+                }
+                cursor.after = entries.last().map(|(key, _, _)| key.clone());
+                entries.len()
+            }
+            ReEncodingStep::GraphPredicateClassCounts(g) => {
+                let (graph, target) = match self.graph_tries(&g) {
+                    Some(tries) => tries,
+                    None => return true,
+                };
+                let entries: Vec<_> = graph.predicate_class_counts_after(after).take(limit).collect();
+                for (_, (p, class), count) in entries.iter() {
+                    if let (Some(new_p), Some(new_class)) = (self.reencode_id(p), self.reencode_id(class)) {
+                        if (new_p, new_class) != (*p, *class) || target.is_some() {
+                            graph.mutate_predicate_class_count(*p, *class, |value| *value = None);
+                            target.as_ref().unwrap_or(&graph).mutate_predicate_class_count(new_p, new_class, |value| {
+                                *value = Some(value.unwrap_or(0) + count)
+                            });
+                        }
+                    }
+                }
+                cursor.after = entries.last().map(|(key, _, _)| key.clone());
+                entries.len()
+            }
+            ReEncodingStep::GraphQuads(g) => {
+                let (old_g, new_g) = match self.reencode(&g) {
+                    Some(terms) => terms,
+                    None => return true,
+                };
+                let entries: Vec<_> = GraphTrie::of::<T>(&g).triples_after(TAG_SPO, after).take(limit).collect();
+                for (_, (s, p, o)) in entries.iter() {
+                    self.move_quad(&[*s, *p, *o], old_g, new_g);
+                }
+                cursor.after = entries.last().map(|(key, _)| key.clone());
+                entries.len()
+            }
+            ReEncodingStep::ClearCounts => {
+                // the quads of the dropped graphs are not counted: their tries are purged without releasing them
+                for key in <GraphPurgeStore<T>>::iter_keys().collect::<Vec<_>>() {
+                    <GraphPurgeStore<T>>::insert(key, false);
+                    self.written += 1;
+                }
+                // a removed entry counts as a read one
+                let maps: [fn(Option<u32>) -> KillStorageResult; 5] = [
+                    <PredicateCountStore<T>>::remove_all,
+                    <DefaultGraphPredicateCountStore<T>>::remove_all,
+                    <TermGraphStore<T>>::remove_all,
+                    <StrRefCountStore<T>>::remove_all,
+                    <TermRefCountStore<T>>::remove_all,
+                ];
+                let mut removed = 0;
+                for remove_all in maps.iter() {
+                    match remove_all(Some((limit - removed) as u32)) {
+                        KillStorageResult::AllRemoved(count) => removed += count as usize,
+                        KillStorageResult::SomeRemaining(_) => removed = limit,
+                    }
+                    if removed >= limit {
+                        break;
+                    }
+                }
+                removed.min(limit)
+            }
+            ReEncodingStep::CountDefaultGraph => {
+                let entries: Vec<(TermId, TermId, TermId)> = match after {
+                    Some(after) => <DefaultSpoStore<T>>::iter_from(after).map(|(key, _)| key).take(limit).collect(),
+                    None => <DefaultSpoStore<T>>::iter_keys().take(limit).collect(),
+                };
+                let mut counts = BTreeMap::<TermId, u64>::new();
+                for (s, p, o) in entries.iter() {
+                    *counts.entry(*p).or_default() += 1;
+                    self.retain_ids(&[*s, *p, *o]);
+                }
+                for (p, count) in counts.iter() {
+                    <DefaultGraphPredicateCountStore<T>>::mutate(p, |value| *value = Some(value.unwrap_or(0) + count));
+                    self.written += 1;
+                }
+                self.add_predicate_counts(counts);
+                cursor.after = entries.last().map(|key| <DefaultSpoStore<T>>::hashed_key_for(*key));
+                entries.len()
+            }
+            ReEncodingStep::CountGraph(g) => {
+                let entries: Vec<_> = GraphTrie::of::<T>(&g).predicate_counts_after(after).take(limit).collect();
+                self.add_predicate_counts(entries.iter().map(|(_, p, count)| (*p, *count)).collect());
+                cursor.after = entries.last().map(|(key, _, _)| key.clone());
+                entries.len()
+            }
+            ReEncodingStep::CountGraphTerms(g) => {
+                let generation = <GraphGenerationStore<T>>::get(g);
+                let entries: Vec<_> = GraphTrie::of::<T>(&g).triples_after(TAG_SPO, after).take(limit).collect();
+                for &(_, (s, p, o)) in entries.iter() {
+                    count_term_graphs::<T>(g, generation, [s, p, o], true);
+                    self.retain_ids(&[s, p, o]);
+                }
+                self.written += 3 * entries.len() as u32;
+                cursor.after = entries.last().map(|(key, _)| key.clone());
+                entries.len()
+            }
+            ReEncodingStep::StageLegacyKeys(layout) => {
+                let mut moved = 0;
+                for (from, to) in layout.moved_maps().iter().zip(STAGING_MAPS.iter()) {
+                    let (from, to) = (map_prefix::<T>(from), map_prefix::<T>(to));
+                    for key in first_keys(&from, limit - moved) {
+                        if let Some(value) = unhashed::get_raw(&key) {
+                            unhashed::put_raw(&[&to[..], &key[from.len()..]].concat(), &value);
+                        }
+                        unhashed::kill(&key);
+                        moved += 1;
+                    }
+                }
+                self.written += 2 * moved as u32;
+                moved
+            }
+            ReEncodingStep::ClearRebuiltMaps(_) => {
+                // a removed entry counts as a read one
+                let mut removed = 0;
+                for map in REBUILT_MAPS.iter() {
+                    match sp_io::storage::clear_prefix(&map_prefix::<T>(map), Some((limit - removed) as u32)) {
+                        KillStorageResult::AllRemoved(count) => removed += count as usize,
+                        KillStorageResult::SomeRemaining(_) => removed = limit,
+                    }
+                    if removed >= limit {
+                        break;
+                    }
+                }
+                removed.min(limit)
+            }
+            ReEncodingStep::InsertLegacyKeys(layout) => {
+                let mut inserted = 0;
+                for (map, name) in STAGING_MAPS.iter().enumerate() {
+                    let prefix = map_prefix::<T>(name);
+                    for key in first_keys(&prefix, limit - inserted) {
+                        skip_invalid(self.insert_staged(layout, map, &key[prefix.len()..]));
+                        unhashed::kill(&key);
+                        inserted += 1;
+                    }
+                }
+                self.moved += inserted as u32;
+                inserted
+            }
+            ReEncodingStep::CollectStrs => {
+                let ids: Vec<u128> = match after {
+                    Some(after) => <Id2StrStore<T>>::iter_keys_from(after).take(limit).collect(),
+                    None => <Id2StrStore<T>>::iter_keys().take(limit).collect(),
+                };
+                for id in ids.iter() {
+                    if !<StrRefCountStore<T>>::contains_key(id) {
+                        <Id2StrStore<T>>::remove(id);
+                        self.written += 1;
+                    }
+                }
+                cursor.after = ids.last().map(|id| <Id2StrStore<T>>::hashed_key_for(id));
+                ids.len()
+            }
+            ReEncodingStep::CountGraphNames => {
+                let names: Vec<TermId> = match after {
+                    Some(after) => <GraphNameStore<T>>::iter_keys_from(after).take(limit).collect(),
+                    None => <GraphNameStore<T>>::iter_keys().take(limit).collect(),
+                };
+                for g in names.iter() {
+                    self.retain_ids(&[*g]);
+                }
+                cursor.after = names.last().map(|g| <GraphNameStore<T>>::hashed_key_for(g));
+                names.len()
+            }
+            ReEncodingStep::CollectTerms => {
+                let hashes: Vec<u128> = match after {
+                    Some(after) => <Id2TermStore<T>>::iter_keys_from(after).take(limit).collect(),
+                    None => <Id2TermStore<T>>::iter_keys().take(limit).collect(),
+                };
+                for hash in hashes.iter() {
+                    if !<TermRefCountStore<T>>::contains_key(hash) {
+                        <Id2TermStore<T>>::remove(hash);
+                        self.written += 1;
+                    }
+                }
+                cursor.after = hashes.last().map(|hash| <Id2TermStore<T>>::hashed_key_for(hash));
+                hashes.len()
+            }
+        };
+        self.entries += read as u32;
+        self.remaining -= read as u32;
+        read < limit
+    }
+
+    /// The step following `step`, `None` once the strings and the terms no longer referenced are removed.
+    ///
+    /// A named graph whose id changes is created with its new id before its statistics and its quads are moved,
+    /// and removed once they are.
+    fn next_step(&mut self, step: &ReEncodingStep) -> Option<ReEncodingStep> {
+        let next_graph = |store: &mut Self, after: Option<&TermId>| {
+            let g = next_graph_name::<T>(after)?;
+            if let Some((_, new_g)) = store.reencode(&g) {
+                if new_g.term_id() != g {
+                    let mut writer = &store.store;
+                    skip_invalid(writer.insert_encoded_named_graph(new_g));
+                }
+            }
+            Some(ReEncodingStep::GraphPredicateCounts(g))
+        };
+        // once the keys are re-encoded, the statistics are recounted
+        let recount = || {
+            <CodecVersionStore<T>>::put(CODEC_VERSION);
+            Some(ReEncodingStep::ClearCounts)
+        };
+        match step {
+            ReEncodingStep::PredicateCounts => Some(ReEncodingStep::PredicateClassCounts),
+            ReEncodingStep::PredicateClassCounts => Some(ReEncodingStep::DefaultGraph),
+            ReEncodingStep::DefaultGraph => next_graph(self, None).or_else(recount),
+            ReEncodingStep::GraphPredicateCounts(g) => Some(ReEncodingStep::GraphPredicateClassCounts(*g)),
+            ReEncodingStep::GraphPredicateClassCounts(g) => Some(ReEncodingStep::GraphQuads(*g)),
+            ReEncodingStep::GraphQuads(g) => {
+                if let Some((old_g, new_g)) = self.reencode(g) {
+                    if new_g.term_id() != *g {
+                        let mut writer = &self.store;
+                        skip_invalid(writer.remove_encoded_named_graph(old_g));
+                    }
+                }
+                next_graph(self, Some(g)).or_else(recount)
+            }
+            ReEncodingStep::ClearCounts => Some(ReEncodingStep::CountDefaultGraph),
+            ReEncodingStep::CountDefaultGraph => Some(ReEncodingStep::CountGraphNames),
+            ReEncodingStep::CountGraphNames => next_graph_name::<T>(None)
+                .map(ReEncodingStep::CountGraph)
+                .or(Some(ReEncodingStep::CollectStrs)),
+            ReEncodingStep::CountGraph(g) => Some(ReEncodingStep::CountGraphTerms(*g)),
+            ReEncodingStep::CountGraphTerms(g) => next_graph_name::<T>(Some(g))
+                .map(ReEncodingStep::CountGraph)
+                .or(Some(ReEncodingStep::CollectStrs)),
+            ReEncodingStep::StageLegacyKeys(layout) => Some(ReEncodingStep::ClearRebuiltMaps(*layout)),
+            ReEncodingStep::ClearRebuiltMaps(layout) => Some(ReEncodingStep::InsertLegacyKeys(*layout)),
+            // the statistics are counted by the insertions
+            ReEncodingStep::InsertLegacyKeys(_) => Some(ReEncodingStep::CollectStrs),
+            ReEncodingStep::CollectStrs => Some(ReEncodingStep::CollectTerms),
+            ReEncodingStep::CollectTerms => None,
+        }
+    }
+
+    /// Inserts the graph name or the quad of a key of the layout `layout` moved to `STAGING_MAPS[map]`.
+    ///
+    /// The terms are decoded with the strings of the older layout, which are kept until the keys are inserted.
+    fn insert_staged(&self, layout: LegacyLayout, map: usize, key: &[u8]) -> Result<(), StoreError> {
+        let terms = layout.decode_key::<T>(key)?;
+        let term = |i: usize| {
+            terms.get(i).copied().ok_or_else(|| StoreError::CorruptedTerm { msg: "truncated key".to_string() })
+        };
+        if map == 0 {
+            let graph_name = self.store.decode_named_or_blank_node(term(0)?)?;
+            return self.store.insert_named_graph(graph_name);
+        }
+        let quad = match (map, layout) {
+            (1, _) => EncodedQuad::new(term(0)?, term(1)?, term(2)?, EncodedTerm::DefaultGraph),
+            (_, LegacyLayout::V0) => EncodedQuad::new(term(0)?, term(1)?, term(2)?, term(3)?),
+            (_, LegacyLayout::V1) => EncodedQuad::new(term(1)?, term(2)?, term(3)?, term(0)?),
+        };
+        let quad = self.store.decode_quad(&quad)?;
+        self.store.insert_quad(quad)
+    }
+
+    /// Counts the references of the terms `ids` to their strings and to their encoding
+    fn retain_ids(&mut self, ids: &[TermId]) {
+        for id in ids {
+            if let Some(term) = skip_invalid(decode_term_id::<T>(id)) {
+                retain_strs::<T>(term);
+                self.written += 1;
+            }
+        }
+    }
+
+    /// Adds counts of quads by predicate to `PredicateCountStore`
+    fn add_predicate_counts(&mut self, counts: BTreeMap<TermId, u64>) {
+        for (p, count) in counts {
+            <PredicateCountStore<T>>::mutate(p, |value| *value = Some(value.unwrap_or(0) + count));
+            self.written += 1;
+        }
+    }
+
+    /// The trie of the named graph `g` and, if its id changes, the trie of its new id
+    fn graph_tries(&mut self, g: &TermId) -> Option<(GraphTrie, Option<GraphTrie>)> {
+        let (_, new_g) = self.reencode(g)?;
+        let new_g = new_g.term_id();
+        Some((GraphTrie::of::<T>(g), Some(new_g).filter(|new_g| new_g != g).map(|new_g| GraphTrie::of::<T>(&new_g))))
+    }
+
+    /// Replaces the quad `ids` of the graph `old_g` by the quad encoded with the current encoding in `new_g`
+    fn move_quad(&mut self, ids: &[TermId; 3], old_g: EncodedTerm, new_g: EncodedTerm) {
+        let mut terms = Vec::with_capacity(3);
+        for id in ids.iter() {
+            match self.reencode(id) {
+                Some(term) => terms.push(term),
+                None => return,
+            }
+        }
+        let old = EncodedQuad::new(terms[0].0, terms[1].0, terms[2].0, old_g);
+        let new = EncodedQuad::new(terms[0].1, terms[1].1, terms[2].1, new_g);
+        let changed = [(old.subject, new.subject), (old.predicate, new.predicate), (old.object, new.object), (old_g, new_g)]
+            .iter()
+            .any(|(old, new)| old.term_id() != new.term_id());
+        if changed {
+            // the strings still used by the new quad are retained before they are released
+            skip_invalid(insert_terms::<T>(&new).and_then(|_| insert_quad_entries::<T>(&self.store, &new)));
+            skip_invalid(remove_quad_entries::<T>(&self.store, &old));
+            self.moved += 1;
+        }
+    }
+
+    fn reencode_id(&mut self, id: &TermId) -> Option<TermId> {
+        self.reencode(id).map(|(_, new)| new.term_id())
+    }
+
+    /// The term of `id` and the term encoded with the current encoding, `None` if it cannot be decoded
+    fn reencode(&mut self, id: &TermId) -> Option<(EncodedTerm, EncodedTerm)> {
+        if let Some(terms) = self.terms.get(id) {
+            return Some(*terms);
+        }
+        let store = &self.store;
+        let terms = skip_invalid((|| {
+            let old = decode_term_id::<T>(id)?;
+            let term = store.decode_term(old)?;
+            let mut writer = store;
+            let new = writer.encode_term(term.as_ref())?;
+            insert_term::<T>(new)?;
+            Ok::<_, StoreError>((old, new))
+        })())?;
+        self.terms.insert(*id, terms);
+        Some(terms)
+    }
+}
+
+pub mod v1 {
+    //! Version 1: the index keys are fixed-width term ids (see `TermId`) instead of the
+    //! `BoundedVec` encoding of the terms, all hashed with `Blake2_128Concat`, and the index values are `()`.
+
+    use frame_support::pallet_prelude::*;
+    use frame_support::traits::OnRuntimeUpgrade;
+
+    use crate::{Config, Pallet};
+    use crate::error::StoreError;
+    use crate::store::codec::EncodedTerm;
+
+    /// Rewrites the indexes of version 0 with the compact keys of version 1.
+    ///
+    /// The quads of `DefaultSpoStore` and `SpogStore` and the graph names are moved aside then inserted again
+    /// in batches across blocks (see `LegacyLayout`), which also rebuilds the statistics and the ordered literal index.
+    pub struct CompactKeys<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for CompactKeys<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() >= 1 {
+                return T::DbWeight::get().reads(1);
+            }
+            super::start_layout_migration::<T>(super::LegacyLayout::V0)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            super::checks::record_quad_count::<T, Self>();
+            Ok(())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            super::checks::check_upgraded::<T, Self>()
+        }
+    }
+
+    /// Decodes a version 0 key: each term is a `Blake2_128Concat` hash followed by its SCALE encoded bytes
+    pub(super) fn decode_v0_key(mut key: &[u8]) -> Result<Vec<EncodedTerm>, StoreError> {
+        let mut terms = Vec::new();
+        while key.len() > 16 {
+            key = &key[16..];
+            let bytes = Vec::<u8>::decode(&mut key)
+                .map_err(|error| StoreError::CorruptedTerm { msg: error.to_string() })?;
+            terms.push(if bytes.is_empty() {
+                EncodedTerm::DefaultGraph
+            } else {
+                EncodedTerm::from_bytes(&bytes)?
+            });
+        }
+        Ok(terms)
+    }
+}
+
+pub mod v2 {
+    //! Version 2: each named graph is stored in its own child trie (see `GraphTrie`) instead of the
+    //! `Gspo`, `Gpos`, `Gosp`, `Spog`, `Posg` and `Ospg` maps, and `GraphNameStore` counts the quads of each graph.
+    //! The quads of the named graphs using each term are counted in `TermGraphStore`, which a lookup without a
+    //! graph reads instead of every graph.
+
+    use frame_support::pallet_prelude::*;
+    use frame_support::traits::OnRuntimeUpgrade;
+
+    use crate::{decode_term_id, Config, Pallet};
+    use crate::error::StoreError;
+    use crate::store::codec::{EncodedTerm, TermId, TERM_ID_SIZE};
+
+    /// Moves the named graphs of version 1 to their child tries.
+    ///
+    /// The quads of `DefaultSpoStore` and `GspoStore` and the graph names are moved aside then inserted again
+    /// in batches across blocks (see `LegacyLayout`), which also fills the statistics of each graph and the graphs
+    /// of the terms.
+    pub struct GraphTries<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for GraphTries<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() != 1 {
+                return T::DbWeight::get().reads(1);
+            }
+            super::start_layout_migration::<T>(super::LegacyLayout::V1)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            super::checks::record_quad_count::<T, Self>();
+            Ok(())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            super::checks::check_upgraded::<T, Self>()
+        }
+    }
+
+    /// Decodes a version 1 key: a `Blake2_128Concat` hash of the first term id followed by the term ids
+    pub(super) fn decode_v1_key<T: Config>(key: &[u8]) -> Result<Vec<EncodedTerm>, StoreError> {
+        if key.len() < 16 || (key.len() - 16) % TERM_ID_SIZE != 0 {
+            return Err(StoreError::CorruptedTerm { msg: format!("invalid key length {}", key.len()) });
+        }
+        key[16..]
+            .chunks(TERM_ID_SIZE)
+            .map(|chunk| {
+                let mut id: TermId = [0; TERM_ID_SIZE];
+                id.copy_from_slice(chunk);
+                decode_term_id::<T>(&id)
+            })
+            .collect()
+    }
+}
+
+pub mod v3 {
+    //! Version 3: the references of the quads and the graph names to the strings of `Id2StrStore` and to the
+    //! terms of `Id2TermStore` are counted in `StrRefCountStore` and `TermRefCountStore`, and the strings and
+    //! the terms are removed with their last reference.
+
+    use frame_support::pallet_prelude::*;
+    use frame_support::traits::OnRuntimeUpgrade;
+
+    use crate::{Config, Pallet};
+
+    /// Version of the storage once migrated, the keys are not changed
+    pub const STORAGE_VERSION: StorageVersion = StorageVersion::new(3);
+
+    /// Starts counting the references to the strings and to the terms, in batches across blocks with the
+    /// statistics (see `ReEncodeTerms`): the strings and the terms without reference are removed once they are
+    /// counted.
+    pub struct StrRefCounts<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for StrRefCounts<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() != 2 {
+                return T::DbWeight::get().reads(1);
+            }
+            super::start_recount::<T>();
+            STORAGE_VERSION.put::<Pallet<T>>();
+            T::DbWeight::get().reads_writes(2, 2)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            super::checks::record_quad_count::<T, Self>();
+            Ok(())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            super::checks::check_upgraded::<T, Self>()
+        }
+    }
+}
+
+pub mod v4 {
+    //! Version 4: the strings were hashed with a key of the runtime instead of the unkeyed `SipHash`. They are now
+    //! hashed with `blake2_128` (term encoding 5, see `CODEC_VERSION`), which `ReEncodeTerms` applies to the keys.
+
+    use frame_support::pallet_prelude::*;
+    use frame_support::traits::OnRuntimeUpgrade;
+
+    use crate::{CodecVersionStore, Config, Pallet};
+
+    /// Version of the storage once migrated, the keys are not changed
+    pub const STORAGE_VERSION: StorageVersion = StorageVersion::new(4);
+
+    /// The term encoding of the keys of version 3: the named nodes are referenced by the hash of their IRI
+    const VERSION_3_ENCODING: u8 = 1;
+
+    /// Stores the term encoding of the keys of version 3, so that `ReEncodeTerms` hashes their strings again in
+    /// batches across blocks.
+    pub struct KeyedStrHashes<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for KeyedStrHashes<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() != 3 {
+                return T::DbWeight::get().reads(1);
+            }
+            <CodecVersionStore<T>>::put(VERSION_3_ENCODING);
+            STORAGE_VERSION.put::<Pallet<T>>();
+            T::DbWeight::get().reads_writes(1, 2)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            super::checks::record_quad_count::<T, Self>();
+            Ok(())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            super::checks::check_upgraded::<T, Self>()
+        }
+    }
+}
+
+pub mod v5 {
+    //! Version 5: the named nodes starting with a namespace (see `Namespaces`) are encoded as a prefix id
+    //! and a local name instead of the hash of their IRI (term encoding 2, see `CODEC_VERSION`).
+
+    use frame_support::pallet_prelude::*;
+    use frame_support::traits::OnRuntimeUpgrade;
+
+    use crate::{CodecVersionStore, Config, Pallet};
+
+    /// Version of the storage once migrated, the keys are not changed
+    pub const STORAGE_VERSION: StorageVersion = StorageVersion::new(5);
+
+    /// The term encoding of the keys of version 4: the named nodes are referenced by the hash of their IRI
+    const VERSION_4_ENCODING: u8 = 1;
+
+    /// Stores the term encoding of the keys of version 4, unless an earlier migration stored the one of its keys,
+    /// so that `ReEncodeTerms` encodes their named nodes with the namespaces of the runtime in batches across blocks.
+    pub struct PrefixedNamedNodes<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for PrefixedNamedNodes<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() != 4 {
+                return T::DbWeight::get().reads(1);
+            }
+            if <CodecVersionStore<T>>::get() == 0 {
+                <CodecVersionStore<T>>::put(VERSION_4_ENCODING);
+            }
+            STORAGE_VERSION.put::<Pallet<T>>();
+            T::DbWeight::get().reads_writes(2, 2)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            super::checks::record_quad_count::<T, Self>();
+            Ok(())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            super::checks::check_upgraded::<T, Self>()
+        }
+    }
+}
+
+pub mod v6 {
+    //! Version 6: the version of the term encoding of the keys is stored (see `CodecVersionStore`), so that
+    //! the keys of an older encoding are re-encoded by `ReEncodeTerms` instead of being misread.
+
+    use frame_support::pallet_prelude::*;
+    use frame_support::traits::OnRuntimeUpgrade;
+
+    use crate::{CodecVersionStore, Config, Pallet};
+
+    /// Version of the storage once migrated, the keys are not changed
+    pub const STORAGE_VERSION: StorageVersion = StorageVersion::new(6);
+
+    /// The term encoding of the keys of version 5: the named nodes with a namespace are prefixed
+    const VERSION_5_ENCODING: u8 = 2;
+
+    /// Stores the term encoding of the keys of version 5, unless an earlier migration stored the one of its keys.
+    pub struct CodecVersionTag<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for CodecVersionTag<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() != 5 {
+                return T::DbWeight::get().reads(1);
+            }
+            if <CodecVersionStore<T>>::get() == 0 {
+                <CodecVersionStore<T>>::put(VERSION_5_ENCODING);
+            }
+            STORAGE_VERSION.put::<Pallet<T>>();
+            T::DbWeight::get().reads_writes(2, 2)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            super::checks::record_quad_count::<T, Self>();
+            Ok(())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            super::checks::check_upgraded::<T, Self>()
+        }
+    }
+}
+
+pub mod v7 {
+    //! Version 7: the quads of the store and of the default graph are counted (see `QuadCountStore`), and the
+    //! quads of the default graph by predicate (see `DefaultGraphPredicateCountStore`), so that simple `COUNT`
+    //! aggregates are answered without a scan. The statistics are recounted with them: the query planner reads a
+    //! missing predicate count as an unknown cardinality, not as an empty one.
+
+    use frame_support::pallet_prelude::*;
+    use frame_support::traits::OnRuntimeUpgrade;
+
+    use crate::consistency::RepairIndexes;
+    use crate::{Config, Pallet};
+
+    /// Version of the storage once migrated, the keys are not changed
+    pub const STORAGE_VERSION: StorageVersion = StorageVersion::new(7);
+
+    /// Starts counting the quads of the default graph and of the store, in batches across blocks with the
+    /// secondary indexes (see `RepairIndexes`), then the quads of the default graph by predicate with the other
+    /// statistics (see `ReEncodeTerms`).
+    pub struct QuadCounts<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for QuadCounts<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() != 6 {
+                return T::DbWeight::get().reads(1);
+            }
+            RepairIndexes::<T>::start();
+            super::start_recount::<T>();
+            STORAGE_VERSION.put::<Pallet<T>>();
+            T::DbWeight::get().reads_writes(2, 3)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            super::checks::record_quad_count::<T, Self>();
+            Ok(())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            super::checks::check_upgraded::<T, Self>()
+        }
+    }
+}
+
+pub mod v8 {
+    //! Version 8: the nodes linked by `owl:sameAs` in the default graph are grouped in equivalence classes
+    //! (see `SameAsStore`), so that the queries can treat them as the same node.
+
+    use frame_support::pallet_prelude::*;
+    use frame_support::traits::OnRuntimeUpgrade;
+
+    use crate::consistency::RepairIndexes;
+    use crate::{Config, Pallet};
+
+    /// Version of the storage once migrated, the keys are not changed
+    pub const STORAGE_VERSION: StorageVersion = StorageVersion::new(8);
+
+    /// Starts linking the nodes of the `owl:sameAs` quads of the default graph, in batches across blocks with
+    /// the secondary indexes (see `RepairIndexes`).
+    pub struct SameAsClasses<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for SameAsClasses<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() != 7 {
+                return T::DbWeight::get().reads(1);
+            }
+            RepairIndexes::<T>::start();
+            STORAGE_VERSION.put::<Pallet<T>>();
+            T::DbWeight::get().reads_writes(1, 2)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            super::checks::record_quad_count::<T, Self>();
+            Ok(())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            super::checks::check_upgraded::<T, Self>()
+        }
+    }
+}
+
+pub mod v9 {
+    //! Version 9: the tokens of the string literals of the default graph are indexed (see `TextIndexStore`),
+    //! so that the queries can look up the literals matching a full-text query. The tokens are keyed by their
+    //! hash and by the hash of their prefix (see `crate::text`).
+
+    use frame_support::pallet_prelude::*;
+    use frame_support::traits::OnRuntimeUpgrade;
+
+    use crate::consistency::RepairIndexes;
+    use crate::{Config, Pallet};
+
+    /// Version of the storage once migrated, the keys are not changed
+    pub const STORAGE_VERSION: StorageVersion = StorageVersion::new(9);
+
+    /// Starts indexing the tokens of the string literal objects of the default graph, in batches across blocks
+    /// with the other secondary indexes (see `RepairIndexes`).
+    pub struct TextIndex<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for TextIndex<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() != 8 {
+                return T::DbWeight::get().reads(1);
+            }
+            RepairIndexes::<T>::start();
+            STORAGE_VERSION.put::<Pallet<T>>();
+            T::DbWeight::get().reads_writes(1, 2)
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn pre_upgrade() -> Result<(), &'static str> {
+            super::checks::record_quad_count::<T, Self>();
+            Ok(())
+        }
+
+        #[cfg(feature = "try-runtime")]
+        fn post_upgrade() -> Result<(), &'static str> {
+            super::checks::check_upgraded::<T, Self>()
+        }
+    }
+}
+
+/// Checks of the migrations with `try-runtime`.
+///
+/// All the `pre_upgrade` hooks run before the first migration and all the `post_upgrade` hooks after the last one:
+/// the number of quads is read with the layout of the storage before the upgrade, and compared with the number
+/// of quads once the store is upgraded.
+#[cfg(feature = "try-runtime")]
+mod checks {
+    use frame_support::pallet_prelude::*;
+    use frame_support::storage::migration::storage_iter;
+    use frame_support::traits::OnRuntimeUpgradeHelpersExt;
+
+    use crate::{
+        decode_term_id, Config, DefaultGraphQuadCountStore, DefaultSpoStore, GraphNameStore, GraphStore, Pallet,
+        QuadCountStore, ReEncodingCursorStore,
+    };
+    use crate::graph_trie::{GraphTrie, TAG_SPO};
+    use crate::store::numeric_encoder::Decoder;
+
+    const QUAD_COUNT: &str = "graphdb:quad_count";
+
+    /// Stores the number of quads before the upgrade
+    pub fn record_quad_count<T: Config, U: OnRuntimeUpgradeHelpersExt>() {
+        let pallet = <Pallet<T> as PalletInfoAccess>::name().as_bytes();
+        let version = StorageVersion::get::<Pallet<T>>();
+        let count = if version == 0 {
+            storage_iter::<bool>(pallet, b"DefaultSpoStore").count() + storage_iter::<bool>(pallet, b"SpogStore").count()
+        } else if version == 1 {
+            storage_iter::<()>(pallet, b"DefaultSpoStore").count() + storage_iter::<()>(pallet, b"GspoStore").count()
+        } else {
+            quad_count::<T>() as usize
+        };
+        U::set_temp_storage(count as u64, QUAD_COUNT);
+    }
+
+    /// Checks that the storage version is the current one, that the store has kept its quads,
+    /// that their terms are decoded and that the graph names and the quad counts count the quads
+    pub fn check_upgraded<T: Config, U: OnRuntimeUpgradeHelpersExt>() -> Result<(), &'static str> {
+        ensure!(
+            StorageVersion::get::<Pallet<T>>() == Pallet::<T>::current_storage_version(),
+            "the storage version is not the current one"
+        );
+        // the quads of a layout migrated in batches are moved by the next blocks
+        if <ReEncodingCursorStore<T>>::exists() {
+            return Ok(());
+        }
+        if let Some(count) = U::get_temp_storage::<u64>(QUAD_COUNT) {
+            ensure!(quad_count::<T>() == count, "the number of quads has changed");
+        }
+        let store = GraphStore::<T>::new();
+        for quad in store.encoded_quads_for_pattern(None, None, None, None) {
+            let quad = quad.map_err(|_| "a quad has an invalid term id")?;
+            store.decode_quad(&quad).map_err(|_| "a quad has an invalid term")?;
+        }
+        for (g, count) in <GraphNameStore<T>>::iter() {
+            decode_term_id::<T>(&g).map_err(|_| "a graph name has an invalid term id")?;
+            let quads = GraphTrie::of::<T>(&g).triples_with_prefix(TAG_SPO, &[]).count() as u64;
+            ensure!(quads == count, "a graph name does not count the quads of its graph");
+        }
+        ensure!(
+            <DefaultGraphQuadCountStore<T>>::get() == <DefaultSpoStore<T>>::iter_keys().count() as u64,
+            "the quad count of the default graph is not the number of its quads"
+        );
+        ensure!(<QuadCountStore<T>>::get() == quad_count::<T>(), "the quad count is not the number of quads");
+        Ok(())
+    }
+
+    fn quad_count<T: Config>() -> u64 {
+        <DefaultSpoStore<T>>::iter_keys().count() as u64 + <GraphNameStore<T>>::iter_values().sum::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frame_support::{Blake2_128, StorageHasher};
+    use frame_support::traits::{OnRuntimeUpgrade, StorageVersion};
+
+    use super::*;
+    use crate::consistency::RepairIndexes;
+    use crate::{
+        DefaultGraphQuadCountStore, IndexRepairCursorStore, QuadCountStore, SameAsMemberStore, SameAsStore,
+        TextIndexStore,
+    };
+    use crate::mock::{new_test_ext, Test};
+    use crate::store::model::StrHash;
+
+    /// Runs the batches of the ongoing migration until it is done, returns the number of blocks it took
+    fn run_batches() -> u32 {
+        let mut blocks = 0;
+        while <ReEncodingCursorStore<Test>>::exists() {
+            ReEncodeTerms::<Test>::reencode_batch();
+            blocks += 1;
+            assert!(blocks < 1000, "the migration does not end");
+        }
+        blocks
+    }
+
+    fn quads() -> Vec<String> {
+        let store = GraphStore::<Test>::new();
+        let mut quads: Vec<String> = store.quads_for_pattern(None, None, None, None)
+            .unwrap()
+            .map(|quad| quad.unwrap().to_string())
+            .collect();
+        quads.sort();
+        quads
+    }
+
+    /// Writes the named graphs of the store with the layout of version 1: their quads in `GspoStore`
+    /// instead of their child tries
+    fn downgrade_to_version_1() {
+        let store = GraphStore::<Test>::new();
+        let gspo = map_prefix::<Test>(b"GspoStore");
+        for quad in store.encoded_quads_for_pattern(None, None, None, None).map(Result::unwrap) {
+            if quad.graph_name == EncodedTerm::DefaultGraph {
+                continue;
+            }
+            let ids = [quad.graph_name.term_id(), quad.subject.term_id(), quad.predicate.term_id(), quad.object.term_id()];
+            let key = [&gspo[..], &Blake2_128::hash(&ids[0])[..], &ids.concat()[..]].concat();
+            unhashed::put_raw(&key, &[]);
+        }
+        for g in <GraphNameStore<Test>>::iter_keys() {
+            GraphTrie::of::<Test>(&g).kill(u32::MAX);
+        }
+        StorageVersion::new(1).put::<Pallet<Test>>();
+    }
+
+    fn predicate_counts() -> Vec<(TermId, u64)> {
+        let mut counts: Vec<_> = <PredicateCountStore<Test>>::iter().collect();
+        counts.sort();
+        counts
+    }
+
+    #[test]
+    fn missing_predicate_counts_are_recounted() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(
+                "INSERT DATA { :a :p 1, 2, 3 . :b :q 4 . GRAPH :g { :a :p 5 . :c :r 6 } }",
+            )
+            .unwrap();
+            let counts = predicate_counts();
+            <PredicateCountStore<Test>>::remove_all(None);
+            StorageVersion::new(6).put::<Pallet<Test>>();
+
+            v7::QuadCounts::<Test>::on_runtime_upgrade();
+            assert_eq!(StorageVersion::get::<Pallet<Test>>(), 7);
+            // the store is not available until the counts are done
+            run_repair();
+            assert!(Pallet::<Test>::execute_update("INSERT DATA { :d :p 7 }").is_err());
+            assert!(run_batches() > 1);
+            assert_eq!(predicate_counts(), counts);
+            Pallet::<Test>::execute_update("INSERT DATA { :d :p 7 }").unwrap();
+        });
+    }
+
+    /// Runs the batches of the ongoing repair of the indexes until it is done
+    fn run_repair() {
+        while <IndexRepairCursorStore<Test>>::exists() {
+            RepairIndexes::<Test>::repair_batch();
+        }
+    }
+
+    #[test]
+    fn quads_are_counted_in_batches() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(
+                "INSERT DATA { :a :p 1, 2, 3 . :b :q 4 . GRAPH :g { :a :p 5 . :c :r 6 } }",
+            )
+            .unwrap();
+            <QuadCountStore<Test>>::kill();
+            <DefaultGraphQuadCountStore<Test>>::kill();
+            StorageVersion::new(6).put::<Pallet<Test>>();
+
+            v7::QuadCounts::<Test>::on_runtime_upgrade();
+            assert_eq!(StorageVersion::get::<Pallet<Test>>(), 7);
+            // the store is not available until the quads are counted
+            assert!(Pallet::<Test>::execute_update("INSERT DATA { :d :p 7 }").is_err());
+            run_repair();
+            run_batches();
+            assert_eq!(<QuadCountStore<Test>>::get(), 6);
+            assert_eq!(<DefaultGraphQuadCountStore<Test>>::get(), 4);
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn same_as_classes_are_linked_in_batches() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a owl:sameAs :b . :b owl:sameAs :c . :c :p 1 }").unwrap();
+            let mut classes: Vec<_> = <SameAsStore<Test>>::iter().collect();
+            classes.sort();
+            assert_eq!(classes.len(), 3);
+            <SameAsStore<Test>>::remove_all(None);
+            <SameAsMemberStore<Test>>::remove_all(None);
+            StorageVersion::new(7).put::<Pallet<Test>>();
+
+            v8::SameAsClasses::<Test>::on_runtime_upgrade();
+            assert_eq!(StorageVersion::get::<Pallet<Test>>(), 8);
+            run_repair();
+            let mut linked: Vec<_> = <SameAsStore<Test>>::iter().collect();
+            linked.sort();
+            assert_eq!(linked, classes);
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn the_text_index_is_built_in_batches() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a :name \"Luna Lovegood\" . :b :name \"Lovecraft\" }")
+                .unwrap();
+            // an entry keyed by the bytes of its token instead of their hash
+            let (_, o, s, p) = <TextIndexStore<Test>>::iter_keys().next().unwrap();
+            let mut token_key = [0u8; 16];
+            token_key[..4].copy_from_slice(b"luna");
+            let stale_key = [&map_prefix::<Test>(b"TextIndexStore")[..], &(token_key, o, s, p).encode()[..]].concat();
+            <TextIndexStore<Test>>::remove_all(None);
+            unhashed::put_raw(&stale_key, &[]);
+            StorageVersion::new(8).put::<Pallet<Test>>();
+
+            v9::TextIndex::<Test>::on_runtime_upgrade();
+            assert_eq!(StorageVersion::get::<Pallet<Test>>(), 9);
+            run_repair();
+            assert!(!unhashed::exists(&stale_key));
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+            let found = Pallet::<Test>::execute_query("SELECT ?s WHERE { ?s text:match \"lov*\" }");
+            assert!(found.contains("http://relationlabs.ai/entity/a"));
+            assert!(found.contains("http://relationlabs.ai/entity/b"));
+        });
+    }
+
+    #[test]
+    fn predicates_of_the_default_graph_are_counted() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(
+                "INSERT DATA { :a :p 1, 2, 3 . :b :q 4 . GRAPH :g { :a :p 5 . :c :r 6 } }",
+            )
+            .unwrap();
+            let mut counts: Vec<_> = <DefaultGraphPredicateCountStore<Test>>::iter_values().collect();
+            counts.sort();
+            assert_eq!(counts, vec![1, 3]);
+            <DefaultGraphPredicateCountStore<Test>>::remove_all(None);
+            StorageVersion::new(6).put::<Pallet<Test>>();
+
+            v7::QuadCounts::<Test>::on_runtime_upgrade();
+            assert_eq!(StorageVersion::get::<Pallet<Test>>(), 7);
+            run_repair();
+            run_batches();
+            let mut recounted: Vec<_> = <DefaultGraphPredicateCountStore<Test>>::iter_values().collect();
+            recounted.sort();
+            assert_eq!(recounted, counts);
+        });
+    }
+
+    #[test]
+    fn recounting_restarts_from_the_start() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a :p 1, 2, 3, 4, 5 . :b :q 6, 7 }").unwrap();
+            let counts = predicate_counts();
+            start_recount::<Test>();
+            ReEncodeTerms::<Test>::reencode_batch();
+            ReEncodeTerms::<Test>::reencode_batch();
+            start_recount::<Test>();
+            run_batches();
+            assert_eq!(predicate_counts(), counts);
+        });
+    }
+
+    #[test]
+    fn references_are_recounted_in_batches() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(
+                "INSERT DATA { :a :p \"a literal longer than a term id\"@en . \
+                    GRAPH :g { :a :p \"a literal longer than a term id\"@en, \"another long literal\"@en } }",
+            )
+            .unwrap();
+            let str_counts: Vec<_> = <StrRefCountStore<Test>>::iter().collect();
+            let mut term_counts: Vec<_> = <TermRefCountStore<Test>>::iter().collect();
+            term_counts.sort();
+            assert!(term_counts.iter().any(|(_, count)| *count == 2));
+            <TermRefCountStore<Test>>::remove_all(None);
+            <Id2TermStore<Test>>::insert(42, BoundedVec::try_from(vec![1, 2, 3]).unwrap());
+            StorageVersion::new(2).put::<Pallet<Test>>();
+
+            v3::StrRefCounts::<Test>::on_runtime_upgrade();
+            assert_eq!(StorageVersion::get::<Pallet<Test>>(), 3);
+            assert!(run_batches() > 1);
+            assert_eq!(<StrRefCountStore<Test>>::iter().count(), str_counts.len());
+            let mut counts: Vec<_> = <TermRefCountStore<Test>>::iter().collect();
+            counts.sort();
+            assert_eq!(counts, term_counts);
+            assert!(!<Id2TermStore<Test>>::contains_key(42));
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn strings_of_an_older_hash_are_hashed_again_in_batches() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a :p 1, 2 . GRAPH :g { :a :p 3 } }").unwrap();
+            // a quad whose string is hashed with another hash
+            let iri = "http://example.org/s";
+            let old = StrHash::from_bytes(iri.as_bytes());
+            <Id2StrStore<Test>>::insert(*old, BoundedVec::try_from(iri.as_bytes().to_vec()).unwrap());
+            let s = EncodedTerm::NamedNode { iri_id: old };
+            let quad = EncodedQuad::new(s, s, EncodedTerm::IntegerLiteral(4), EncodedTerm::DefaultGraph);
+            insert_quad_entries::<Test>(&GraphStore::new(), &quad).unwrap();
+            <CodecVersionStore<Test>>::kill();
+            StorageVersion::new(3).put::<Pallet<Test>>();
+
+            v4::KeyedStrHashes::<Test>::on_runtime_upgrade();
+            assert_eq!(StorageVersion::get::<Pallet<Test>>(), 4);
+            ReEncodeTerms::<Test>::on_runtime_upgrade();
+            assert!(run_batches() > 1);
+            assert_eq!(<CodecVersionStore<Test>>::get(), CODEC_VERSION);
+            assert!(!<Id2StrStore<Test>>::contains_key(*old));
+            assert!(<Id2StrStore<Test>>::contains_key(*StrHash::new(iri)));
+            assert!(quads().iter().any(|quad| quad.starts_with("<http://example.org/s> <http://example.org/s> \"4\"")));
+            assert_eq!(quads().len(), 4);
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn named_nodes_of_a_namespace_are_prefixed_in_batches() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a :p 1, 2 . GRAPH :g { :a :p 3 } }").unwrap();
+            // a named node of a namespace referenced by the hash of its IRI
+            let iri = "http://relationlabs.ai/entity/x";
+            let id = StrHash::new(iri);
+            <Id2StrStore<Test>>::insert(*id, BoundedVec::try_from(iri.as_bytes().to_vec()).unwrap());
+            let s = EncodedTerm::NamedNode { iri_id: id };
+            let quad = EncodedQuad::new(s, s, EncodedTerm::IntegerLiteral(4), EncodedTerm::DefaultGraph);
+            insert_quad_entries::<Test>(&GraphStore::new(), &quad).unwrap();
+            <CodecVersionStore<Test>>::kill();
+            StorageVersion::new(4).put::<Pallet<Test>>();
+
+            v5::PrefixedNamedNodes::<Test>::on_runtime_upgrade();
+            assert_eq!(StorageVersion::get::<Pallet<Test>>(), 5);
+            assert_eq!(<CodecVersionStore<Test>>::get(), 1);
+            v6::CodecVersionTag::<Test>::on_runtime_upgrade();
+            assert_eq!(<CodecVersionStore<Test>>::get(), 1);
+            ReEncodeTerms::<Test>::on_runtime_upgrade();
+            assert!(run_batches() > 1);
+            assert!(!<Id2StrStore<Test>>::contains_key(*id));
+            assert!(quads().iter().any(|quad| quad.starts_with(
+                "<http://relationlabs.ai/entity/x> <http://relationlabs.ai/entity/x> \"4\""
+            )));
+            assert_eq!(quads().len(), 4);
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn a_named_graph_whose_id_changes_is_moved_with_its_statistics() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a :p 1 . GRAPH :g { :a :p 2 } }").unwrap();
+            // a named graph of a namespace referenced by the hash of its IRI, used as subject and predicate too
+            let iri = "http://relationlabs.ai/entity/h";
+            let id = StrHash::new(iri);
+            <Id2StrStore<Test>>::insert(*id, BoundedVec::try_from(iri.as_bytes().to_vec()).unwrap());
+            let old_g = EncodedTerm::NamedNode { iri_id: id };
+            let store = GraphStore::<Test>::new();
+            let mut writer = &store;
+            for i in 0..5 {
+                writer.insert_encoded(&EncodedQuad::new(old_g, old_g, EncodedTerm::IntegerLiteral(i), old_g)).unwrap();
+            }
+            let quads = quads();
+            <CodecVersionStore<Test>>::put(CODEC_VERSION - 1);
+
+            ReEncodeTerms::<Test>::on_runtime_upgrade();
+            assert!(Pallet::<Test>::execute_update("INSERT DATA { :d :p 5 }").is_err());
+            let mut steps = Vec::new();
+            while let Some(cursor) = <ReEncodingCursorStore<Test>>::get() {
+                steps.push(cursor.step);
+                ReEncodeTerms::<Test>::reencode_batch();
+                assert!(steps.len() < 1000, "the migration does not end");
+            }
+            assert!(steps.contains(&ReEncodingStep::GraphQuads(old_g.term_id())));
+            assert!(steps.contains(&ReEncodingStep::CollectTerms));
+
+            assert_eq!(self::quads(), quads);
+            assert!(!<GraphNameStore<Test>>::contains_key(old_g.term_id()));
+            let (g, count) = <GraphNameStore<Test>>::iter().find(|(_, count)| *count == 5).unwrap();
+            let counts: u64 = GraphTrie::of::<Test>(&g).predicate_counts().map(|(_, count)| count).sum();
+            assert_eq!(counts, 5);
+            assert!(GraphTrie::of::<Test>(&old_g.term_id()).predicate_counts().next().is_none());
+            assert!(!<Id2StrStore<Test>>::contains_key(*id));
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+            Pallet::<Test>::execute_update("INSERT DATA { :d :p 5 }").unwrap();
+        });
+    }
+
+    #[test]
+    fn an_older_layout_is_migrated_in_batches() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(
+                "INSERT DATA { :a :p 1, 2 . :b :q :a . GRAPH :g { :a :p 3 . :c :r \"a literal\" } GRAPH :h { :b :q 4 } }",
+            )
+            .unwrap();
+            let quads = quads();
+            let counts = predicate_counts();
+            downgrade_to_version_1();
+
+            v2::GraphTries::<Test>::on_runtime_upgrade();
+            assert_eq!(StorageVersion::get::<Pallet<Test>>(), Pallet::<Test>::current_storage_version());
+            // the later migrations do nothing
+            v3::StrRefCounts::<Test>::on_runtime_upgrade();
+            ReEncodeTerms::<Test>::on_runtime_upgrade();
+            assert!(Pallet::<Test>::execute_update("INSERT DATA { :d :p 5 }").is_err());
+
+            assert!(run_batches() > 1);
+            assert_eq!(self::quads(), quads);
+            assert_eq!(predicate_counts(), counts);
+            assert_eq!(<QuadCountStore<Test>>::get(), 6);
+            assert_eq!(first_keys(&map_prefix::<Test>(b"GspoStore"), 1).len(), 0);
+            for map in STAGING_MAPS.iter() {
+                assert_eq!(first_keys(&map_prefix::<Test>(map), 1).len(), 0);
+            }
+            // the strings are still referenced
+            assert!(<Id2StrStore<Test>>::iter_keys().all(|id| <StrRefCountStore<Test>>::contains_key(id)));
+            Pallet::<Test>::execute_update("INSERT DATA { :d :p 5 }").unwrap();
+        });
+    }
+}
@@ -0,0 +1,131 @@
+use crate as pallet_graphdb;
+use frame_support::parameter_types;
+use frame_support::traits::{ConstU16, ConstU64};
+use frame_support::weights::{constants::{RocksDbWeight, WEIGHT_PER_SECOND}, Weight};
+use frame_system as system;
+use sp_core::H256;
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+};
+
+type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+type Block = frame_system::mocking::MockBlock<Test>;
+
+// Configure a mock runtime to test the pallet.
+frame_support::construct_runtime!(
+    pub enum Test where
+        Block = Block,
+        NodeBlock = Block,
+        UncheckedExtrinsic = UncheckedExtrinsic,
+    {
+        System: frame_system::{Pallet, Call, Config, Storage, Event<T>},
+        Timestamp: pallet_timestamp::{Pallet, Call, Storage, Inherent},
+        Balances: pallet_balances::{Pallet, Call, Storage, Config<T>, Event<T>},
+        Graphdb: pallet_graphdb::{Pallet, Call, Storage, Event<T>},
+    }
+);
+
+impl system::Config for Test {
+    type BaseCallFilter = frame_support::traits::Everything;
+    type BlockWeights = ();
+    type BlockLength = ();
+    type DbWeight = RocksDbWeight;
+    type Origin = Origin;
+    type Call = Call;
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = u64;
+    type Lookup = IdentityLookup<Self::AccountId>;
+    type Header = Header;
+    type Event = Event;
+    type BlockHashCount = ConstU64<250>;
+    type Version = ();
+    type PalletInfo = PalletInfo;
+    type AccountData = pallet_balances::AccountData<u64>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+    type SS58Prefix = ConstU16<42>;
+    type OnSetCode = ();
+    type MaxConsumers = frame_support::traits::ConstU32<16>;
+}
+
+impl pallet_timestamp::Config for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = ConstU64<1>;
+    type WeightInfo = ();
+}
+
+impl pallet_balances::Config for Test {
+    type MaxLocks = ();
+    type MaxReserves = ();
+    type ReserveIdentifier = [u8; 8];
+    type Balance = u64;
+    type Event = Event;
+    type DustRemoval = ();
+    type ExistentialDeposit = ConstU64<1>;
+    type AccountStore = System;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    // in the storage so that a test can disable some indexes
+    pub storage QuadIndexes: pallet_graphdb::QuadIndexes = pallet_graphdb::QuadIndexes::ALL
+        .with(pallet_graphdb::QuadIndexes::SPATIAL)
+        .with(pallet_graphdb::QuadIndexes::VECTOR)
+        .with(pallet_graphdb::QuadIndexes::TEXT);
+}
+
+parameter_types! {
+    pub const MaxValueLength: u32 = 1024;
+    pub const Namespaces: &'static [&'static str] = &["http://example.com/"];
+    // small batches so that the tests run the cursors over several blocks
+    pub const ReEncodingBatchSize: u32 = 3;
+    pub const IndexRepairBatchSize: u32 = 3;
+    pub const GraphPurgeBatchSize: u32 = 3;
+    pub const InferredGraph: Option<&'static str> = Some("http://example.com/inferred");
+    pub const RulesGraph: Option<&'static str> = Some("http://example.com/rules");
+    pub const DerivedGraph: &'static str = "http://example.com/derived";
+    pub const MaxRuleRounds: u32 = 8;
+    pub const ShapesGraph: Option<&'static str> = Some("http://example.com/shapes");
+    pub const MaxFunctionSize: u32 = 64 * 1024;
+    pub const MaxFunctionFuel: u64 = 100_000;
+    pub const FunctionDeposit: u64 = 100;
+    pub const FunctionByteDeposit: u64 = 1;
+    pub const MaxUpdateWeight: Weight = WEIGHT_PER_SECOND;
+}
+
+impl pallet_graphdb::Config for Test {
+    type Event = Event;
+    type MaxValueLength = MaxValueLength;
+    type QuadIndexes = QuadIndexes;
+    type Namespaces = Namespaces;
+    type ReEncodingBatchSize = ReEncodingBatchSize;
+    type IndexRepairBatchSize = IndexRepairBatchSize;
+    type GraphPurgeBatchSize = GraphPurgeBatchSize;
+    type InferredGraph = InferredGraph;
+    type RulesGraph = RulesGraph;
+    type DerivedGraph = DerivedGraph;
+    type MaxRuleRounds = MaxRuleRounds;
+    type ShapesGraph = ShapesGraph;
+    type MaxFunctionSize = MaxFunctionSize;
+    type MaxFunctionFuel = MaxFunctionFuel;
+    type Currency = Balances;
+    type FunctionDeposit = FunctionDeposit;
+    type FunctionByteDeposit = FunctionByteDeposit;
+    type MaxUpdateWeight = MaxUpdateWeight;
+}
+
+// Build genesis storage according to the mock runtime.
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    let mut storage = system::GenesisConfig::default().build_storage::<Test>().unwrap();
+    // the accounts registering functions reserve a deposit
+    pallet_balances::GenesisConfig::<Test> { balances: vec![(1, 10_000), (2, 10_000), (3, 10)] }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+    storage.into()
+}
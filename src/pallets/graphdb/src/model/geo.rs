@@ -0,0 +1,668 @@
+//! Geometries of the [GeoSPARQL](https://www.ogc.org/standards/geosparql) `geo:wktLiteral` datatype.
+//!
+//! The points, line strings and polygons of the Well-Known Text format are supported, with the coordinates of
+//! the default `CRS84` reference system: longitude then latitude, in degrees. The relations between geometries
+//! are computed in the plane of the coordinates. The distances in metres are the great-circle distances between
+//! the closest points of the geometries in this plane, which are exact for points.
+
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// The default coordinate reference system, the only one supported
+const CRS84: &str = "http://www.opengis.net/def/crs/OGC/1.3/CRS84";
+
+/// Mean radius of the Earth, in metres
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Length of a degree of latitude, in metres
+const METRES_PER_DEGREE: f64 = EARTH_RADIUS * PI / 180.;
+
+/// Number of sides of the polygons approximating the circles of `Geometry::buffer`
+const CIRCLE_SIDES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    /// Longitude
+    pub x: f64,
+    /// Latitude
+    pub y: f64,
+}
+
+impl Point {
+    fn new(x: f64, y: f64) -> Self {
+        // -0 and 0 are the same coordinate
+        Self { x: x + 0., y: y + 0. }
+    }
+
+    fn sub(self, other: Self) -> Self {
+        Self { x: self.x - other.x, y: self.y - other.y }
+    }
+
+    fn dot(self, other: Self) -> f64 {
+        self.x * other.x + self.y * other.y
+    }
+
+    fn cross(self, other: Self) -> f64 {
+        self.x * other.y - self.y * other.x
+    }
+
+    fn lerp(self, other: Self, t: f64) -> Self {
+        Self { x: self.x + (other.x - self.x) * t, y: self.y + (other.y - self.y) * t }
+    }
+
+    fn distance(self, other: Self) -> f64 {
+        let d = self.sub(other);
+        d.dot(d).sqrt()
+    }
+
+    /// Great-circle distance, in metres
+    fn haversine(self, other: Self) -> f64 {
+        let (lat_a, lat_b) = (self.y.to_radians(), other.y.to_radians());
+        let h = ((lat_b - lat_a) / 2.).sin().powi(2)
+            + lat_a.cos() * lat_b.cos() * ((other.x - self.x).to_radians() / 2.).sin().powi(2);
+        2. * EARTH_RADIUS * h.sqrt().min(1.).asin()
+    }
+}
+
+/// A geometry with the coordinates of the `CRS84` reference system
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point(Point),
+    /// At least two points
+    LineString(Vec<Point>),
+    /// The exterior ring then the holes, each ring is closed and has at least four points
+    Polygon(Vec<Vec<Point>>),
+}
+
+/// The units of distance of the GeoSPARQL functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Metre,
+    Degree,
+    Radian,
+}
+
+impl Unit {
+    pub fn from_iri(iri: &str) -> Option<Self> {
+        match iri {
+            "http://www.opengis.net/def/uom/OGC/1.0/metre" => Some(Self::Metre),
+            "http://www.opengis.net/def/uom/OGC/1.0/degree" => Some(Self::Degree),
+            "http://www.opengis.net/def/uom/OGC/1.0/radian" => Some(Self::Radian),
+            _ => None,
+        }
+    }
+}
+
+/// The bounding box of geometries, the longitudes do not wrap around
+#[derive(Debug, Clone, Copy)]
+pub struct Envelope {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl Envelope {
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min_x <= other.max_x && other.min_x <= self.max_x && self.min_y <= other.max_y && other.min_y <= self.max_y
+    }
+
+    pub fn contains(&self, other: &Self) -> bool {
+        self.min_x <= other.min_x && other.max_x <= self.max_x && self.min_y <= other.min_y && other.max_y <= self.max_y
+    }
+
+    /// The box of the points at most `distance` away from the box, within the range of the coordinates.
+    ///
+    /// A box crossing the antimeridian is widened to all the longitudes, the great-circle distances wrap around.
+    pub fn expand(&self, distance: f64, unit: Unit) -> Self {
+        let (dx, dy) = match unit {
+            Unit::Degree => (distance, distance),
+            Unit::Radian => (distance.to_degrees(), distance.to_degrees()),
+            Unit::Metre => {
+                let dy = distance / METRES_PER_DEGREE;
+                let latitude = self.min_y.abs().max(self.max_y.abs()) + dy;
+                let ratio = (distance / EARTH_RADIUS / 2.).sin() / latitude.to_radians().cos();
+                if latitude >= 90. || ratio >= 1. {
+                    (360., dy)
+                } else {
+                    (2. * ratio.asin().to_degrees(), dy)
+                }
+            }
+        };
+        let expanded = Self {
+            min_x: self.min_x - dx,
+            min_y: (self.min_y - dy).max(-90.),
+            max_x: self.max_x + dx,
+            max_y: (self.max_y + dy).min(90.),
+        };
+        if expanded.min_x < -180. || expanded.max_x > 180. {
+            Self { min_x: -180., max_x: 180., ..expanded }
+        } else {
+            expanded
+        }
+    }
+}
+
+impl PartialEq for Envelope {
+    fn eq(&self, other: &Self) -> bool {
+        self.min_x.to_bits() == other.min_x.to_bits()
+            && self.min_y.to_bits() == other.min_y.to_bits()
+            && self.max_x.to_bits() == other.max_x.to_bits()
+            && self.max_y.to_bits() == other.max_y.to_bits()
+    }
+}
+
+impl Eq for Envelope {}
+
+impl Hash for Envelope {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for value in [self.min_x, self.min_y, self.max_x, self.max_y].iter() {
+            value.to_bits().hash(state);
+        }
+    }
+}
+
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BOX({} {}, {} {})", self.min_x, self.min_y, self.max_x, self.max_y)
+    }
+}
+
+impl Geometry {
+    pub fn envelope(&self) -> Envelope {
+        let mut envelope = Envelope { min_x: f64::INFINITY, min_y: f64::INFINITY, max_x: -f64::INFINITY, max_y: -f64::INFINITY };
+        for point in self.points() {
+            envelope.min_x = envelope.min_x.min(point.x);
+            envelope.min_y = envelope.min_y.min(point.y);
+            envelope.max_x = envelope.max_x.max(point.x);
+            envelope.max_y = envelope.max_y.max(point.y);
+        }
+        envelope
+    }
+
+    fn points(&self) -> Vec<Point> {
+        match self {
+            Self::Point(point) => vec![*point],
+            Self::LineString(points) => points.clone(),
+            Self::Polygon(rings) => rings.iter().flatten().copied().collect(),
+        }
+    }
+
+    /// The segments of the geometry, a point is a segment of length zero
+    fn segments(&self) -> Vec<(Point, Point)> {
+        match self {
+            Self::Point(point) => vec![(*point, *point)],
+            Self::LineString(points) => points.windows(2).map(|w| (w[0], w[1])).collect(),
+            Self::Polygon(rings) => rings.iter().flat_map(|ring| ring.windows(2).map(|w| (w[0], w[1]))).collect(),
+        }
+    }
+
+    /// Whether `point` is strictly inside the area of a polygon, the boundary excluded
+    fn area_contains(&self, point: Point) -> bool {
+        let rings = match self {
+            Self::Polygon(rings) => rings,
+            _ => return false,
+        };
+        if self.segments().iter().any(|(a, b)| on_segment(point, *a, *b)) {
+            return false;
+        }
+        // even-odd rule: the holes are crossed twice
+        let mut inside = false;
+        for ring in rings {
+            for w in ring.windows(2) {
+                let (a, b) = (w[0], w[1]);
+                if (a.y > point.y) != (b.y > point.y)
+                    && point.x < a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y)
+                {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    /// Whether `point` belongs to the geometry, its boundary included
+    fn covers(&self, point: Point) -> bool {
+        self.segments().iter().any(|(a, b)| on_segment(point, *a, *b)) || self.area_contains(point)
+    }
+
+    /// Whether `point` belongs to the interior of the geometry
+    fn interior_contains(&self, point: Point) -> bool {
+        match self {
+            Self::Point(p) => *p == point,
+            Self::LineString(points) => {
+                let first = points[0];
+                let last = points[points.len() - 1];
+                // the end points of an open line string are its boundary
+                self.covers(point) && (first == last || (point != first && point != last))
+            }
+            Self::Polygon(_) => self.area_contains(point),
+        }
+    }
+
+    /// The points sampling the geometry against `other`: its vertices and a point of each piece of its segments
+    /// between two crossings of `other`, the pieces being either inside, outside or along `other`.
+    fn samples(&self, other: &Self) -> Vec<Point> {
+        let others = other.segments();
+        let mut samples = Vec::new();
+        for (a, b) in self.segments() {
+            let mut cuts = vec![0., 1.];
+            for (c, d) in others.iter() {
+                cuts.extend(crossings(a, b, *c, *d));
+            }
+            cuts.sort_by(|x, y| x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal));
+            cuts.dedup();
+            samples.push(a);
+            for w in cuts.windows(2) {
+                samples.push(a.lerp(b, (w[0] + w[1]) / 2.));
+                samples.push(a.lerp(b, w[1]));
+            }
+        }
+        samples
+    }
+
+    /// [sfIntersects](http://www.opengis.net/def/function/geosparql/sfIntersects): the geometries share a point
+    pub fn intersects(&self, other: &Self) -> bool {
+        if !self.envelope().intersects(&other.envelope()) {
+            return false;
+        }
+        let others = other.segments();
+        self.segments()
+            .iter()
+            .any(|(a, b)| others.iter().any(|(c, d)| segments_intersect(*a, *b, *c, *d)))
+            || other.points().iter().any(|point| self.area_contains(*point))
+            || self.points().iter().any(|point| other.area_contains(*point))
+    }
+
+    /// [sfWithin](http://www.opengis.net/def/function/geosparql/sfWithin): all the points of the geometry belong
+    /// to `other` and their interiors share a point
+    pub fn within(&self, other: &Self) -> bool {
+        if !other.envelope().contains(&self.envelope()) {
+            return false;
+        }
+        let samples = self.samples(other);
+        if !samples.iter().all(|point| other.covers(*point)) {
+            return false;
+        }
+        match (self, other) {
+            (Self::Polygon(_), Self::Polygon(_)) => {
+                // the boundary of `other` does not enter the area, e.g. a hole of `other`
+                !other.samples(self).iter().any(|point| self.area_contains(*point))
+            }
+            (Self::Polygon(_), _) => false,
+            _ => samples.iter().any(|point| other.interior_contains(*point)),
+        }
+    }
+
+    /// [distance](http://www.opengis.net/def/function/geosparql/distance): the distance between the closest
+    /// points of the geometries, zero if they intersect
+    pub fn distance(&self, other: &Self, unit: Unit) -> f64 {
+        if self.intersects(other) {
+            return 0.;
+        }
+        let mut closest = (f64::INFINITY, Point::new(0., 0.), Point::new(0., 0.));
+        for (a, b) in self.segments() {
+            for (c, d) in other.segments() {
+                for (p, q) in [
+                    (a, closest_point(a, c, d)),
+                    (b, closest_point(b, c, d)),
+                    (closest_point(c, a, b), c),
+                    (closest_point(d, a, b), d),
+                ]
+                .iter()
+                {
+                    let distance = p.distance(*q);
+                    if distance < closest.0 {
+                        closest = (distance, *p, *q);
+                    }
+                }
+            }
+        }
+        let (distance, p, q) = closest;
+        match unit {
+            Unit::Degree => distance,
+            Unit::Radian => distance.to_radians(),
+            Unit::Metre => p.haversine(q),
+        }
+    }
+
+    /// [buffer](http://www.opengis.net/def/function/geosparql/buffer): the polygon of the points at most
+    /// `radius` away from the geometry, `None` if the radius is not positive or if the buffer reaches a pole.
+    ///
+    /// The buffer is the convex hull of polygons enclosing the circles around the vertices: it is exact, up to
+    /// the polygons, for points, segments and convex polygons, and larger than the exact buffer otherwise.
+    pub fn buffer(&self, radius: f64, unit: Unit) -> Option<Self> {
+        if radius <= 0. || !radius.is_finite() {
+            return None;
+        }
+        let envelope = self.envelope();
+        let (rx, ry) = match unit {
+            Unit::Degree => (radius, radius),
+            Unit::Radian => (radius.to_degrees(), radius.to_degrees()),
+            Unit::Metre => {
+                let ry = radius / METRES_PER_DEGREE;
+                let latitude = envelope.min_y.abs().max(envelope.max_y.abs()) + ry;
+                if latitude >= 90. {
+                    return None;
+                }
+                (ry / latitude.to_radians().cos(), ry)
+            }
+        };
+        // the sides of the polygons are tangent to the circles
+        let scale = 1. / (PI / CIRCLE_SIDES as f64).cos();
+        let mut points = Vec::new();
+        for center in self.points() {
+            for i in 0..CIRCLE_SIDES {
+                let angle = 2. * PI * i as f64 / CIRCLE_SIDES as f64;
+                points.push(Point::new(
+                    center.x + rx * scale * angle.cos(),
+                    center.y + ry * scale * angle.sin(),
+                ));
+            }
+        }
+        Some(Self::Polygon(vec![convex_hull(points)]))
+    }
+}
+
+/// Whether `point` belongs to the segment `[a, b]`
+fn on_segment(point: Point, a: Point, b: Point) -> bool {
+    b.sub(a).cross(point.sub(a)) == 0.
+        && point.x >= a.x.min(b.x)
+        && point.x <= a.x.max(b.x)
+        && point.y >= a.y.min(b.y)
+        && point.y <= a.y.max(b.y)
+}
+
+fn segments_intersect(a: Point, b: Point, c: Point, d: Point) -> bool {
+    let d1 = d.sub(c).cross(a.sub(c));
+    let d2 = d.sub(c).cross(b.sub(c));
+    let d3 = b.sub(a).cross(c.sub(a));
+    let d4 = b.sub(a).cross(d.sub(a));
+    if ((d1 > 0. && d2 < 0.) || (d1 < 0. && d2 > 0.)) && ((d3 > 0. && d4 < 0.) || (d3 < 0. && d4 > 0.)) {
+        return true;
+    }
+    on_segment(a, c, d) || on_segment(b, c, d) || on_segment(c, a, b) || on_segment(d, a, b)
+}
+
+/// The positions on `[a, b]`, from 0 to 1, where the segment `[c, d]` meets it
+fn crossings(a: Point, b: Point, c: Point, d: Point) -> Vec<f64> {
+    let r = b.sub(a);
+    let s = d.sub(c);
+    let length = r.dot(r);
+    if length == 0. {
+        return Vec::new();
+    }
+    let denominator = r.cross(s);
+    let position = |t: f64| Some(t).filter(|t| (0. ..=1.).contains(t));
+    if denominator == 0. {
+        // parallel: the ends of the overlap of collinear segments
+        if r.cross(c.sub(a)) != 0. {
+            return Vec::new();
+        }
+        return [c, d].iter().filter_map(|p| position(p.sub(a).dot(r) / length)).collect();
+    }
+    let t = c.sub(a).cross(s) / denominator;
+    let u = c.sub(a).cross(r) / denominator;
+    if (0. ..=1.).contains(&u) {
+        position(t).into_iter().collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// The point of the segment `[a, b]` closest to `point`
+fn closest_point(point: Point, a: Point, b: Point) -> Point {
+    let r = b.sub(a);
+    let length = r.dot(r);
+    if length == 0. {
+        return a;
+    }
+    a.lerp(b, (point.sub(a).dot(r) / length).clamp(0., 1.))
+}
+
+/// The closed ring of the convex hull of `points` (Andrew's monotone chain)
+fn convex_hull(mut points: Vec<Point>) -> Vec<Point> {
+    points.sort_by(|a, b| {
+        (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap_or(std::cmp::Ordering::Equal)
+    });
+    points.dedup();
+    let mut hull: Vec<Point> = Vec::new();
+    for pass in 0..2 {
+        let start = hull.len();
+        let iter: Box<dyn Iterator<Item = &Point>> = if pass == 0 {
+            Box::new(points.iter())
+        } else {
+            Box::new(points.iter().rev())
+        };
+        for point in iter {
+            while hull.len() >= start + 2
+                && hull[hull.len() - 1].sub(hull[hull.len() - 2]).cross(point.sub(hull[hull.len() - 2])) <= 0.
+            {
+                hull.pop();
+            }
+            hull.push(*point);
+        }
+        hull.pop();
+    }
+    hull.push(hull[0]);
+    hull
+}
+
+impl FromStr for Geometry {
+    type Err = GeometryParseError;
+
+    /// Parses a Well-Known Text geometry, optionally preceded by the IRI of the `CRS84` reference system
+    fn from_str(input: &str) -> Result<Self, GeometryParseError> {
+        let mut input = input.trim();
+        if let Some(rest) = input.strip_prefix('<') {
+            let end = rest.find('>').ok_or(GeometryParseError { msg: "the reference system IRI is not closed" })?;
+            if &rest[..end] != CRS84 {
+                return Err(GeometryParseError { msg: "only the CRS84 reference system is supported" });
+            }
+            input = &rest[end + 1..];
+        }
+        let mut reader = WktReader { input: input.trim_start() };
+        let kind = reader.keyword();
+        let geometry = if kind.eq_ignore_ascii_case("POINT") {
+            reader.expect('(')?;
+            let point = reader.point()?;
+            reader.expect(')')?;
+            Self::Point(point)
+        } else if kind.eq_ignore_ascii_case("LINESTRING") {
+            let points = reader.points()?;
+            if points.len() < 2 {
+                return Err(GeometryParseError { msg: "a line string has at least two points" });
+            }
+            Self::LineString(points)
+        } else if kind.eq_ignore_ascii_case("POLYGON") {
+            reader.expect('(')?;
+            let mut rings = vec![reader.points()?];
+            while reader.eat(',') {
+                rings.push(reader.points()?);
+            }
+            reader.expect(')')?;
+            for ring in rings.iter() {
+                if ring.len() < 4 || ring[0] != ring[ring.len() - 1] {
+                    return Err(GeometryParseError { msg: "a polygon ring is closed and has at least four points" });
+                }
+            }
+            Self::Polygon(rings)
+        } else {
+            return Err(GeometryParseError { msg: "only points, line strings and polygons are supported" });
+        };
+        if !reader.input.trim().is_empty() {
+            return Err(GeometryParseError { msg: "unexpected content after the geometry" });
+        }
+        Ok(geometry)
+    }
+}
+
+/// Writes the canonical Well-Known Text of the geometry, without reference system
+impl fmt::Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_points(f: &mut fmt::Formatter<'_>, points: &[Point]) -> fmt::Result {
+            f.write_str("(")?;
+            for (i, point) in points.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{} {}", point.x, point.y)?;
+            }
+            f.write_str(")")
+        }
+
+        match self {
+            Self::Point(point) => write!(f, "POINT({} {})", point.x, point.y),
+            Self::LineString(points) => {
+                f.write_str("LINESTRING")?;
+                write_points(f, points)
+            }
+            Self::Polygon(rings) => {
+                f.write_str("POLYGON(")?;
+                for (i, ring) in rings.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write_points(f, ring)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+struct WktReader<'a> {
+    input: &'a str,
+}
+
+impl<'a> WktReader<'a> {
+    fn keyword(&mut self) -> &'a str {
+        let end = self.input.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(self.input.len());
+        let (keyword, rest) = self.input.split_at(end);
+        self.input = rest.trim_start();
+        keyword
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if let Some(rest) = self.input.strip_prefix(c) {
+            self.input = rest.trim_start();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), GeometryParseError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(GeometryParseError { msg: "unexpected character in the geometry" })
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, GeometryParseError> {
+        let end = self
+            .input
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E')))
+            .unwrap_or(self.input.len());
+        let value = self.input[..end]
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or(GeometryParseError { msg: "invalid coordinate" })?;
+        self.input = self.input[end..].trim_start();
+        Ok(value)
+    }
+
+    fn point(&mut self) -> Result<Point, GeometryParseError> {
+        let x = self.number()?;
+        let y = self.number()?;
+        Ok(Point::new(x, y))
+    }
+
+    fn points(&mut self) -> Result<Vec<Point>, GeometryParseError> {
+        self.expect('(')?;
+        let mut points = vec![self.point()?];
+        while self.eat(',') {
+            points.push(self.point()?);
+        }
+        self.expect(')')?;
+        Ok(points)
+    }
+}
+
+/// An error raised while parsing a Well-Known Text geometry
+#[derive(Debug, Clone)]
+pub struct GeometryParseError {
+    msg: &'static str,
+}
+
+impl fmt::Display for GeometryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid WKT geometry: {}", self.msg)
+    }
+}
+
+impl Error for GeometryParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry(wkt: &str) -> Geometry {
+        wkt.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        assert_eq!(
+            geometry("<http://www.opengis.net/def/crs/OGC/1.3/CRS84> point ( 2.35 48.85 )").to_string(),
+            "POINT(2.35 48.85)"
+        );
+        assert_eq!(
+            geometry("Polygon((0 0,4 0,4 4,0 4,0 0),(1 1, 2 1, 2 2, 1 1))").to_string(),
+            "POLYGON((0 0, 4 0, 4 4, 0 4, 0 0), (1 1, 2 1, 2 2, 1 1))"
+        );
+        assert!("POLYGON((0 0, 1 0, 1 1))".parse::<Geometry>().is_err());
+        assert!("LINESTRING(0 0)".parse::<Geometry>().is_err());
+        assert!("<http://www.opengis.net/def/crs/EPSG/0/4326> POINT(1 2)".parse::<Geometry>().is_err());
+    }
+
+    #[test]
+    fn relations() {
+        let square = geometry("POLYGON((0 0, 4 0, 4 4, 0 4, 0 0), (1 1, 2 1, 2 2, 1 2, 1 1))");
+        assert!(geometry("POINT(3 3)").within(&square));
+        assert!(!geometry("POINT(1.5 1.5)").within(&square));
+        assert!(!geometry("POINT(0 2)").within(&square));
+        assert!(geometry("POINT(0 2)").intersects(&square));
+        assert!(geometry("LINESTRING(0 3, 4 3)").within(&square));
+        assert!(!geometry("LINESTRING(0 0, 4 0)").within(&square));
+        assert!(!geometry("LINESTRING(0.5 0.5, 3 3)").within(&square));
+        assert!(geometry("POLYGON((2.5 2.5, 3.5 2.5, 3.5 3.5, 2.5 2.5))").within(&square));
+        assert!(!geometry("POLYGON((0.5 0.5, 3.5 0.5, 3.5 3.5, 0.5 3.5, 0.5 0.5))").within(&square));
+        assert!(geometry("LINESTRING(-1 -1, 5 5)").intersects(&square));
+        assert!(!geometry("POINT(5 5)").intersects(&square));
+        assert!(geometry("POINT(2 2)").within(&geometry("LINESTRING(0 0, 4 4)")));
+        assert!(!geometry("POINT(0 0)").within(&geometry("LINESTRING(0 0, 4 4)")));
+    }
+
+    #[test]
+    fn distances_and_buffers() {
+        let paris = geometry("POINT(2.3522 48.8566)");
+        let london = geometry("POINT(-0.1276 51.5072)");
+        let distance = paris.distance(&london, Unit::Metre);
+        assert!((distance - 343_560.).abs() < 1_000., "{}", distance);
+        assert_eq!(geometry("POINT(0 0)").distance(&geometry("LINESTRING(3 -1, 3 1)"), Unit::Degree), 3.);
+
+        let buffer = paris.buffer(5_000., Unit::Metre).unwrap();
+        assert!(geometry("POINT(2.4 48.87)").within(&buffer));
+        assert!(!geometry("POINT(2.45 48.87)").within(&buffer));
+        assert!(paris.buffer(-1., Unit::Metre).is_none());
+        // the polygon encloses the circle
+        let envelope = paris.envelope().expand(5_000., Unit::Metre);
+        assert!(buffer.envelope().contains(&envelope));
+    }
+}
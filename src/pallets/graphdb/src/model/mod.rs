@@ -3,6 +3,7 @@
 //! Inspired by [RDF/JS](https://rdf.js.org/data-model-spec/) and [Apache Commons RDF](http://commons.apache.org/proper/commons-rdf/)
 
 mod blank_node;
+pub(crate) mod geo;
 mod literal;
 mod named_node;
 mod parser;
@@ -10,6 +11,7 @@ mod parser;
 mod sophia;
 mod triple;
 pub mod vocab;
+pub(crate) mod vector;
 pub(crate) mod xsd;
 
 pub use crate::model::blank_node::{BlankNode, BlankNodeIdParseError, BlankNodeRef};
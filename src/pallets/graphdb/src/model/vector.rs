@@ -0,0 +1,157 @@
+//! Embeddings of the `vec:floatVector` datatype.
+//!
+//! The lexical form of a vector is the list of its components between brackets, e.g. `"[0.12, -3.5, 1e-3]"`.
+//! The components are single precision floats: the canonical form writes the shortest decimal of each of them,
+//! so that the equal vectors have the same literal whatever the precision of the input.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Most components of a vector
+pub const MAX_DIMENSIONS: usize = 4096;
+
+/// A vector of finite single precision floats, with at least one component
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vector(Vec<f32>);
+
+impl Vector {
+    pub fn components(&self) -> &[f32] {
+        &self.0
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The dot product, `None` if the vectors have different dimensions
+    pub fn dot(&self, other: &Self) -> Option<f64> {
+        if self.0.len() != other.0.len() {
+            return None;
+        }
+        Some(self.0.iter().zip(&other.0).map(|(a, b)| f64::from(*a) * f64::from(*b)).sum())
+    }
+
+    /// The Euclidean norm
+    pub fn norm(&self) -> f64 {
+        self.0.iter().map(|a| f64::from(*a) * f64::from(*a)).sum::<f64>().sqrt()
+    }
+
+    /// One minus the cosine of the angle of the vectors, between 0 and 2.
+    /// `None` if the vectors have different dimensions or if one of them is zero.
+    pub fn cosine_distance(&self, other: &Self) -> Option<f64> {
+        let norms = self.norm() * other.norm();
+        if norms == 0. {
+            return None;
+        }
+        Some((1. - self.dot(other)? / norms).clamp(0., 2.))
+    }
+
+    /// The Euclidean distance, `None` if the vectors have different dimensions
+    pub fn l2_distance(&self, other: &Self) -> Option<f64> {
+        if self.0.len() != other.0.len() {
+            return None;
+        }
+        Some(
+            self.0
+                .iter()
+                .zip(&other.0)
+                .map(|(a, b)| (f64::from(*a) - f64::from(*b)).powi(2))
+                .sum::<f64>()
+                .sqrt(),
+        )
+    }
+}
+
+impl From<Vector> for Vec<f32> {
+    fn from(vector: Vector) -> Self {
+        vector.0
+    }
+}
+
+impl FromStr for Vector {
+    type Err = VectorParseError;
+
+    /// Parses the components of a vector between brackets, separated by commas
+    fn from_str(input: &str) -> Result<Self, VectorParseError> {
+        let input = input
+            .trim()
+            .strip_prefix('[')
+            .and_then(|input| input.strip_suffix(']'))
+            .ok_or(VectorParseError { msg: "a vector is written between brackets" })?;
+        let mut components = Vec::new();
+        for component in input.split(',') {
+            if components.len() == MAX_DIMENSIONS {
+                return Err(VectorParseError { msg: "a vector has at most 4096 components" });
+            }
+            let component = component
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| VectorParseError { msg: "a component is not a number" })?;
+            if !component.is_finite() {
+                return Err(VectorParseError { msg: "a component is not finite" });
+            }
+            // -0 and 0 are the same component
+            components.push(component + 0.);
+        }
+        Ok(Self(components))
+    }
+}
+
+/// Writes the canonical form of the vector
+impl fmt::Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+        for (i, component) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", component)?;
+        }
+        f.write_str("]")
+    }
+}
+
+/// An error raised while parsing a vector
+#[derive(Debug, Clone)]
+pub struct VectorParseError {
+    msg: &'static str,
+}
+
+impl fmt::Display for VectorParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid vector: {}", self.msg)
+    }
+}
+
+impl Error for VectorParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(value: &str) -> Vector {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        assert_eq!(vector(" [1,2.50, -3e-2 ,-0] ").to_string(), "[1, 2.5, -0.03, 0]");
+        assert_eq!(vector("[0.1]").to_string(), "[0.1]");
+        assert!("[]".parse::<Vector>().is_err());
+        assert!("[1, 2".parse::<Vector>().is_err());
+        assert!("[1, NaN]".parse::<Vector>().is_err());
+        assert!("[1, inf]".parse::<Vector>().is_err());
+    }
+
+    #[test]
+    fn distances() {
+        let (a, b) = (vector("[1, 0]"), vector("[0, 2]"));
+        assert_eq!(a.dot(&b), Some(0.));
+        assert_eq!(a.cosine_distance(&b), Some(1.));
+        assert_eq!(a.cosine_distance(&vector("[3, 0]")), Some(0.));
+        assert_eq!(a.l2_distance(&b), Some(5_f64.sqrt()));
+        assert_eq!(a.cosine_distance(&vector("[0, 0]")), None);
+        assert_eq!(a.dot(&vector("[1, 0, 0]")), None);
+    }
+}
@@ -108,6 +108,162 @@ pub mod rdfs {
         NamedNodeRef::new_unchecked("http://www.w3.org/2000/01/rdf-schema#subPropertyOf");
 }
 
+pub mod geo {
+    //! [GeoSPARQL](https://www.ogc.org/standards/geosparql) vocabulary, the geometry literals of the store
+    use crate::model::named_node::NamedNodeRef;
+
+    /// Geometries in the Well-Known Text format (see `crate::model::geo`).
+    pub const WKT_LITERAL: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.opengis.net/ont/geosparql#wktLiteral");
+}
+
+pub mod geof {
+    //! [GeoSPARQL](https://www.ogc.org/standards/geosparql) functions evaluated by the store
+    use crate::model::named_node::NamedNodeRef;
+
+    /// The polygon of the points at most a distance away from a geometry.
+    pub const BUFFER: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.opengis.net/def/function/geosparql/buffer");
+    /// The distance between the closest points of two geometries.
+    pub const DISTANCE: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.opengis.net/def/function/geosparql/distance");
+    /// Whether two geometries share a point.
+    pub const SF_INTERSECTS: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.opengis.net/def/function/geosparql/sfIntersects");
+    /// Whether a geometry is inside another one.
+    pub const SF_WITHIN: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.opengis.net/def/function/geosparql/sfWithin");
+}
+
+pub mod vec {
+    //! Vocabulary of the embeddings of the store and of their distance functions
+    use crate::model::named_node::NamedNodeRef;
+
+    /// The cosine distance of two vectors, one minus the cosine of their angle.
+    pub const COSINE_DISTANCE: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://relationlabs.ai/vector/cosineDistance");
+    /// The dot product of two vectors.
+    pub const DOT_PRODUCT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://relationlabs.ai/vector/dotProduct");
+    /// Vectors of single precision floats (see `crate::model::vector`).
+    pub const FLOAT_VECTOR: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://relationlabs.ai/vector/floatVector");
+    /// The Euclidean distance of two vectors.
+    pub const L2_DISTANCE: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://relationlabs.ai/vector/l2Distance");
+}
+
+pub mod owl {
+    //! [OWL 2](https://www.w3.org/TR/owl2-syntax/) vocabulary, the terms interpreted by the store
+    use crate::model::named_node::NamedNodeRef;
+
+    /// The subject property relates the objects of the object property to its subjects.
+    pub const INVERSE_OF: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/2002/07/owl#inverseOf");
+    /// The subject and the object denote the same individual.
+    pub const SAME_AS: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/2002/07/owl#sameAs");
+    /// The class of the properties relating their objects to their subjects.
+    pub const SYMMETRIC_PROPERTY: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/2002/07/owl#SymmetricProperty");
+    /// The class of the transitive properties.
+    pub const TRANSITIVE_PROPERTY: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/2002/07/owl#TransitiveProperty");
+}
+
+pub mod sh {
+    //! [SHACL](https://www.w3.org/TR/shacl/) vocabulary, the terms of the SHACL Core shapes checked by the store
+    use crate::model::named_node::NamedNodeRef;
+
+    /// The class the value nodes must be instances of.
+    pub const CLASS: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#class");
+    /// The component of `sh:class`.
+    pub const CLASS_CONSTRAINT_COMPONENT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#ClassConstraintComponent");
+    /// Whether the data graph conforms to the shapes graph.
+    pub const CONFORMS: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#conforms");
+    /// The datatype of the value nodes, they must be literals.
+    pub const DATATYPE: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#datatype");
+    /// The component of `sh:datatype`.
+    pub const DATATYPE_CONSTRAINT_COMPONENT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#DatatypeConstraintComponent");
+    /// The flags of `sh:pattern`.
+    pub const FLAGS: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#flags");
+    /// The focus node of a validation result.
+    pub const FOCUS_NODE: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#focusNode");
+    /// The maximum number of value nodes.
+    pub const MAX_COUNT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#maxCount");
+    /// The component of `sh:maxCount`.
+    pub const MAX_COUNT_CONSTRAINT_COMPONENT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#MaxCountConstraintComponent");
+    /// The minimum number of value nodes.
+    pub const MIN_COUNT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#minCount");
+    /// The component of `sh:minCount`.
+    pub const MIN_COUNT_CONSTRAINT_COMPONENT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#MinCountConstraintComponent");
+    /// The path of a property shape from the focus nodes to the value nodes.
+    pub const PATH: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#path");
+    /// The regular expression the string of the value nodes must match.
+    pub const PATTERN: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#pattern");
+    /// The component of `sh:pattern`.
+    pub const PATTERN_CONSTRAINT_COMPONENT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#PatternConstraintComponent");
+    /// A property shape of a shape.
+    pub const PROPERTY: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#property");
+    /// A validation result of a validation report.
+    pub const RESULT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#result");
+    /// The message of a validation result.
+    pub const RESULT_MESSAGE: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#resultMessage");
+    /// The path of the property shape of a validation result.
+    pub const RESULT_PATH: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#resultPath");
+    /// The severity of a validation result.
+    pub const RESULT_SEVERITY: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#resultSeverity");
+    /// The component of the constraint of a validation result.
+    pub const SOURCE_CONSTRAINT_COMPONENT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#sourceConstraintComponent");
+    /// The shape of a validation result.
+    pub const SOURCE_SHAPE: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#sourceShape");
+    /// The focus nodes of a shape are the instances of the class.
+    pub const TARGET_CLASS: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#targetClass");
+    /// The node is a focus node of a shape.
+    pub const TARGET_NODE: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#targetNode");
+    /// The focus nodes of a shape are the objects of the property.
+    pub const TARGET_OBJECTS_OF: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#targetObjectsOf");
+    /// The focus nodes of a shape are the subjects of the property.
+    pub const TARGET_SUBJECTS_OF: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#targetSubjectsOf");
+    /// The class of the validation reports.
+    pub const VALIDATION_REPORT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#ValidationReport");
+    /// The class of the validation results.
+    pub const VALIDATION_RESULT: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#ValidationResult");
+    /// The value node of a validation result.
+    pub const VALUE: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#value");
+    /// The severity of the violations of constraints.
+    pub const VIOLATION: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("http://www.w3.org/ns/shacl#Violation");
+}
+
 pub mod xsd {
     //! [RDF compatible XSD datatypes](https://www.w3.org/TR/rdf11-concepts/#dfn-rdf-compatible-xsd-types)
     use crate::model::named_node::NamedNodeRef;
@@ -108,6 +108,11 @@ impl DateTime {
         self.timestamp.to_be_bytes()
     }
 
+    /// Seconds since the epoch, in UTC if the timezone is known
+    pub(crate) fn timestamp_seconds(&self) -> Decimal {
+        self.timestamp.value
+    }
+
     /// [op:subtract-dateTimes](https://www.w3.org/TR/xpath-functions/#func-subtract-dateTimes)
     pub fn checked_sub(&self, rhs: impl Into<Self>) -> Option<Duration> {
         self.timestamp.checked_sub(rhs.into().timestamp)
@@ -287,6 +292,11 @@ impl Time {
         self.timestamp.to_be_bytes()
     }
 
+    /// Seconds since the epoch, in UTC if the timezone is known
+    pub(crate) fn timestamp_seconds(&self) -> Decimal {
+        self.timestamp.value
+    }
+
     /// [op:subtract-times](https://www.w3.org/TR/xpath-functions/#func-subtract-times)
     pub fn checked_sub(&self, rhs: impl Into<Self>) -> Option<Duration> {
         self.timestamp.checked_sub(rhs.into().timestamp)
@@ -439,6 +449,11 @@ impl Date {
         self.timestamp.to_be_bytes()
     }
 
+    /// Seconds since the epoch, in UTC if the timezone is known
+    pub(crate) fn timestamp_seconds(&self) -> Decimal {
+        self.timestamp.value
+    }
+
     /// [op:subtract-dates](https://www.w3.org/TR/xpath-functions/#func-subtract-dates)
     pub fn checked_sub(&self, rhs: impl Into<Self>) -> Option<Duration> {
         self.timestamp.checked_sub(rhs.into().timestamp)
@@ -0,0 +1,167 @@
+//! Removal of the tries of the dropped named graphs.
+//!
+//! Dropping or clearing a named graph starts a new generation of its trie (see `GraphTrie::of`), which an update
+//! does in a bounded number of writes whatever the size of the graph. The tries of the older generations are
+//! removed at the start of the next blocks, `Config::GraphPurgeBatchSize` keys at a time: the quads release
+//! their strings, their terms and their entries of `TermGraphStore` with their `SPO` entries, then the rest
+//! of the trie is killed.
+
+use frame_support::pallet_prelude::*;
+use frame_support::storage::child::KillStorageResult;
+
+use crate::{count_term_graphs, decode_term_id, release_strs, Config, GraphPurgeStore, GraphStore};
+use crate::graph_trie::{GraphTrie, TAG_SPO};
+
+/// Removes the next keys of a trie of an older generation, if any (called by `on_initialize`)
+pub fn purge_batch<T: Config>() -> Weight {
+    let ((g, generation), release) = match <GraphPurgeStore<T>>::iter().next() {
+        Some(entry) => entry,
+        None => return T::DbWeight::get().reads(1),
+    };
+    let limit = T::GraphPurgeBatchSize::get().max(1);
+    let trie = GraphTrie::with_generation(&g, generation);
+    let store = GraphStore::<T>::new();
+
+    // the quads are removed as they are read: the batch starts from the first remaining one
+    let triples: Vec<_> = trie.triples_after(TAG_SPO, None).take(limit as usize).collect();
+    for &(_, (s, p, o)) in triples.iter() {
+        if release {
+            for id in [s, p, o].iter() {
+                match decode_term_id::<T>(id) {
+                    Ok(term) => release_strs::<T>(&store, term),
+                    Err(error) => frame_support::log::warn!(
+                        target: "runtime::graphdb",
+                        "the strings of a dropped quad are not released: {}", error
+                    ),
+                }
+            }
+        }
+        count_term_graphs::<T>(g, generation, [s, p, o], false);
+        trie.remove_triple(TAG_SPO, (s, p, o));
+    }
+    store.collect_strs();
+
+    let mut removed = triples.len() as u32;
+    if removed < limit {
+        // the other indexes and the statistics of the graph
+        match trie.kill(limit - removed) {
+            KillStorageResult::AllRemoved(count) => {
+                removed += count;
+                <GraphPurgeStore<T>>::remove((g, generation));
+            }
+            KillStorageResult::SomeRemaining(count) => removed += count,
+        }
+    }
+
+    // estimate: a quad reads and writes the counts of its strings, of its terms and of the graphs of its terms
+    let quads = triples.len() as u64;
+    T::DbWeight::get().reads_writes(2 + quads * 10, 1 + removed as u64 + quads * 9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GraphGenerationStore, GraphNameStore, Id2StrStore, Pallet, StrRefCountStore, TermGraphStore};
+    use crate::mock::{new_test_ext, Graphdb, Origin, Test};
+    use crate::model::NamedNodeRef;
+    use crate::store::numeric_encoder::ReadEncoder;
+
+    /// The quads with the subject `:name` in any graph
+    fn quads_of(name: &str) -> Vec<String> {
+        let iri = format!("http://relationlabs.ai/entity/{}", name);
+        let store = GraphStore::<Test>::new();
+        let mut quads: Vec<String> = store
+            .quads_for_pattern(Some(NamedNodeRef::new_unchecked(&iri).into()), None, None, None)
+            .unwrap()
+            .map(|quad| quad.unwrap().to_string())
+            .collect();
+        quads.sort();
+        quads
+    }
+
+    /// Runs the purge until no trie is left to remove, returns the number of blocks it took
+    fn run_purge() -> u32 {
+        let mut blocks = 0;
+        while <GraphPurgeStore<Test>>::iter().next().is_some() {
+            purge_batch::<Test>();
+            blocks += 1;
+            assert!(blocks < 1000, "the purge does not end");
+        }
+        blocks
+    }
+
+    #[test]
+    fn a_dropped_graph_is_purged_in_batches() {
+        new_test_ext().execute_with(|| {
+            let triples: Vec<String> = (0..20)
+                .map(|i| format!(":s{} :p \"a literal longer than a term id {}\" .", i, i))
+                .collect();
+            let update = format!("INSERT DATA {{ :a :p 1 . GRAPH :g {{ :a :p 2 . {} }} }}", triples.join(" "));
+            Pallet::<Test>::execute_update(update).unwrap();
+            let g = <GraphNameStore<Test>>::iter_keys().next().unwrap();
+
+            Graphdb::sparql_update(Origin::signed(1), b"DROP GRAPH :g".to_vec()).unwrap();
+            assert_eq!(quads_of("a").len(), 1);
+            assert!(quads_of("s0").is_empty());
+            assert_eq!(<GraphGenerationStore<Test>>::get(g), 1);
+
+            assert!(run_purge() > 1);
+            assert!(GraphTrie::with_generation(&g, 0).triples_after(TAG_SPO, None).next().is_none());
+            assert_eq!(<TermGraphStore<Test>>::iter().count(), 0);
+            assert!(<Id2StrStore<Test>>::iter_keys().all(|id| <StrRefCountStore<Test>>::contains_key(id)));
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn a_refilled_graph_only_has_its_new_quads() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { GRAPH :g { :a :p 1, 2, 3, 4, 5 } }").unwrap();
+            Pallet::<Test>::execute_update("CLEAR GRAPH :g").unwrap();
+            Pallet::<Test>::execute_update("INSERT DATA { GRAPH :g { :a :p 6 } }").unwrap();
+            assert_eq!(quads_of("a"), vec!["<http://relationlabs.ai/entity/a> <http://relationlabs.ai/entity/p> \
+                \"6\"^^<http://www.w3.org/2001/XMLSchema#integer> <http://relationlabs.ai/entity/g>".to_owned()]);
+
+            // the purge of the older generation keeps the quads of the current one
+            run_purge();
+            assert_eq!(quads_of("a").len(), 1);
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+
+            // a cleared store keeps the strings of the quads inserted after it
+            let update = "INSERT DATA { GRAPH :h { :b :p \"a literal longer than a term id\" } }";
+            Pallet::<Test>::execute_update(update).unwrap();
+            Pallet::<Test>::execute_update("CLEAR ALL").unwrap();
+            Pallet::<Test>::execute_update(update).unwrap();
+            run_purge();
+            assert_eq!(quads_of("b").len(), 1);
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn a_lookup_without_a_graph_reads_the_graphs_of_its_term() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(
+                "INSERT DATA { :a :p 1 . GRAPH :g { :a :p 2 . :b :q :a } GRAPH :h { :b :q 3 } GRAPH :i { :c :q 4 } }",
+            )
+            .unwrap();
+            let store = GraphStore::<Test>::new();
+            let graphs = |name: &str| {
+                let iri = format!("http://relationlabs.ai/entity/{}", name);
+                let term = store.get_encoded_named_node(NamedNodeRef::new_unchecked(&iri)).unwrap().unwrap();
+                <TermGraphStore<Test>>::iter_key_prefix((term.term_id(),)).count()
+            };
+            assert_eq!((graphs("a"), graphs("b"), graphs("q")), (1, 2, 3));
+            assert_eq!(quads_of("a").len(), 2);
+            assert_eq!(quads_of("b").len(), 2);
+            assert_eq!(quads_of("c").len(), 1);
+
+            // the quads of a dropped graph are not read before its trie is purged
+            Pallet::<Test>::execute_update("DROP GRAPH :g").unwrap();
+            assert_eq!(quads_of("a").len(), 1);
+            assert_eq!(quads_of("b").len(), 1);
+            run_purge();
+            assert_eq!((graphs("a"), graphs("b"), graphs("q")), (0, 1, 2));
+        });
+    }
+}
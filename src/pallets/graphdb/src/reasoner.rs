@@ -0,0 +1,485 @@
+//! Forward-chaining materialization of the [OWL 2 RL](https://www.w3.org/TR/owl2-profiles/#OWL_2_RL) rules
+//! on property characteristics.
+//!
+//! The `owl:inverseOf` statements and the `owl:SymmetricProperty` and `owl:TransitiveProperty` types of the
+//! default graph make its quads entail others (rules `prp-inv1`, `prp-inv2`, `prp-symp` and `prp-trp`). The
+//! entailed quads missing from the default graph are written to the named graph `Config::InferredGraph` once
+//! an update or a load is done, and the queries read this graph with the default graph.
+//!
+//! The quads inserted by the update are chained semi-naively. Those removed are retracted with the
+//! delete/rederive algorithm: all their consequences are deleted, then the ones still entailed by the
+//! remaining quads are derived again. A change of the schema recomputes the whole graph: the update changing
+//! it is charged for reading all the quads of the properties of the schema.
+//!
+//! The inferred graph is only written by the reasoner and by root (see `crate::Access`). Its lookups, the
+//! quads it writes and its membership checks are charged to the meter of the update.
+
+use std::collections::{HashMap, HashSet};
+use std::iter::once;
+
+use frame_support::traits::Get;
+
+use crate::error::StoreError;
+use crate::model::NamedNodeRef;
+use crate::model::vocab::{owl, rdf};
+use crate::store::WritableEncodedStore;
+use crate::store::codec::{EncodedQuad, EncodedTerm};
+use crate::store::numeric_encoder::{ReadEncoder, WriteEncoder};
+use crate::{Config, GraphStore};
+
+/// A quad of the default graph or of the inferred graph, as `(subject, predicate, object)`
+type Fact = (EncodedTerm, EncodedTerm, EncodedTerm);
+
+/// The quads of the default graph inserted and removed since the last materialization
+#[derive(Debug, Default)]
+pub(crate) struct BaseChanges {
+    inserted: Vec<Fact>,
+    removed: Vec<Fact>,
+}
+
+impl BaseChanges {
+    /// Records the insertion or the removal of a quad, only those of the default graph are kept
+    pub fn record(&mut self, quad: &EncodedQuad, inserted: bool) {
+        if quad.graph_name.is_default_graph() {
+            let fact = (quad.subject, quad.predicate, quad.object);
+            if inserted {
+                self.inserted.push(fact);
+            } else {
+                self.removed.push(fact);
+            }
+        }
+    }
+
+    /// Forgets the changes, the store is empty
+    pub fn clear(&mut self) {
+        self.inserted.clear();
+        self.removed.clear();
+    }
+}
+
+/// Updates the inferred graph with the changes of the default graph recorded by `store`, if the runtime
+/// has one
+pub(crate) fn materialize<T: Config>(store: &GraphStore<T>) -> Result<(), StoreError> {
+    let changes = std::mem::take(&mut *store.base_changes.borrow_mut());
+    let graph_iri = match T::InferredGraph::get() {
+        Some(graph_iri) => graph_iri,
+        None => return Ok(()),
+    };
+    if changes.inserted.is_empty() && changes.removed.is_empty() {
+        return Ok(());
+    }
+    let mut reasoner = Reasoner::new(store, graph_iri)?;
+    // a quad may be inserted then removed by the same update, or the other way round
+    let mut inserted = HashSet::new();
+    for fact in changes.inserted {
+        if reasoner.is_base(&fact)? {
+            inserted.insert(fact);
+        }
+    }
+    let mut removed = HashSet::new();
+    for fact in changes.removed {
+        if !reasoner.is_base(&fact)? {
+            removed.insert(fact);
+        }
+    }
+    if inserted.iter().chain(removed.iter()).any(|fact| reasoner.schema.is_about(fact)) {
+        reasoner.recompute()
+    } else {
+        reasoner.retract(removed)?;
+        reasoner.derive(inserted)
+    }
+}
+
+/// The vocabulary of the rules and the properties it describes in the default graph
+struct Schema {
+    rdf_type: Option<EncodedTerm>,
+    inverse_of: Option<EncodedTerm>,
+    symmetric_property: Option<EncodedTerm>,
+    transitive_property: Option<EncodedTerm>,
+    symmetric: HashSet<EncodedTerm>,
+    transitive: HashSet<EncodedTerm>,
+    /// The inverse properties of each property, `owl:inverseOf` is read in both directions
+    inverses: HashMap<EncodedTerm, Vec<EncodedTerm>>,
+}
+
+impl Schema {
+    fn load<T: Config>(store: &GraphStore<T>) -> Result<Self, StoreError> {
+        let mut schema = Self {
+            rdf_type: store.get_encoded_named_node(rdf::TYPE)?,
+            inverse_of: store.get_encoded_named_node(owl::INVERSE_OF)?,
+            symmetric_property: store.get_encoded_named_node(owl::SYMMETRIC_PROPERTY)?,
+            transitive_property: store.get_encoded_named_node(owl::TRANSITIVE_PROPERTY)?,
+            symmetric: HashSet::new(),
+            transitive: HashSet::new(),
+            inverses: HashMap::new(),
+        };
+        if let Some(inverse_of) = schema.inverse_of {
+            for quad in store.encoded_quads_for_pattern(None, Some(inverse_of), None, Some(EncodedTerm::DefaultGraph)) {
+                let quad = quad?;
+                if quad.subject.is_named_node() && quad.object.is_named_node() {
+                    schema.inverses.entry(quad.subject).or_default().push(quad.object);
+                    schema.inverses.entry(quad.object).or_default().push(quad.subject);
+                }
+            }
+        }
+        for (class, properties) in [
+            (schema.symmetric_property, &mut schema.symmetric),
+            (schema.transitive_property, &mut schema.transitive),
+        ] {
+            if let (Some(rdf_type), Some(class)) = (schema.rdf_type, class) {
+                for quad in store.encoded_quads_for_pattern(None, Some(rdf_type), Some(class), Some(EncodedTerm::DefaultGraph)) {
+                    let quad = quad?;
+                    if quad.subject.is_named_node() {
+                        properties.insert(quad.subject);
+                    }
+                }
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Whether a quad of the default graph is a statement of the schema
+    fn is_about(&self, (_, p, o): &Fact) -> bool {
+        Some(*p) == self.inverse_of
+            || (Some(*p) == self.rdf_type
+            && (Some(*o) == self.symmetric_property || Some(*o) == self.transitive_property))
+    }
+
+    /// The properties of the quads a rule applies to or derives
+    fn properties(&self) -> HashSet<EncodedTerm> {
+        self.symmetric.iter()
+            .chain(self.transitive.iter())
+            .chain(self.inverses.keys())
+            .copied()
+            .collect()
+    }
+}
+
+struct Reasoner<'a, T: Config> {
+    store: &'a GraphStore<T>,
+    schema: Schema,
+    graph_iri: &'static str,
+    /// The name of the inferred graph, encoded on the first quad inferred
+    graph_name: Option<EncodedTerm>,
+    /// The quads removed from the default graph, still matched while their consequences are deleted
+    removed: HashSet<Fact>,
+}
+
+impl<'a, T: Config> Reasoner<'a, T> {
+    fn new(store: &'a GraphStore<T>, graph_iri: &'static str) -> Result<Self, StoreError> {
+        Ok(Self {
+            store,
+            schema: Schema::load(store)?,
+            graph_iri,
+            graph_name: store.get_encoded_named_node(NamedNodeRef::new_unchecked(graph_iri))?,
+            removed: HashSet::new(),
+        })
+    }
+
+    /// Clears the inferred graph and derives the consequences of all the quads of the default graph
+    fn recompute(&mut self) -> Result<(), StoreError> {
+        if let Some(graph_name) = self.graph_name {
+            let mut store = self.store;
+            store.clear_encoded_graph(graph_name)?;
+        }
+        let mut facts = Vec::new();
+        for p in self.schema.properties() {
+            for quad in self.store.encoded_quads_for_pattern(None, Some(p), None, Some(EncodedTerm::DefaultGraph)) {
+                let quad = quad?;
+                facts.push((quad.subject, quad.predicate, quad.object));
+            }
+        }
+        self.chain(facts)
+    }
+
+    /// Derives the consequences of the quads inserted in the default graph, those which were inferred are
+    /// removed from the inferred graph
+    fn derive(&mut self, inserted: HashSet<Fact>) -> Result<(), StoreError> {
+        let mut facts = Vec::new();
+        for fact in inserted {
+            if self.is_inferred(&fact)? {
+                self.remove(&fact)?;
+            } else {
+                facts.push(fact);
+            }
+        }
+        self.chain(facts)
+    }
+
+    /// Deletes the consequences of the quads removed from the default graph, then derives again those
+    /// still entailed
+    fn retract(&mut self, removed: HashSet<Fact>) -> Result<(), StoreError> {
+        self.removed = removed;
+        let mut deleted = HashSet::new();
+        let mut queue = self.removed.iter().copied().collect::<Vec<_>>();
+        while let Some(fact) = queue.pop() {
+            for consequence in self.consequences(&fact)? {
+                if self.is_inferred(&consequence)? && deleted.insert(consequence) {
+                    queue.push(consequence);
+                }
+            }
+        }
+        let removed = std::mem::take(&mut self.removed);
+        for fact in deleted.iter() {
+            self.remove(fact)?;
+        }
+
+        let mut facts = Vec::new();
+        for fact in deleted.into_iter().chain(removed) {
+            if self.is_derivable(&fact)? && self.infer(fact)? {
+                facts.push(fact);
+            }
+        }
+        self.chain(facts)
+    }
+
+    /// Infers the consequences of `facts` and of the quads inferred from them
+    fn chain(&mut self, mut facts: Vec<Fact>) -> Result<(), StoreError> {
+        while let Some(fact) = facts.pop() {
+            for consequence in self.consequences(&fact)? {
+                if self.infer(consequence)? {
+                    facts.push(consequence);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The quads entailed by `fact` with the quads of the store
+    fn consequences(&self, &(s, p, o): &Fact) -> Result<Vec<Fact>, StoreError> {
+        let mut consequences = Vec::new();
+        // a literal is not a subject
+        if !o.is_literal() {
+            if self.schema.symmetric.contains(&p) {
+                consequences.push((o, p, s));
+            }
+            for q in self.schema.inverses.get(&p).into_iter().flatten() {
+                consequences.push((o, *q, s));
+            }
+            if self.schema.transitive.contains(&p) {
+                for z in self.objects(o, p)? {
+                    consequences.push((s, p, z));
+                }
+            }
+        }
+        if self.schema.transitive.contains(&p) {
+            for x in self.subjects(p, s)? {
+                consequences.push((x, p, o));
+            }
+        }
+        Ok(consequences)
+    }
+
+    /// Whether a quad is entailed in one step by the quads of the store
+    fn is_derivable(&self, &(s, p, o): &Fact) -> Result<bool, StoreError> {
+        if o.is_literal() {
+            return Ok(false);
+        }
+        if self.schema.symmetric.contains(&p) && self.holds(&(o, p, s))? {
+            return Ok(true);
+        }
+        for q in self.schema.inverses.get(&p).into_iter().flatten() {
+            if self.holds(&(o, *q, s))? {
+                return Ok(true);
+            }
+        }
+        if self.schema.transitive.contains(&p) {
+            for z in self.objects(s, p)? {
+                if self.holds(&(z, p, o))? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Writes a quad to the inferred graph unless it holds, returns whether it was written
+    fn infer(&mut self, fact: Fact) -> Result<bool, StoreError> {
+        if self.holds(&fact)? {
+            return Ok(false);
+        }
+        let mut store = self.store;
+        let graph_name = match self.graph_name {
+            Some(graph_name) => graph_name,
+            None => {
+                let graph_name = store.encode_named_node(NamedNodeRef::new_unchecked(self.graph_iri))?;
+                self.graph_name = Some(graph_name);
+                graph_name
+            }
+        };
+        store.insert_encoded(&EncodedQuad::new(fact.0, fact.1, fact.2, graph_name))?;
+        Ok(true)
+    }
+
+    /// Removes a quad from the inferred graph
+    fn remove(&self, &(s, p, o): &Fact) -> Result<(), StoreError> {
+        if let Some(graph_name) = self.graph_name {
+            let mut store = self.store;
+            store.remove_encoded(&EncodedQuad::new(s, p, o, graph_name))?;
+        }
+        Ok(())
+    }
+
+    fn is_base(&self, &(s, p, o): &Fact) -> Result<bool, StoreError> {
+        self.store.contains_encoded_quad_metered(&EncodedQuad::new(s, p, o, EncodedTerm::DefaultGraph))
+    }
+
+    fn is_inferred(&self, &(s, p, o): &Fact) -> Result<bool, StoreError> {
+        match self.graph_name {
+            Some(graph_name) => self.store.contains_encoded_quad_metered(&EncodedQuad::new(s, p, o, graph_name)),
+            None => Ok(false),
+        }
+    }
+
+    fn holds(&self, fact: &Fact) -> Result<bool, StoreError> {
+        Ok(self.removed.contains(fact) || self.is_base(fact)? || self.is_inferred(fact)?)
+    }
+
+    /// The objects of the quads holding with the subject `s` and the predicate `p`
+    fn objects(&self, s: EncodedTerm, p: EncodedTerm) -> Result<Vec<EncodedTerm>, StoreError> {
+        let mut objects = self.removed.iter()
+            .filter(|fact| fact.0 == s && fact.1 == p)
+            .map(|fact| fact.2)
+            .collect::<Vec<_>>();
+        for graph_name in once(EncodedTerm::DefaultGraph).chain(self.graph_name) {
+            for quad in self.store.encoded_quads_for_pattern(Some(s), Some(p), None, Some(graph_name)) {
+                objects.push(quad?.object);
+            }
+        }
+        Ok(objects)
+    }
+
+    /// The subjects of the quads holding with the predicate `p` and the object `o`
+    fn subjects(&self, p: EncodedTerm, o: EncodedTerm) -> Result<Vec<EncodedTerm>, StoreError> {
+        let mut subjects = self.removed.iter()
+            .filter(|fact| fact.1 == p && fact.2 == o)
+            .map(|fact| fact.0)
+            .collect::<Vec<_>>();
+        for graph_name in once(EncodedTerm::DefaultGraph).chain(self.graph_name) {
+            for quad in self.store.encoded_quads_for_pattern(None, Some(p), Some(o), Some(graph_name)) {
+                subjects.push(quad?.subject);
+            }
+        }
+        Ok(subjects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frame_support::assert_noop;
+    use frame_support::pallet_prelude::*;
+
+    use crate::{Error, Pallet};
+    use crate::mock::{new_test_ext, Graphdb, Origin, Test};
+
+    const SCHEMA: &str = "INSERT DATA { :follows owl:inverseOf :followedBy . :friends a owl:SymmetricProperty . \
+        :ancestor a owl:TransitiveProperty }";
+
+    /// Executes a signed update, returns its weight
+    fn update(update: &str) -> Weight {
+        let info = Graphdb::sparql_update(Origin::signed(1), update.as_bytes().to_vec()).unwrap();
+        info.actual_weight.unwrap()
+    }
+
+    /// Whether the inferred graph has the triple `triple`
+    fn is_inferred(triple: &str) -> bool {
+        let query = format!("ASK {{ GRAPH <http://example.com/inferred> {{ {} }} }}", triple);
+        Pallet::<Test>::execute_query(query).contains("true")
+    }
+
+    #[test]
+    fn the_consequences_of_the_inserted_quads_are_inferred() {
+        new_test_ext().execute_with(|| {
+            update(SCHEMA);
+            update("INSERT DATA { :a :follows :b . :e :followedBy :f . :c :friends :d . :x :ancestor :y . \
+                :y :ancestor :z . :z :ancestor :w }");
+            // prp-inv1, prp-inv2
+            assert!(is_inferred(":b :followedBy :a"));
+            assert!(is_inferred(":f :follows :e"));
+            // prp-symp
+            assert!(is_inferred(":d :friends :c"));
+            // prp-trp, chained
+            assert!(is_inferred(":x :ancestor :z"));
+            assert!(is_inferred(":x :ancestor :w"));
+            assert!(is_inferred(":y :ancestor :w"));
+            assert!(!is_inferred(":a :follows :b"));
+            // the queries read the inferred graph with the default graph
+            assert!(Pallet::<Test>::execute_query("ASK { :x :ancestor :w }").contains("true"));
+        });
+    }
+
+    #[test]
+    fn the_consequences_of_a_removed_quad_are_deleted_unless_rederived() {
+        new_test_ext().execute_with(|| {
+            update(SCHEMA);
+            update("INSERT DATA { :a :ancestor :b . :b :ancestor :c . :a :ancestor :x . :x :ancestor :c . \
+                :c :friends :d }");
+            assert!(is_inferred(":a :ancestor :c"));
+
+            // still entailed through :x
+            update("DELETE DATA { :a :ancestor :b }");
+            assert!(is_inferred(":a :ancestor :c"));
+            update("DELETE DATA { :x :ancestor :c }");
+            assert!(!is_inferred(":a :ancestor :c"));
+
+            update("DELETE DATA { :c :friends :d }");
+            assert!(!is_inferred(":d :friends :c"));
+        });
+    }
+
+    #[test]
+    fn an_inserted_quad_is_no_longer_inferred() {
+        new_test_ext().execute_with(|| {
+            update(SCHEMA);
+            update("INSERT DATA { :c :friends :d }");
+            assert!(is_inferred(":d :friends :c"));
+            update("INSERT DATA { :d :friends :c }");
+            assert!(!is_inferred(":d :friends :c"));
+            // each quad entails the other
+            update("DELETE DATA { :c :friends :d }");
+            assert!(is_inferred(":c :friends :d"));
+        });
+    }
+
+    #[test]
+    fn a_schema_change_recomputes_the_inferred_graph() {
+        new_test_ext().execute_with(|| {
+            let quads: Vec<String> = (0..20).map(|i| format!(":s{} :friends :o{} .", i, i)).collect();
+            update(&format!("INSERT DATA {{ {} }}", quads.join(" ")));
+            assert!(!is_inferred(":o0 :friends :s0"));
+
+            let data = update("INSERT DATA { :a :knows :b }");
+            let schema = update("INSERT DATA { :friends a owl:SymmetricProperty }");
+            assert!(is_inferred(":o0 :friends :s0"));
+            assert!(is_inferred(":o19 :friends :s19"));
+            // the update is charged for reading and writing the quads of the recomputation
+            assert!(schema > 10 * data);
+
+            update("DELETE DATA { :friends a owl:SymmetricProperty }");
+            assert!(!is_inferred(":o0 :friends :s0"));
+        });
+    }
+
+    #[test]
+    fn only_root_writes_the_inferred_graph() {
+        new_test_ext().execute_with(|| {
+            update(SCHEMA);
+            update("INSERT DATA { :c :friends :d }");
+            for sparql in [
+                "INSERT DATA { GRAPH <http://example.com/inferred> { :a :friends :b } }",
+                "DELETE DATA { GRAPH <http://example.com/inferred> { :d :friends :c } }",
+                "DROP GRAPH <http://example.com/inferred>",
+                "CLEAR ALL",
+            ] {
+                let error = Graphdb::sparql_update(Origin::signed(1), sparql.as_bytes().to_vec()).unwrap_err();
+                assert_eq!(error.error, Error::<Test>::ProtectedGraph.into());
+            }
+            assert!(is_inferred(":d :friends :c"));
+
+            let sparql = b"DELETE DATA { GRAPH <http://example.com/inferred> { :d :friends :c } }".to_vec();
+            assert_noop!(Graphdb::root_sparql_update(Origin::signed(1), sparql.clone()), DispatchError::BadOrigin);
+            Graphdb::root_sparql_update(Origin::root(), sparql).unwrap();
+            assert!(!is_inferred(":d :friends :c"));
+        });
+    }
+}
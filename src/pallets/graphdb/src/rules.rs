@@ -0,0 +1,342 @@
+//! User-defined inference rules.
+//!
+//! A rule is a SPARQL CONSTRUCT query, the string object of a `<http://relationlabs.ai/rule/construct>` quad of
+//! the named graph `Config::RulesGraph`, e.g. `:mutualFriends rule:construct "CONSTRUCT { ?a :mutualFriends ?b }
+//! WHERE { ?a :friends ?b . ?b :friends ?a }"`. The prefixes of the pallet are declared before it.
+//!
+//! Once an update or a load is done, and the OWL consequences are materialized (see `reasoner`), the rules are
+//! evaluated to a fixpoint on the default graph of the queries, and the triples they construct which are not
+//! in it are written to the named graph `Config::DerivedGraph`. The rounds are semi-naive: a rule only
+//! constructs the solutions using a triple written in the previous round, or inserted by the update in the
+//! first one. A rule is thus evaluated again on the derived triples, the OWL reasoner does not read them.
+//!
+//! The rules must be monotonic (no OPTIONAL, MINUS or NOT EXISTS). When a quad they read is removed, or when
+//! the rules change, the derived graph is cleared and derived again from all the quads.
+//!
+//! The rules graph and the derived graph are only written by root and by the rules (see `crate::Access`). The
+//! evaluation of the rules is charged to the meter of the update: the lookups of their patterns, the checks of
+//! the triples they construct and the quads they write. An update whose rules fail, or do not reach a fixpoint
+//! in `Config::MaxRuleRounds` rounds, fails.
+
+use std::collections::HashSet;
+
+use frame_support::traits::Get;
+
+use crate::error::StoreError;
+use crate::model::{GraphName, NamedNode, NamedNodeRef, Term, Triple};
+use crate::sparql::{EvaluationError, Rule};
+use crate::store::WritableEncodedStore;
+use crate::store::codec::{EncodedQuad, EncodedTerm};
+use crate::store::numeric_encoder::{Decoder, ReadEncoder};
+use crate::{Config, GraphStore, PREFIX};
+
+/// The predicate of the rules in the rules graph
+pub(crate) const CONSTRUCT: &str = "http://relationlabs.ai/rule/construct";
+
+/// The quads inserted and removed since the rules were last evaluated
+#[derive(Debug, Default)]
+pub(crate) struct QuadChanges {
+    inserted: Vec<EncodedQuad>,
+    removed: Vec<EncodedQuad>,
+    /// Whether a named graph was cleared or removed, its quads are not recorded
+    cleared: bool,
+}
+
+impl QuadChanges {
+    /// Records the insertion or the removal of a quad
+    pub fn record(&mut self, quad: &EncodedQuad, inserted: bool) {
+        if inserted {
+            self.inserted.push(*quad);
+        } else {
+            self.removed.push(*quad);
+        }
+    }
+
+    /// Records that a named graph was cleared or removed
+    pub fn record_cleared(&mut self) {
+        self.cleared = true;
+    }
+
+    /// Forgets the changes, the store is empty
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// The default graph of the queries: the default graph, the inferred graph and the derived graph if the
+/// runtime has them
+pub(crate) fn query_default_graph<T: Config>() -> Vec<GraphName> {
+    let mut graphs = vec![GraphName::DefaultGraph];
+    if let Some(inferred) = T::InferredGraph::get() {
+        graphs.push(NamedNode::new_unchecked(inferred).into());
+    }
+    if T::RulesGraph::get().is_some() {
+        graphs.push(NamedNode::new_unchecked(T::DerivedGraph::get()).into());
+    }
+    graphs
+}
+
+/// Evaluates the rules of the rules graph on the changes recorded by `store`, if the runtime has one
+pub(crate) fn derive<T: Config>(store: &GraphStore<T>) -> Result<(), EvaluationError> {
+    let changes = std::mem::take(&mut *store.rule_changes.borrow_mut());
+    let result = match T::RulesGraph::get() {
+        Some(rules_graph) => Deriver::new(store, rules_graph)?.run(changes),
+        None => Ok(()),
+    };
+    // the quads written by the rules are not changes of the next update
+    store.rule_changes.borrow_mut().clear();
+    result
+}
+
+struct Deriver<'a, T: Config> {
+    store: &'a GraphStore<T>,
+    rules_graph: &'static str,
+    default_graph: Vec<GraphName>,
+    /// The graphs of `default_graph` which are stored
+    encoded_graphs: Vec<EncodedTerm>,
+    rules_graph_name: Option<EncodedTerm>,
+    derived_graph_name: Option<EncodedTerm>,
+}
+
+impl<'a, T: Config> Deriver<'a, T> {
+    fn new(store: &'a GraphStore<T>, rules_graph: &'static str) -> Result<Self, StoreError> {
+        let default_graph = query_default_graph::<T>();
+        let mut encoded_graphs = Vec::new();
+        for graph in &default_graph {
+            if let Some(graph) = store.get_encoded_graph_name(graph.as_ref())? {
+                encoded_graphs.push(graph);
+            }
+        }
+        Ok(Self {
+            store,
+            rules_graph,
+            default_graph,
+            encoded_graphs,
+            rules_graph_name: store.get_encoded_named_node(NamedNodeRef::new_unchecked(rules_graph))?,
+            derived_graph_name: store.get_encoded_named_node(NamedNodeRef::new_unchecked(T::DerivedGraph::get()))?,
+        })
+    }
+
+    fn run(&self, changes: QuadChanges) -> Result<(), EvaluationError> {
+        let is_rule = |quad: &EncodedQuad| Some(quad.graph_name) == self.rules_graph_name;
+        let mut recompute = changes.cleared
+            || changes.inserted.iter().any(is_rule)
+            || changes.removed.iter().any(is_rule);
+        // a quad removed from the inferred graph may have been inserted in the default graph
+        for quad in changes.removed.iter().filter(|quad| self.encoded_graphs.contains(&quad.graph_name)) {
+            if recompute {
+                break;
+            }
+            recompute = !self.holds(quad)?;
+        }
+
+        let delta = if recompute {
+            if let Some(derived_graph_name) = self.derived_graph_name {
+                let mut store = self.store;
+                store.clear_encoded_graph(derived_graph_name)?;
+            }
+            None
+        } else {
+            let mut delta = Vec::new();
+            for quad in &changes.inserted {
+                if self.encoded_graphs.contains(&quad.graph_name) && self.store.contains_encoded_quad_metered(quad)? {
+                    delta.push(self.store.decode_triple(quad)?);
+                }
+            }
+            if delta.is_empty() {
+                return Ok(());
+            }
+            Some(delta)
+        };
+
+        let rules = self.rules()?;
+        if rules.is_empty() {
+            return Ok(());
+        }
+        self.fixpoint(&rules, delta)
+    }
+
+    /// The rules of the rules graph, those which do not parse are skipped
+    fn rules(&self) -> Result<Vec<Rule>, StoreError> {
+        let mut rules = Vec::new();
+        let rules_graph = NamedNodeRef::new_unchecked(self.rules_graph);
+        let construct = NamedNodeRef::new_unchecked(CONSTRUCT);
+        for quad in self.store.quads_for_pattern(None, Some(construct), None, Some(rules_graph.into()))? {
+            let quad = quad?;
+            let query = match &quad.object {
+                Term::Literal(literal) => literal.value(),
+                _ => continue,
+            };
+            match Rule::parse(&format!("{}\n{}", PREFIX, query)) {
+                Ok(rule) => rules.push(rule),
+                Err(error) => {
+                    frame_support::log::warn!(target: "runtime::graphdb", "skipped rule {}: {}", quad.subject, error)
+                }
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Evaluates the rules until they construct no new triple, from all the solutions without `delta`
+    fn fixpoint(&self, rules: &[Rule], mut delta: Option<Vec<Triple>>) -> Result<(), EvaluationError> {
+        let derived_graph = NamedNode::new_unchecked(T::DerivedGraph::get());
+        for _ in 0..T::MaxRuleRounds::get() {
+            let mut derived = Vec::new();
+            let mut seen = HashSet::new();
+            for rule in rules {
+                for triple in rule.evaluate(self.store.clone(), &self.default_graph, delta.as_deref())? {
+                    if !seen.contains(&triple) && !self.entails(&triple)? {
+                        seen.insert(triple.clone());
+                        derived.push(triple);
+                    }
+                }
+            }
+            if derived.is_empty() {
+                return Ok(());
+            }
+            for triple in &derived {
+                self.store.insert_quad(triple.clone().in_graph(derived_graph.clone()))?;
+            }
+            delta = Some(derived);
+        }
+        Err(StoreError::RuleRoundsExceeded { max: T::MaxRuleRounds::get() }.into())
+    }
+
+    /// Whether a triple is in the default graph of the queries
+    fn entails(&self, triple: &Triple) -> Result<bool, StoreError> {
+        for graph in &self.default_graph {
+            // the strings of the terms are looked up
+            self.store.meter.charge::<T>(3, 0)?;
+            if let Some(quad) = self.store.get_encoded_quad(triple.as_ref().in_graph(graph.as_ref()))? {
+                if self.store.contains_encoded_quad_metered(&quad)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether the triple of a quad is still in the default graph of the queries
+    fn holds(&self, quad: &EncodedQuad) -> Result<bool, StoreError> {
+        for graph_name in &self.encoded_graphs {
+            let quad = EncodedQuad::new(quad.subject, quad.predicate, quad.object, *graph_name);
+            if self.store.contains_encoded_quad_metered(&quad)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frame_support::pallet_prelude::*;
+
+    use crate::{Error, Pallet};
+    use crate::mock::{new_test_ext, Graphdb, Origin, Test};
+
+    const REACH: &str = "INSERT DATA { GRAPH <http://example.com/rules> { :reach rule:construct \
+        \"CONSTRUCT { ?a :reach ?c } WHERE { ?a :reach ?b . ?b :reach ?c }\" } }";
+
+    /// Executes a signed update, returns its weight
+    fn update(update: &str) -> Weight {
+        let info = Graphdb::sparql_update(Origin::signed(1), update.as_bytes().to_vec()).unwrap();
+        info.actual_weight.unwrap()
+    }
+
+    /// Whether the derived graph has the triple `triple`
+    fn is_derived(triple: &str) -> bool {
+        let query = format!("ASK {{ GRAPH <http://example.com/derived> {{ {} }} }}", triple);
+        Pallet::<Test>::execute_query(query).contains("true")
+    }
+
+    #[test]
+    fn the_rules_are_evaluated_to_a_fixpoint() {
+        new_test_ext().execute_with(|| {
+            Graphdb::root_sparql_update(Origin::root(), REACH.as_bytes().to_vec()).unwrap();
+            update("INSERT DATA { :a :reach :b . :b :reach :c . :c :reach :d . :d :reach :e }");
+            // the triples derived in a round are read by the next one
+            for triple in [":a :reach :c", ":a :reach :d", ":a :reach :e", ":b :reach :e"] {
+                assert!(is_derived(triple), "{} is not derived", triple);
+            }
+            assert!(!is_derived(":a :reach :b"));
+            // the queries read the derived graph with the default graph
+            assert!(Pallet::<Test>::execute_query("ASK { :a :reach :e }").contains("true"));
+        });
+    }
+
+    #[test]
+    fn an_insertion_only_evaluates_the_solutions_using_it() {
+        new_test_ext().execute_with(|| {
+            Graphdb::root_sparql_update(Origin::root(), REACH.as_bytes().to_vec()).unwrap();
+            let chain: Vec<String> = (0..12).map(|i| format!(":n{} :reach :n{} .", i, i + 1)).collect();
+            update(&format!("INSERT DATA {{ {} }}", chain.join(" ")));
+
+            update("INSERT DATA { :n12 :reach :n13 }");
+            assert!(is_derived(":n0 :reach :n13"));
+            // a quad the rules do not read constructs nothing, without evaluating the rules on all the quads
+            let unrelated = update("INSERT DATA { :x :knows :y }");
+            // a rule change evaluates them on all the quads
+            let rule = "INSERT DATA { GRAPH <http://example.com/rules> { :knows rule:construct \
+                \"CONSTRUCT { ?b :knows ?a } WHERE { ?a :knows ?b }\" } }";
+            let recomputed = Graphdb::root_sparql_update(Origin::root(), rule.as_bytes().to_vec()).unwrap();
+            assert!(10 * unrelated < recomputed.actual_weight.unwrap());
+            assert!(is_derived(":y :knows :x"));
+            assert!(is_derived(":n0 :reach :n13"));
+        });
+    }
+
+    #[test]
+    fn a_removal_derives_the_graph_again() {
+        new_test_ext().execute_with(|| {
+            Graphdb::root_sparql_update(Origin::root(), REACH.as_bytes().to_vec()).unwrap();
+            update("INSERT DATA { :a :reach :b . :b :reach :c . :c :reach :d . :a :reach :x . :x :reach :d }");
+            assert!(is_derived(":a :reach :c"));
+
+            update("DELETE DATA { :b :reach :c }");
+            assert!(!is_derived(":a :reach :c"));
+            assert!(!is_derived(":b :reach :d"));
+            // still derived through :x
+            assert!(is_derived(":a :reach :d"));
+
+            let rules = b"CLEAR GRAPH <http://example.com/rules>".to_vec();
+            Graphdb::root_sparql_update(Origin::root(), rules).unwrap();
+            assert!(!is_derived(":a :reach :d"));
+        });
+    }
+
+    #[test]
+    fn an_update_whose_rules_do_not_reach_a_fixpoint_fails() {
+        new_test_ext().execute_with(|| {
+            let rule = "INSERT DATA { GRAPH <http://example.com/rules> { :count rule:construct \
+                \"CONSTRUCT { :c :count ?m } WHERE { :c :count ?n . BIND(?n + 1 AS ?m) }\" } }";
+            Graphdb::root_sparql_update(Origin::root(), rule.as_bytes().to_vec()).unwrap();
+            let error = Graphdb::sparql_update(Origin::signed(1), b"INSERT DATA { :c :count 0 }".to_vec()).unwrap_err();
+            assert_eq!(error.error, Error::<Test>::RuleRoundsExceeded.into());
+            assert!(Pallet::<Test>::execute_query("ASK { :c :count ?n }").contains("false"));
+
+            // the errors of the evaluation fail the update
+            let rule = "INSERT DATA { GRAPH <http://example.com/rules> { :near rule:construct \
+                \"CONSTRUCT { ?a :near ?b } WHERE { ?a :at ?x . ?b :at ?y }\" } }";
+            Graphdb::root_sparql_update(Origin::root(), rule.as_bytes().to_vec()).unwrap();
+            let places: Vec<String> = (0..40).map(|i| format!(":p{} :at {} .", i, i)).collect();
+            let update = format!("INSERT DATA {{ {} }}", places.join(" ")).into_bytes();
+            let error = Graphdb::sparql_update(Origin::signed(1), update).unwrap_err();
+            assert_eq!(error.error, Error::<Test>::UpdateTooHeavy.into());
+        });
+    }
+
+    #[test]
+    fn only_root_writes_the_rules_and_the_derived_graph() {
+        new_test_ext().execute_with(|| {
+            for sparql in [
+                REACH,
+                "INSERT DATA { GRAPH <http://example.com/derived> { :a :reach :b } }",
+                "DROP GRAPH <http://example.com/rules>",
+            ] {
+                let error = Graphdb::sparql_update(Origin::signed(1), sparql.as_bytes().to_vec()).unwrap_err();
+                assert_eq!(error.error, Error::<Test>::ProtectedGraph.into());
+            }
+        });
+    }
+}
@@ -0,0 +1,238 @@
+//! Equivalence classes of the `owl:sameAs` links of the default graph.
+//!
+//! The nodes linked by `owl:sameAs` quads of the default graph, directly or not, form a class whose canonical
+//! representative is the member with the lowest term id: `SameAsStore` maps each member of a class with several
+//! members to it, and `SameAsMemberStore` lists the members of each class. They are kept up to date with the index
+//! entries of the links.
+//!
+//! The queries evaluated with `EqualityMode::SameAs` read them (see `GraphStore::encoded_quads_for_equal_pattern`):
+//! a term of a pattern matches the quads of all the members of its class, and the terms of the quads found are
+//! replaced by their canonical representative. The quads of the aliases are not duplicated in the store.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use frame_support::traits::Get;
+
+use crate::{decode_term_id, Config, DefaultSpoStore, SameAsMemberStore, SameAsStore};
+use crate::error::StoreError;
+use crate::model::vocab::owl;
+use crate::store::codec::{EncodedQuad, EncodedTerm, TermId};
+use crate::store::namespace::Namespaces;
+use crate::store::small_string::SmallString;
+
+/// `owl:sameAs`, encoded with the namespaces of the runtime
+pub(crate) fn same_as<T: Config>() -> EncodedTerm {
+    // the built-in owl namespace is the longest one for owl:sameAs, or a longer one of the runtime
+    let (prefix, local) = Namespaces::new(T::Namespaces::get())
+        .split(owl::SAME_AS.as_str())
+        .expect("owl:sameAs is in the owl namespace");
+    let local = SmallString::try_from(local).expect("the local name of owl:sameAs is inlined");
+    EncodedTerm::SmallPrefixedNamedNode { prefix, local }
+}
+
+/// Whether a quad is a `owl:sameAs` link between two distinct nodes of the default graph
+pub(crate) fn is_link<T: Config>(quad: &EncodedQuad) -> bool {
+    quad.graph_name.is_default_graph()
+        && !quad.subject.is_literal()
+        && !quad.object.is_literal()
+        && quad.subject != quad.object
+        && quad.predicate == same_as::<T>()
+}
+
+/// Merges the classes of the nodes of a link inserted in the default graph
+pub(crate) fn link<T: Config>(quad: &EncodedQuad) {
+    let a = canonical::<T>(&quad.subject.term_id());
+    let b = canonical::<T>(&quad.object.term_id());
+    if a == b {
+        return;
+    }
+    let (kept, merged) = if a < b { (a, b) } else { (b, a) };
+    // the classes of a single node are not stored
+    if !<SameAsStore<T>>::contains_key(kept) {
+        <SameAsStore<T>>::insert(kept, kept);
+        <SameAsMemberStore<T>>::insert((kept, kept), ());
+    }
+    for member in members::<T>(&merged) {
+        <SameAsMemberStore<T>>::remove((merged, member));
+        <SameAsStore<T>>::insert(member, kept);
+        <SameAsMemberStore<T>>::insert((kept, member), ());
+    }
+}
+
+/// Splits the class of the nodes of a link removed from the default graph along the links left
+pub(crate) fn unlink<T: Config>(quad: &EncodedQuad) {
+    let class = match <SameAsStore<T>>::get(quad.subject.term_id()) {
+        Some(class) => class,
+        None => return,
+    };
+    let members = members::<T>(&class);
+    let same_as = same_as::<T>().term_id();
+
+    // union-find on the members, all the links of a member are in its class
+    let index = members.iter().enumerate().map(|(i, member)| (*member, i)).collect::<BTreeMap<_, _>>();
+    let mut parents = (0..members.len()).collect::<Vec<_>>();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+    for (i, member) in members.iter().enumerate() {
+        for object in <DefaultSpoStore<T>>::iter_key_prefix((*member, same_as)) {
+            if let Some(j) = index.get(&object) {
+                let (i, j) = (root(&mut parents, i), root(&mut parents, *j));
+                parents[i.max(j)] = i.min(j);
+            }
+        }
+    }
+
+    for member in members.iter() {
+        <SameAsStore<T>>::remove(member);
+        <SameAsMemberStore<T>>::remove((class, *member));
+    }
+    let mut classes = BTreeMap::<usize, Vec<TermId>>::new();
+    for (i, member) in members.iter().enumerate() {
+        classes.entry(root(&mut parents, i)).or_default().push(*member);
+    }
+    for members in classes.values().filter(|members| members.len() > 1) {
+        // the members are sorted
+        let kept = members[0];
+        for member in members {
+            <SameAsStore<T>>::insert(member, kept);
+            <SameAsMemberStore<T>>::insert((kept, *member), ());
+        }
+    }
+}
+
+/// The canonical representative of the class of `term`, `None` if it is alone in its class
+pub(crate) fn canonical_term<T: Config>(term: EncodedTerm) -> Result<Option<EncodedTerm>, StoreError> {
+    if term.is_literal() {
+        return Ok(None);
+    }
+    <SameAsStore<T>>::get(term.term_id()).map(|id| decode_term_id::<T>(&id)).transpose()
+}
+
+/// The members of the class of `term`, `term` itself if it is alone in its class
+pub(crate) fn equal_terms<T: Config>(term: EncodedTerm) -> Result<Vec<EncodedTerm>, StoreError> {
+    if term.is_literal() {
+        return Ok(vec![term]);
+    }
+    match <SameAsStore<T>>::get(term.term_id()) {
+        Some(class) => members::<T>(&class).iter().map(decode_term_id::<T>).collect(),
+        None => Ok(vec![term]),
+    }
+}
+
+fn canonical<T: Config>(id: &TermId) -> TermId {
+    <SameAsStore<T>>::get(id).unwrap_or(*id)
+}
+
+/// The members of a class, sorted, the class itself if it has a single member
+fn members<T: Config>(class: &TermId) -> Vec<TermId> {
+    let members = <SameAsMemberStore<T>>::iter_key_prefix((*class,)).collect::<Vec<_>>();
+    if members.is_empty() {
+        vec![*class]
+    } else {
+        members
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EqualityMode, GraphStore, Pallet};
+    use crate::mock::{new_test_ext, Test};
+    use crate::model::NamedNodeRef;
+    use crate::store::numeric_encoder::ReadEncoder;
+
+    /// The encoded node `:name`
+    fn node(name: &str) -> EncodedTerm {
+        let iri = format!("http://relationlabs.ai/entity/{}", name);
+        GraphStore::<Test>::new().get_encoded_named_node(NamedNodeRef::new_unchecked(&iri)).unwrap().unwrap()
+    }
+
+    /// The members of the class of `:name`, sorted
+    fn class_of(name: &str) -> Vec<EncodedTerm> {
+        let mut members = equal_terms::<Test>(node(name)).unwrap();
+        members.sort_by_key(|term| term.term_id());
+        members
+    }
+
+    fn nodes(names: &[&str]) -> Vec<EncodedTerm> {
+        let mut nodes: Vec<_> = names.iter().map(|name| node(name)).collect();
+        nodes.sort_by_key(|term| term.term_id());
+        nodes
+    }
+
+    #[test]
+    fn a_link_merges_the_classes_of_its_nodes() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a owl:sameAs :b . :c owl:sameAs :d . :e :p :f }").unwrap();
+            assert_eq!(class_of("a"), nodes(&["a", "b"]));
+            assert_eq!(class_of("d"), nodes(&["c", "d"]));
+            assert_eq!(class_of("e"), nodes(&["e"]));
+            assert_eq!(canonical_term::<Test>(node("e")).unwrap(), None);
+
+            Pallet::<Test>::execute_update("INSERT DATA { :b owl:sameAs :c }").unwrap();
+            let members = nodes(&["a", "b", "c", "d"]);
+            assert_eq!(class_of("a"), members);
+            for name in ["a", "b", "c", "d"] {
+                assert_eq!(canonical_term::<Test>(node(name)).unwrap(), Some(members[0]));
+            }
+            // a literal or a quad of a named graph is not a link
+            Pallet::<Test>::execute_update("INSERT DATA { :e owl:sameAs \"e\" . GRAPH :g { :e owl:sameAs :f } }")
+                .unwrap();
+            assert_eq!(class_of("e"), nodes(&["e"]));
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn removing_a_link_splits_the_class_along_the_links_left() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(
+                "INSERT DATA { :a owl:sameAs :b . :b owl:sameAs :c . :c owl:sameAs :d . :a owl:sameAs :c }",
+            )
+            .unwrap();
+            // still linked through :a owl:sameAs :c
+            Pallet::<Test>::execute_update("DELETE DATA { :b owl:sameAs :c }").unwrap();
+            assert_eq!(class_of("b"), nodes(&["a", "b", "c", "d"]));
+
+            Pallet::<Test>::execute_update("DELETE DATA { :a owl:sameAs :c }").unwrap();
+            assert_eq!(class_of("a"), nodes(&["a", "b"]));
+            assert_eq!(class_of("d"), nodes(&["c", "d"]));
+            assert_eq!(canonical_term::<Test>(node("d")).unwrap(), Some(nodes(&["c", "d"])[0]));
+
+            Pallet::<Test>::execute_update("DELETE DATA { :a owl:sameAs :b }").unwrap();
+            assert_eq!(class_of("a"), nodes(&["a"]));
+            assert_eq!(canonical_term::<Test>(node("b")).unwrap(), None);
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn a_pattern_matches_the_quads_of_the_members_of_its_class() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a owl:sameAs :b . :a :p 1 . :b :p 2 . :c :p 3 }").unwrap();
+            let query = "SELECT ?o WHERE { :a :p ?o }";
+            let results = Pallet::<Test>::execute_query_with_equality(query, EqualityMode::SameAs);
+            for value in ["\"value\":\"1\"", "\"value\":\"2\""] {
+                assert!(results.contains(value), "{} is not in {}", value, results);
+            }
+            assert!(!results.contains("\"value\":\"3\""));
+            // without equality only the quads of :a match
+            assert!(!Pallet::<Test>::execute_query(query).contains("\"value\":\"2\""));
+
+            // the subjects are replaced by the canonical representative of their class
+            let subjects = "SELECT DISTINCT ?s WHERE { ?s :p ?o . FILTER(?o < 3) }";
+            let results = Pallet::<Test>::execute_query_with_equality(subjects, EqualityMode::SameAs);
+            assert_eq!(results.matches("\"type\":\"uri\"").count(), 1);
+
+            Pallet::<Test>::execute_update("DELETE DATA { :a owl:sameAs :b }").unwrap();
+            let results = Pallet::<Test>::execute_query_with_equality(query, EqualityMode::SameAs);
+            assert!(!results.contains("\"value\":\"2\""));
+        });
+    }
+}
@@ -0,0 +1,633 @@
+//! Validation of the data against the [SHACL Core](https://www.w3.org/TR/shacl/) shapes of the named graph
+//! `Config::ShapesGraph`.
+//!
+//! The data graph is the default graph of the queries. The shapes are the subjects of `sh:targetClass`,
+//! `sh:targetNode`, `sh:targetSubjectsOf` or `sh:targetObjectsOf` quads, with constraints on their focus nodes
+//! and on the values of their `sh:property` shapes, whose `sh:path` must be a property. The constraints
+//! checked are `sh:datatype`, `sh:minCount`, `sh:maxCount`, `sh:class` and `sh:pattern` (with `sh:flags`).
+//!
+//! After an update only the focus nodes it touched are validated: the subjects and the objects of the quads
+//! written, and the subjects of the values whose type changed. A change of the shapes, or a named graph
+//! cleared, validates all the focus nodes.
+//!
+//! Only root writes the shapes graph: a signed update could otherwise drop the shapes, or add shapes that
+//! every later update violates.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use frame_support::traits::Get;
+use regex::Regex;
+
+use crate::error::StoreError;
+use crate::model::vocab::{rdf, rdfs, sh};
+use crate::model::{BlankNode, GraphName, Literal, NamedNode, NamedNodeRef, NamedOrBlankNode, Term, Triple};
+use crate::sparql::compile_regex;
+use crate::store::codec::EncodedQuad;
+use crate::store::numeric_encoder::{Decoder, ReadEncoder};
+use crate::{rules, Config, GraphStore};
+
+/// The quads written since the last validation
+#[derive(Debug, Default)]
+pub(crate) struct ShapeChanges {
+    quads: Vec<EncodedQuad>,
+    /// Whether a named graph was cleared or removed, its quads are not recorded
+    cleared: bool,
+}
+
+impl ShapeChanges {
+    /// Records the insertion or the removal of a quad
+    pub fn record(&mut self, quad: &EncodedQuad) {
+        self.quads.push(*quad);
+    }
+
+    /// Records that a named graph was cleared or removed
+    pub fn record_cleared(&mut self) {
+        self.cleared = true;
+    }
+
+    /// Forgets the changes, the store is empty
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// A [SHACL validation report](https://www.w3.org/TR/shacl/#validation-report), written as N-Triples
+#[derive(Debug, Default)]
+pub(crate) struct ValidationReport {
+    results: Vec<ValidationResult>,
+}
+
+#[derive(Debug)]
+struct ValidationResult {
+    focus_node: Term,
+    path: Option<NamedNode>,
+    value: Option<Term>,
+    source_shape: Term,
+    component: NamedNodeRef<'static>,
+    message: String,
+}
+
+impl ValidationReport {
+    /// Whether the data conforms to the shapes
+    pub fn conforms(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// The triples of the report, its nodes are the blank nodes `report` and `result<n>`
+    pub fn triples(&self) -> Vec<Triple> {
+        let report = BlankNode::new_unchecked("report");
+        let mut triples = vec![
+            Triple::new(report.clone(), rdf::TYPE, sh::VALIDATION_REPORT),
+            Triple::new(report.clone(), sh::CONFORMS, Literal::from(self.conforms())),
+        ];
+        for (i, result) in self.results.iter().enumerate() {
+            let node = BlankNode::new_unchecked(format!("result{}", i));
+            triples.push(Triple::new(report.clone(), sh::RESULT, node.clone()));
+            triples.push(Triple::new(node.clone(), rdf::TYPE, sh::VALIDATION_RESULT));
+            triples.push(Triple::new(node.clone(), sh::FOCUS_NODE, result.focus_node.clone()));
+            if let Some(path) = &result.path {
+                triples.push(Triple::new(node.clone(), sh::RESULT_PATH, path.clone()));
+            }
+            if let Some(value) = &result.value {
+                triples.push(Triple::new(node.clone(), sh::VALUE, value.clone()));
+            }
+            triples.push(Triple::new(node.clone(), sh::SOURCE_SHAPE, result.source_shape.clone()));
+            triples.push(Triple::new(node.clone(), sh::SOURCE_CONSTRAINT_COMPONENT, result.component));
+            triples.push(Triple::new(node.clone(), sh::RESULT_SEVERITY, sh::VIOLATION));
+            triples.push(Triple::new(node, sh::RESULT_MESSAGE, Literal::new_simple_literal(&result.message)));
+        }
+        triples
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for triple in self.triples() {
+            writeln!(f, "{} .", triple)?;
+        }
+        Ok(())
+    }
+}
+
+/// Validates the focus nodes touched by the changes recorded by `store` against the shapes, if the runtime
+/// has a shapes graph
+pub(crate) fn validate_changes<T: Config>(store: &GraphStore<T>) -> Result<ValidationReport, StoreError> {
+    let changes = std::mem::take(&mut *store.shape_changes.borrow_mut());
+    let validator = match Validator::load(store)? {
+        Some(validator) => validator,
+        None => return Ok(ValidationReport::default()),
+    };
+    let shapes_graph_name = store.get_encoded_named_node(NamedNodeRef::new_unchecked(validator.shapes_graph))?;
+    if changes.cleared || changes.quads.iter().any(|quad| Some(quad.graph_name) == shapes_graph_name) {
+        return validator.validate_all();
+    }
+
+    let mut data_graphs = Vec::new();
+    for graph in &validator.data_graphs {
+        if let Some(graph) = store.get_encoded_graph_name(graph.as_ref())? {
+            data_graphs.push(graph);
+        }
+    }
+    let rdf_type = store.get_encoded_named_node(rdf::TYPE)?;
+    let mut nodes = Vec::new();
+    let mut typed = Vec::new();
+    for quad in changes.quads.iter().filter(|quad| data_graphs.contains(&quad.graph_name)) {
+        nodes.push(quad.subject);
+        nodes.push(quad.object);
+        if Some(quad.predicate) == rdf_type {
+            typed.push(quad.subject);
+        }
+    }
+    let mut focus_nodes = Vec::new();
+    for node in nodes {
+        let node = store.decode_term(node)?;
+        if !focus_nodes.contains(&node) {
+            focus_nodes.push(node);
+        }
+    }
+    for node in typed {
+        // the focus nodes with the node as value of a `sh:class` constraint
+        for subject in validator.subjects(None, &store.decode_term(node)?)? {
+            if !focus_nodes.contains(&subject) {
+                focus_nodes.push(subject);
+            }
+        }
+    }
+    validator.validate(&focus_nodes)
+}
+
+/// Validates all the focus nodes of the shapes
+pub(crate) fn validate_all<T: Config>(store: &GraphStore<T>) -> Result<ValidationReport, StoreError> {
+    match Validator::load(store)? {
+        Some(validator) => validator.validate_all(),
+        None => Ok(ValidationReport::default()),
+    }
+}
+
+enum Target {
+    Class(Term),
+    Node(Term),
+    SubjectsOf(NamedNode),
+    ObjectsOf(NamedNode),
+}
+
+#[derive(Default)]
+struct Constraints {
+    datatype: Option<NamedNode>,
+    min_count: Option<usize>,
+    max_count: Option<usize>,
+    classes: Vec<Term>,
+    pattern: Option<(String, Regex)>,
+}
+
+struct PropertyShape {
+    id: Term,
+    path: NamedNode,
+    constraints: Constraints,
+}
+
+struct NodeShape {
+    id: Term,
+    targets: Vec<Target>,
+    constraints: Constraints,
+    properties: Vec<PropertyShape>,
+}
+
+struct Validator<'a, T: Config> {
+    store: &'a GraphStore<T>,
+    shapes_graph: &'static str,
+    data_graphs: Vec<GraphName>,
+    shapes: Vec<NodeShape>,
+}
+
+impl<'a, T: Config> Validator<'a, T> {
+    /// Reads the shapes of the shapes graph, `None` if the runtime has none
+    fn load(store: &'a GraphStore<T>) -> Result<Option<Self>, StoreError> {
+        let shapes_graph = match T::ShapesGraph::get() {
+            Some(shapes_graph) => shapes_graph,
+            None => return Ok(None),
+        };
+        let mut validator = Self {
+            store,
+            shapes_graph,
+            data_graphs: rules::query_default_graph::<T>(),
+            shapes: Vec::new(),
+        };
+        let mut ids = Vec::new();
+        for target in [sh::TARGET_CLASS, sh::TARGET_NODE, sh::TARGET_SUBJECTS_OF, sh::TARGET_OBJECTS_OF].iter() {
+            for quad in store.quads_for_pattern(None, Some(*target), None, Some(validator.shapes_graph().into()))? {
+                let id = Term::from(quad?.subject);
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        for id in ids {
+            let mut targets = Vec::new();
+            for class in validator.shape_values(&id, sh::TARGET_CLASS)? {
+                targets.push(Target::Class(class));
+            }
+            for node in validator.shape_values(&id, sh::TARGET_NODE)? {
+                targets.push(Target::Node(node));
+            }
+            for property in validator.shape_values(&id, sh::TARGET_SUBJECTS_OF)? {
+                if let Term::NamedNode(property) = property {
+                    targets.push(Target::SubjectsOf(property));
+                }
+            }
+            for property in validator.shape_values(&id, sh::TARGET_OBJECTS_OF)? {
+                if let Term::NamedNode(property) = property {
+                    targets.push(Target::ObjectsOf(property));
+                }
+            }
+            let mut properties = Vec::new();
+            for property in validator.shape_values(&id, sh::PROPERTY)? {
+                match validator.shape_values(&property, sh::PATH)?.pop() {
+                    Some(Term::NamedNode(path)) => properties.push(PropertyShape {
+                        constraints: validator.constraints(&property)?,
+                        id: property,
+                        path,
+                    }),
+                    _ => frame_support::log::warn!(
+                        target: "runtime::graphdb",
+                        "skipped property shape {}: only the property paths are supported",
+                        property
+                    ),
+                }
+            }
+            validator.shapes.push(NodeShape {
+                constraints: validator.constraints(&id)?,
+                id,
+                targets,
+                properties,
+            });
+        }
+        Ok(Some(validator))
+    }
+
+    fn constraints(&self, shape: &Term) -> Result<Constraints, StoreError> {
+        let count = |values: Vec<Term>| match values.first() {
+            Some(Term::Literal(literal)) => literal.value().parse().ok(),
+            _ => None,
+        };
+        let mut constraints = Constraints {
+            datatype: match self.shape_values(shape, sh::DATATYPE)?.pop() {
+                Some(Term::NamedNode(datatype)) => Some(datatype),
+                _ => None,
+            },
+            min_count: count(self.shape_values(shape, sh::MIN_COUNT)?),
+            max_count: count(self.shape_values(shape, sh::MAX_COUNT)?),
+            classes: self.shape_values(shape, sh::CLASS)?,
+            pattern: None,
+        };
+        if let Some(Term::Literal(pattern)) = self.shape_values(shape, sh::PATTERN)?.pop() {
+            let flags = match self.shape_values(shape, sh::FLAGS)?.pop() {
+                Some(Term::Literal(flags)) => Some(flags.value().to_owned()),
+                _ => None,
+            };
+            match compile_regex(pattern.value(), flags.as_deref()) {
+                Some(regex) => constraints.pattern = Some((pattern.value().to_owned(), regex)),
+                None => frame_support::log::warn!(
+                    target: "runtime::graphdb",
+                    "skipped the invalid pattern of the shape {}",
+                    shape
+                ),
+            }
+        }
+        Ok(constraints)
+    }
+
+    fn validate_all(&self) -> Result<ValidationReport, StoreError> {
+        let mut report = ValidationReport::default();
+        for shape in &self.shapes {
+            let mut focus_nodes = Vec::new();
+            for target in &shape.targets {
+                match target {
+                    Target::Class(class) => {
+                        for class in self.sub_classes(class)? {
+                            focus_nodes.extend(self.subjects(Some(rdf::TYPE), &class)?);
+                        }
+                    }
+                    Target::Node(node) => focus_nodes.push(node.clone()),
+                    Target::SubjectsOf(property) => {
+                        for quad in self.data_quads(None, Some(property.as_ref()), None)? {
+                            focus_nodes.push(quad.subject.into());
+                        }
+                    }
+                    Target::ObjectsOf(property) => {
+                        for quad in self.data_quads(None, Some(property.as_ref()), None)? {
+                            focus_nodes.push(quad.object);
+                        }
+                    }
+                }
+            }
+            let mut seen = HashSet::new();
+            for focus_node in focus_nodes {
+                if seen.insert(focus_node.clone()) {
+                    self.validate_shape(shape, &focus_node, &mut report)?;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    fn validate(&self, focus_nodes: &[Term]) -> Result<ValidationReport, StoreError> {
+        let mut report = ValidationReport::default();
+        for shape in &self.shapes {
+            for focus_node in focus_nodes {
+                if self.is_target(shape, focus_node)? {
+                    self.validate_shape(shape, focus_node, &mut report)?;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    fn is_target(&self, shape: &NodeShape, node: &Term) -> Result<bool, StoreError> {
+        for target in &shape.targets {
+            let is_target = match target {
+                Target::Class(class) => self.is_instance(node, class)?,
+                Target::Node(target) => target == node,
+                Target::SubjectsOf(property) => !self.objects(node, property.as_ref())?.is_empty(),
+                Target::ObjectsOf(property) => !self.subjects(Some(property.as_ref()), node)?.is_empty(),
+            };
+            if is_target {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn validate_shape(&self, shape: &NodeShape, focus_node: &Term, report: &mut ValidationReport) -> Result<(), StoreError> {
+        self.check(&shape.id, None, &shape.constraints, focus_node, vec![focus_node.clone()], report)?;
+        for property in &shape.properties {
+            let values = self.objects(focus_node, property.path.as_ref())?;
+            self.check(&property.id, Some(&property.path), &property.constraints, focus_node, values, report)?;
+        }
+        Ok(())
+    }
+
+    fn check(
+        &self,
+        shape: &Term,
+        path: Option<&NamedNode>,
+        constraints: &Constraints,
+        focus_node: &Term,
+        values: Vec<Term>,
+        report: &mut ValidationReport,
+    ) -> Result<(), StoreError> {
+        let mut violation = |value: Option<&Term>, component, message: String| {
+            report.results.push(ValidationResult {
+                focus_node: focus_node.clone(),
+                path: path.cloned(),
+                value: value.cloned(),
+                source_shape: shape.clone(),
+                component,
+                message,
+            })
+        };
+        if let Some(min_count) = constraints.min_count {
+            if values.len() < min_count {
+                violation(None, sh::MIN_COUNT_CONSTRAINT_COMPONENT, format!("Less than {} values", min_count));
+            }
+        }
+        if let Some(max_count) = constraints.max_count {
+            if values.len() > max_count {
+                violation(None, sh::MAX_COUNT_CONSTRAINT_COMPONENT, format!("More than {} values", max_count));
+            }
+        }
+        for value in &values {
+            if let Some(datatype) = &constraints.datatype {
+                let valid = match value {
+                    Term::Literal(literal) => literal.datatype() == datatype.as_ref(),
+                    _ => false,
+                };
+                if !valid {
+                    violation(
+                        Some(value),
+                        sh::DATATYPE_CONSTRAINT_COMPONENT,
+                        format!("The value is not a literal of datatype {}", datatype),
+                    );
+                }
+            }
+            for class in &constraints.classes {
+                if !self.is_instance(value, class)? {
+                    violation(
+                        Some(value),
+                        sh::CLASS_CONSTRAINT_COMPONENT,
+                        format!("The value is not an instance of {}", class),
+                    );
+                }
+            }
+            if let Some((pattern, regex)) = &constraints.pattern {
+                let valid = match value {
+                    Term::NamedNode(node) => regex.is_match(node.as_str()),
+                    Term::Literal(literal) => regex.is_match(literal.value()),
+                    Term::BlankNode(_) => false,
+                };
+                if !valid {
+                    violation(
+                        Some(value),
+                        sh::PATTERN_CONSTRAINT_COMPONENT,
+                        format!("The value does not match the pattern {}", pattern),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a node is an instance of a class or of one of its sub-classes
+    fn is_instance(&self, node: &Term, class: &Term) -> Result<bool, StoreError> {
+        if node.is_literal() {
+            return Ok(false);
+        }
+        let mut classes = self.objects(node, rdf::TYPE)?;
+        let mut seen = HashSet::new();
+        while let Some(node_class) = classes.pop() {
+            if &node_class == class {
+                return Ok(true);
+            }
+            if seen.insert(node_class.clone()) && !node_class.is_literal() {
+                classes.extend(self.objects(&node_class, rdfs::SUB_CLASS_OF)?);
+            }
+        }
+        Ok(false)
+    }
+
+    /// A class and its sub-classes
+    fn sub_classes(&self, class: &Term) -> Result<Vec<Term>, StoreError> {
+        let mut classes = vec![class.clone()];
+        let mut i = 0;
+        while i < classes.len() {
+            for sub_class in self.subjects(Some(rdfs::SUB_CLASS_OF), &classes[i].clone())? {
+                if !classes.contains(&sub_class) {
+                    classes.push(sub_class);
+                }
+            }
+            i += 1;
+        }
+        Ok(classes)
+    }
+
+    /// The values of a property of a node in the data graph
+    fn objects(&self, subject: &Term, predicate: NamedNodeRef<'_>) -> Result<Vec<Term>, StoreError> {
+        let subject = match subject {
+            Term::NamedNode(node) => NamedOrBlankNode::from(node.clone()),
+            Term::BlankNode(node) => node.clone().into(),
+            Term::Literal(_) => return Ok(Vec::new()),
+        };
+        let mut objects = Vec::new();
+        for quad in self.data_quads(Some(&subject), Some(predicate), None)? {
+            if !objects.contains(&quad.object) {
+                objects.push(quad.object);
+            }
+        }
+        Ok(objects)
+    }
+
+    /// The subjects of the quads of the data graph with an object, and a predicate if set
+    fn subjects(&self, predicate: Option<NamedNodeRef<'_>>, object: &Term) -> Result<Vec<Term>, StoreError> {
+        let mut subjects = Vec::new();
+        for quad in self.data_quads(None, predicate, Some(object))? {
+            let subject = Term::from(quad.subject);
+            if !subjects.contains(&subject) {
+                subjects.push(subject);
+            }
+        }
+        Ok(subjects)
+    }
+
+    fn data_quads(
+        &self,
+        subject: Option<&NamedOrBlankNode>,
+        predicate: Option<NamedNodeRef<'_>>,
+        object: Option<&Term>,
+    ) -> Result<Vec<crate::model::Quad>, StoreError> {
+        let mut quads = Vec::new();
+        for graph in &self.data_graphs {
+            for quad in self.store.quads_for_pattern(
+                subject.map(|s| s.as_ref()),
+                predicate,
+                object.map(|o| o.as_ref()),
+                Some(graph.as_ref()),
+            )? {
+                quads.push(quad?);
+            }
+        }
+        Ok(quads)
+    }
+
+    /// The values of a property of a shape in the shapes graph
+    fn shape_values(&self, shape: &Term, predicate: NamedNodeRef<'_>) -> Result<Vec<Term>, StoreError> {
+        let shape = match shape {
+            Term::NamedNode(node) => NamedOrBlankNode::from(node.clone()),
+            Term::BlankNode(node) => node.clone().into(),
+            Term::Literal(_) => return Ok(Vec::new()),
+        };
+        self.store
+            .quads_for_pattern(Some(shape.as_ref()), Some(predicate), None, Some(self.shapes_graph().into()))?
+            .map(|quad| Ok(quad?.object))
+            .collect()
+    }
+
+    fn shapes_graph(&self) -> NamedNodeRef<'static> {
+        NamedNodeRef::new_unchecked(self.shapes_graph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frame_support::assert_ok;
+
+    use crate::mock::{new_test_ext, Graphdb, Origin, Test};
+    use crate::{Error, Pallet};
+
+    const SHAPES: &str = "INSERT DATA { GRAPH <http://example.com/shapes> { \
+        :PersonShape sh:targetClass :Person ; sh:property :name , :email , :employer . \
+        :name sh:path :name ; sh:datatype xsd:string ; sh:minCount 1 ; sh:maxCount 1 . \
+        :email sh:path :email ; sh:pattern \"^[a-z]+@[a-z]+\\\\.com$\" ; sh:flags \"i\" . \
+        :employer sh:path :worksFor ; sh:class :Company } }";
+
+    fn root_update(update: &str) {
+        assert_ok!(Graphdb::root_sparql_update(Origin::root(), update.as_bytes().to_vec()));
+    }
+
+    /// Whether all the focus nodes conform to the shapes
+    fn conforms() -> bool {
+        Pallet::<Test>::validate_shapes().contains("\"true\"^^")
+    }
+
+    /// Fails if the signed update `update` is not reverted as a violation of the shapes
+    fn assert_violates(update: &str) {
+        let error = Graphdb::sparql_update(Origin::signed(1), update.as_bytes().to_vec()).unwrap_err();
+        assert_eq!(error.error, Error::<Test>::ShapesViolated.into());
+        assert!(conforms());
+    }
+
+    #[test]
+    fn the_values_are_checked_against_the_constraints() {
+        new_test_ext().execute_with(|| {
+            root_update(SHAPES);
+            assert_ok!(Graphdb::sparql_update(
+                Origin::signed(1),
+                b"INSERT DATA { :alice a :Person ; :name \"Alice\" ; :email \"Alice@Example.com\" }".to_vec()
+            ));
+            // sh:datatype
+            assert_violates("INSERT DATA { :bob a :Person ; :name 42 }");
+            // sh:minCount
+            assert_violates("INSERT DATA { :bob a :Person }");
+            assert_violates("DELETE DATA { :alice :name \"Alice\" }");
+            // sh:maxCount
+            assert_violates("INSERT DATA { :alice :name \"Al\" }");
+            // sh:pattern, with its flags
+            assert_violates("INSERT DATA { :alice :email \"alice@example.org\" }");
+            assert_violates("INSERT DATA { :alice :email <mailto:alice@example.com> }");
+            assert!(Pallet::<Test>::execute_query("ASK { :bob ?p ?o }").contains("false"));
+        });
+    }
+
+    #[test]
+    fn the_class_of_a_value_is_inherited_from_its_super_classes() {
+        new_test_ext().execute_with(|| {
+            root_update(SHAPES);
+            let update = b"INSERT DATA { :Startup rdfs:subClassOf :Company . :acme a :Startup . \
+                :alice a :Person ; :name \"Alice\" ; :worksFor :acme }";
+            assert_ok!(Graphdb::sparql_update(Origin::signed(1), update.to_vec()));
+            assert_violates("INSERT DATA { :alice :worksFor :nobody }");
+            assert_violates("INSERT DATA { :alice :worksFor \"acme\" }");
+            // the value is validated again when its type changes
+            assert_violates("DELETE DATA { :acme a :Startup }");
+        });
+    }
+
+    #[test]
+    fn a_change_of_the_shapes_validates_all_the_focus_nodes() {
+        new_test_ext().execute_with(|| {
+            assert_ok!(Graphdb::sparql_update(Origin::signed(1), b"INSERT DATA { :bob a :Person }".to_vec()));
+            let error = Graphdb::root_sparql_update(Origin::root(), SHAPES.as_bytes().to_vec()).unwrap_err();
+            assert_eq!(error.error, Error::<Test>::ShapesViolated.into());
+
+            root_update("INSERT DATA { :bob :name \"Bob\" }");
+            root_update(SHAPES);
+            assert!(conforms());
+        });
+    }
+
+    #[test]
+    fn only_root_writes_the_shapes() {
+        new_test_ext().execute_with(|| {
+            root_update(SHAPES);
+            for update in [
+                "DELETE DATA { GRAPH <http://example.com/shapes> { :name sh:minCount 1 } }",
+                "INSERT DATA { GRAPH <http://example.com/shapes> { :name sh:maxCount 0 } }",
+                "DROP GRAPH <http://example.com/shapes>",
+            ] {
+                let error = Graphdb::sparql_update(Origin::signed(1), update.as_bytes().to_vec()).unwrap_err();
+                assert_eq!(error.error, Error::<Test>::ProtectedGraph.into());
+            }
+            assert_violates("INSERT DATA { :bob a :Person }");
+        });
+    }
+}
@@ -7,9 +7,10 @@
 use crate::model::*;
 use crate::sparql::model::*;
 use crate::sparql::parser::{parse_query, parse_update, ParseError};
+use crate::sparql::{QueryOptions, UpdateOptions};
 use oxiri::Iri;
 use rio_api::model as rio;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
 use std::rc::Rc;
@@ -76,7 +77,16 @@ pub enum Query {
 impl Query {
     /// Parses a SPARQL query with an optional base IRI to resolve relative IRIs in the query
     pub fn parse(query: &str, base_iri: Option<&str>) -> Result<Self, ParseError> {
-        parse_query(query, base_iri)
+        parse_query(query, base_iri, HashSet::default())
+    }
+
+    /// Parses a SPARQL query whose calls to the custom aggregates of `options` are aggregates
+    pub fn parse_with_options(
+        query: &str,
+        base_iri: Option<&str>,
+        options: &QueryOptions,
+    ) -> Result<Self, ParseError> {
+        parse_query(query, base_iri, options.custom_functions.aggregates.keys().cloned().collect())
     }
 
     /// Returns [the query dataset specification](https://www.w3.org/TR/sparql11-query/#specifyingDataset)
@@ -222,7 +232,17 @@ pub struct Update {
 impl Update {
     /// Parses a SPARQL update with an optional base IRI to resolve relative IRIs in the query
     pub fn parse(update: &str, base_iri: Option<&str>) -> Result<Self, ParseError> {
-        parse_update(update, base_iri)
+        parse_update(update, base_iri, HashSet::default())
+    }
+
+    /// Parses a SPARQL update whose calls to the custom aggregates of `options` are aggregates
+    pub fn parse_with_options(
+        update: &str,
+        base_iri: Option<&str>,
+        options: &UpdateOptions,
+    ) -> Result<Self, ParseError> {
+        let aggregates = &options.query_options().custom_functions.aggregates;
+        parse_update(update, base_iri, aggregates.keys().cloned().collect())
     }
 }
 
@@ -1,9 +1,15 @@
+use crate::model::geo::Envelope;
+use crate::model::vector::Vector;
 use crate::sparql::algebra::QueryDataset;
+use crate::sparql::entailment::EqualityMode;
+use crate::sparql::plan_builder::CardinalityEstimator;
 use crate::sparql::EvaluationError;
 use crate::store::numeric_encoder::{
     EncodedQuad, EncodedTerm, ReadEncoder, StrContainer, StrEncodingAware, StrId, StrLookup,
 };
-use crate::store::ReadableEncodedStore;
+use crate::store::namespace::Namespaces;
+use crate::store::{EncodedObjectRange, GraphNameShape, QuadPatternShape, ReadableEncodedStore};
+use crate::text::TextQuery;
 use lasso::{Rodeo, Spur};
 use std::cell::RefCell;
 use std::iter::{empty, once, Once};
@@ -12,6 +18,7 @@ pub(crate) struct DatasetView<S: ReadableEncodedStore> {
     store: S,
     extra: RefCell<Rodeo>,
     dataset: EncodedDatasetSpec<S::StrId>,
+    equality: EqualityMode,
 }
 
 impl<S: ReadableEncodedStore> DatasetView<S> {
@@ -46,10 +53,16 @@ impl<S: ReadableEncodedStore> DatasetView<S> {
             store,
             extra: RefCell::new(Rodeo::default()),
             dataset,
+            equality: EqualityMode::Terms,
         })
     }
 
-    #[allow(clippy::needless_collect)]
+    /// Compares the terms of the quads with `equality`
+    pub fn with_equality(mut self, equality: EqualityMode) -> Self {
+        self.equality = equality;
+        self
+    }
+
     fn encoded_quads_for_pattern_in_dataset(
         &self,
         subject: Option<EncodedTerm<S::StrId>>,
@@ -57,40 +70,43 @@ impl<S: ReadableEncodedStore> DatasetView<S> {
         object: Option<EncodedTerm<S::StrId>>,
         graph_name: Option<EncodedTerm<S::StrId>>,
     ) -> Box<dyn Iterator<Item = Result<EncodedQuad<DatasetStrId<S::StrId>>, EvaluationError>>>
+    {
+        self.encoded_quads_in_dataset(graph_name, |graph_name| match self.equality {
+            EqualityMode::Terms => self
+                .store
+                .encoded_quads_for_pattern(subject, predicate, object, graph_name),
+            EqualityMode::SameAs => self
+                .store
+                .encoded_quads_for_equal_pattern(subject, predicate, object, graph_name),
+        })
+    }
+
+    /// Runs `lookup` on the store graphs the dataset maps `graph_name` to
+    #[allow(clippy::needless_collect)]
+    fn encoded_quads_in_dataset(
+        &self,
+        graph_name: Option<EncodedTerm<S::StrId>>,
+        lookup: impl Fn(Option<EncodedTerm<S::StrId>>) -> S::QuadsIter,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<DatasetStrId<S::StrId>>, EvaluationError>>>
     {
         if let Some(graph_name) = graph_name {
             if graph_name.is_default_graph() {
                 if let Some(default_graph_graphs) = &self.dataset.default {
                     if default_graph_graphs.len() == 1 {
                         // Single graph optimization
-                        Box::new(
-                            map_iter(self.store.encoded_quads_for_pattern(
-                                subject,
-                                predicate,
-                                object,
-                                Some(default_graph_graphs[0]),
+                        Box::new(map_iter(lookup(Some(default_graph_graphs[0]))).map(|quad| {
+                            let quad = quad?;
+                            Ok(EncodedQuad::new(
+                                quad.subject,
+                                quad.predicate,
+                                quad.object,
+                                EncodedTerm::DefaultGraph,
                             ))
-                            .map(|quad| {
-                                let quad = quad?;
-                                Ok(EncodedQuad::new(
-                                    quad.subject,
-                                    quad.predicate,
-                                    quad.object,
-                                    EncodedTerm::DefaultGraph,
-                                ))
-                            }),
-                        )
+                        }))
                     } else {
                         let iters = default_graph_graphs
                             .iter()
-                            .map(|graph_name| {
-                                self.store.encoded_quads_for_pattern(
-                                    subject,
-                                    predicate,
-                                    object,
-                                    Some(*graph_name),
-                                )
-                            })
+                            .map(|graph_name| lookup(Some(*graph_name)))
                             .collect::<Vec<_>>();
                         Box::new(map_iter(iters.into_iter().flatten()).map(|quad| {
                             let quad = quad?;
@@ -103,10 +119,7 @@ impl<S: ReadableEncodedStore> DatasetView<S> {
                         }))
                     }
                 } else {
-                    Box::new(map_iter(
-                        self.store
-                            .encoded_quads_for_pattern(subject, predicate, object, None),
-                    ))
+                    Box::new(map_iter(lookup(None)))
                 }
             } else if self
                 .dataset
@@ -114,39 +127,21 @@ impl<S: ReadableEncodedStore> DatasetView<S> {
                 .as_ref()
                 .map_or(true, |d| d.contains(&graph_name))
             {
-                Box::new(map_iter(self.store.encoded_quads_for_pattern(
-                    subject,
-                    predicate,
-                    object,
-                    Some(graph_name),
-                )))
+                Box::new(map_iter(lookup(Some(graph_name))))
             } else {
                 Box::new(empty())
             }
         } else if let Some(named_graphs) = &self.dataset.named {
             let iters = named_graphs
                 .iter()
-                .map(|graph_name| {
-                    self.store.encoded_quads_for_pattern(
-                        subject,
-                        predicate,
-                        object,
-                        Some(*graph_name),
-                    )
-                })
+                .map(|graph_name| lookup(Some(*graph_name)))
                 .collect::<Vec<_>>();
             Box::new(map_iter(iters.into_iter().flatten()))
         } else {
-            Box::new(
-                map_iter(
-                    self.store
-                        .encoded_quads_for_pattern(subject, predicate, object, None),
-                )
-                .filter(|quad| match quad {
-                    Err(_) => true,
-                    Ok(quad) => quad.graph_name != EncodedTerm::DefaultGraph,
-                }),
-            )
+            Box::new(map_iter(lookup(None)).filter(|quad| match quad {
+                Err(_) => true,
+                Ok(quad) => quad.graph_name != EncodedTerm::DefaultGraph,
+            }))
         }
     }
 }
@@ -154,6 +149,10 @@ impl<S: ReadableEncodedStore> DatasetView<S> {
 impl<S: ReadableEncodedStore> StrEncodingAware for DatasetView<S> {
     type Error = EvaluationError;
     type StrId = DatasetStrId<S::StrId>;
+
+    fn namespaces(&self) -> Namespaces {
+        self.store.namespaces()
+    }
 }
 
 impl<S: ReadableEncodedStore> StrLookup for DatasetView<S> {
@@ -215,6 +214,250 @@ impl<S: ReadableEncodedStore> ReadableEncodedStore for DatasetView<S> {
             "Graphs lookup is not implemented by DatasetView",
         ))
     }
+
+    fn encoded_quads_indexes_for_pattern(&self, shape: QuadPatternShape) -> Vec<&'static str> {
+        let graph_names = match shape.graph_name {
+            GraphNameShape::DefaultGraph => self.default_graph_shapes(),
+            GraphNameShape::NamedGraph => vec![GraphNameShape::NamedGraph],
+            GraphNameShape::Any => self.named_graphs_shapes(),
+            GraphNameShape::Bound => {
+                let mut shapes = self.default_graph_shapes();
+                shapes.push(GraphNameShape::NamedGraph);
+                shapes
+            }
+        };
+        let mut indexes = Vec::new();
+        for graph_name in graph_names {
+            for index in self
+                .store
+                .encoded_quads_indexes_for_pattern(QuadPatternShape {
+                    graph_name,
+                    ..shape
+                })
+            {
+                if !indexes.contains(&index) {
+                    indexes.push(index);
+                }
+            }
+        }
+        indexes
+    }
+
+    fn encoded_predicate_cardinality(&self, predicate: EncodedTerm<Self::StrId>) -> Option<u64> {
+        if let Ok(predicate) = predicate.try_map_id(unwrap_store_id) {
+            self.store.encoded_predicate_cardinality(predicate)
+        } else {
+            // The term is not in the store so no quad uses it
+            Some(0)
+        }
+    }
+
+    fn encoded_predicate_class_cardinality(
+        &self,
+        predicate: EncodedTerm<Self::StrId>,
+        class: EncodedTerm<Self::StrId>,
+    ) -> Option<u64> {
+        if let (Ok(predicate), Ok(class)) = (
+            predicate.try_map_id(unwrap_store_id),
+            class.try_map_id(unwrap_store_id),
+        ) {
+            self.store
+                .encoded_predicate_class_cardinality(predicate, class)
+        } else {
+            Some(0)
+        }
+    }
+
+    fn encoded_quad_count(
+        &self,
+        predicate: Option<EncodedTerm<Self::StrId>>,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Option<u64> {
+        if self.equality == EqualityMode::SameAs {
+            // the quads of the members of a class are merged
+            return None;
+        }
+        let (predicate, graph_name) =
+            if let Some((_, predicate, _, graph_name)) = try_map_quad_pattern(None, predicate, None, graph_name) {
+                (predicate, graph_name)
+            } else {
+                // The term is not in the store so no quad uses it
+                return Some(0);
+            };
+        let count = |graph_name| self.store.encoded_quad_count(predicate, graph_name);
+        if let Some(graph_name) = graph_name {
+            if graph_name.is_default_graph() {
+                if let Some(default_graph_graphs) = &self.dataset.default {
+                    default_graph_graphs.iter().map(|graph_name| count(Some(*graph_name))).sum()
+                } else {
+                    count(None)
+                }
+            } else if self
+                .dataset
+                .named
+                .as_ref()
+                .map_or(true, |d| d.contains(&graph_name))
+            {
+                count(Some(graph_name))
+            } else {
+                Some(0)
+            }
+        } else if let Some(named_graphs) = &self.dataset.named {
+            named_graphs.iter().map(|graph_name| count(Some(*graph_name))).sum()
+        } else {
+            Some(count(None)?.saturating_sub(count(Some(EncodedTerm::DefaultGraph))?))
+        }
+    }
+
+    fn encoded_quads_for_object_range(
+        &self,
+        predicate: EncodedTerm<Self::StrId>,
+        range: &EncodedObjectRange<Self::StrId>,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<DatasetStrId<S::StrId>>, EvaluationError>>>
+    {
+        let range =
+            transpose(range.lower.map(|t| t.try_map_id(unwrap_store_id).ok())).and_then(|lower| {
+                Some(EncodedObjectRange {
+                    lower,
+                    upper: transpose(range.upper.map(|t| t.try_map_id(unwrap_store_id).ok()))?,
+                })
+            });
+        let range = if let Some(range) = range.filter(|_| self.equality == EqualityMode::Terms) {
+            range
+        } else {
+            // Bounds only known by the query can't be looked up in the store index,
+            // and the index does not replace the nodes by the canonical node of their owl:sameAs class
+            return self.encoded_quads_for_pattern(None, Some(predicate), None, graph_name);
+        };
+        if let Some((_, Some(predicate), _, graph_name)) =
+            try_map_quad_pattern(None, Some(predicate), None, graph_name)
+        {
+            self.encoded_quads_in_dataset(graph_name, |graph_name| {
+                self.store
+                    .encoded_quads_for_object_range(predicate, &range, graph_name)
+            })
+        } else {
+            Box::new(empty())
+        }
+    }
+
+    fn encoded_object_range_indexes(&self) -> Vec<&'static str> {
+        self.store.encoded_object_range_indexes()
+    }
+
+    fn encoded_quads_for_text(
+        &self,
+        predicate: Option<EncodedTerm<Self::StrId>>,
+        query: &TextQuery,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<DatasetStrId<S::StrId>>, EvaluationError>>>
+    {
+        if self.equality != EqualityMode::Terms {
+            // the index does not replace the nodes by the canonical node of their owl:sameAs class
+            return self.encoded_quads_for_pattern(None, predicate, None, graph_name);
+        }
+        if let Some((_, predicate, _, graph_name)) =
+            try_map_quad_pattern(None, predicate, None, graph_name)
+        {
+            self.encoded_quads_in_dataset(graph_name, |graph_name| {
+                self.store
+                    .encoded_quads_for_text(predicate, query, graph_name)
+            })
+        } else {
+            Box::new(empty())
+        }
+    }
+
+    fn encoded_text_indexes(&self) -> Vec<&'static str> {
+        self.store.encoded_text_indexes()
+    }
+
+    fn encoded_quads_for_area(
+        &self,
+        predicate: Option<EncodedTerm<Self::StrId>>,
+        area: &Envelope,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<DatasetStrId<S::StrId>>, EvaluationError>>>
+    {
+        if self.equality != EqualityMode::Terms {
+            // the index does not replace the nodes by the canonical node of their owl:sameAs class
+            return self.encoded_quads_for_pattern(None, predicate, None, graph_name);
+        }
+        if let Some((_, predicate, _, graph_name)) =
+            try_map_quad_pattern(None, predicate, None, graph_name)
+        {
+            self.encoded_quads_in_dataset(graph_name, |graph_name| {
+                self.store
+                    .encoded_quads_for_area(predicate, area, graph_name)
+            })
+        } else {
+            Box::new(empty())
+        }
+    }
+
+    fn encoded_spatial_indexes(&self) -> Vec<&'static str> {
+        self.store.encoded_spatial_indexes()
+    }
+
+    fn encoded_quads_for_vector(
+        &self,
+        predicate: EncodedTerm<Self::StrId>,
+        query: &Vector,
+        k: usize,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<DatasetStrId<S::StrId>>, EvaluationError>>>
+    {
+        if self.equality != EqualityMode::Terms {
+            // the index does not replace the nodes by the canonical node of their owl:sameAs class
+            return self.encoded_quads_for_pattern(None, Some(predicate), None, graph_name);
+        }
+        if let Some((_, Some(predicate), _, graph_name)) =
+            try_map_quad_pattern(None, Some(predicate), None, graph_name)
+        {
+            self.encoded_quads_in_dataset(graph_name, |graph_name| {
+                self.store
+                    .encoded_quads_for_vector(predicate, query, k, graph_name)
+            })
+        } else {
+            Box::new(empty())
+        }
+    }
+
+    fn encoded_vector_indexes(&self) -> Vec<&'static str> {
+        self.store.encoded_vector_indexes()
+    }
+}
+
+impl<S: ReadableEncodedStore> DatasetView<S> {
+    /// Shapes of the store lookups done for the query default graph
+    fn default_graph_shapes(&self) -> Vec<GraphNameShape> {
+        if let Some(default_graph_graphs) = &self.dataset.default {
+            let mut shapes = Vec::new();
+            for graph_name in default_graph_graphs {
+                let shape = if graph_name.is_default_graph() {
+                    GraphNameShape::DefaultGraph
+                } else {
+                    GraphNameShape::NamedGraph
+                };
+                if !shapes.contains(&shape) {
+                    shapes.push(shape);
+                }
+            }
+            shapes
+        } else {
+            vec![GraphNameShape::Any]
+        }
+    }
+
+    /// Shapes of the store lookups done for an unbound graph name
+    fn named_graphs_shapes(&self) -> Vec<GraphNameShape> {
+        if self.dataset.named.is_some() {
+            vec![GraphNameShape::NamedGraph]
+        } else {
+            vec![GraphNameShape::Any]
+        }
+    }
 }
 
 fn map_iter<'a, I: StrId>(
@@ -267,6 +510,28 @@ fn unwrap_store_id<I: StrId>(id: DatasetStrId<I>) -> Result<I, ()> {
     }
 }
 
+impl<'a, S: ReadableEncodedStore> CardinalityEstimator for &'a DatasetView<S> {
+    fn predicate_cardinality(&self, predicate: EncodedTerm<Self::StrId>) -> Option<u64> {
+        self.encoded_predicate_cardinality(predicate)
+    }
+
+    fn predicate_class_cardinality(
+        &self,
+        predicate: EncodedTerm<Self::StrId>,
+        class: EncodedTerm<Self::StrId>,
+    ) -> Option<u64> {
+        self.encoded_predicate_class_cardinality(predicate, class)
+    }
+
+    fn quad_count(
+        &self,
+        predicate: Option<EncodedTerm<Self::StrId>>,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Option<u64> {
+        self.encoded_quad_count(predicate, graph_name)
+    }
+}
+
 impl<'a, S: ReadableEncodedStore> StrContainer for &'a DatasetView<S> {
     fn insert_str(&mut self, value: &str) -> Result<Self::StrId, EvaluationError> {
         if let Some(id) = self.store.get_str_id(value).map_err(|e| e.into())? {
@@ -0,0 +1,293 @@
+//! [RDFS entailment](https://www.w3.org/TR/sparql11-entailment/#RDFSEntailmentRegime) at query time.
+//!
+//! The `rdfs:subClassOf`, `rdfs:subPropertyOf`, `rdfs:domain` and `rdfs:range` statements of the dataset
+//! of the query are read before it is planned. The triple patterns with a named node as predicate, or with
+//! `rdf:type` and a named node as class, are then rewritten by `PlanBuilder` into the union of the patterns
+//! entailing them. The other patterns only match the stored quads, and the axiomatic triples of RDFS are
+//! not entailed.
+
+use crate::model::vocab::{rdf, rdfs};
+use crate::model::{NamedNode, NamedNodeRef, Term};
+use crate::sparql::algebra::{GraphPattern, NamedNodeOrVariable, TermOrVariable, TriplePattern};
+use crate::sparql::dataset::DatasetView;
+use crate::sparql::error::EvaluationError;
+use crate::sparql::model::Variable;
+use crate::store::numeric_encoder::{Decoder, EncodedTerm, ReadEncoder};
+use crate::store::ReadableEncodedStore;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+
+/// The entailment regime used to match the basic graph patterns of a query
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum EntailmentRegime {
+    /// The patterns only match the stored quads
+    Simple,
+    /// The patterns also match the quads entailed by the RDFS statements of the dataset
+    Rdfs,
+}
+
+impl Default for EntailmentRegime {
+    #[inline]
+    fn default() -> Self {
+        Self::Simple
+    }
+}
+
+/// How the terms of the quads are compared when the basic graph patterns of a query are matched
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum EqualityMode {
+    /// Two distinct terms denote distinct resources
+    Terms,
+    /// The nodes linked by `owl:sameAs` in the default graph of the store denote the same resource: the patterns
+    /// match the quads of all of them and the answers use a canonical one
+    SameAs,
+}
+
+impl Default for EqualityMode {
+    #[inline]
+    fn default() -> Self {
+        Self::Terms
+    }
+}
+
+/// The RDFS statements of a dataset, by object
+pub(crate) struct RdfsSchema {
+    /// The direct sub-classes of each class
+    sub_classes: HashMap<NamedNode, Vec<NamedNode>>,
+    /// The direct sub-properties of each property
+    sub_properties: HashMap<NamedNode, Vec<NamedNode>>,
+    /// The properties whose domain is each class
+    domain_of: HashMap<NamedNode, Vec<NamedNode>>,
+    /// The properties whose range is each class
+    range_of: HashMap<NamedNode, Vec<NamedNode>>,
+}
+
+impl RdfsSchema {
+    /// Reads the schema of the dataset if `regime` entails RDFS, from its default graph and its named graphs
+    pub fn load<S: ReadableEncodedStore>(
+        regime: EntailmentRegime,
+        dataset: &DatasetView<S>,
+    ) -> Result<Option<Rc<Self>>, EvaluationError> {
+        if regime == EntailmentRegime::Simple {
+            return Ok(None);
+        }
+        Ok(Some(Rc::new(Self {
+            sub_classes: statements(dataset, rdfs::SUB_CLASS_OF)?,
+            sub_properties: statements(dataset, rdfs::SUB_PROPERTY_OF)?,
+            domain_of: statements(dataset, rdfs::DOMAIN)?,
+            range_of: statements(dataset, rdfs::RANGE)?,
+        })))
+    }
+
+    /// The union of the patterns entailing `pattern`, without duplicates, `None` if only the pattern itself does.
+    ///
+    /// The union is projected on the variables of `pattern` and on `graph_variable`, the variable of the graph
+    /// the pattern is matched in, so that the bindings of the other patterns are joined with it.
+    pub fn rewrite(&self, pattern: &TriplePattern, graph_variable: Option<&Variable>) -> Option<GraphPattern> {
+        let predicate = match &pattern.predicate {
+            NamedNodeOrVariable::NamedNode(predicate) => predicate,
+            NamedNodeOrVariable::Variable(_) => return None,
+        };
+        let alternatives = if predicate.as_ref() == rdf::TYPE {
+            if let TermOrVariable::Term(Term::NamedNode(class)) = &pattern.object {
+                self.type_alternatives(&pattern.subject, class)
+            } else {
+                return None;
+            }
+        } else {
+            closure(&self.sub_properties, predicate)
+                .into_iter()
+                .map(|p| TriplePattern::new(pattern.subject.clone(), p, pattern.object.clone()))
+                .collect()
+        };
+        if alternatives.len() <= 1 {
+            return None;
+        }
+
+        let mut projection = Vec::new();
+        for term in [&pattern.subject, &pattern.object].iter() {
+            match term {
+                TermOrVariable::Variable(v) => projection.push(v.clone()),
+                // the plan builder matches the blank nodes of the patterns as variables with the same name
+                TermOrVariable::Term(Term::BlankNode(bnode)) => {
+                    projection.push(Variable::new_unchecked(bnode.as_str()))
+                }
+                TermOrVariable::Term(_) => (),
+            }
+        }
+        projection.extend(graph_variable.cloned());
+        let mut seen = BTreeSet::new();
+        projection.retain(|v| seen.insert(v.as_str().to_owned()));
+        let union = alternatives
+            .into_iter()
+            .map(|alternative| GraphPattern::BGP(vec![alternative]))
+            .reduce(|left, right| GraphPattern::Union {
+                left: Box::new(left),
+                right: Box::new(right),
+            })?;
+        Some(GraphPattern::Distinct {
+            inner: Box::new(GraphPattern::Project {
+                inner: Box::new(union),
+                projection,
+            }),
+        })
+    }
+
+    /// The patterns entailing that `subject` is an instance of `class`: it has the type of a sub-class, or it
+    /// is the subject (the object) of a property whose domain (range) is a sub-class
+    fn type_alternatives(&self, subject: &TermOrVariable, class: &NamedNode) -> Vec<TriplePattern> {
+        let classes = closure(&self.sub_classes, class);
+        let mut alternatives: Vec<TriplePattern> = classes
+            .iter()
+            .map(|c| TriplePattern::new(subject.clone(), rdf::TYPE.into_owned(), c.clone()))
+            .collect();
+        for c in classes.iter() {
+            for p in self.domain_of.get(c).into_iter().flatten() {
+                for p in closure(&self.sub_properties, p) {
+                    alternatives.push(TriplePattern::new(subject.clone(), p, Variable::new_random()));
+                }
+            }
+            for p in self.range_of.get(c).into_iter().flatten() {
+                for p in closure(&self.sub_properties, p) {
+                    alternatives.push(TriplePattern::new(Variable::new_random(), p, subject.clone()));
+                }
+            }
+        }
+        alternatives
+    }
+}
+
+/// The subjects of the statements with the predicate `predicate` and a named node as subject and object,
+/// by object
+fn statements<S: ReadableEncodedStore>(
+    dataset: &DatasetView<S>,
+    predicate: NamedNodeRef<'_>,
+) -> Result<HashMap<NamedNode, Vec<NamedNode>>, EvaluationError> {
+    let mut statements = HashMap::<NamedNode, Vec<NamedNode>>::new();
+    let predicate = if let Some(predicate) = dataset.get_encoded_named_node(predicate)? {
+        predicate
+    } else {
+        return Ok(statements);
+    };
+    for graph_name in [Some(EncodedTerm::DefaultGraph), None].iter() {
+        for quad in dataset.encoded_quads_for_pattern(None, Some(predicate), None, *graph_name) {
+            let quad = quad?;
+            if let (Term::NamedNode(subject), Term::NamedNode(object)) = (
+                dataset.decode_term(quad.subject)?,
+                dataset.decode_term(quad.object)?,
+            ) {
+                let subjects = statements.entry(object).or_default();
+                if !subjects.contains(&subject) {
+                    subjects.push(subject);
+                }
+            }
+        }
+    }
+    Ok(statements)
+}
+
+/// `node` and the nodes reachable from it in `edges`, in a stable order
+fn closure(edges: &HashMap<NamedNode, Vec<NamedNode>>, node: &NamedNode) -> Vec<NamedNode> {
+    let mut seen = BTreeSet::new();
+    let mut nodes = vec![node.clone()];
+    seen.insert(node.as_str());
+    let mut i = 0;
+    while i < nodes.len() {
+        for next in edges.get(&nodes[i]).into_iter().flatten() {
+            if seen.insert(next.as_str()) {
+                nodes.push(next.clone());
+            }
+        }
+        i += 1;
+    }
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EntailmentRegime;
+    use crate::mock::{new_test_ext, Test};
+    use crate::Pallet;
+
+    const DATA: &str = "INSERT DATA {
+        :Student rdfs:subClassOf :Person . :teaches rdfs:subPropertyOf :knows .
+        :advises rdfs:domain :Teacher ; rdfs:range :Student .
+        :alice a :Student ; :name \"Alice\" . :carol a :Student ; :name \"Carol\" .
+        :bob :teaches :dave ; :advises :carol, :erin ; :name \"Bob\" .
+        GRAPH :g { :frank a :Student . :gina :advises :hugo } GRAPH :h { :hugo :name \"Hugo\" } }";
+
+    /// The solutions of a query with RDFS entailment, as the local names of their values separated by spaces
+    fn solutions(query: &str) -> Vec<String> {
+        let results = Pallet::<Test>::execute_query_with_entailment(query, EntailmentRegime::Rdfs);
+        let results: serde_json::Value = serde_json::from_str(&results).unwrap();
+        let variables = results["head"]["vars"].as_array().unwrap().clone();
+        let mut solutions: Vec<String> = results["results"]["bindings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|solution| {
+                let values: Vec<&str> = variables
+                    .iter()
+                    .map(|variable| match solution[variable.as_str().unwrap()]["value"].as_str() {
+                        Some(value) => value.rsplit('/').next().unwrap(),
+                        None => "-",
+                    })
+                    .collect();
+                values.join(" ")
+            })
+            .collect();
+        solutions.sort();
+        solutions
+    }
+
+    #[test]
+    fn the_patterns_match_the_entailed_quads_once() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(DATA).unwrap();
+            // rdfs:subClassOf and rdfs:range, :carol is a :Student twice
+            assert_eq!(solutions("SELECT ?x WHERE { ?x a :Person }"), ["alice", "carol", "erin"]);
+            // rdfs:subPropertyOf
+            assert_eq!(solutions("SELECT ?o WHERE { :bob :knows ?o }"), ["dave"]);
+            // rdfs:domain, :bob advises two students
+            assert_eq!(solutions("SELECT ?x WHERE { ?x a :Teacher }"), ["bob"]);
+            assert!(Pallet::<Test>::execute_query_with_entailment("ASK { :erin a :Person }", EntailmentRegime::Rdfs)
+                .contains("true"));
+            // the simple entailment only matches the stored quads
+            assert!(Pallet::<Test>::execute_query("ASK { :erin a :Person }").contains("false"));
+        });
+    }
+
+    #[test]
+    fn the_entailed_patterns_are_joined_with_the_other_bindings() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(DATA).unwrap();
+            assert_eq!(
+                solutions("SELECT ?n WHERE { ?x :name ?n . ?x a :Person }"),
+                ["Alice", "Carol"]
+            );
+            assert_eq!(
+                solutions("SELECT ?n ?t WHERE { ?x :name ?n OPTIONAL { ?x a :Teacher BIND(:teacher AS ?t) } }"),
+                ["Alice -", "Bob teacher", "Carol -"]
+            );
+        });
+    }
+
+    #[test]
+    fn the_entailed_patterns_of_a_graph_match_its_quads() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(DATA).unwrap();
+            assert_eq!(
+                solutions("SELECT ?x ?g WHERE { GRAPH ?g { ?x a :Person } }"),
+                ["frank g", "hugo g"]
+            );
+            assert_eq!(solutions("SELECT ?x WHERE { GRAPH :g { ?x a :Teacher } }"), ["gina"]);
+            assert_eq!(solutions("SELECT ?x WHERE { GRAPH :h { ?x a :Person } }"), Vec::<String>::new());
+            // the graph of the entailed quad is the graph of the other patterns
+            assert_eq!(solutions("SELECT ?x WHERE { GRAPH ?g { ?x a :Student . ?x :name ?n } }"), Vec::<String>::new());
+            assert_eq!(
+                solutions("SELECT ?x ?g WHERE { GRAPH ?g { ?y :advises ?x . ?x a :Person } }"),
+                ["hugo g"]
+            );
+        });
+    }
+}
@@ -1,4 +1,4 @@
-use crate::error::invalid_data_error;
+use crate::error::{invalid_data_error, StoreError};
 use crate::sparql::ParseError;
 use crate::store::numeric_encoder::DecoderError;
 use std::convert::Infallible;
@@ -83,6 +83,14 @@ impl EvaluationError {
         })
     }
 
+    /// The store error the evaluation failed with, if any.
+    pub(crate) fn store_error(&self) -> Option<&StoreError> {
+        match self {
+            Self::Io(error) => error.get_ref()?.downcast_ref(),
+            _ => None,
+        }
+    }
+
     /// Builds an error from a printable error message.
     pub(crate) fn msg(msg: impl Into<String>) -> Self {
         Self::Query(QueryError {
@@ -97,6 +105,12 @@ impl From<Infallible> for EvaluationError {
     }
 }
 
+impl From<StoreError> for EvaluationError {
+    fn from(error: StoreError) -> Self {
+        Self::Io(error.into())
+    }
+}
+
 impl From<ParseError> for EvaluationError {
     fn from(error: ParseError) -> Self {
         Self::Parsing(error)
@@ -1,15 +1,20 @@
-use crate::model::vocab::{rdf, xsd};
+use crate::model::geo::{Envelope, Geometry, Unit};
+use crate::model::vector::Vector;
+use crate::model::vocab::{geo, rdf, vec, xsd};
 use crate::model::xsd::*;
-use crate::model::Triple;
+use crate::model::{Term, Triple};
 use crate::model::{BlankNode, LiteralRef, NamedNodeRef};
 use crate::sparql::algebra::{GraphPattern, Query, QueryDataset};
 use crate::sparql::error::EvaluationError;
+use crate::sparql::explain::PlanStats;
 use crate::sparql::model::*;
 use crate::sparql::plan::*;
 use crate::sparql::service::ServiceHandler;
+use crate::sparql::{CustomFunction, CustomFunctions};
 use crate::store::numeric_encoder::*;
 use crate::store::small_string::SmallString;
-use crate::store::ReadableEncodedStore;
+use crate::store::{EncodedObjectRange, ReadableEncodedStore};
+use crate::text::TextQuery;
 use digest::Digest;
 use md5::Md5;
 use oxilangtag::LanguageTag;
@@ -36,6 +41,9 @@ pub(crate) struct SimpleEvaluator<S> {
     base_iri: Option<Rc<Iri<String>>>,
     now: DateTime,
     service_handler: Rc<dyn ServiceHandler<Error = EvaluationError>>,
+    custom_functions: Rc<CustomFunctions>,
+    stats: Option<Rc<PlanStats>>,
+    stats_node: usize, // the plan node storage reads are counted for
 }
 
 impl<S> Clone for SimpleEvaluator<S> {
@@ -45,6 +53,9 @@ impl<S> Clone for SimpleEvaluator<S> {
             base_iri: self.base_iri.clone(),
             now: self.now,
             service_handler: self.service_handler.clone(),
+            custom_functions: self.custom_functions.clone(),
+            stats: self.stats.clone(),
+            stats_node: self.stats_node,
         }
     }
 }
@@ -57,15 +68,25 @@ where
         dataset: Rc<S>,
         base_iri: Option<Rc<Iri<String>>>,
         service_handler: Rc<dyn ServiceHandler<Error = EvaluationError>>,
+        custom_functions: Rc<CustomFunctions>,
     ) -> Self {
         Self {
             dataset,
             base_iri,
             now: DateTime::now().unwrap(),
             service_handler,
+            custom_functions,
+            stats: None,
+            stats_node: 0,
         }
     }
 
+    /// Collects rows and storage reads per plan node during evaluation
+    pub fn with_stats(mut self, stats: Rc<PlanStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
     pub fn evaluate_select_plan(
         &self,
         plan: &PlanNode<S::StrId>,
@@ -124,6 +145,37 @@ where
         &self,
         node: &PlanNode<S::StrId>,
         from: EncodedTuple<S::StrId>,
+    ) -> EncodedTuplesIterator<S::StrId> {
+        let iter = if let Some(stats) = &self.stats {
+            let key = PlanStats::key(node);
+            stats.add_evaluation(key);
+            let eval = Self {
+                stats_node: key,
+                ..self.clone()
+            };
+            let stats = stats.clone();
+            Box::new(eval.eval_node(node, from).inspect(move |tuple| {
+                if tuple.is_ok() {
+                    stats.add_row(key)
+                }
+            }))
+        } else {
+            self.eval_node(node, from)
+        };
+        if self.custom_functions.is_empty() {
+            iter
+        } else {
+            Box::new(FunctionErrorIterator {
+                iter,
+                custom_functions: self.custom_functions.clone(),
+            })
+        }
+    }
+
+    fn eval_node(
+        &self,
+        node: &PlanNode<S::StrId>,
+        from: EncodedTuple<S::StrId>,
     ) -> EncodedTuplesIterator<S::StrId> {
         match node {
             PlanNode::Init => Box::new(once(Ok(from))),
@@ -156,20 +208,60 @@ where
                 predicate,
                 object,
                 graph_name,
+            }
+            | PlanNode::QuadPatternRangeJoin {
+                child,
+                subject,
+                predicate,
+                object,
+                graph_name,
+                ..
+            }
+            | PlanNode::QuadPatternSpatialJoin {
+                child,
+                subject,
+                predicate,
+                object,
+                graph_name,
+                ..
             } => {
                 let eval = self.clone();
                 let subject = *subject;
                 let predicate = *predicate;
                 let object = *object;
                 let graph_name = *graph_name;
+                let range = if let PlanNode::QuadPatternRangeJoin { range, .. } = node {
+                    Some(*range)
+                } else {
+                    None
+                };
+                let area = if let PlanNode::QuadPatternSpatialJoin { area, .. } = node {
+                    Some(*area)
+                } else {
+                    None
+                };
                 Box::new(self.eval_plan(child, from).flat_map_ok(move |tuple| {
-                    let mut iter: Box<dyn Iterator<Item = _>> =
-                        Box::new(eval.dataset.encoded_quads_for_pattern(
-                            get_pattern_value(&subject, &tuple),
-                            get_pattern_value(&predicate, &tuple),
-                            get_pattern_value(&object, &tuple),
-                            get_pattern_value(&graph_name, &tuple),
-                        ));
+                    let input_subject = get_pattern_value(&subject, &tuple);
+                    let input_predicate = get_pattern_value(&predicate, &tuple);
+                    let input_object = get_pattern_value(&object, &tuple);
+                    let input_graph_name = get_pattern_value(&graph_name, &tuple);
+                    let mut iter = match (range, area, input_subject, input_predicate, input_object) {
+                        // The range scan is only worth it if nothing else restricts the lookup
+                        (Some(range), _, None, Some(input_predicate), None) => eval
+                            .encoded_quads_for_object_range(
+                                input_predicate,
+                                &range,
+                                input_graph_name,
+                            ),
+                        (_, Some(area), None, Some(input_predicate), None) => eval
+                            .encoded_quads_for_area(Some(input_predicate), &area, input_graph_name),
+                        _ => eval.encoded_quads_for_pattern(
+                            input_subject,
+                            input_predicate,
+                            input_object,
+                            input_graph_name,
+                        ),
+                    };
                     if subject.is_var() && subject == predicate {
                         iter = Box::new(iter.filter(|quad| match quad {
                             Err(_) => true,
@@ -220,6 +312,131 @@ where
                     iter
                 }))
             }
+            PlanNode::TextMatch {
+                child,
+                subject,
+                predicate,
+                query,
+                graph_name,
+                score,
+                literal,
+            } => {
+                let eval = self.clone();
+                let subject = *subject;
+                let predicate = *predicate;
+                let query = query.clone();
+                let graph_name = *graph_name;
+                let score = *score;
+                let literal = *literal;
+                Box::new(self.eval_plan(child, from).flat_map_ok(move |tuple| {
+                    let input_subject = get_pattern_value(&subject, &tuple);
+                    let input_graph_name = get_pattern_value(&graph_name, &tuple);
+                    let iter = if input_subject.is_some() {
+                        eval.encoded_quads_for_pattern(
+                            input_subject,
+                            predicate,
+                            None,
+                            input_graph_name,
+                        )
+                    } else {
+                        eval.encoded_quads_for_text(predicate, &query, input_graph_name)
+                    };
+                    let eval = eval.clone();
+                    let query = query.clone();
+                    let iter: EncodedTuplesIterator<_> = Box::new(iter.filter_map(move |quad| {
+                        let quad = match quad {
+                            Ok(quad) => quad,
+                            Err(error) => return Some(Err(error)),
+                        };
+                        // The store may return literals which do not match
+                        let relevance = query.score(&eval.to_string(quad.object)?)?;
+                        let mut new_tuple = tuple.clone();
+                        put_pattern_value(&subject, quad.subject, &mut new_tuple);
+                        put_pattern_value(&graph_name, quad.graph_name, &mut new_tuple);
+                        for (position, value) in [
+                            (literal, quad.object),
+                            (score, EncodedTerm::DoubleLiteral(relevance)),
+                        ]
+                        .iter()
+                        {
+                            if let Some(position) = position {
+                                match new_tuple.get(*position) {
+                                    Some(bound) if bound != *value => return None,
+                                    _ => new_tuple.set(*position, *value),
+                                }
+                            }
+                        }
+                        Some(Ok(new_tuple))
+                    }));
+                    iter
+                }))
+            }
+            PlanNode::VectorNearest {
+                child,
+                subject,
+                predicate,
+                query,
+                k,
+                graph_name,
+                distance,
+                vector,
+            } => {
+                let eval = self.clone();
+                let subject = *subject;
+                let predicate = *predicate;
+                let query = *query;
+                let k = *k;
+                let graph_name = *graph_name;
+                let distance = *distance;
+                let vector = *vector;
+                Box::new(self.eval_plan(child, from).flat_map_ok(move |tuple| {
+                    let query = match get_pattern_value(&query, &tuple).and_then(|query| eval.to_vector(query)) {
+                        Some(query) => query,
+                        None => return Box::new(empty()) as EncodedTuplesIterator<_>,
+                    };
+                    let input_graph_name = get_pattern_value(&graph_name, &tuple);
+                    // The store may return other quads: the nearest ones are picked again
+                    let mut nearest = Vec::new();
+                    for quad in eval.encoded_quads_for_vector(predicate, &query, k, input_graph_name) {
+                        let quad = match quad {
+                            Ok(quad) => quad,
+                            Err(error) => return Box::new(once(Err(error))),
+                        };
+                        if let Some(distance) = eval
+                            .to_vector(quad.object)
+                            .and_then(|vector| vector.cosine_distance(&query))
+                        {
+                            nearest.push((distance, quad));
+                        }
+                    }
+                    nearest.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+                    nearest.truncate(k);
+                    // A bound subject only keeps its quads among the nearest ones
+                    let input_subject = get_pattern_value(&subject, &tuple);
+                    Box::new(nearest.into_iter().filter_map(move |(cosine_distance, quad)| {
+                        if matches!(input_subject, Some(input_subject) if input_subject != quad.subject) {
+                            return None;
+                        }
+                        let mut new_tuple = tuple.clone();
+                        put_pattern_value(&subject, quad.subject, &mut new_tuple);
+                        put_pattern_value(&graph_name, quad.graph_name, &mut new_tuple);
+                        for (position, value) in [
+                            (vector, quad.object),
+                            (distance, EncodedTerm::DoubleLiteral(cosine_distance)),
+                        ]
+                        .iter()
+                        {
+                            if let Some(position) = position {
+                                match new_tuple.get(*position) {
+                                    Some(bound) if bound != *value => return None,
+                                    _ => new_tuple.set(*position, *value),
+                                }
+                            }
+                        }
+                        Some(Ok(new_tuple))
+                    }))
+                }))
+            }
             PlanNode::PathPatternJoin {
                 child,
                 subject,
@@ -307,6 +524,21 @@ where
                     buffered_results: errors,
                 })
             }
+            PlanNode::HashJoin { left, right, keys } => {
+                let mut errors = Vec::default();
+                let mut table = JoinHashTable::new(keys.clone());
+                for result in self.eval_plan(left, from.clone()) {
+                    match result {
+                        Ok(tuple) => table.insert(tuple),
+                        Err(error) => errors.push(Err(error)),
+                    }
+                }
+                Box::new(HashJoinIterator {
+                    table,
+                    right_iter: self.eval_plan(right, from),
+                    buffered_results: errors,
+                })
+            }
             PlanNode::AntiJoin { left, right } => {
                 //TODO: dumb implementation
                 let right: Vec<_> = self
@@ -341,6 +573,28 @@ where
                     })
                 }
             }
+            PlanNode::HashLeftJoin {
+                left,
+                right,
+                keys,
+                expression,
+            } => {
+                let mut errors = Vec::default();
+                let mut table = JoinHashTable::new(keys.clone());
+                for result in self.eval_plan(right, from.clone()) {
+                    match result {
+                        Ok(tuple) => table.insert(tuple),
+                        Err(error) => errors.push(Err(error)),
+                    }
+                }
+                Box::new(HashLeftJoinIterator {
+                    eval: self.clone(),
+                    table,
+                    expression: expression.clone(),
+                    left_iter: self.eval_plan(left, from),
+                    buffered_results: errors,
+                })
+            }
             PlanNode::Filter { child, expression } => {
                 let eval = self.clone();
                 let expression = expression.clone();
@@ -417,27 +671,38 @@ where
             }
             PlanNode::Skip { child, count } => Box::new(self.eval_plan(child, from).skip(*count)),
             PlanNode::Limit { child, count } => Box::new(self.eval_plan(child, from).take(*count)),
-            PlanNode::Project { child, mapping } => {
-                //TODO: use from somewhere?
+            PlanNode::Project { child, mapping, .. } => {
+                // the projected variables bound by `from` are bound in the child, and the other
+                // bindings of `from` are kept in the output
                 let mapping = mapping.clone();
-                Box::new(
-                    self.eval_plan(child, EncodedTuple::with_capacity(mapping.len()))
-                        .map(move |tuple| {
-                            let tuple = tuple?;
-                            let mut output_tuple = EncodedTuple::with_capacity(from.capacity());
-                            for (input_key, output_key) in mapping.iter() {
-                                if let Some(value) = tuple.get(*input_key) {
-                                    output_tuple.set(*output_key, value)
-                                }
+                let mut input_tuple = EncodedTuple::with_capacity(mapping.len());
+                for (input_key, output_key) in mapping.iter() {
+                    if let Some(value) = from.get(*output_key) {
+                        input_tuple.set(*input_key, value)
+                    }
+                }
+                Box::new(self.eval_plan(child, input_tuple).filter_map(move |tuple| {
+                    let tuple = match tuple {
+                        Ok(tuple) => tuple,
+                        Err(error) => return Some(Err(error)),
+                    };
+                    let mut output_tuple = from.clone();
+                    for (input_key, output_key) in mapping.iter() {
+                        if let Some(value) = tuple.get(*input_key) {
+                            match output_tuple.get(*output_key) {
+                                Some(existing) if existing != value => return None,
+                                _ => output_tuple.set(*output_key, value),
                             }
-                            Ok(output_tuple)
-                        }),
-                )
+                        }
+                    }
+                    Some(Ok(output_tuple))
+                }))
             }
             PlanNode::Aggregate {
                 child,
                 key_mapping,
                 aggregates,
+                ..
             } => {
                 let tuple_size = from.capacity(); //TODO: not nice
                 let key_mapping = key_mapping.clone();
@@ -482,8 +747,20 @@ where
                         }
                     });
                 if accumulators_for_group.is_empty() {
-                    // There is always at least one group
-                    accumulators_for_group.insert(vec![None; key_mapping.len()], Vec::default());
+                    // There is always at least one group, its aggregates are the ones of no solution
+                    // (a count of zero)
+                    accumulators_for_group.insert(
+                        vec![None; key_mapping.len()],
+                        aggregates
+                            .iter()
+                            .map(|(aggregate, _)| {
+                                self.accumulator_for_aggregate(
+                                    &aggregate.function,
+                                    aggregate.distinct,
+                                )
+                            })
+                            .collect(),
+                    );
                 }
                 Box::new(
                     errors
@@ -510,6 +787,84 @@ where
         }
     }
 
+    /// Store lookup, counting the storage reads of the current node when collecting stats
+    fn encoded_quads_for_pattern(
+        &self,
+        subject: Option<EncodedTerm<S::StrId>>,
+        predicate: Option<EncodedTerm<S::StrId>>,
+        object: Option<EncodedTerm<S::StrId>>,
+        graph_name: Option<EncodedTerm<S::StrId>>,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<S::StrId>, EvaluationError>>> {
+        self.count_reads(
+            self.dataset
+                .encoded_quads_for_pattern(subject, predicate, object, graph_name),
+        )
+    }
+
+    fn encoded_quads_for_object_range(
+        &self,
+        predicate: EncodedTerm<S::StrId>,
+        range: &EncodedObjectRange<S::StrId>,
+        graph_name: Option<EncodedTerm<S::StrId>>,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<S::StrId>, EvaluationError>>> {
+        self.count_reads(
+            self.dataset
+                .encoded_quads_for_object_range(predicate, range, graph_name),
+        )
+    }
+
+    fn encoded_quads_for_text(
+        &self,
+        predicate: Option<EncodedTerm<S::StrId>>,
+        query: &TextQuery,
+        graph_name: Option<EncodedTerm<S::StrId>>,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<S::StrId>, EvaluationError>>> {
+        self.count_reads(
+            self.dataset
+                .encoded_quads_for_text(predicate, query, graph_name),
+        )
+    }
+
+    fn encoded_quads_for_area(
+        &self,
+        predicate: Option<EncodedTerm<S::StrId>>,
+        area: &Envelope,
+        graph_name: Option<EncodedTerm<S::StrId>>,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<S::StrId>, EvaluationError>>> {
+        self.count_reads(
+            self.dataset
+                .encoded_quads_for_area(predicate, area, graph_name),
+        )
+    }
+
+    fn encoded_quads_for_vector(
+        &self,
+        predicate: EncodedTerm<S::StrId>,
+        query: &Vector,
+        k: usize,
+        graph_name: Option<EncodedTerm<S::StrId>>,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<S::StrId>, EvaluationError>>> {
+        self.count_reads(
+            self.dataset
+                .encoded_quads_for_vector(predicate, query, k, graph_name),
+        )
+    }
+
+    /// Counts the store lookup and the quads it returns in the profile of the current node
+    fn count_reads(
+        &self,
+        iter: S::QuadsIter,
+    ) -> Box<dyn Iterator<Item = Result<EncodedQuad<S::StrId>, EvaluationError>>> {
+        if let Some(stats) = &self.stats {
+            let stats = stats.clone();
+            let node = self.stats_node;
+            stats.add_reads(node, 1);
+            Box::new(iter.inspect(move |_| stats.add_reads(node, 1)))
+        } else {
+            Box::new(iter)
+        }
+    }
+
     fn evaluate_service(
         &self,
         service_name: &PatternValue<S::StrId>,
@@ -576,6 +931,17 @@ where
                     Box::new(GroupConcatAccumulator::new(self.clone(), separator.clone()))
                 }
             }
+            PlanAggregationFunction::Custom(name) => {
+                let accumulator = CustomAccumulator::new(
+                    self.clone(),
+                    self.custom_functions.aggregates.get(name).cloned(),
+                );
+                if distinct {
+                    Box::new(DistinctAccumulator::new(accumulator))
+                } else {
+                    Box::new(accumulator)
+                }
+            }
         }
     }
 
@@ -587,8 +953,7 @@ where
     ) -> Box<dyn Iterator<Item = Result<EncodedTerm<S::StrId>, EvaluationError>>> {
         match path {
             PlanPropertyPath::Path(p) => Box::new(
-                self.dataset
-                    .encoded_quads_for_pattern(Some(start), Some(*p), None, Some(graph_name))
+                self.encoded_quads_for_pattern(Some(start), Some(*p), None, Some(graph_name))
                     .map(|t| Ok(t?.object)),
             ),
             PlanPropertyPath::Reverse(p) => self.eval_path_to(p, start, graph_name),
@@ -625,8 +990,7 @@ where
             PlanPropertyPath::NegatedPropertySet(ps) => {
                 let ps = ps.clone();
                 Box::new(
-                    self.dataset
-                        .encoded_quads_for_pattern(Some(start), None, None, Some(graph_name))
+                    self.encoded_quads_for_pattern(Some(start), None, None, Some(graph_name))
                         .filter_map(move |t| match t {
                             Ok(t) => {
                                 if ps.contains(&t.predicate) {
@@ -650,8 +1014,7 @@ where
     ) -> Box<dyn Iterator<Item = Result<EncodedTerm<S::StrId>, EvaluationError>>> {
         match path {
             PlanPropertyPath::Path(p) => Box::new(
-                self.dataset
-                    .encoded_quads_for_pattern(None, Some(*p), Some(end), Some(graph_name))
+                self.encoded_quads_for_pattern(None, Some(*p), Some(end), Some(graph_name))
                     .map(|t| Ok(t?.subject)),
             ),
             PlanPropertyPath::Reverse(p) => self.eval_path_from(p, end, graph_name),
@@ -688,8 +1051,7 @@ where
             PlanPropertyPath::NegatedPropertySet(ps) => {
                 let ps = ps.clone();
                 Box::new(
-                    self.dataset
-                        .encoded_quads_for_pattern(None, None, Some(end), Some(graph_name))
+                    self.encoded_quads_for_pattern(None, None, Some(end), Some(graph_name))
                         .filter_map(move |t| match t {
                             Ok(t) => {
                                 if ps.contains(&t.predicate) {
@@ -716,8 +1078,7 @@ where
     > {
         match path {
             PlanPropertyPath::Path(p) => Box::new(
-                self.dataset
-                    .encoded_quads_for_pattern(None, Some(*p), None, Some(graph_name))
+                self.encoded_quads_for_pattern(None, Some(*p), None, Some(graph_name))
                     .map(|t| t.map(|t| (t.subject, t.object))),
             ),
             PlanPropertyPath::Reverse(p) => Box::new(
@@ -768,8 +1129,7 @@ where
             PlanPropertyPath::NegatedPropertySet(ps) => {
                 let ps = ps.clone();
                 Box::new(
-                    self.dataset
-                        .encoded_quads_for_pattern(None, None, None, Some(graph_name))
+                    self.encoded_quads_for_pattern(None, None, None, Some(graph_name))
                         .filter_map(move |t| match t {
                             Ok(t) => {
                                 if ps.contains(&t.predicate) {
@@ -790,8 +1150,7 @@ where
         graph_name: EncodedTerm<S::StrId>,
     ) -> impl Iterator<Item = Result<(EncodedTerm<S::StrId>, EncodedTerm<S::StrId>), EvaluationError>>
     {
-        self.dataset
-            .encoded_quads_for_pattern(None, None, None, Some(graph_name))
+        self.encoded_quads_for_pattern(None, None, None, Some(graph_name))
             .flat_map_ok(|t| once(Ok(t.subject)).chain(once(Ok(t.object))))
             .map(|e| e.map(|e| (e, e)))
     }
@@ -1368,6 +1727,20 @@ where
                 }
                 None
             }
+            PlanExpression::CustomFunction(name, l) => {
+                let function = match self.custom_functions.function(name) {
+                    Ok(function) => function?,
+                    Err(error) => {
+                        self.custom_functions.fail(error);
+                        return None;
+                    }
+                };
+                let arguments = l
+                    .iter()
+                    .map(|e| self.dataset.decode_term(self.eval_expression(e, tuple)?).ok())
+                    .collect::<Option<Vec<_>>>()?;
+                self.encode_term(self.call_function(&function, &arguments)?)
+            }
             PlanExpression::If(a, b, c) => {
                 if self.to_bool(self.eval_expression(a, tuple)?)? {
                     self.eval_expression(b, tuple)
@@ -1383,13 +1756,7 @@ where
             }
             PlanExpression::StrDt(lexical_form, datatype) => {
                 let value = self.to_simple_string(self.eval_expression(lexical_form, tuple)?)?;
-                let datatype = if let EncodedTerm::NamedNode { iri_id } =
-                    self.eval_expression(datatype, tuple)?
-                {
-                    self.dataset.get_str(iri_id).ok()?
-                } else {
-                    None
-                }?;
+                let datatype = self.to_named_node_str(self.eval_expression(datatype, tuple)?)?;
                 let mut encoder = self.dataset.as_ref();
                 encoder
                     .encode_literal(LiteralRef::new_typed_literal(
@@ -1562,6 +1929,77 @@ where
                     self.to_string_id(self.eval_expression(e, tuple)?)?,
                 ))
             }
+            PlanExpression::GeoDistance(a, b, unit) => {
+                let a = self.to_geometry(self.eval_expression(a, tuple)?)?;
+                let b = self.to_geometry(self.eval_expression(b, tuple)?)?;
+                let unit = self.to_unit(self.eval_expression(unit, tuple)?)?;
+                Some(a.distance(&b, unit).into())
+            }
+            PlanExpression::GeoBuffer(geometry, radius, unit) => {
+                let geometry = self.to_geometry(self.eval_expression(geometry, tuple)?)?;
+                let radius = self.to_f64(self.eval_expression(radius, tuple)?)?;
+                let unit = self.to_unit(self.eval_expression(unit, tuple)?)?;
+                self.build_wkt_literal(&geometry.buffer(radius, unit)?)
+            }
+            PlanExpression::GeoIntersects(a, b) => {
+                let a = self.to_geometry(self.eval_expression(a, tuple)?)?;
+                let b = self.to_geometry(self.eval_expression(b, tuple)?)?;
+                Some(a.intersects(&b).into())
+            }
+            PlanExpression::GeoWithin(a, b) => {
+                let a = self.to_geometry(self.eval_expression(a, tuple)?)?;
+                let b = self.to_geometry(self.eval_expression(b, tuple)?)?;
+                Some(a.within(&b).into())
+            }
+            PlanExpression::CosineDistance(a, b) => {
+                let a = self.to_vector(self.eval_expression(a, tuple)?)?;
+                let b = self.to_vector(self.eval_expression(b, tuple)?)?;
+                Some(a.cosine_distance(&b)?.into())
+            }
+            PlanExpression::DotProduct(a, b) => {
+                let a = self.to_vector(self.eval_expression(a, tuple)?)?;
+                let b = self.to_vector(self.eval_expression(b, tuple)?)?;
+                Some(a.dot(&b)?.into())
+            }
+            PlanExpression::L2Distance(a, b) => {
+                let a = self.to_vector(self.eval_expression(a, tuple)?)?;
+                let b = self.to_vector(self.eval_expression(b, tuple)?)?;
+                Some(a.l2_distance(&b)?.into())
+            }
+        }
+    }
+
+    fn to_geometry(&self, term: EncodedTerm<S::StrId>) -> Option<Geometry> {
+        match term {
+            EncodedTerm::SmallWktLiteral(value) => value.parse().ok(),
+            EncodedTerm::BigWktLiteral { value_id } => {
+                self.dataset.get_str(value_id).ok()??.parse().ok()
+            }
+            _ => None,
+        }
+    }
+
+    fn to_vector(&self, term: EncodedTerm<S::StrId>) -> Option<Vector> {
+        match term {
+            EncodedTerm::SmallVectorLiteral(value) => value.parse().ok(),
+            EncodedTerm::BigVectorLiteral { value_id } => {
+                self.dataset.get_str(value_id).ok()??.parse().ok()
+            }
+            _ => None,
+        }
+    }
+
+    fn to_unit(&self, term: EncodedTerm<S::StrId>) -> Option<Unit> {
+        Unit::from_iri(&self.to_named_node_str(term)?)
+    }
+
+    fn to_f64(&self, term: EncodedTerm<S::StrId>) -> Option<f64> {
+        match term {
+            EncodedTerm::FloatLiteral(value) => Some(value.into()),
+            EncodedTerm::DoubleLiteral(value) => Some(value),
+            EncodedTerm::IntegerLiteral(value) => Some(value as f64),
+            EncodedTerm::DecimalLiteral(value) => Some(value.to_f64()),
+            _ => None,
         }
     }
 
@@ -1584,17 +2022,24 @@ where
         match term {
             EncodedTerm::DefaultGraph => None,
             EncodedTerm::NamedNode { iri_id } => Some(iri_id.into()),
+            EncodedTerm::SmallPrefixedNamedNode { .. } | EncodedTerm::BigPrefixedNamedNode { .. } => {
+                self.build_string_id(&self.to_named_node_str(term)?)
+            }
             EncodedTerm::NumericalBlankNode { .. }
             | EncodedTerm::SmallBlankNode { .. }
             | EncodedTerm::BigBlankNode { .. } => None,
             EncodedTerm::SmallStringLiteral(value)
             | EncodedTerm::SmallSmallLangStringLiteral { value, .. }
             | EncodedTerm::SmallBigLangStringLiteral { value, .. }
-            | EncodedTerm::SmallTypedLiteral { value, .. } => Some(value.into()),
+            | EncodedTerm::SmallTypedLiteral { value, .. }
+            | EncodedTerm::SmallWktLiteral(value)
+            | EncodedTerm::SmallVectorLiteral(value) => Some(value.into()),
             EncodedTerm::BigStringLiteral { value_id }
             | EncodedTerm::BigSmallLangStringLiteral { value_id, .. }
             | EncodedTerm::BigBigLangStringLiteral { value_id, .. }
-            | EncodedTerm::BigTypedLiteral { value_id, .. } => Some(value_id.into()),
+            | EncodedTerm::BigTypedLiteral { value_id, .. }
+            | EncodedTerm::BigWktLiteral { value_id }
+            | EncodedTerm::BigVectorLiteral { value_id } => Some(value_id.into()),
             EncodedTerm::BooleanLiteral(value) => {
                 self.build_string_id(if value { "true" } else { "false" })
             }
@@ -1680,10 +2125,30 @@ where
         }
     }
 
+    fn to_named_node_str(&self, term: EncodedTerm<S::StrId>) -> Option<String> {
+        Some(self.dataset.decode_named_node(term).ok()?.into_string())
+    }
+
+    /// Calls a custom function, keeps the error failing the evaluation until the plan returns it
+    fn call_function(&self, function: &CustomFunction, arguments: &[Term]) -> Option<Term> {
+        match function(arguments) {
+            Ok(value) => value,
+            Err(error) => {
+                self.custom_functions.fail(error);
+                None
+            }
+        }
+    }
+
+    fn encode_term(&self, term: Term) -> Option<EncodedTerm<S::StrId>> {
+        self.dataset.as_ref().encode_term(term.as_ref()).ok()
+    }
+
     fn build_named_node(&self, iri: &str) -> Option<EncodedTerm<S::StrId>> {
-        Some(EncodedTerm::NamedNode {
-            iri_id: self.dataset.as_ref().encode_str(iri).ok()?,
-        })
+        self.dataset
+            .as_ref()
+            .encode_named_node(NamedNodeRef::new_unchecked(iri))
+            .ok()
     }
 
     fn build_string_literal(&self, value: &str) -> Option<EncodedTerm<S::StrId>> {
@@ -1697,6 +2162,13 @@ where
         }
     }
 
+    fn build_wkt_literal(&self, geometry: &Geometry) -> Option<EncodedTerm<S::StrId>> {
+        Some(match self.build_string_id(&geometry.to_string())? {
+            SmallStringOrId::Small(value) => EncodedTerm::SmallWktLiteral(value),
+            SmallStringOrId::Big(value_id) => EncodedTerm::BigWktLiteral { value_id },
+        })
+    }
+
     fn build_lang_string_literal(
         &self,
         value: &str,
@@ -1776,30 +2248,11 @@ where
     ) -> Option<Regex> {
         // TODO Avoid to compile the regex each time
         let pattern = self.to_simple_string(pattern)?;
-        let mut regex_builder = RegexBuilder::new(&pattern);
-        regex_builder.size_limit(REGEX_SIZE_LIMIT);
-        if let Some(flags) = flags {
-            let flags = self.to_simple_string(flags)?;
-            for flag in flags.chars() {
-                match flag {
-                    's' => {
-                        regex_builder.dot_matches_new_line(true);
-                    }
-                    'm' => {
-                        regex_builder.multi_line(true);
-                    }
-                    'i' => {
-                        regex_builder.case_insensitive(true);
-                    }
-                    'x' => {
-                        regex_builder.ignore_whitespace(true);
-                    }
-                    'q' => (), //TODO: implement
-                    _ => (),
-                }
-            }
-        }
-        regex_builder.build().ok()
+        let flags = match flags {
+            Some(flags) => Some(self.to_simple_string(flags)?),
+            None => None,
+        };
+        compile_regex(&pattern, flags.as_deref())
     }
 
     fn parse_numeric_operands(
@@ -1866,6 +2319,8 @@ where
         match a {
             EncodedTerm::DefaultGraph
             | EncodedTerm::NamedNode { .. }
+            | EncodedTerm::SmallPrefixedNamedNode { .. }
+            | EncodedTerm::BigPrefixedNamedNode { .. }
             | EncodedTerm::NumericalBlankNode { .. }
             | EncodedTerm::SmallBlankNode { .. }
             | EncodedTerm::BigBlankNode { .. }
@@ -1886,6 +2341,8 @@ where
             EncodedTerm::SmallTypedLiteral { .. } => match b {
                 EncodedTerm::SmallTypedLiteral { .. } if a == b => Some(true),
                 EncodedTerm::NamedNode { .. }
+                | EncodedTerm::SmallPrefixedNamedNode { .. }
+                | EncodedTerm::BigPrefixedNamedNode { .. }
                 | EncodedTerm::NumericalBlankNode { .. }
                 | EncodedTerm::SmallBlankNode { .. }
                 | EncodedTerm::BigBlankNode { .. }
@@ -1899,6 +2356,8 @@ where
             EncodedTerm::BigTypedLiteral { .. } => match b {
                 EncodedTerm::BigTypedLiteral { .. } if a == b => Some(true),
                 EncodedTerm::NamedNode { .. }
+                | EncodedTerm::SmallPrefixedNamedNode { .. }
+                | EncodedTerm::BigPrefixedNamedNode { .. }
                 | EncodedTerm::NumericalBlankNode { .. }
                 | EncodedTerm::SmallBlankNode { .. }
                 | EncodedTerm::BigBlankNode { .. }
@@ -2007,6 +2466,20 @@ where
                 _ if b.is_unknown_typed_literal() => None,
                 _ => Some(false),
             },
+            // the geometries are encoded with their canonical text
+            EncodedTerm::SmallWktLiteral(_) | EncodedTerm::BigWktLiteral { .. } => match b {
+                EncodedTerm::SmallWktLiteral(_) | EncodedTerm::BigWktLiteral { .. } => Some(a == b),
+                _ if b.is_unknown_typed_literal() => None,
+                _ => Some(false),
+            },
+            // the vectors too
+            EncodedTerm::SmallVectorLiteral(_) | EncodedTerm::BigVectorLiteral { .. } => match b {
+                EncodedTerm::SmallVectorLiteral(_) | EncodedTerm::BigVectorLiteral { .. } => {
+                    Some(a == b)
+                }
+                _ if b.is_unknown_typed_literal() => None,
+                _ => Some(false),
+            },
         }
     }
 
@@ -2033,10 +2506,10 @@ where
                     _ if b.is_blank_node() => Ordering::Equal,
                     _ => Ordering::Less,
                 },
-                EncodedTerm::NamedNode { iri_id: a } => match b {
-                    EncodedTerm::NamedNode { iri_id: b } => {
-                        self.compare_str_ids(a, b).unwrap_or(Ordering::Equal)
-                    }
+                _ if a.is_named_node() => match b {
+                    _ if b.is_named_node() => self
+                        .compare_named_nodes(a, b)
+                        .unwrap_or(Ordering::Equal),
                     _ if b.is_blank_node() => Ordering::Greater,
                     _ => Ordering::Less,
                 },
@@ -2174,6 +2647,19 @@ where
         }
     }
 
+    fn compare_named_nodes(
+        &self,
+        a: EncodedTerm<S::StrId>,
+        b: EncodedTerm<S::StrId>,
+    ) -> Option<Ordering> {
+        match (a, b) {
+            (EncodedTerm::NamedNode { iri_id: a }, EncodedTerm::NamedNode { iri_id: b }) => {
+                self.compare_str_ids(a, b)
+            }
+            (a, b) => Some(self.to_named_node_str(a)?.cmp(&self.to_named_node_str(b)?)),
+        }
+    }
+
     fn compare_str_ids(&self, a: S::StrId, b: S::StrId) -> Option<Ordering> {
         Some(
             self.dataset
@@ -2205,6 +2691,8 @@ where
         //TODO: optimize?
         match value {
             EncodedTerm::NamedNode { .. }
+            | EncodedTerm::SmallPrefixedNamedNode { .. }
+            | EncodedTerm::BigPrefixedNamedNode { .. }
             | EncodedTerm::SmallBlankNode { .. }
             | EncodedTerm::BigBlankNode { .. }
             | EncodedTerm::NumericalBlankNode { .. }
@@ -2219,9 +2707,9 @@ where
                 self.build_named_node(rdf::LANG_STRING.as_str())
             }
             EncodedTerm::SmallTypedLiteral { datatype_id, .. }
-            | EncodedTerm::BigTypedLiteral { datatype_id, .. } => Some(EncodedTerm::NamedNode {
-                iri_id: datatype_id,
-            }),
+            | EncodedTerm::BigTypedLiteral { datatype_id, .. } => {
+                self.build_named_node(&self.dataset.get_str(datatype_id).ok()??)
+            }
             EncodedTerm::BooleanLiteral(..) => self.build_named_node(xsd::BOOLEAN.as_str()),
             EncodedTerm::FloatLiteral(..) => self.build_named_node(xsd::FLOAT.as_str()),
             EncodedTerm::DoubleLiteral(..) => self.build_named_node(xsd::DOUBLE.as_str()),
@@ -2242,6 +2730,12 @@ where
             EncodedTerm::DayTimeDurationLiteral(..) => {
                 self.build_named_node(xsd::DAY_TIME_DURATION.as_str())
             }
+            EncodedTerm::SmallWktLiteral(..) | EncodedTerm::BigWktLiteral { .. } => {
+                self.build_named_node(geo::WKT_LITERAL.as_str())
+            }
+            EncodedTerm::SmallVectorLiteral(..) | EncodedTerm::BigVectorLiteral { .. } => {
+                self.build_named_node(vec::FLOAT_VECTOR.as_str())
+            }
         }
     }
 }
@@ -2490,6 +2984,80 @@ impl<I: StrId> Iterator for JoinIterator<I> {
     }
 }
 
+/// Tuples indexed by the values they have for the join keys
+struct JoinHashTable<I: StrId> {
+    keys: Rc<Vec<usize>>,
+    bound: HashMap<Vec<EncodedTerm<I>>, Vec<EncodedTuple<I>>>,
+    unbound: Vec<EncodedTuple<I>>, // tuples with some keys not bound
+}
+
+impl<I: StrId> JoinHashTable<I> {
+    fn new(keys: Rc<Vec<usize>>) -> Self {
+        Self {
+            keys,
+            bound: HashMap::default(),
+            unbound: Vec::default(),
+        }
+    }
+
+    fn key(&self, tuple: &EncodedTuple<I>) -> Option<Vec<EncodedTerm<I>>> {
+        self.keys.iter().map(|key| tuple.get(*key)).collect()
+    }
+
+    fn insert(&mut self, tuple: EncodedTuple<I>) {
+        if let Some(key) = self.key(&tuple) {
+            self.bound.entry(key).or_default().push(tuple)
+        } else {
+            self.unbound.push(tuple)
+        }
+    }
+
+    /// Returns the tuples that might be compatible with the given one
+    fn candidates<'a>(
+        &'a self,
+        tuple: &EncodedTuple<I>,
+    ) -> Box<dyn Iterator<Item = &'a EncodedTuple<I>> + 'a> {
+        if let Some(key) = self.key(tuple) {
+            Box::new(
+                self.bound
+                    .get(&key)
+                    .into_iter()
+                    .flatten()
+                    .chain(self.unbound.iter()),
+            )
+        } else {
+            Box::new(self.bound.values().flatten().chain(self.unbound.iter()))
+        }
+    }
+}
+
+struct HashJoinIterator<I: StrId> {
+    table: JoinHashTable<I>,
+    right_iter: EncodedTuplesIterator<I>,
+    buffered_results: Vec<Result<EncodedTuple<I>, EvaluationError>>,
+}
+
+impl<I: StrId> Iterator for HashJoinIterator<I> {
+    type Item = Result<EncodedTuple<I>, EvaluationError>;
+
+    fn next(&mut self) -> Option<Result<EncodedTuple<I>, EvaluationError>> {
+        loop {
+            if let Some(result) = self.buffered_results.pop() {
+                return Some(result);
+            }
+            let right_tuple = match self.right_iter.next()? {
+                Ok(right_tuple) => right_tuple,
+                Err(error) => return Some(Err(error)),
+            };
+            for left_tuple in self.table.candidates(&right_tuple) {
+                if let Some(result_tuple) = left_tuple.combine_with(&right_tuple) {
+                    self.buffered_results.push(Ok(result_tuple))
+                }
+            }
+        }
+    }
+}
+
 struct AntiJoinIterator<I: StrId> {
     left_iter: EncodedTuplesIterator<I>,
     right: Vec<EncodedTuple<I>>,
@@ -2546,6 +3114,48 @@ where
     }
 }
 
+struct HashLeftJoinIterator<S: ReadableEncodedStore + 'static> {
+    eval: SimpleEvaluator<S>,
+    table: JoinHashTable<S::StrId>,
+    expression: Option<Rc<PlanExpression<S::StrId>>>,
+    left_iter: EncodedTuplesIterator<S::StrId>,
+    buffered_results: Vec<Result<EncodedTuple<S::StrId>, EvaluationError>>,
+}
+
+impl<S: ReadableEncodedStore<Error = EvaluationError> + 'static> Iterator
+    for HashLeftJoinIterator<S>
+where
+    for<'a> &'a S: StrContainer<StrId = S::StrId>,
+{
+    type Item = Result<EncodedTuple<S::StrId>, EvaluationError>;
+
+    fn next(&mut self) -> Option<Result<EncodedTuple<S::StrId>, EvaluationError>> {
+        if let Some(result) = self.buffered_results.pop() {
+            return Some(result);
+        }
+        let left_tuple = match self.left_iter.next()? {
+            Ok(left_tuple) => left_tuple,
+            Err(error) => return Some(Err(error)),
+        };
+        for right_tuple in self.table.candidates(&left_tuple) {
+            if let Some(result_tuple) = left_tuple.combine_with(right_tuple) {
+                let accepted = if let Some(expression) = &self.expression {
+                    self.eval
+                        .eval_expression(expression, &result_tuple)
+                        .and_then(|term| self.eval.to_bool(term))
+                        .unwrap_or(false)
+                } else {
+                    true
+                };
+                if accepted {
+                    self.buffered_results.push(Ok(result_tuple))
+                }
+            }
+        }
+        Some(self.buffered_results.pop().unwrap_or(Ok(left_tuple)))
+    }
+}
+
 struct BadLeftJoinIterator<S: ReadableEncodedStore + 'static> {
     eval: SimpleEvaluator<S>,
     right_plan: Rc<PlanNode<S::StrId>>,
@@ -2631,6 +3241,24 @@ where
     }
 }
 
+/// Returns the error of a custom function called while evaluating the next solution
+struct FunctionErrorIterator<I: StrId> {
+    iter: EncodedTuplesIterator<I>,
+    custom_functions: Rc<CustomFunctions>,
+}
+
+impl<I: StrId> Iterator for FunctionErrorIterator<I> {
+    type Item = Result<EncodedTuple<I>, EvaluationError>;
+
+    fn next(&mut self) -> Option<Result<EncodedTuple<I>, EvaluationError>> {
+        let next = self.iter.next();
+        match self.custom_functions.take_error() {
+            Some(error) => Some(Err(error)),
+            None => next,
+        }
+    }
+}
+
 struct ConstructIterator<S: ReadableEncodedStore + 'static> {
     eval: SimpleEvaluator<S>,
     iter: EncodedTuplesIterator<S::StrId>,
@@ -2818,6 +3446,33 @@ fn transitive_closure<T: Copy + Eq + Hash, NI: Iterator<Item = Result<T, Evaluat
     errors.into_iter().map(Err).chain(all.into_iter().map(Ok))
 }
 
+/// Compiles a regular expression with the flags of the [XPath `fn:matches`](https://www.w3.org/TR/xpath-functions/#flags) function
+pub(crate) fn compile_regex(pattern: &str, flags: Option<&str>) -> Option<Regex> {
+    let mut regex_builder = RegexBuilder::new(pattern);
+    regex_builder.size_limit(REGEX_SIZE_LIMIT);
+    if let Some(flags) = flags {
+        for flag in flags.chars() {
+            match flag {
+                's' => {
+                    regex_builder.dot_matches_new_line(true);
+                }
+                'm' => {
+                    regex_builder.multi_line(true);
+                }
+                'i' => {
+                    regex_builder.case_insensitive(true);
+                }
+                'x' => {
+                    regex_builder.ignore_whitespace(true);
+                }
+                'q' => (), //TODO: implement
+                _ => (),
+            }
+        }
+    }
+    regex_builder.build().ok()
+}
+
 fn hash_deduplicate<T: Eq + Hash + Clone>(
     iter: impl Iterator<Item = Result<T, EvaluationError>>,
 ) -> impl Iterator<Item = Result<T, EvaluationError>> {
@@ -3158,6 +3813,45 @@ where
     }
 }
 
+/// Collects the values of a group for an aggregate registered on the query options
+struct CustomAccumulator<S: ReadableEncodedStore + 'static> {
+    eval: SimpleEvaluator<S>,
+    function: Option<CustomFunction>,
+    values: Vec<EncodedTerm<S::StrId>>,
+}
+
+impl<S: ReadableEncodedStore + 'static> CustomAccumulator<S> {
+    fn new(eval: SimpleEvaluator<S>, function: Option<CustomFunction>) -> Self {
+        Self {
+            eval,
+            function,
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<S: ReadableEncodedStore<Error = EvaluationError> + 'static> Accumulator<S::StrId>
+    for CustomAccumulator<S>
+where
+    for<'a> &'a S: StrContainer<StrId = S::StrId>,
+{
+    fn add(&mut self, element: Option<EncodedTerm<S::StrId>>) {
+        if let Some(element) = element {
+            self.values.push(element);
+        }
+    }
+
+    fn state(&self) -> Option<EncodedTerm<S::StrId>> {
+        let values = self
+            .values
+            .iter()
+            .map(|value| self.eval.dataset.decode_term(*value).ok())
+            .collect::<Option<Vec<_>>>()?;
+        self.eval
+            .encode_term(self.eval.call_function(self.function.as_ref()?, &values)?)
+    }
+}
+
 fn generate_uuid(buffer: &mut String) {
     let mut uuid = random::<u128>().to_ne_bytes();
     uuid[6] = (uuid[6] & 0x0F) | 0x40;
@@ -3221,3 +3915,4 @@ fn uuid() {
         buffer
     );
 }
+
@@ -0,0 +1,895 @@
+//! Human readable rendering of query plans (EXPLAIN) with optional evaluation statistics (PROFILE).
+
+use crate::sparql::model::Variable;
+use crate::sparql::plan::*;
+use crate::store::numeric_encoder::{Decoder, EncodedTerm, StrId};
+use crate::store::{GraphNameShape, QuadPatternShape, ReadableEncodedStore};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+
+/// Counters collected for a plan node while the plan is evaluated in profile mode.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub(crate) struct NodeStats {
+    /// Number of times the node has been evaluated (e.g. once per left tuple for the right side of an OPTIONAL)
+    pub evaluations: u64,
+    /// Number of tuples produced by the node
+    pub rows: u64,
+    /// Number of storage reads done by the node itself: one per index lookup and one per quad read
+    pub reads: u64,
+}
+
+/// Evaluation statistics of a plan.
+///
+/// Nodes are identified by their address: plans are not moved while they are evaluated.
+#[derive(Default)]
+pub(crate) struct PlanStats {
+    nodes: RefCell<HashMap<usize, NodeStats>>,
+}
+
+impl PlanStats {
+    pub fn key<I: StrId>(node: &PlanNode<I>) -> usize {
+        node as *const PlanNode<I> as usize
+    }
+
+    pub fn add_evaluation(&self, key: usize) {
+        self.nodes.borrow_mut().entry(key).or_default().evaluations += 1;
+    }
+
+    pub fn add_row(&self, key: usize) {
+        self.nodes.borrow_mut().entry(key).or_default().rows += 1;
+    }
+
+    pub fn add_reads(&self, key: usize, count: u64) {
+        self.nodes.borrow_mut().entry(key).or_default().reads += count;
+    }
+
+    pub fn get(&self, key: usize) -> NodeStats {
+        self.nodes.borrow().get(&key).copied().unwrap_or_default()
+    }
+
+    pub fn total_reads(&self) -> u64 {
+        self.nodes.borrow().values().map(|stats| stats.reads).sum()
+    }
+}
+
+/// Writes the plan as an indented tree, one node per line, children after their parent.
+///
+/// Quad patterns are annotated with the indexes the store reads for them
+/// and, if `stats` are given, every node with the counters collected during evaluation.
+pub(crate) fn write_plan<S: ReadableEncodedStore>(
+    store: &S,
+    plan: &PlanNode<S::StrId>,
+    variables: &[Variable],
+    stats: Option<&PlanStats>,
+) -> String {
+    let mut writer = PlanWriter {
+        store,
+        stats,
+        output: String::new(),
+    };
+    writer.write_node(plan, variables, &BTreeSet::new(), 0);
+    if let Some(stats) = stats {
+        writer
+            .output
+            .push_str(&format!("Total storage reads: {}\n", stats.total_reads()));
+    }
+    writer.output
+}
+
+struct PlanWriter<'a, S: ReadableEncodedStore> {
+    store: &'a S,
+    stats: Option<&'a PlanStats>,
+    output: String,
+}
+
+impl<'a, S: ReadableEncodedStore> PlanWriter<'a, S> {
+    /// `bound` are the variables that may be bound in the tuples given as input to the node
+    fn write_node(
+        &mut self,
+        node: &PlanNode<S::StrId>,
+        variables: &[Variable],
+        bound: &BTreeSet<usize>,
+        depth: usize,
+    ) {
+        let mut exists = Vec::new();
+        let label = match node {
+            PlanNode::Init => "Init".to_owned(),
+            PlanNode::StaticBindings { tuples } => {
+                format!("StaticBindings ({} tuples)", tuples.len())
+            }
+            PlanNode::Service {
+                service_name,
+                silent,
+                ..
+            } => format!(
+                "Service{} {}",
+                if *silent { " SILENT" } else { "" },
+                self.pattern_value(service_name, variables)
+            ),
+            PlanNode::QuadPatternJoin {
+                child,
+                subject,
+                predicate,
+                object,
+                graph_name,
+            } => {
+                let mut known = child.maybe_bound_variables();
+                known.extend(bound);
+                let shape = QuadPatternShape {
+                    subject: is_known(subject, &known),
+                    predicate: is_known(predicate, &known),
+                    object: is_known(object, &known),
+                    graph_name: graph_name_shape(graph_name, &known),
+                };
+                let indexes = self.store.encoded_quads_indexes_for_pattern(shape);
+                format!(
+                    "QuadPatternJoin {} {} {} GRAPH {}{}",
+                    self.pattern_value(subject, variables),
+                    self.pattern_value(predicate, variables),
+                    self.pattern_value(object, variables),
+                    self.pattern_value(graph_name, variables),
+                    if indexes.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" [index: {}]", indexes.join(", "))
+                    }
+                )
+            }
+            PlanNode::QuadPatternRangeJoin {
+                subject,
+                predicate,
+                object,
+                graph_name,
+                range,
+                ..
+            } => {
+                let indexes = self.store.encoded_object_range_indexes();
+                format!(
+                    "QuadPatternRangeJoin {} {} {} GRAPH {} RANGE [{}, {}]{}",
+                    self.pattern_value(subject, variables),
+                    self.pattern_value(predicate, variables),
+                    self.pattern_value(object, variables),
+                    self.pattern_value(graph_name, variables),
+                    range
+                        .lower
+                        .map_or_else(|| "*".to_owned(), |lower| self.term(lower)),
+                    range
+                        .upper
+                        .map_or_else(|| "*".to_owned(), |upper| self.term(upper)),
+                    if indexes.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" [index: {}]", indexes.join(", "))
+                    }
+                )
+            }
+            PlanNode::QuadPatternSpatialJoin {
+                subject,
+                predicate,
+                object,
+                graph_name,
+                area,
+                ..
+            } => {
+                let indexes = self.store.encoded_spatial_indexes();
+                format!(
+                    "QuadPatternSpatialJoin {} {} {} GRAPH {} AREA {}{}",
+                    self.pattern_value(subject, variables),
+                    self.pattern_value(predicate, variables),
+                    self.pattern_value(object, variables),
+                    self.pattern_value(graph_name, variables),
+                    area,
+                    if indexes.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" [index: {}]", indexes.join(", "))
+                    }
+                )
+            }
+            PlanNode::TextMatch {
+                subject,
+                predicate,
+                query,
+                graph_name,
+                score,
+                literal,
+                ..
+            } => {
+                let indexes = self.store.encoded_text_indexes();
+                let mut label = format!(
+                    "TextMatch {}{} \"{}\" GRAPH {}",
+                    self.pattern_value(subject, variables),
+                    predicate.map_or_else(String::new, |predicate| format!(" {}", self.term(predicate))),
+                    query,
+                    self.pattern_value(graph_name, variables)
+                );
+                if let Some(score) = score {
+                    label.push_str(&format!(" SCORE {}", variable(variables, *score)));
+                }
+                if let Some(literal) = literal {
+                    label.push_str(&format!(" LITERAL {}", variable(variables, *literal)));
+                }
+                if !indexes.is_empty() {
+                    label.push_str(&format!(" [index: {}]", indexes.join(", ")));
+                }
+                label
+            }
+            PlanNode::VectorNearest {
+                subject,
+                predicate,
+                query,
+                k,
+                graph_name,
+                distance,
+                vector,
+                ..
+            } => {
+                let indexes = self.store.encoded_vector_indexes();
+                let mut label = format!(
+                    "VectorNearest {} {} {} K {} GRAPH {}",
+                    self.pattern_value(subject, variables),
+                    self.term(*predicate),
+                    self.pattern_value(query, variables),
+                    k,
+                    self.pattern_value(graph_name, variables)
+                );
+                if let Some(distance) = distance {
+                    label.push_str(&format!(" DISTANCE {}", variable(variables, *distance)));
+                }
+                if let Some(vector) = vector {
+                    label.push_str(&format!(" VECTOR {}", variable(variables, *vector)));
+                }
+                if !indexes.is_empty() {
+                    label.push_str(&format!(" [index: {}]", indexes.join(", ")));
+                }
+                label
+            }
+            PlanNode::PathPatternJoin {
+                subject,
+                path,
+                object,
+                graph_name,
+                ..
+            } => format!(
+                "PathPatternJoin {} {} {} GRAPH {}",
+                self.pattern_value(subject, variables),
+                self.path(path),
+                self.pattern_value(object, variables),
+                self.pattern_value(graph_name, variables)
+            ),
+            PlanNode::Join { .. } => "Join".to_owned(),
+            PlanNode::HashJoin { keys, .. } => {
+                format!("HashJoin ON {}", join_keys(keys, variables))
+            }
+            PlanNode::AntiJoin { .. } => "AntiJoin".to_owned(),
+            PlanNode::Filter { expression, .. } => {
+                format!(
+                    "Filter {}",
+                    self.expression(expression, variables, &mut exists)
+                )
+            }
+            PlanNode::Union { .. } => "Union".to_owned(),
+            PlanNode::LeftJoin { .. } => "LeftJoin".to_owned(),
+            PlanNode::HashLeftJoin {
+                keys, expression, ..
+            } => {
+                let mut label = format!("HashLeftJoin ON {}", join_keys(keys, variables));
+                if let Some(expression) = expression {
+                    label.push_str(&format!(
+                        " FILTER {}",
+                        self.expression(expression, variables, &mut exists)
+                    ));
+                }
+                label
+            }
+            PlanNode::Extend {
+                position,
+                expression,
+                ..
+            } => format!(
+                "Extend {} := {}",
+                variable(variables, *position),
+                self.expression(expression, variables, &mut exists)
+            ),
+            PlanNode::Sort { by, .. } => format!(
+                "Sort {}",
+                by.iter()
+                    .map(|comparator| match comparator {
+                        Comparator::Asc(e) =>
+                            format!("ASC({})", self.expression(e, variables, &mut exists)),
+                        Comparator::Desc(e) =>
+                            format!("DESC({})", self.expression(e, variables, &mut exists)),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            PlanNode::HashDeduplicate { .. } => "HashDeduplicate".to_owned(),
+            PlanNode::Skip { count, .. } => format!("Skip {}", count),
+            PlanNode::Limit { count, .. } => format!("Limit {}", count),
+            PlanNode::Project { mapping, .. } => format!(
+                "Project {}",
+                mapping
+                    .iter()
+                    .map(|(_, output)| variable(variables, *output))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            PlanNode::Aggregate {
+                key_mapping,
+                aggregates,
+                variables: inner_variables,
+                ..
+            } => {
+                let mut label = "Aggregate".to_owned();
+                if !key_mapping.is_empty() {
+                    label.push_str(" GROUP BY");
+                    for (_, output) in key_mapping.iter() {
+                        label.push(' ');
+                        label.push_str(&variable(variables, *output));
+                    }
+                }
+                for (aggregate, output) in aggregates.iter() {
+                    label.push_str(&format!(
+                        " ({} AS {})",
+                        self.aggregate(aggregate, inner_variables, &mut exists),
+                        variable(variables, *output)
+                    ));
+                }
+                label
+            }
+        };
+        self.write_line(node, &label, depth);
+
+        match node {
+            PlanNode::Init | PlanNode::StaticBindings { .. } => (),
+            PlanNode::QuadPatternJoin { child, .. }
+            | PlanNode::QuadPatternRangeJoin { child, .. }
+            | PlanNode::QuadPatternSpatialJoin { child, .. }
+            | PlanNode::TextMatch { child, .. }
+            | PlanNode::VectorNearest { child, .. }
+            | PlanNode::PathPatternJoin { child, .. }
+            | PlanNode::Service { child, .. }
+            | PlanNode::Sort { child, .. }
+            | PlanNode::HashDeduplicate { child }
+            | PlanNode::Skip { child, .. }
+            | PlanNode::Limit { child, .. } => {
+                self.write_exists(&exists, child, variables, bound, depth + 1);
+                self.write_node(child, variables, bound, depth + 1)
+            }
+            PlanNode::Filter { child, .. } | PlanNode::Extend { child, .. } => {
+                self.write_exists(&exists, child, variables, bound, depth + 1);
+                self.write_node(child, variables, bound, depth + 1)
+            }
+            PlanNode::Join { left, right }
+            | PlanNode::HashJoin { left, right, .. }
+            | PlanNode::AntiJoin { left, right } => {
+                self.write_node(left, variables, bound, depth + 1);
+                self.write_node(right, variables, bound, depth + 1);
+            }
+            PlanNode::HashLeftJoin { left, right, .. } => {
+                self.write_exists(&exists, node, variables, bound, depth + 1);
+                self.write_node(left, variables, bound, depth + 1);
+                self.write_node(right, variables, bound, depth + 1);
+            }
+            PlanNode::LeftJoin { left, right, .. } => {
+                self.write_node(left, variables, bound, depth + 1);
+                // The right side is evaluated once per left tuple
+                let mut right_bound = left.maybe_bound_variables();
+                right_bound.extend(bound);
+                self.write_node(right, variables, &right_bound, depth + 1);
+            }
+            PlanNode::Union { children } => {
+                for child in children {
+                    self.write_node(child, variables, bound, depth + 1);
+                }
+            }
+            PlanNode::Project {
+                child,
+                variables: inner_variables,
+                ..
+            }
+            | PlanNode::Aggregate {
+                child,
+                variables: inner_variables,
+                ..
+            } => {
+                self.write_exists(&exists, child, inner_variables, &BTreeSet::new(), depth + 1);
+                self.write_node(child, inner_variables, &BTreeSet::new(), depth + 1)
+            }
+        }
+    }
+
+    /// EXISTS plans are evaluated once per tuple produced by the child of the node using them
+    fn write_exists(
+        &mut self,
+        exists: &[Rc<PlanNode<S::StrId>>],
+        child: &PlanNode<S::StrId>,
+        variables: &[Variable],
+        bound: &BTreeSet<usize>,
+        depth: usize,
+    ) {
+        if exists.is_empty() {
+            return;
+        }
+        let mut exists_bound = child.maybe_bound_variables();
+        exists_bound.extend(bound);
+        for plan in exists {
+            self.write_line_raw("Exists", depth);
+            self.write_node(plan, variables, &exists_bound, depth + 1);
+        }
+    }
+
+    fn write_line(&mut self, node: &PlanNode<S::StrId>, label: &str, depth: usize) {
+        if let Some(stats) = self.stats {
+            let stats = stats.get(PlanStats::key(node));
+            self.write_line_raw(
+                &format!(
+                    "{} (evaluations: {}, rows: {}, reads: {})",
+                    label, stats.evaluations, stats.rows, stats.reads
+                ),
+                depth,
+            )
+        } else {
+            self.write_line_raw(label, depth)
+        }
+    }
+
+    fn write_line_raw(&mut self, label: &str, depth: usize) {
+        for _ in 0..depth {
+            self.output.push_str("  ");
+        }
+        self.output.push_str(label);
+        self.output.push('\n');
+    }
+
+    fn term(&self, term: EncodedTerm<S::StrId>) -> String {
+        if term.is_default_graph() {
+            "DEFAULT".to_owned()
+        } else {
+            match self.store.decode_term(term) {
+                Ok(term) => term.to_string(),
+                Err(_) => "<unknown term>".to_owned(),
+            }
+        }
+    }
+
+    fn pattern_value(&self, value: &PatternValue<S::StrId>, variables: &[Variable]) -> String {
+        match value {
+            PatternValue::Constant(term) => self.term(*term),
+            PatternValue::Variable(key) => variable(variables, *key),
+        }
+    }
+
+    fn path(&self, path: &PlanPropertyPath<S::StrId>) -> String {
+        match path {
+            PlanPropertyPath::Path(p) => self.term(*p),
+            PlanPropertyPath::Reverse(p) => format!("^{}", self.path(p)),
+            PlanPropertyPath::Sequence(a, b) => format!("({} / {})", self.path(a), self.path(b)),
+            PlanPropertyPath::Alternative(a, b) => {
+                format!("({} | {})", self.path(a), self.path(b))
+            }
+            PlanPropertyPath::ZeroOrMore(p) => format!("{}*", self.path(p)),
+            PlanPropertyPath::OneOrMore(p) => format!("{}+", self.path(p)),
+            PlanPropertyPath::ZeroOrOne(p) => format!("{}?", self.path(p)),
+            PlanPropertyPath::NegatedPropertySet(ps) => {
+                format!(
+                    "!({})",
+                    ps.iter()
+                        .map(|p| self.term(*p))
+                        .collect::<Vec<_>>()
+                        .join(" | ")
+                )
+            }
+        }
+    }
+
+    fn aggregate(
+        &self,
+        aggregate: &PlanAggregation<S::StrId>,
+        variables: &[Variable],
+        exists: &mut Vec<Rc<PlanNode<S::StrId>>>,
+    ) -> String {
+        let custom;
+        let name = match &aggregate.function {
+            PlanAggregationFunction::Count => "COUNT",
+            PlanAggregationFunction::Sum => "SUM",
+            PlanAggregationFunction::Min => "MIN",
+            PlanAggregationFunction::Max => "MAX",
+            PlanAggregationFunction::Avg => "AVG",
+            PlanAggregationFunction::Sample => "SAMPLE",
+            PlanAggregationFunction::GroupConcat { .. } => "GROUP_CONCAT",
+            PlanAggregationFunction::Custom(name) => {
+                custom = name.to_string();
+                &custom
+            }
+        };
+        let parameter = aggregate
+            .parameter
+            .as_ref()
+            .map_or_else(|| "*".to_owned(), |p| self.expression(p, variables, exists));
+        format!(
+            "{}({}{})",
+            name,
+            if aggregate.distinct { "DISTINCT " } else { "" },
+            parameter
+        )
+    }
+
+    fn expression(
+        &self,
+        expression: &PlanExpression<S::StrId>,
+        variables: &[Variable],
+        exists: &mut Vec<Rc<PlanNode<S::StrId>>>,
+    ) -> String {
+        match expression {
+            PlanExpression::Constant(t) => self.term(*t),
+            PlanExpression::Variable(v) => variable(variables, *v),
+            PlanExpression::Exists(plan) => {
+                exists.push(plan.clone());
+                "EXISTS".to_owned()
+            }
+            PlanExpression::Or(a, b) => self.infix("||", a, b, variables, exists),
+            PlanExpression::And(a, b) => self.infix("&&", a, b, variables, exists),
+            PlanExpression::Equal(a, b) => self.infix("=", a, b, variables, exists),
+            PlanExpression::Greater(a, b) => self.infix(">", a, b, variables, exists),
+            PlanExpression::GreaterOrEqual(a, b) => self.infix(">=", a, b, variables, exists),
+            PlanExpression::Less(a, b) => self.infix("<", a, b, variables, exists),
+            PlanExpression::LessOrEqual(a, b) => self.infix("<=", a, b, variables, exists),
+            PlanExpression::Add(a, b) => self.infix("+", a, b, variables, exists),
+            PlanExpression::Subtract(a, b) => self.infix("-", a, b, variables, exists),
+            PlanExpression::Multiply(a, b) => self.infix("*", a, b, variables, exists),
+            PlanExpression::Divide(a, b) => self.infix("/", a, b, variables, exists),
+            PlanExpression::In(a, bs) => format!(
+                "({} IN ({}))",
+                self.expression(a, variables, exists),
+                bs.iter()
+                    .map(|b| self.expression(b, variables, exists))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            PlanExpression::UnaryPlus(e) => format!("+{}", self.expression(e, variables, exists)),
+            PlanExpression::UnaryMinus(e) => format!("-{}", self.expression(e, variables, exists)),
+            PlanExpression::Not(e) => format!("!{}", self.expression(e, variables, exists)),
+            PlanExpression::Bound(v) => format!("BOUND({})", variable(variables, *v)),
+            PlanExpression::BNode(e) => {
+                self.function("BNODE", e.iter().map(|e| e.as_ref()), variables, exists)
+            }
+            PlanExpression::Rand => "RAND()".to_owned(),
+            PlanExpression::Now => "NOW()".to_owned(),
+            PlanExpression::Uuid => "UUID()".to_owned(),
+            PlanExpression::StrUuid => "STRUUID()".to_owned(),
+            PlanExpression::Str(e) => self.function("STR", Some(e.as_ref()), variables, exists),
+            PlanExpression::Lang(e) => self.function("LANG", Some(e.as_ref()), variables, exists),
+            PlanExpression::Datatype(e) => {
+                self.function("DATATYPE", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::Iri(e) => self.function("IRI", Some(e.as_ref()), variables, exists),
+            PlanExpression::Abs(e) => self.function("ABS", Some(e.as_ref()), variables, exists),
+            PlanExpression::Ceil(e) => self.function("CEIL", Some(e.as_ref()), variables, exists),
+            PlanExpression::Floor(e) => self.function("FLOOR", Some(e.as_ref()), variables, exists),
+            PlanExpression::Round(e) => self.function("ROUND", Some(e.as_ref()), variables, exists),
+            PlanExpression::StrLen(e) => {
+                self.function("STRLEN", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::UCase(e) => self.function("UCASE", Some(e.as_ref()), variables, exists),
+            PlanExpression::LCase(e) => self.function("LCASE", Some(e.as_ref()), variables, exists),
+            PlanExpression::EncodeForUri(e) => {
+                self.function("ENCODE_FOR_URI", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::Year(e) => self.function("YEAR", Some(e.as_ref()), variables, exists),
+            PlanExpression::Month(e) => self.function("MONTH", Some(e.as_ref()), variables, exists),
+            PlanExpression::Day(e) => self.function("DAY", Some(e.as_ref()), variables, exists),
+            PlanExpression::Hours(e) => self.function("HOURS", Some(e.as_ref()), variables, exists),
+            PlanExpression::Minutes(e) => {
+                self.function("MINUTES", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::Seconds(e) => {
+                self.function("SECONDS", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::Timezone(e) => {
+                self.function("TIMEZONE", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::Tz(e) => self.function("TZ", Some(e.as_ref()), variables, exists),
+            PlanExpression::Md5(e) => self.function("MD5", Some(e.as_ref()), variables, exists),
+            PlanExpression::Sha1(e) => self.function("SHA1", Some(e.as_ref()), variables, exists),
+            PlanExpression::Sha256(e) => {
+                self.function("SHA256", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::Sha384(e) => {
+                self.function("SHA384", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::Sha512(e) => {
+                self.function("SHA512", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::IsIri(e) => self.function("isIRI", Some(e.as_ref()), variables, exists),
+            PlanExpression::IsBlank(e) => {
+                self.function("isBLANK", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::IsLiteral(e) => {
+                self.function("isLITERAL", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::IsNumeric(e) => {
+                self.function("isNUMERIC", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::BooleanCast(e) => {
+                self.function("xsd:boolean", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::DoubleCast(e) => {
+                self.function("xsd:double", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::FloatCast(e) => {
+                self.function("xsd:float", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::DecimalCast(e) => {
+                self.function("xsd:decimal", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::IntegerCast(e) => {
+                self.function("xsd:integer", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::DateCast(e) => {
+                self.function("xsd:date", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::TimeCast(e) => {
+                self.function("xsd:time", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::DateTimeCast(e) => {
+                self.function("xsd:dateTime", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::DurationCast(e) => {
+                self.function("xsd:duration", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::YearMonthDurationCast(e) => {
+                self.function("xsd:yearMonthDuration", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::DayTimeDurationCast(e) => {
+                self.function("xsd:dayTimeDuration", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::StringCast(e) => {
+                self.function("xsd:string", Some(e.as_ref()), variables, exists)
+            }
+            PlanExpression::LangMatches(a, b) => self.function(
+                "LANGMATCHES",
+                vec![a.as_ref(), b.as_ref()],
+                variables,
+                exists,
+            ),
+            PlanExpression::Contains(a, b) => {
+                self.function("CONTAINS", vec![a.as_ref(), b.as_ref()], variables, exists)
+            }
+            PlanExpression::StrStarts(a, b) => {
+                self.function("STRSTARTS", vec![a.as_ref(), b.as_ref()], variables, exists)
+            }
+            PlanExpression::StrEnds(a, b) => {
+                self.function("STRENDS", vec![a.as_ref(), b.as_ref()], variables, exists)
+            }
+            PlanExpression::StrBefore(a, b) => {
+                self.function("STRBEFORE", vec![a.as_ref(), b.as_ref()], variables, exists)
+            }
+            PlanExpression::StrAfter(a, b) => {
+                self.function("STRAFTER", vec![a.as_ref(), b.as_ref()], variables, exists)
+            }
+            PlanExpression::StrLang(a, b) => {
+                self.function("STRLANG", vec![a.as_ref(), b.as_ref()], variables, exists)
+            }
+            PlanExpression::StrDt(a, b) => {
+                self.function("STRDT", vec![a.as_ref(), b.as_ref()], variables, exists)
+            }
+            PlanExpression::SameTerm(a, b) => {
+                self.function("sameTerm", vec![a.as_ref(), b.as_ref()], variables, exists)
+            }
+            PlanExpression::SubStr(a, b, c) => self.function(
+                "SUBSTR",
+                vec![a.as_ref(), b.as_ref()].into_iter().chain(c.as_deref()),
+                variables,
+                exists,
+            ),
+            PlanExpression::Regex(a, b, c) => self.function(
+                "REGEX",
+                vec![a.as_ref(), b.as_ref()].into_iter().chain(c.as_deref()),
+                variables,
+                exists,
+            ),
+            PlanExpression::Replace(a, b, c, d) => self.function(
+                "REPLACE",
+                vec![a.as_ref(), b.as_ref(), c.as_ref()]
+                    .into_iter()
+                    .chain(d.as_deref()),
+                variables,
+                exists,
+            ),
+            PlanExpression::GeoDistance(a, b, c) => self.function(
+                "geof:distance",
+                vec![a.as_ref(), b.as_ref(), c.as_ref()],
+                variables,
+                exists,
+            ),
+            PlanExpression::GeoBuffer(a, b, c) => self.function(
+                "geof:buffer",
+                vec![a.as_ref(), b.as_ref(), c.as_ref()],
+                variables,
+                exists,
+            ),
+            PlanExpression::GeoIntersects(a, b) => self.function(
+                "geof:sfIntersects",
+                vec![a.as_ref(), b.as_ref()],
+                variables,
+                exists,
+            ),
+            PlanExpression::GeoWithin(a, b) => self.function(
+                "geof:sfWithin",
+                vec![a.as_ref(), b.as_ref()],
+                variables,
+                exists,
+            ),
+            PlanExpression::CosineDistance(a, b) => self.function(
+                "vec:cosineDistance",
+                vec![a.as_ref(), b.as_ref()],
+                variables,
+                exists,
+            ),
+            PlanExpression::DotProduct(a, b) => self.function(
+                "vec:dotProduct",
+                vec![a.as_ref(), b.as_ref()],
+                variables,
+                exists,
+            ),
+            PlanExpression::L2Distance(a, b) => self.function(
+                "vec:l2Distance",
+                vec![a.as_ref(), b.as_ref()],
+                variables,
+                exists,
+            ),
+            PlanExpression::If(a, b, c) => self.function(
+                "IF",
+                vec![a.as_ref(), b.as_ref(), c.as_ref()],
+                variables,
+                exists,
+            ),
+            PlanExpression::Concat(es) => self.function("CONCAT", es, variables, exists),
+            PlanExpression::Coalesce(es) => self.function("COALESCE", es, variables, exists),
+            PlanExpression::CustomFunction(name, es) => {
+                self.function(&name.to_string(), es, variables, exists)
+            }
+        }
+    }
+
+    fn infix(
+        &self,
+        operator: &str,
+        a: &PlanExpression<S::StrId>,
+        b: &PlanExpression<S::StrId>,
+        variables: &[Variable],
+        exists: &mut Vec<Rc<PlanNode<S::StrId>>>,
+    ) -> String {
+        format!(
+            "({} {} {})",
+            self.expression(a, variables, exists),
+            operator,
+            self.expression(b, variables, exists)
+        )
+    }
+
+    fn function<'e>(
+        &self,
+        name: &str,
+        parameters: impl IntoIterator<Item = &'e PlanExpression<S::StrId>>,
+        variables: &[Variable],
+        exists: &mut Vec<Rc<PlanNode<S::StrId>>>,
+    ) -> String
+    where
+        S::StrId: 'e,
+    {
+        format!(
+            "{}({})",
+            name,
+            parameters
+                .into_iter()
+                .map(|p| self.expression(p, variables, exists))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+fn variable(variables: &[Variable], key: usize) -> String {
+    variables
+        .get(key)
+        .map_or_else(|| format!("?_{}", key), |v| v.to_string())
+}
+
+fn join_keys(keys: &[usize], variables: &[Variable]) -> String {
+    if keys.is_empty() {
+        "()".to_owned()
+    } else {
+        keys.iter()
+            .map(|key| variable(variables, *key))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn is_known<I: StrId>(value: &PatternValue<I>, known: &BTreeSet<usize>) -> bool {
+    match value {
+        PatternValue::Constant(_) => true,
+        PatternValue::Variable(v) => known.contains(v),
+    }
+}
+
+fn graph_name_shape<I: StrId>(
+    graph_name: &PatternValue<I>,
+    known: &BTreeSet<usize>,
+) -> GraphNameShape {
+    match graph_name {
+        PatternValue::Constant(graph_name) if graph_name.is_default_graph() => {
+            GraphNameShape::DefaultGraph
+        }
+        PatternValue::Constant(_) => GraphNameShape::NamedGraph,
+        PatternValue::Variable(v) if known.contains(v) => GraphNameShape::Bound,
+        PatternValue::Variable(_) => GraphNameShape::Any,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{new_test_ext, Test};
+    use crate::Pallet;
+
+    const DATA: &str = "INSERT DATA { GRAPH :g { :alice :knows :bob, :carol . :bob :knows :carol } }";
+
+    fn line_of<'a>(plan: &'a str, node: &str) -> &'a str {
+        plan.lines()
+            .find(|line| line.trim_start().starts_with(node))
+            .unwrap_or_else(|| panic!("no {} in the plan:\n{}", node, plan))
+    }
+
+    #[test]
+    fn explain_writes_the_patterns_with_their_indexes() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(DATA).unwrap();
+            let plan = Pallet::<Test>::explain_query(
+                "SELECT ?o WHERE { GRAPH :g { :alice :knows ?o } }",
+            )
+            .unwrap();
+            let line = line_of(&plan, "QuadPatternJoin");
+            assert!(line.contains("<http://relationlabs.ai/entity/alice>"), "{}", plan);
+            assert!(line.contains("?o GRAPH <http://relationlabs.ai/entity/g>"), "{}", plan);
+            assert!(line.ends_with("[index: Gspo]"), "{}", plan);
+            // explain does not evaluate the query
+            assert!(!plan.contains("rows:"), "{}", plan);
+            assert!(!plan.contains("Total storage reads"), "{}", plan);
+        });
+    }
+
+    #[test]
+    fn explain_uses_the_index_of_the_bound_terms() {
+        new_test_ext().execute_with(|| {
+            let plan = Pallet::<Test>::explain_query(
+                "SELECT ?s WHERE { GRAPH :g { ?s :knows :carol } }",
+            )
+            .unwrap();
+            assert!(line_of(&plan, "QuadPatternJoin").ends_with("[index: Gosp]"), "{}", plan);
+        });
+    }
+
+    #[test]
+    fn profile_counts_the_rows_of_each_node() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(DATA).unwrap();
+            let plan = Pallet::<Test>::profile_query(
+                "SELECT ?o WHERE { GRAPH :g { :alice :knows ?o } }",
+            )
+            .unwrap();
+            assert!(line_of(&plan, "QuadPatternJoin").contains("evaluations: 1, rows: 2,"), "{}", plan);
+            assert!(plan.lines().last().unwrap().starts_with("Total storage reads: "), "{}", plan);
+        });
+    }
+
+    #[test]
+    fn explain_returns_the_parse_errors() {
+        new_test_ext().execute_with(|| {
+            assert!(Pallet::<Test>::explain_query("SELECT ?o WHERE {").is_err());
+            assert!(Pallet::<Test>::profile_query("not a query").is_err());
+        });
+    }
+}
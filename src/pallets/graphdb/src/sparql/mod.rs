@@ -4,8 +4,10 @@
 
 pub mod algebra;
 mod dataset;
+mod entailment;
 mod error;
 mod eval;
+mod explain;
 mod http;
 mod model;
 mod parser;
@@ -14,11 +16,16 @@ mod plan_builder;
 mod service;
 mod update;
 mod results;
+mod rules;
 
 pub use crate::sparql::algebra::{Query, Update};
 use crate::sparql::dataset::DatasetView;
+pub use crate::sparql::entailment::{EntailmentRegime, EqualityMode};
+use crate::sparql::entailment::RdfsSchema;
 pub use crate::sparql::error::EvaluationError;
+pub(crate) use crate::sparql::eval::compile_regex;
 use crate::sparql::eval::SimpleEvaluator;
+use crate::sparql::explain::PlanStats;
 pub use crate::sparql::model::QueryResults;
 pub use crate::sparql::model::QueryResultsFormat;
 pub use crate::sparql::model::QuerySolution;
@@ -27,11 +34,15 @@ pub use crate::sparql::model::QueryTripleIter;
 pub use crate::sparql::model::{Variable, VariableNameParseError};
 pub use crate::sparql::parser::ParseError;
 use crate::sparql::plan_builder::PlanBuilder;
+pub(crate) use crate::sparql::rules::Rule;
 pub use crate::sparql::service::ServiceHandler;
 use crate::sparql::service::{EmptyServiceHandler, ErrorConversionServiceHandler};
 use crate::sparql::update::SimpleUpdateEvaluator;
+use crate::model::{NamedNode, Term};
 use crate::store::numeric_encoder::StrContainer;
 use crate::store::{ReadableEncodedStore, StoreOrParseError, WritableEncodedStore};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::io;
 use std::rc::Rc;
@@ -47,12 +58,14 @@ pub(crate) fn evaluate_query<R: ReadableEncodedStore + 'static>(
             base_iri,
             dataset,
         } => {
-            let dataset = DatasetView::new(store, &dataset)?;
-            let (plan, variables) = PlanBuilder::build(&dataset, &pattern)?;
+            let dataset = DatasetView::new(store, &dataset)?.with_equality(options.equality);
+            let schema = RdfsSchema::load(options.entailment, &dataset)?;
+            let (plan, variables) = PlanBuilder::build(&dataset, &pattern, schema, &options.custom_functions)?;
             SimpleEvaluator::new(
                 Rc::new(dataset),
                 base_iri.map(Rc::new),
                 options.service_handler,
+                options.custom_functions,
             )
             .evaluate_select_plan(&plan, Rc::new(variables))
         }
@@ -61,12 +74,14 @@ pub(crate) fn evaluate_query<R: ReadableEncodedStore + 'static>(
             base_iri,
             dataset,
         } => {
-            let dataset = DatasetView::new(store, &dataset)?;
-            let (plan, _) = PlanBuilder::build(&dataset, &pattern)?;
+            let dataset = DatasetView::new(store, &dataset)?.with_equality(options.equality);
+            let schema = RdfsSchema::load(options.entailment, &dataset)?;
+            let (plan, _) = PlanBuilder::build(&dataset, &pattern, schema, &options.custom_functions)?;
             SimpleEvaluator::new(
                 Rc::new(dataset),
                 base_iri.map(Rc::new),
                 options.service_handler,
+                options.custom_functions,
             )
             .evaluate_ask_plan(&plan)
         }
@@ -76,13 +91,15 @@ pub(crate) fn evaluate_query<R: ReadableEncodedStore + 'static>(
             base_iri,
             dataset,
         } => {
-            let dataset = DatasetView::new(store, &dataset)?;
-            let (plan, variables) = PlanBuilder::build(&dataset, &pattern)?;
+            let dataset = DatasetView::new(store, &dataset)?.with_equality(options.equality);
+            let schema = RdfsSchema::load(options.entailment, &dataset)?;
+            let (plan, variables) = PlanBuilder::build(&dataset, &pattern, schema, &options.custom_functions)?;
             let construct = PlanBuilder::build_graph_template(&dataset, &template, variables)?;
             SimpleEvaluator::new(
                 Rc::new(dataset),
                 base_iri.map(Rc::new),
                 options.service_handler,
+                options.custom_functions,
             )
             .evaluate_construct_plan(&plan, construct)
         }
@@ -91,18 +108,106 @@ pub(crate) fn evaluate_query<R: ReadableEncodedStore + 'static>(
             base_iri,
             dataset,
         } => {
-            let dataset = DatasetView::new(store, &dataset)?;
-            let (plan, _) = PlanBuilder::build(&dataset, &pattern)?;
+            let dataset = DatasetView::new(store, &dataset)?.with_equality(options.equality);
+            let schema = RdfsSchema::load(options.entailment, &dataset)?;
+            let (plan, _) = PlanBuilder::build(&dataset, &pattern, schema, &options.custom_functions)?;
             SimpleEvaluator::new(
                 Rc::new(dataset),
                 base_iri.map(Rc::new),
                 options.service_handler,
+                options.custom_functions,
             )
             .evaluate_describe_plan(&plan)
         }
     }
 }
 
+/// Renders the evaluation plan of a query as a readable tree (EXPLAIN).
+///
+/// If `profile` is set, the query is evaluated to completion first and each plan node
+/// is annotated with the rows it produced and the storage reads it did (PROFILE).
+pub(crate) fn explain_query<R: ReadableEncodedStore + 'static>(
+    store: R,
+    query: impl TryInto<Query, Error = impl Into<EvaluationError>>,
+    options: QueryOptions,
+    profile: bool,
+) -> Result<String, EvaluationError> {
+    let query = query.try_into().map_err(|e| e.into())?;
+    let (pattern, base_iri, dataset) = match &query {
+        Query::Select {
+            pattern,
+            base_iri,
+            dataset,
+        }
+        | Query::Construct {
+            pattern,
+            base_iri,
+            dataset,
+            ..
+        }
+        | Query::Describe {
+            pattern,
+            base_iri,
+            dataset,
+        } => (pattern, base_iri, dataset),
+        Query::Ask {
+            pattern,
+            base_iri,
+            dataset,
+        } => (pattern.as_ref(), base_iri, dataset),
+    };
+    let dataset = Rc::new(DatasetView::new(store, dataset)?.with_equality(options.equality));
+    let schema = RdfsSchema::load(options.entailment, dataset.as_ref())?;
+    let (plan, variables) = PlanBuilder::build(dataset.as_ref(), pattern, schema, &options.custom_functions)?;
+    let stats = if profile {
+        let stats = Rc::new(PlanStats::default());
+        let evaluator = SimpleEvaluator::new(
+            dataset.clone(),
+            base_iri.clone().map(Rc::new),
+            options.service_handler,
+            options.custom_functions,
+        )
+        .with_stats(stats.clone());
+        let results = match &query {
+            Query::Select { .. } => {
+                evaluator.evaluate_select_plan(&plan, Rc::new(variables.clone()))?
+            }
+            Query::Ask { .. } => evaluator.evaluate_ask_plan(&plan)?,
+            Query::Construct { template, .. } => {
+                let construct = PlanBuilder::build_graph_template(
+                    dataset.as_ref(),
+                    template,
+                    variables.clone(),
+                )?;
+                evaluator.evaluate_construct_plan(&plan, construct)?
+            }
+            Query::Describe { .. } => evaluator.evaluate_describe_plan(&plan)?,
+        };
+        match results {
+            QueryResults::Boolean(_) => (),
+            QueryResults::Solutions(solutions) => {
+                for solution in solutions {
+                    solution?;
+                }
+            }
+            QueryResults::Graph(triples) => {
+                for triple in triples {
+                    triple?;
+                }
+            }
+        }
+        Some(stats)
+    } else {
+        None
+    };
+    Ok(explain::write_plan(
+        dataset.as_ref(),
+        &plan,
+        &variables,
+        stats.as_deref(),
+    ))
+}
+
 /// Options for SPARQL query evaluation.
 ///
 ///
@@ -111,6 +216,9 @@ pub(crate) fn evaluate_query<R: ReadableEncodedStore + 'static>(
 #[derive(Clone)]
 pub struct QueryOptions {
     pub(crate) service_handler: Rc<dyn ServiceHandler<Error = EvaluationError>>,
+    pub(crate) entailment: EntailmentRegime,
+    pub(crate) equality: EqualityMode,
+    pub(crate) custom_functions: Rc<CustomFunctions>,
 }
 
 impl Default for QueryOptions {
@@ -122,6 +230,9 @@ impl Default for QueryOptions {
             } else {
                 Rc::new(EmptyServiceHandler)
             },
+            entailment: EntailmentRegime::default(),
+            equality: EqualityMode::default(),
+            custom_functions: Rc::new(CustomFunctions::default()),
         }
     }
 }
@@ -140,6 +251,131 @@ impl QueryOptions {
         self.service_handler = Rc::new(EmptyServiceHandler);
         self
     }
+
+    /// Matches the basic graph patterns with the given [`EntailmentRegime`], the stored quads only by default
+    #[inline]
+    pub fn with_entailment(mut self, entailment: EntailmentRegime) -> Self {
+        self.entailment = entailment;
+        self
+    }
+
+    /// Compares the terms of the quads with the given [`EqualityMode`], the stored terms are distinct by default.
+    ///
+    /// The updates always match the stored terms.
+    #[inline]
+    pub fn with_equality(mut self, equality: EqualityMode) -> Self {
+        self.equality = equality;
+        self
+    }
+
+    /// Evaluates the calls to the function `name` with `evaluator`.
+    ///
+    /// The evaluator gets the values of the arguments and returns the value of the call, `None` if the call is an
+    /// error. The calls with an unbound argument are errors. The built-in functions, like the XSD casts, are not
+    /// overridden.
+    #[inline]
+    pub fn with_custom_function(
+        mut self,
+        name: NamedNode,
+        evaluator: impl Fn(&[Term]) -> Option<Term> + 'static,
+    ) -> Self {
+        Rc::make_mut(&mut self.custom_functions)
+            .functions
+            .insert(name, Rc::new(move |arguments: &[Term]| Ok(evaluator(arguments))));
+        self
+    }
+
+    /// Evaluates the aggregates `name` with `evaluator`.
+    ///
+    /// The evaluator gets the values of the aggregated expression in a group, without the unbound ones and the
+    /// duplicates if the aggregate is `DISTINCT`, and returns the value of the aggregate, `None` if it is an error.
+    #[inline]
+    pub fn with_custom_aggregate(
+        mut self,
+        name: NamedNode,
+        evaluator: impl Fn(&[Term]) -> Option<Term> + 'static,
+    ) -> Self {
+        Rc::make_mut(&mut self.custom_functions)
+            .aggregates
+            .insert(name, Rc::new(move |values: &[Term]| Ok(evaluator(values))));
+        self
+    }
+
+    /// Looks up the functions that are not registered on the options with `resolver`, once per query
+    #[inline]
+    pub(crate) fn with_function_resolver(
+        mut self,
+        resolver: impl Fn(&NamedNode) -> Result<Option<CustomFunction>, EvaluationError> + 'static,
+    ) -> Self {
+        Rc::make_mut(&mut self.custom_functions).resolver = Some(Rc::new(resolver));
+        self
+    }
+}
+
+/// A function or an aggregate of a query: the value of a call, `None` if the call is an error, or an error that
+/// fails the evaluation
+pub(crate) type CustomFunction = Rc<dyn Fn(&[Term]) -> Result<Option<Term>, EvaluationError>>;
+
+/// Looks up a function that is not registered on the [`QueryOptions`]
+pub(crate) type FunctionResolver = Rc<dyn Fn(&NamedNode) -> Result<Option<CustomFunction>, EvaluationError>>;
+
+/// The custom functions and aggregates of a query, by IRI
+#[derive(Default)]
+pub(crate) struct CustomFunctions {
+    pub functions: HashMap<NamedNode, CustomFunction>,
+    pub aggregates: HashMap<NamedNode, CustomFunction>,
+    /// Looks up the other functions, e.g. the user-defined functions of the store (see `crate::functions`)
+    pub resolver: Option<FunctionResolver>,
+    /// The functions looked up by the resolver
+    resolved: RefCell<HashMap<NamedNode, Option<CustomFunction>>>,
+    /// The error of a call that fails the evaluation, returned by the next solution
+    error: RefCell<Option<EvaluationError>>,
+}
+
+impl Clone for CustomFunctions {
+    fn clone(&self) -> Self {
+        Self {
+            functions: self.functions.clone(),
+            aggregates: self.aggregates.clone(),
+            resolver: self.resolver.clone(),
+            resolved: self.resolved.clone(),
+            error: RefCell::default(),
+        }
+    }
+}
+
+impl CustomFunctions {
+    /// The function `name`, registered on the options or found by the resolver
+    pub fn function(&self, name: &NamedNode) -> Result<Option<CustomFunction>, EvaluationError> {
+        if let Some(function) = self.functions.get(name) {
+            return Ok(Some(function.clone()));
+        }
+        let resolver = match &self.resolver {
+            Some(resolver) => resolver,
+            None => return Ok(None),
+        };
+        if let Some(function) = self.resolved.borrow().get(name) {
+            return Ok(function.clone());
+        }
+        let function = resolver(name)?;
+        self.resolved.borrow_mut().insert(name.clone(), function.clone());
+        Ok(function)
+    }
+
+    /// Whether the query has no function that can fail its evaluation
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.aggregates.is_empty() && self.resolver.is_none()
+    }
+
+    /// Keeps the error of a call until the evaluation returns it
+    pub fn fail(&self, error: EvaluationError) {
+        self.error.borrow_mut().get_or_insert(error);
+    }
+
+    /// The error of a call that failed since the last solution
+    pub fn take_error(&self) -> Option<EvaluationError> {
+        self.error.borrow_mut().take()
+    }
 }
 
 /// Options for SPARQL update evaluation
@@ -14,8 +14,14 @@ use std::str::Chars;
 use std::str::FromStr;
 use std::{char, fmt};
 
-/// Parses a SPARQL query with an optional base IRI to resolve relative IRIs in the query
-pub fn parse_query(query: &str, base_iri: Option<&str>) -> Result<Query, ParseError> {
+/// Parses a SPARQL query with an optional base IRI to resolve relative IRIs in the query.
+///
+/// The calls to the functions of `custom_aggregates` are parsed as aggregates.
+pub fn parse_query(
+    query: &str,
+    base_iri: Option<&str>,
+    custom_aggregates: HashSet<NamedNode>,
+) -> Result<Query, ParseError> {
     let mut state = ParserState {
         base_iri: if let Some(base_iri) = base_iri {
             Some(Iri::parse(base_iri.to_owned()).map_err(|e| ParseError {
@@ -28,6 +34,7 @@ pub fn parse_query(query: &str, base_iri: Option<&str>) -> Result<Query, ParseEr
         used_bnodes: HashSet::default(),
         currently_used_bnodes: HashSet::default(),
         aggregates: Vec::default(),
+        custom_aggregates,
     };
 
     parser::QueryUnit(&unescape_unicode_codepoints(query), &mut state).map_err(|e| ParseError {
@@ -35,8 +42,14 @@ pub fn parse_query(query: &str, base_iri: Option<&str>) -> Result<Query, ParseEr
     })
 }
 
-/// Parses a SPARQL update with an optional base IRI to resolve relative IRIs in the query
-pub fn parse_update(update: &str, base_iri: Option<&str>) -> Result<Update, ParseError> {
+/// Parses a SPARQL update with an optional base IRI to resolve relative IRIs in the query.
+///
+/// The calls to the functions of `custom_aggregates` are parsed as aggregates.
+pub fn parse_update(
+    update: &str,
+    base_iri: Option<&str>,
+    custom_aggregates: HashSet<NamedNode>,
+) -> Result<Update, ParseError> {
     let mut state = ParserState {
         base_iri: if let Some(base_iri) = base_iri {
             Some(Iri::parse(base_iri.to_owned()).map_err(|e| ParseError {
@@ -49,6 +62,7 @@ pub fn parse_update(update: &str, base_iri: Option<&str>) -> Result<Update, Pars
         used_bnodes: HashSet::default(),
         currently_used_bnodes: HashSet::default(),
         aggregates: Vec::default(),
+        custom_aggregates,
     };
 
     let operations =
@@ -484,6 +498,8 @@ pub struct ParserState {
     used_bnodes: HashSet<BlankNode>,
     currently_used_bnodes: HashSet<BlankNode>,
     aggregates: Vec<Vec<(Variable, AggregationFunction)>>,
+    /// The functions whose calls are aggregates, the calls being ambiguous in the grammar
+    custom_aggregates: HashSet<NamedNode>,
 }
 
 impl ParserState {
@@ -1844,10 +1860,11 @@ parser! {
             name:iri() _ "(" _ e:Expression() _ ")" { AggregationFunction::Custom { name, expr: Box::new(e), distinct: false } }
 
         //[128]
-        rule iriOrFunction() -> Expression = i: iri() _ a: ArgList()? {
+        rule iriOrFunction() -> Expression = i: iri() _ a: ArgList()? {?
             match a {
-                Some(a) => Expression::FunctionCall(Function::Custom(i), a),
-                None => i.into()
+                Some(_) if state.custom_aggregates.contains(&i) => Err("custom aggregate"),
+                Some(a) => Ok(Expression::FunctionCall(Function::Custom(i), a)),
+                None => Ok(i.into())
             }
         }
 
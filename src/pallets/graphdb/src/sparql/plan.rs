@@ -1,6 +1,10 @@
+use crate::model::geo::Envelope;
+use crate::model::NamedNode;
 use crate::sparql::algebra::GraphPattern;
 use crate::sparql::model::Variable;
 use crate::store::numeric_encoder::{EncodedTerm, StrId};
+use crate::store::EncodedObjectRange;
+use crate::text::TextQuery;
 use std::collections::BTreeSet;
 use std::rc::Rc;
 
@@ -24,6 +28,56 @@ pub enum PlanNode<I: StrId> {
         object: PatternValue<I>,
         graph_name: PatternValue<I>,
     },
+    /// A quad pattern whose object variable is restricted by a filter to a range of values.
+    ///
+    /// The store is asked for the quads in the range when the object is not bound yet.
+    QuadPatternRangeJoin {
+        child: Rc<PlanNode<I>>,
+        subject: PatternValue<I>,
+        predicate: PatternValue<I>,
+        object: PatternValue<I>,
+        graph_name: PatternValue<I>,
+        range: EncodedObjectRange<I>,
+    },
+    /// A quad pattern whose object variable is restricted by a filter to the geometries intersecting an area.
+    ///
+    /// The store is asked for the quads in the area when the object is not bound yet.
+    QuadPatternSpatialJoin {
+        child: Rc<PlanNode<I>>,
+        subject: PatternValue<I>,
+        predicate: PatternValue<I>,
+        object: PatternValue<I>,
+        graph_name: PatternValue<I>,
+        area: Envelope,
+    },
+    /// The `text:match` property function (see `crate::text`): the string literals matching `query`, objects of
+    /// the quads of `subject` with `predicate` (any if `None`), with their relevance bound to `score`.
+    ///
+    /// The store is asked for the quads matching the query when the subject is not bound yet.
+    TextMatch {
+        child: Rc<PlanNode<I>>,
+        subject: PatternValue<I>,
+        predicate: Option<EncodedTerm<I>>,
+        query: Rc<TextQuery>,
+        graph_name: PatternValue<I>,
+        score: Option<usize>,
+        literal: Option<usize>,
+    },
+    /// The `vec:nearest` property function (see `crate::hnsw`): the subjects of the `k` quads with `predicate`
+    /// whose `vec:floatVector` object is the closest to `query`, with their cosine distance bound to `distance` and
+    /// their vector to `vector`.
+    ///
+    /// The nearest quads are looked up for each input tuple, then joined with it.
+    VectorNearest {
+        child: Rc<PlanNode<I>>,
+        subject: PatternValue<I>,
+        predicate: EncodedTerm<I>,
+        query: PatternValue<I>,
+        k: usize,
+        graph_name: PatternValue<I>,
+        distance: Option<usize>,
+        vector: Option<usize>,
+    },
     PathPatternJoin {
         child: Rc<PlanNode<I>>,
         subject: PatternValue<I>,
@@ -35,6 +89,11 @@ pub enum PlanNode<I: StrId> {
         left: Rc<PlanNode<I>>,
         right: Rc<PlanNode<I>>,
     },
+    HashJoin {
+        left: Rc<PlanNode<I>>,
+        right: Rc<PlanNode<I>>,
+        keys: Rc<Vec<usize>>, // variables that might be bound by both sides
+    },
     AntiJoin {
         left: Rc<PlanNode<I>>,
         right: Rc<PlanNode<I>>,
@@ -51,6 +110,13 @@ pub enum PlanNode<I: StrId> {
         right: Rc<PlanNode<I>>,
         possible_problem_vars: Rc<Vec<usize>>, //Variables that should not be part of the entry of the left join
     },
+    HashLeftJoin {
+        // The right side is evaluated once, it must not depend on the left bindings
+        left: Rc<PlanNode<I>>,
+        right: Rc<PlanNode<I>>,
+        keys: Rc<Vec<usize>>, // variables that might be bound by both sides
+        expression: Option<Rc<PlanExpression<I>>>,
+    },
     Extend {
         child: Rc<PlanNode<I>>,
         position: usize,
@@ -74,12 +140,14 @@ pub enum PlanNode<I: StrId> {
     Project {
         child: Rc<PlanNode<I>>,
         mapping: Rc<Vec<(usize, usize)>>, // pairs of (variable key in child, variable key in output)
+        variables: Rc<Vec<Variable>>,     // variables of the child scope
     },
     Aggregate {
         // By definition the group by key are the range 0..key_mapping.len()
         child: Rc<PlanNode<I>>,
         key_mapping: Rc<Vec<(usize, usize)>>, // aggregate key pairs of (variable key in child, variable key in output)
         aggregates: Rc<Vec<(PlanAggregation<I>, usize)>>,
+        variables: Rc<Vec<Variable>>, // variables of the child scope
     },
 }
 
@@ -109,6 +177,22 @@ impl<I: StrId> PlanNode<I> {
                 predicate,
                 object,
                 graph_name,
+            }
+            | PlanNode::QuadPatternRangeJoin {
+                child,
+                subject,
+                predicate,
+                object,
+                graph_name,
+                ..
+            }
+            | PlanNode::QuadPatternSpatialJoin {
+                child,
+                subject,
+                predicate,
+                object,
+                graph_name,
+                ..
             } => {
                 if let PatternValue::Variable(var) = subject {
                     set.insert(*var);
@@ -124,6 +208,42 @@ impl<I: StrId> PlanNode<I> {
                 }
                 child.add_maybe_bound_variables(set);
             }
+            PlanNode::TextMatch {
+                child,
+                subject,
+                graph_name,
+                score,
+                literal,
+                ..
+            } => {
+                if let PatternValue::Variable(var) = subject {
+                    set.insert(*var);
+                }
+                if let PatternValue::Variable(var) = graph_name {
+                    set.insert(*var);
+                }
+                set.extend(score);
+                set.extend(literal);
+                child.add_maybe_bound_variables(set);
+            }
+            PlanNode::VectorNearest {
+                child,
+                subject,
+                graph_name,
+                distance,
+                vector,
+                ..
+            } => {
+                if let PatternValue::Variable(var) = subject {
+                    set.insert(*var);
+                }
+                if let PatternValue::Variable(var) = graph_name {
+                    set.insert(*var);
+                }
+                set.extend(distance);
+                set.extend(vector);
+                child.add_maybe_bound_variables(set);
+            }
             PlanNode::PathPatternJoin {
                 child,
                 subject,
@@ -152,11 +272,24 @@ impl<I: StrId> PlanNode<I> {
                 }
             }
             PlanNode::Join { left, right, .. }
+            | PlanNode::HashJoin { left, right, .. }
             | PlanNode::AntiJoin { left, right, .. }
             | PlanNode::LeftJoin { left, right, .. } => {
                 left.add_maybe_bound_variables(set);
                 right.add_maybe_bound_variables(set);
             }
+            PlanNode::HashLeftJoin {
+                left,
+                right,
+                expression,
+                ..
+            } => {
+                if let Some(expression) = expression {
+                    expression.add_maybe_bound_variables(set);
+                }
+                left.add_maybe_bound_variables(set);
+                right.add_maybe_bound_variables(set);
+            }
             PlanNode::Extend {
                 child,
                 position,
@@ -171,7 +304,7 @@ impl<I: StrId> PlanNode<I> {
             | PlanNode::HashDeduplicate { child }
             | PlanNode::Skip { child, .. }
             | PlanNode::Limit { child, .. } => child.add_maybe_bound_variables(set),
-            PlanNode::Project { mapping, child } => {
+            PlanNode::Project { mapping, child, .. } => {
                 let child_bound = child.maybe_bound_variables();
                 for (child_i, output_i) in mapping.iter() {
                     if child_bound.contains(child_i) {
@@ -278,6 +411,8 @@ pub enum PlanExpression<I: StrId> {
     Sha384(Box<PlanExpression<I>>),
     Sha512(Box<PlanExpression<I>>),
     Coalesce(Vec<PlanExpression<I>>),
+    /// A function registered on the query options
+    CustomFunction(NamedNode, Vec<PlanExpression<I>>),
     If(
         Box<PlanExpression<I>>,
         Box<PlanExpression<I>>,
@@ -307,6 +442,21 @@ pub enum PlanExpression<I: StrId> {
     YearMonthDurationCast(Box<PlanExpression<I>>),
     DayTimeDurationCast(Box<PlanExpression<I>>),
     StringCast(Box<PlanExpression<I>>),
+    GeoDistance(
+        Box<PlanExpression<I>>,
+        Box<PlanExpression<I>>,
+        Box<PlanExpression<I>>,
+    ),
+    GeoBuffer(
+        Box<PlanExpression<I>>,
+        Box<PlanExpression<I>>,
+        Box<PlanExpression<I>>,
+    ),
+    GeoIntersects(Box<PlanExpression<I>>, Box<PlanExpression<I>>),
+    GeoWithin(Box<PlanExpression<I>>, Box<PlanExpression<I>>),
+    CosineDistance(Box<PlanExpression<I>>, Box<PlanExpression<I>>),
+    DotProduct(Box<PlanExpression<I>>, Box<PlanExpression<I>>),
+    L2Distance(Box<PlanExpression<I>>, Box<PlanExpression<I>>),
 }
 
 impl<I: StrId> PlanExpression<I> {
@@ -386,6 +536,11 @@ impl<I: StrId> PlanExpression<I> {
             | PlanExpression::StrLang(a, b)
             | PlanExpression::StrDt(a, b)
             | PlanExpression::SameTerm(a, b)
+            | PlanExpression::GeoIntersects(a, b)
+            | PlanExpression::GeoWithin(a, b)
+            | PlanExpression::CosineDistance(a, b)
+            | PlanExpression::DotProduct(a, b)
+            | PlanExpression::L2Distance(a, b)
             | PlanExpression::SubStr(a, b, None)
             | PlanExpression::Regex(a, b, None) => {
                 a.add_maybe_bound_variables(set);
@@ -394,7 +549,9 @@ impl<I: StrId> PlanExpression<I> {
             PlanExpression::If(a, b, c)
             | PlanExpression::SubStr(a, b, Some(c))
             | PlanExpression::Regex(a, b, Some(c))
-            | PlanExpression::Replace(a, b, c, None) => {
+            | PlanExpression::Replace(a, b, c, None)
+            | PlanExpression::GeoDistance(a, b, c)
+            | PlanExpression::GeoBuffer(a, b, c) => {
                 a.add_maybe_bound_variables(set);
                 b.add_maybe_bound_variables(set);
                 c.add_maybe_bound_variables(set);
@@ -406,7 +563,9 @@ impl<I: StrId> PlanExpression<I> {
                 d.add_maybe_bound_variables(set);
             }
 
-            PlanExpression::Concat(es) | PlanExpression::Coalesce(es) => {
+            PlanExpression::Concat(es)
+            | PlanExpression::Coalesce(es)
+            | PlanExpression::CustomFunction(_, es) => {
                 for e in es {
                     e.add_maybe_bound_variables(set);
                 }
@@ -440,6 +599,8 @@ pub enum PlanAggregationFunction {
     Avg,
     Sample,
     GroupConcat { separator: Rc<String> },
+    /// An aggregate registered on the query options
+    Custom(NamedNode),
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, Hash)]
@@ -1,23 +1,64 @@
-use crate::model::{BlankNode, Literal, NamedNode, Term};
+use crate::model::geo::{Envelope, Geometry, Unit};
+use crate::model::vector::Vector;
+use crate::model::vocab::{geo, geof, rdf, vec, xsd};
+use crate::model::{BlankNode, Literal, NamedNode, NamedNodeRef, Term};
 use crate::sparql::algebra::*;
+use crate::sparql::entailment::RdfsSchema;
 use crate::sparql::error::EvaluationError;
+use crate::sparql::CustomFunctions;
 use crate::sparql::model::*;
 use crate::sparql::plan::*;
-use crate::store::numeric_encoder::{EncodedTerm, WriteEncoder};
-use std::collections::{BTreeSet, HashSet};
+use crate::store::numeric_encoder::{EncodedTerm, StrEncodingAware, StrId, WriteEncoder};
+use crate::store::EncodedObjectRange;
+use crate::hnsw;
+use crate::text::{self, TextQuery};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::rc::Rc;
 
 pub(crate) struct PlanBuilder<E: WriteEncoder> {
     encoder: E,
+    /// The schema the basic graph patterns are rewritten with, if RDFS is entailed
+    rdfs_schema: Option<Rc<RdfsSchema>>,
+    /// The functions and aggregates registered on the query options
+    custom_functions: Rc<CustomFunctions>,
 }
 
-impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
+/// Cardinality statistics used to order the patterns of basic graph patterns.
+pub(crate) trait CardinalityEstimator: StrEncodingAware {
+    /// Number of quads with the given predicate, `None` if unknown.
+    fn predicate_cardinality(&self, predicate: EncodedTerm<Self::StrId>) -> Option<u64>;
+
+    /// Number of quads with the given predicate whose subject is an instance of `class`, `None` if unknown.
+    fn predicate_class_cardinality(
+        &self,
+        predicate: EncodedTerm<Self::StrId>,
+        class: EncodedTerm<Self::StrId>,
+    ) -> Option<u64>;
+
+    /// Number of quads with the given predicate (any if `None`) in the given graph (any named graph if `None`),
+    /// `None` if unknown.
+    fn quad_count(
+        &self,
+        predicate: Option<EncodedTerm<Self::StrId>>,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Option<u64>;
+}
+
+impl<E: WriteEncoder<Error = EvaluationError> + CardinalityEstimator> PlanBuilder<E> {
     pub fn build(
         encoder: E,
         pattern: &GraphPattern,
+        rdfs_schema: Option<Rc<RdfsSchema>>,
+        custom_functions: &Rc<CustomFunctions>,
     ) -> Result<(PlanNode<E::StrId>, Vec<Variable>), EvaluationError> {
         let mut variables = Vec::default();
-        let plan = PlanBuilder { encoder }.build_for_graph_pattern(
+        let plan = PlanBuilder {
+            encoder,
+            rdfs_schema,
+            custom_functions: custom_functions.clone(),
+        }
+        .build_for_graph_pattern(
             pattern,
             &mut variables,
             PatternValue::Constant(EncodedTerm::DefaultGraph),
@@ -30,7 +71,12 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
         template: &[TriplePattern],
         mut variables: Vec<Variable>,
     ) -> Result<Vec<TripleTemplate<E::StrId>>, EvaluationError> {
-        PlanBuilder { encoder }.build_for_graph_template(template, &mut variables)
+        PlanBuilder {
+            encoder,
+            rdfs_schema: None,
+            custom_functions: Rc::default(),
+        }
+        .build_for_graph_template(template, &mut variables)
     }
 
     fn build_for_graph_pattern(
@@ -40,7 +86,16 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
         graph_name: PatternValue<E::StrId>,
     ) -> Result<PlanNode<E::StrId>, EvaluationError> {
         Ok(match pattern {
-            GraphPattern::BGP(p) => self.build_for_bgp(p, variables, graph_name)?,
+            GraphPattern::BGP(p) => {
+                if let Some(rdfs_schema) = self.rdfs_schema.take() {
+                    // the patterns of the rewrites are only matched with the stored quads
+                    let plan = self.build_for_entailed_bgp(p, &rdfs_schema, variables, graph_name);
+                    self.rdfs_schema = Some(rdfs_schema);
+                    plan?
+                } else {
+                    self.build_for_bgp(p, variables, graph_name)?
+                }
+            }
             GraphPattern::Path {
                 subject,
                 path,
@@ -69,26 +124,48 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                         graph_name,
                     }
                 } else {
-                    PlanNode::Join {
-                        left: Rc::new(self.build_for_graph_pattern(left, variables, graph_name)?),
-                        right: Rc::new(self.build_for_graph_pattern(right, variables, graph_name)?),
-                    }
+                    let left = self.build_for_graph_pattern(left, variables, graph_name)?;
+                    let right = self.build_for_graph_pattern(right, variables, graph_name)?;
+                    join(left, right)
                 }
             }
             GraphPattern::LeftJoin { left, right, expr } => {
                 let left = self.build_for_graph_pattern(left, variables, graph_name)?;
                 let right = self.build_for_graph_pattern(right, variables, graph_name)?;
+                let expression = if let Some(expr) = expr {
+                    Some(self.build_for_expression(expr, variables, graph_name)?)
+                } else {
+                    None
+                };
+
+                // A right side that does not depend on the left bindings is evaluated only once,
+                // unless its filter reads them. Basic graph patterns are kept in a nested loop join
+                // to use bound index lookups.
+                let left_variables = left.maybe_bound_variables();
+                let mut used = BTreeSet::new();
+                if let Some(expression) = &expression {
+                    expression.add_maybe_bound_variables(&mut used);
+                }
+                if !is_bgp_plan(&right)
+                    && is_independent_of(&right, &left_variables)
+                    && used.is_disjoint(&left_variables)
+                {
+                    return Ok(PlanNode::HashLeftJoin {
+                        keys: Rc::new(join_keys(&left, &right)),
+                        left: Rc::new(left),
+                        right: Rc::new(right),
+                        expression: expression.map(Rc::new),
+                    });
+                }
 
                 let mut possible_problem_vars = BTreeSet::new();
                 self.add_left_join_problematic_variables(&right, &mut possible_problem_vars);
 
                 //We add the extra filter if needed
-                let right = if let Some(expr) = expr {
+                let right = if let Some(expression) = expression {
                     PlanNode::Filter {
                         child: Rc::new(right),
-                        expression: Rc::new(
-                            self.build_for_expression(expr, variables, graph_name)?,
-                        ),
+                        expression: Rc::new(expression),
                     }
                 } else {
                     right
@@ -100,10 +177,20 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                     possible_problem_vars: Rc::new(possible_problem_vars.into_iter().collect()),
                 }
             }
-            GraphPattern::Filter { expr, inner } => PlanNode::Filter {
-                child: Rc::new(self.build_for_graph_pattern(inner, variables, graph_name)?),
-                expression: Rc::new(self.build_for_expression(expr, variables, graph_name)?),
-            },
+            GraphPattern::Filter { expr, inner } => {
+                let child = self.build_for_graph_pattern(inner, variables, graph_name)?;
+                let expression = self.build_for_expression(expr, variables, graph_name)?;
+                // The filter is kept: the range scans may return values out of the range
+                // and the spatial lookups geometries out of the area
+                let mut ranges = HashMap::new();
+                add_object_ranges(&expression, &mut ranges);
+                let mut areas = HashMap::new();
+                add_spatial_areas(expr, variables, &mut areas);
+                PlanNode::Filter {
+                    child: Rc::new(push_object_ranges(child, &ranges, &areas)),
+                    expression: Rc::new(expression),
+                }
+            }
             GraphPattern::Union { left, right } => {
                 //We flatten the UNIONs
                 let mut stack: Vec<&GraphPattern> = vec![left, right];
@@ -158,37 +245,48 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                 by,
                 aggregates,
             } => {
+                if let Some(tuple) =
+                    self.build_count_from_statistics(inner, by, aggregates, variables, graph_name)?
+                {
+                    return Ok(PlanNode::StaticBindings {
+                        tuples: vec![tuple],
+                    });
+                }
                 let mut inner_variables = by.clone();
                 let inner_graph_name =
                     self.convert_pattern_value_id(graph_name, variables, &mut inner_variables);
 
+                let child = Rc::new(self.build_for_graph_pattern(
+                    inner,
+                    &mut inner_variables,
+                    inner_graph_name,
+                )?);
+                let key_mapping = Rc::new(
+                    by.iter()
+                        .map(|k| {
+                            (
+                                variable_key(&mut inner_variables, k),
+                                variable_key(variables, k),
+                            )
+                        })
+                        .collect(),
+                );
+                let aggregates = Rc::new(
+                    aggregates
+                        .iter()
+                        .map(|(v, a)| {
+                            Ok((
+                                self.build_for_aggregate(a, &mut inner_variables, graph_name)?,
+                                variable_key(variables, v),
+                            ))
+                        })
+                        .collect::<Result<Vec<_>, EvaluationError>>()?,
+                );
                 PlanNode::Aggregate {
-                    child: Rc::new(self.build_for_graph_pattern(
-                        inner,
-                        &mut inner_variables,
-                        inner_graph_name,
-                    )?),
-                    key_mapping: Rc::new(
-                        by.iter()
-                            .map(|k| {
-                                (
-                                    variable_key(&mut inner_variables, k),
-                                    variable_key(variables, k),
-                                )
-                            })
-                            .collect(),
-                    ),
-                    aggregates: Rc::new(
-                        aggregates
-                            .iter()
-                            .map(|(v, a)| {
-                                Ok((
-                                    self.build_for_aggregate(a, &mut inner_variables, graph_name)?,
-                                    variable_key(variables, v),
-                                ))
-                            })
-                            .collect::<Result<Vec<_>, EvaluationError>>()?,
-                    ),
+                    child,
+                    key_mapping,
+                    aggregates,
+                    variables: Rc::new(inner_variables),
                 }
             }
             GraphPattern::Table {
@@ -218,12 +316,13 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                 let mut inner_variables = projection.clone();
                 let inner_graph_name =
                     self.convert_pattern_value_id(graph_name, variables, &mut inner_variables);
+                let child = Rc::new(self.build_for_graph_pattern(
+                    inner,
+                    &mut inner_variables,
+                    inner_graph_name,
+                )?);
                 PlanNode::Project {
-                    child: Rc::new(self.build_for_graph_pattern(
-                        inner,
-                        &mut inner_variables,
-                        inner_graph_name,
-                    )?),
+                    child,
                     mapping: Rc::new(
                         projection
                             .iter()
@@ -233,6 +332,7 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                             })
                             .collect(),
                     ),
+                    variables: Rc::new(inner_variables),
                 }
             }
             GraphPattern::Distinct { inner } => PlanNode::HashDeduplicate {
@@ -270,8 +370,37 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
         variables: &mut Vec<Variable>,
         graph_name: PatternValue<E::StrId>,
     ) -> Result<PlanNode<E::StrId>, EvaluationError> {
+        let (text_matches, p) = split_text_matches(p)?;
+        let (nearest, p) = split_vector_nearest(&p)?;
+        let patterns = if let Some(patterns) = self.sort_bgp_with_statistics(&p)? {
+            patterns
+        } else {
+            sort_bgp(&p)
+        };
+        // The full-text matches are looked up first in the index of the literals
         let mut plan = PlanNode::Init;
-        for pattern in sort_bgp(p) {
+        for text_match in text_matches {
+            plan = PlanNode::TextMatch {
+                child: Rc::new(plan),
+                subject: self.pattern_value_from_term_or_variable(&text_match.subject, variables)?,
+                predicate: match &text_match.predicate {
+                    Some(predicate) => Some(self.build_named_node(predicate)?),
+                    None => None,
+                },
+                query: Rc::new(text_match.query),
+                graph_name,
+                score: text_match.score.map(|score| variable_key(variables, &score)),
+                literal: text_match.literal.map(|literal| variable_key(variables, &literal)),
+            }
+        }
+        // So are the nearest vectors of a constant query, the other queries are bound by the patterns
+        let (constant, bound): (Vec<_>, Vec<_>) = nearest
+            .into_iter()
+            .partition(|nearest| matches!(nearest.query, TermOrVariable::Term(_)));
+        for nearest in constant {
+            plan = self.build_vector_nearest(plan, nearest, variables, graph_name)?;
+        }
+        for pattern in patterns {
             plan = PlanNode::QuadPatternJoin {
                 child: Rc::new(plan),
                 subject: self.pattern_value_from_term_or_variable(&pattern.subject, variables)?,
@@ -281,9 +410,167 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                 graph_name,
             }
         }
+        for nearest in bound {
+            plan = self.build_vector_nearest(plan, nearest, variables, graph_name)?;
+        }
         Ok(plan)
     }
 
+    fn build_vector_nearest(
+        &mut self,
+        child: PlanNode<E::StrId>,
+        nearest: VectorNearestPattern,
+        variables: &mut Vec<Variable>,
+        graph_name: PatternValue<E::StrId>,
+    ) -> Result<PlanNode<E::StrId>, EvaluationError> {
+        Ok(PlanNode::VectorNearest {
+            child: Rc::new(child),
+            subject: self.pattern_value_from_term_or_variable(&nearest.subject, variables)?,
+            predicate: self.build_named_node(&nearest.predicate)?,
+            query: self.pattern_value_from_term_or_variable(&nearest.query, variables)?,
+            k: nearest.k,
+            graph_name,
+            distance: nearest.distance.map(|distance| variable_key(variables, &distance)),
+            vector: nearest.vector.map(|vector| variable_key(variables, &vector)),
+        })
+    }
+
+    /// Joins the patterns of a basic graph pattern matched with the stored quads with the rewrites (see
+    /// `RdfsSchema::rewrite`) of the patterns entailed by other quads
+    fn build_for_entailed_bgp(
+        &mut self,
+        p: &[TriplePattern],
+        rdfs_schema: &RdfsSchema,
+        variables: &mut Vec<Variable>,
+        graph_name: PatternValue<E::StrId>,
+    ) -> Result<PlanNode<E::StrId>, EvaluationError> {
+        let graph_variable = match graph_name {
+            PatternValue::Variable(key) => Some(variables[key].clone()),
+            PatternValue::Constant(_) => None,
+        };
+        let mut stored = Vec::new();
+        let mut rewrites = Vec::new();
+        for pattern in p {
+            match rdfs_schema.rewrite(pattern, graph_variable.as_ref()) {
+                Some(rewrite) => rewrites.push(rewrite),
+                None => stored.push(pattern.clone()),
+            }
+        }
+        let mut plan = if stored.is_empty() {
+            None
+        } else {
+            Some(self.build_for_bgp(&stored, variables, graph_name)?)
+        };
+        for rewrite in rewrites {
+            let right = self.build_for_graph_pattern(&rewrite, variables, graph_name)?;
+            plan = Some(match plan {
+                Some(left) => join(left, right),
+                None => right,
+            });
+        }
+        Ok(plan.unwrap_or(PlanNode::Init))
+    }
+
+    /// Orders the patterns of a basic graph pattern using the store cardinality statistics.
+    ///
+    /// The pattern with the smallest estimated number of rows is greedily picked first.
+    /// The estimate of a pattern sharing variables with the already picked ones is the one of
+    /// a bound index scan, the other patterns are estimated as unbound scans.
+    /// Returns `None` if the store keeps no statistics.
+    fn sort_bgp_with_statistics<'a>(
+        &mut self,
+        p: &'a [TriplePattern],
+    ) -> Result<Option<Vec<&'a TriplePattern>>, EvaluationError> {
+        let rdf_type = self.encoder.encode_named_node(rdf::TYPE)?;
+
+        // The classes the BGP gives to its subjects
+        let mut classes = Vec::new();
+        for pattern in p {
+            if let (
+                NamedNodeOrVariable::NamedNode(predicate),
+                TermOrVariable::Term(Term::NamedNode(class)),
+            ) = (&pattern.predicate, &pattern.object)
+            {
+                if predicate.as_ref() == rdf::TYPE {
+                    classes.push((&pattern.subject, self.build_named_node(class)?));
+                }
+            }
+        }
+
+        let mut cardinalities = Vec::with_capacity(p.len());
+        for pattern in p {
+            let predicate = if let NamedNodeOrVariable::NamedNode(predicate) = &pattern.predicate {
+                self.build_named_node(predicate)?
+            } else {
+                cardinalities.push(PatternCardinality {
+                    rows: f64::INFINITY,
+                    object_included: false,
+                });
+                continue;
+            };
+            let cardinality = match &pattern.object {
+                TermOrVariable::Term(Term::NamedNode(class)) if predicate == rdf_type => {
+                    let class = self.build_named_node(class)?;
+                    match self.encoder.predicate_class_cardinality(predicate, class) {
+                        Some(rows) => PatternCardinality {
+                            rows: rows as f64,
+                            object_included: true,
+                        },
+                        None => return Ok(None),
+                    }
+                }
+                _ => {
+                    let mut rows = match self.encoder.predicate_cardinality(predicate) {
+                        Some(rows) => rows,
+                        None => return Ok(None),
+                    };
+                    for (subject, class) in &classes {
+                        if **subject == pattern.subject {
+                            if let Some(class_rows) =
+                                self.encoder.predicate_class_cardinality(predicate, *class)
+                            {
+                                rows = rows.min(class_rows);
+                            }
+                        }
+                    }
+                    PatternCardinality {
+                        rows: rows as f64,
+                        object_included: false,
+                    }
+                }
+            };
+            cardinalities.push(cardinality);
+        }
+
+        let mut assigned_variables = HashSet::default();
+        let mut assigned_blank_nodes = HashSet::default();
+        let mut remaining: Vec<usize> = (0..p.len()).collect();
+        let mut new_p = Vec::with_capacity(p.len());
+        while !remaining.is_empty() {
+            let mut best = 0;
+            let mut best_key = None;
+            for (position, i) in remaining.iter().enumerate() {
+                let rows = estimate_pattern_rows(
+                    &p[*i],
+                    &cardinalities[*i],
+                    &assigned_variables,
+                    &assigned_blank_nodes,
+                );
+                let binds = count_pattern_binds(&p[*i], &assigned_variables, &assigned_blank_nodes);
+                if best_key.map_or(true, |(best_rows, best_binds)| {
+                    rows < best_rows || (rows <= best_rows && binds > best_binds)
+                }) {
+                    best = position;
+                    best_key = Some((rows, binds));
+                }
+            }
+            let pattern = &p[remaining.remove(best)];
+            add_pattern_variables(pattern, &mut assigned_variables, &mut assigned_blank_nodes);
+            new_p.push(pattern);
+        }
+        Ok(Some(new_p))
+    }
+
     fn build_for_path(
         &mut self,
         path: &PropertyPathExpression,
@@ -702,6 +989,55 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                             graph_name,
                             "string",
                         )?
+                    } else if name == geof::DISTANCE.as_str() {
+                        check_arity(parameters, 3, "geof:distance")?;
+                        PlanExpression::GeoDistance(
+                            Box::new(self.build_for_expression(&parameters[0], variables, graph_name)?),
+                            Box::new(self.build_for_expression(&parameters[1], variables, graph_name)?),
+                            Box::new(self.build_for_expression(&parameters[2], variables, graph_name)?),
+                        )
+                    } else if name == geof::BUFFER.as_str() {
+                        check_arity(parameters, 3, "geof:buffer")?;
+                        PlanExpression::GeoBuffer(
+                            Box::new(self.build_for_expression(&parameters[0], variables, graph_name)?),
+                            Box::new(self.build_for_expression(&parameters[1], variables, graph_name)?),
+                            Box::new(self.build_for_expression(&parameters[2], variables, graph_name)?),
+                        )
+                    } else if name == geof::SF_INTERSECTS.as_str() {
+                        check_arity(parameters, 2, "geof:sfIntersects")?;
+                        PlanExpression::GeoIntersects(
+                            Box::new(self.build_for_expression(&parameters[0], variables, graph_name)?),
+                            Box::new(self.build_for_expression(&parameters[1], variables, graph_name)?),
+                        )
+                    } else if name == geof::SF_WITHIN.as_str() {
+                        check_arity(parameters, 2, "geof:sfWithin")?;
+                        PlanExpression::GeoWithin(
+                            Box::new(self.build_for_expression(&parameters[0], variables, graph_name)?),
+                            Box::new(self.build_for_expression(&parameters[1], variables, graph_name)?),
+                        )
+                    } else if name == vec::COSINE_DISTANCE.as_str() {
+                        check_arity(parameters, 2, "vec:cosineDistance")?;
+                        PlanExpression::CosineDistance(
+                            Box::new(self.build_for_expression(&parameters[0], variables, graph_name)?),
+                            Box::new(self.build_for_expression(&parameters[1], variables, graph_name)?),
+                        )
+                    } else if name == vec::DOT_PRODUCT.as_str() {
+                        check_arity(parameters, 2, "vec:dotProduct")?;
+                        PlanExpression::DotProduct(
+                            Box::new(self.build_for_expression(&parameters[0], variables, graph_name)?),
+                            Box::new(self.build_for_expression(&parameters[1], variables, graph_name)?),
+                        )
+                    } else if name == vec::L2_DISTANCE.as_str() {
+                        check_arity(parameters, 2, "vec:l2Distance")?;
+                        PlanExpression::L2Distance(
+                            Box::new(self.build_for_expression(&parameters[0], variables, graph_name)?),
+                            Box::new(self.build_for_expression(&parameters[1], variables, graph_name)?),
+                        )
+                    } else if self.custom_functions.function(name)?.is_some() {
+                        PlanExpression::CustomFunction(
+                            name.clone(),
+                            self.expression_list(parameters, variables, graph_name)?,
+                        )
                     } else {
                         return Err(EvaluationError::msg(format!(
                             "Not supported custom function {}",
@@ -867,10 +1203,144 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                 parameter: Some(self.build_for_expression(expr, variables, graph_name)?),
                 distinct: *distinct,
             }),
-            AggregationFunction::Custom { .. } => Err(EvaluationError::msg(
-                "Custom aggregation functions are not supported yet",
-            )),
+            AggregationFunction::Custom {
+                name,
+                expr,
+                distinct,
+            } => {
+                if !self.custom_functions.aggregates.contains_key(name) {
+                    return Err(EvaluationError::msg(format!(
+                        "Not supported custom aggregate {}",
+                        name
+                    )));
+                }
+                Ok(PlanAggregation {
+                    function: PlanAggregationFunction::Custom(name.clone()),
+                    parameter: Some(self.build_for_expression(expr, variables, graph_name)?),
+                    distinct: *distinct,
+                })
+            }
+        }
+    }
+
+    /// The single solution of a group without keys (or with the constant key the parser adds when there is no
+    /// `GROUP BY`) whose aggregates are read from the quad counts of the store, see `count_from_statistics`.
+    fn build_count_from_statistics(
+        &mut self,
+        inner: &GraphPattern,
+        by: &[Variable],
+        aggregates: &[(Variable, AggregationFunction)],
+        variables: &mut Vec<Variable>,
+        graph_name: PatternValue<E::StrId>,
+    ) -> Result<Option<EncodedTuple<E::StrId>>, EvaluationError> {
+        let (inner, constant_key) = match (by, inner) {
+            ([], inner) => (inner, None),
+            (
+                [key],
+                GraphPattern::Extend {
+                    inner,
+                    var,
+                    expr: Expression::Literal(value),
+                },
+            ) if key == var => (inner.as_ref(), Some((key, value))),
+            _ => return Ok(None),
+        };
+        let count = if let Some(count) = self.count_from_statistics(inner, aggregates, graph_name)? {
+            count
+        } else {
+            return Ok(None);
+        };
+        let mut values = Vec::with_capacity(aggregates.len() + 1);
+        if let Some((key, value)) = constant_key {
+            values.push((variable_key(variables, key), self.build_literal(value)?));
+        }
+        for (v, _) in aggregates {
+            values.push((variable_key(variables, v), EncodedTerm::IntegerLiteral(count)));
+        }
+        let mut tuple = EncodedTuple::with_capacity(variables.len());
+        for (key, value) in values {
+            tuple.set(key, value);
+        }
+        Ok(Some(tuple))
+    }
+
+    /// The value of aggregates that only count the solutions of a single quad pattern, read from the quad
+    /// counts of the store.
+    ///
+    /// The subject, the object and the graph name (if it is not a constant) of the pattern must be distinct
+    /// variables, and its predicate a distinct variable or a named node. `None` if the aggregates have to be
+    /// evaluated.
+    fn count_from_statistics(
+        &mut self,
+        inner: &GraphPattern,
+        aggregates: &[(Variable, AggregationFunction)],
+        graph_name: PatternValue<E::StrId>,
+    ) -> Result<Option<i64>, EvaluationError> {
+        let (pattern, graph_name, graph_variable) = match (inner, graph_name) {
+            (GraphPattern::BGP(p), PatternValue::Constant(graph_name)) if p.len() == 1 => {
+                (&p[0], Some(graph_name), None)
+            }
+            (GraphPattern::Graph { graph_name, inner }, _) => match (graph_name, inner.as_ref()) {
+                (NamedNodeOrVariable::NamedNode(node), GraphPattern::BGP(p)) if p.len() == 1 => {
+                    (&p[0], Some(self.build_named_node(node)?), None)
+                }
+                (NamedNodeOrVariable::Variable(v), GraphPattern::BGP(p)) if p.len() == 1 => {
+                    (&p[0], None, Some(v))
+                }
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        if let Some(rdfs_schema) = &self.rdfs_schema {
+            // the counts do not include the entailed quads
+            if rdfs_schema.rewrite(pattern, None).is_some() {
+                return Ok(None);
+            }
+        }
+
+        let mut pattern_variables = Vec::new();
+        if let (TermOrVariable::Variable(subject), TermOrVariable::Variable(object)) =
+            (&pattern.subject, &pattern.object)
+        {
+            pattern_variables.push(subject);
+            pattern_variables.push(object);
+        } else {
+            return Ok(None);
+        }
+        let predicate = match &pattern.predicate {
+            NamedNodeOrVariable::NamedNode(node) => Some(self.build_named_node(node)?),
+            NamedNodeOrVariable::Variable(v) => {
+                pattern_variables.push(v);
+                None
+            }
+        };
+        pattern_variables.extend(graph_variable);
+        let variable_count = pattern_variables.len();
+        pattern_variables.sort();
+        pattern_variables.dedup();
+        if pattern_variables.len() != variable_count {
+            return Ok(None);
         }
+
+        // the variables of the pattern are always bound: counting them is counting the solutions
+        if !aggregates.iter().all(|(_, aggregate)| match aggregate {
+            AggregationFunction::Count {
+                expr: None,
+                distinct: false,
+            } => true,
+            AggregationFunction::Count {
+                expr: Some(expr),
+                distinct: false,
+            } => matches!(expr.as_ref(), Expression::Variable(v) if pattern_variables.contains(&v)),
+            _ => false,
+        }) {
+            return Ok(None);
+        }
+
+        Ok(self
+            .encoder
+            .quad_count(predicate, graph_name)
+            .and_then(|count| i64::try_from(count).ok()))
     }
 
     fn build_for_graph_template(
@@ -975,6 +1445,10 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
             PlanNode::Init
             | PlanNode::StaticBindings { .. }
             | PlanNode::QuadPatternJoin { .. }
+            | PlanNode::QuadPatternRangeJoin { .. }
+            | PlanNode::QuadPatternSpatialJoin { .. }
+            | PlanNode::TextMatch { .. }
+            | PlanNode::VectorNearest { .. }
             | PlanNode::PathPatternJoin { .. } => (),
             PlanNode::Filter { child, expression } => {
                 expression.add_maybe_bound_variables(set); //TODO: only if it is not already bound
@@ -985,7 +1459,7 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                     self.add_left_join_problematic_variables(child, set);
                 }
             }
-            PlanNode::Join { left, right, .. } => {
+            PlanNode::Join { left, right, .. } | PlanNode::HashJoin { left, right, .. } => {
                 self.add_left_join_problematic_variables(&*left, set);
                 self.add_left_join_problematic_variables(&*right, set);
             }
@@ -996,6 +1470,18 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
                 self.add_left_join_problematic_variables(&*left, set);
                 right.add_maybe_bound_variables(set);
             }
+            PlanNode::HashLeftJoin {
+                left,
+                right,
+                expression,
+                ..
+            } => {
+                self.add_left_join_problematic_variables(&*left, set);
+                right.add_maybe_bound_variables(set);
+                if let Some(expression) = expression {
+                    expression.add_maybe_bound_variables(set);
+                }
+            }
             PlanNode::Extend {
                 child, expression, ..
             } => {
@@ -1010,7 +1496,7 @@ impl<E: WriteEncoder<Error = EvaluationError>> PlanBuilder<E> {
             | PlanNode::Limit { child, .. } => {
                 self.add_left_join_problematic_variables(&*child, set)
             }
-            PlanNode::Project { mapping, child } => {
+            PlanNode::Project { mapping, child, .. } => {
                 let mut child_bound = BTreeSet::new();
                 self.add_left_join_problematic_variables(&*child, &mut child_bound);
                 for (child_i, output_i) in mapping.iter() {
@@ -1081,6 +1567,544 @@ fn slice_key<T: Eq>(slice: &[T], element: &T) -> Option<usize> {
     None
 }
 
+/// Joins two plans evaluated once each: a hash join avoids comparing all the pairs
+fn join<I: StrId>(left: PlanNode<I>, right: PlanNode<I>) -> PlanNode<I> {
+    let keys = join_keys(&left, &right);
+    if keys.is_empty() {
+        PlanNode::Join {
+            left: Rc::new(left),
+            right: Rc::new(right),
+        }
+    } else {
+        PlanNode::HashJoin {
+            left: Rc::new(left),
+            right: Rc::new(right),
+            keys: Rc::new(keys),
+        }
+    }
+}
+
+/// The variables the join tuples are hashed on
+fn join_keys<I: StrId>(left: &PlanNode<I>, right: &PlanNode<I>) -> Vec<usize> {
+    left.maybe_bound_variables()
+        .intersection(&right.maybe_bound_variables())
+        .copied()
+        .collect()
+}
+
+/// Collects the `?var <op> constant` conditions of a filter conjunction that an ordered index can answer.
+fn add_object_ranges<I: StrId>(
+    expression: &PlanExpression<I>,
+    ranges: &mut HashMap<usize, EncodedObjectRange<I>>,
+) {
+    let (variable, bound, is_lower) = match expression {
+        PlanExpression::And(a, b) => {
+            add_object_ranges(a, ranges);
+            add_object_ranges(b, ranges);
+            return;
+        }
+        PlanExpression::Greater(a, b) | PlanExpression::GreaterOrEqual(a, b) => {
+            match (a.as_ref(), b.as_ref()) {
+                (PlanExpression::Variable(v), PlanExpression::Constant(c)) => (*v, *c, true),
+                (PlanExpression::Constant(c), PlanExpression::Variable(v)) => (*v, *c, false),
+                _ => return,
+            }
+        }
+        PlanExpression::Less(a, b) | PlanExpression::LessOrEqual(a, b) => {
+            match (a.as_ref(), b.as_ref()) {
+                (PlanExpression::Variable(v), PlanExpression::Constant(c)) => (*v, *c, false),
+                (PlanExpression::Constant(c), PlanExpression::Variable(v)) => (*v, *c, true),
+                _ => return,
+            }
+        }
+        _ => return,
+    };
+    if !is_ordered_literal(&bound) {
+        return;
+    }
+    let range = ranges.entry(variable).or_insert(EncodedObjectRange {
+        lower: None,
+        upper: None,
+    });
+    // Only the first bound on each side is used, the filter checks the others
+    if is_lower {
+        range.lower.get_or_insert(bound);
+    } else {
+        range.upper.get_or_insert(bound);
+    }
+}
+
+fn is_ordered_literal<I: StrId>(term: &EncodedTerm<I>) -> bool {
+    matches!(
+        term,
+        EncodedTerm::IntegerLiteral(_)
+            | EncodedTerm::DecimalLiteral(_)
+            | EncodedTerm::FloatLiteral(_)
+            | EncodedTerm::DoubleLiteral(_)
+            | EncodedTerm::DateTimeLiteral(_)
+            | EncodedTerm::DateLiteral(_)
+            | EncodedTerm::TimeLiteral(_)
+    )
+}
+
+/// Collects the areas of a filter conjunction that the geometries of a variable must intersect: the
+/// `geof:sfWithin`/`geof:sfIntersects` conditions between a variable and a geometry constant (or the
+/// `geof:buffer` of a constant), and the `geof:distance(?var, constant, unit) < radius` conditions.
+fn add_spatial_areas(
+    expression: &Expression,
+    variables: &[Variable],
+    areas: &mut HashMap<usize, Envelope>,
+) {
+    let (variable, area) = match expression {
+        Expression::And(a, b) => {
+            add_spatial_areas(a, variables, areas);
+            add_spatial_areas(b, variables, areas);
+            return;
+        }
+        Expression::FunctionCall(Function::Custom(name), parameters)
+            if (*name == geof::SF_WITHIN || *name == geof::SF_INTERSECTS) && parameters.len() == 2 =>
+        {
+            match (&parameters[0], &parameters[1]) {
+                (Expression::Variable(v), geometry) | (geometry, Expression::Variable(v)) => {
+                    match constant_geometry(geometry) {
+                        Some(geometry) => (v, geometry.envelope()),
+                        None => return,
+                    }
+                }
+                _ => return,
+            }
+        }
+        Expression::Less(a, b)
+        | Expression::LessOrEqual(a, b)
+        | Expression::Greater(b, a)
+        | Expression::GreaterOrEqual(b, a) => match (a.as_ref(), b.as_ref()) {
+            (Expression::FunctionCall(Function::Custom(name), parameters), Expression::Literal(radius))
+                if *name == geof::DISTANCE && parameters.len() == 3 =>
+            {
+                let (v, geometry) = match (&parameters[0], &parameters[1]) {
+                    (Expression::Variable(v), geometry) | (geometry, Expression::Variable(v)) => {
+                        match constant_geometry(geometry) {
+                            Some(geometry) => (v, geometry),
+                            None => return,
+                        }
+                    }
+                    _ => return,
+                };
+                match (constant_number(radius), constant_unit(&parameters[2])) {
+                    (Some(radius), Some(unit)) if radius >= 0. => (v, geometry.envelope().expand(radius, unit)),
+                    _ => return,
+                }
+            }
+            _ => return,
+        },
+        _ => return,
+    };
+    if let Some(variable) = slice_key(variables, variable) {
+        // Only the first area is used, the filter checks the others
+        areas.entry(variable).or_insert(area);
+    }
+}
+
+/// The geometry of a `geo:wktLiteral` constant or of the `geof:buffer` of one
+fn constant_geometry(expression: &Expression) -> Option<Geometry> {
+    match expression {
+        Expression::Literal(literal) if literal.datatype() == geo::WKT_LITERAL => literal.value().parse().ok(),
+        Expression::FunctionCall(Function::Custom(name), parameters)
+            if *name == geof::BUFFER && parameters.len() == 3 =>
+        {
+            let radius = match &parameters[1] {
+                Expression::Literal(radius) => constant_number(radius)?,
+                _ => return None,
+            };
+            constant_geometry(&parameters[0])?.buffer(radius, constant_unit(&parameters[2])?)
+        }
+        _ => None,
+    }
+}
+
+fn constant_number(literal: &Literal) -> Option<f64> {
+    let datatype = literal.datatype();
+    if datatype == xsd::INTEGER || datatype == xsd::DECIMAL || datatype == xsd::DOUBLE || datatype == xsd::FLOAT {
+        literal.value().parse().ok()
+    } else {
+        None
+    }
+}
+
+fn constant_unit(expression: &Expression) -> Option<Unit> {
+    match expression {
+        Expression::NamedNode(unit) => Unit::from_iri(unit.as_str()),
+        _ => None,
+    }
+}
+
+/// Turns the quad patterns of a basic graph pattern plan that introduce a variable restricted
+/// by `ranges` in object position into range scans, and the ones restricted by `areas` into spatial lookups.
+///
+/// Patterns with a bound subject are left as is, the subject index is more selective.
+fn push_object_ranges<I: StrId>(
+    node: PlanNode<I>,
+    ranges: &HashMap<usize, EncodedObjectRange<I>>,
+    areas: &HashMap<usize, Envelope>,
+) -> PlanNode<I> {
+    if ranges.is_empty() && areas.is_empty() {
+        return node;
+    }
+    if let PlanNode::QuadPatternJoin {
+        child,
+        subject,
+        predicate,
+        object,
+        graph_name,
+    } = node
+    {
+        let bound = child.maybe_bound_variables();
+        let restricted = match (subject, predicate, object) {
+            (PatternValue::Variable(s), PatternValue::Constant(_), PatternValue::Variable(o))
+                if s != o && !bound.contains(&s) && !bound.contains(&o) =>
+            {
+                Some(o)
+            }
+            _ => None,
+        };
+        let child = Rc::new(push_object_ranges(
+            Rc::try_unwrap(child).unwrap_or_else(|child| (*child).clone()),
+            ranges,
+            areas,
+        ));
+        if let Some(range) = restricted.and_then(|o| ranges.get(&o).copied()) {
+            PlanNode::QuadPatternRangeJoin {
+                child,
+                subject,
+                predicate,
+                object,
+                graph_name,
+                range,
+            }
+        } else if let Some(area) = restricted.and_then(|o| areas.get(&o).copied()) {
+            PlanNode::QuadPatternSpatialJoin {
+                child,
+                subject,
+                predicate,
+                object,
+                graph_name,
+                area,
+            }
+        } else {
+            PlanNode::QuadPatternJoin {
+                child,
+                subject,
+                predicate,
+                object,
+                graph_name,
+            }
+        }
+    } else {
+        node
+    }
+}
+
+fn is_bgp_plan<I: StrId>(node: &PlanNode<I>) -> bool {
+    match node {
+        PlanNode::Init => true,
+        PlanNode::QuadPatternJoin { child, .. }
+        | PlanNode::QuadPatternRangeJoin { child, .. }
+        | PlanNode::QuadPatternSpatialJoin { child, .. }
+        | PlanNode::TextMatch { child, .. }
+        | PlanNode::VectorNearest { child, .. }
+        | PlanNode::PathPatternJoin { child, .. } => is_bgp_plan(child),
+        _ => false,
+    }
+}
+
+/// Checks if evaluating the plan with some of the given variables already bound gives the same
+/// tuples as evaluating it alone and joining the result afterwards.
+fn is_independent_of<I: StrId>(node: &PlanNode<I>, variables: &BTreeSet<usize>) -> bool {
+    match node {
+        // These nodes do not read their input tuple
+        PlanNode::Init | PlanNode::StaticBindings { .. } | PlanNode::Aggregate { .. } => true,
+        // The projected variables bound in the input tuple are bound in the child
+        PlanNode::Project { child, mapping, .. } => {
+            let inner = mapping
+                .iter()
+                .filter(|(_, output_key)| variables.contains(output_key))
+                .map(|(input_key, _)| *input_key)
+                .collect();
+            is_independent_of(child, &inner)
+        }
+        PlanNode::QuadPatternJoin { child, .. }
+        | PlanNode::QuadPatternRangeJoin { child, .. }
+        | PlanNode::QuadPatternSpatialJoin { child, .. }
+        | PlanNode::TextMatch { child, .. }
+        | PlanNode::PathPatternJoin { child, .. }
+        | PlanNode::Sort { child, .. }
+        | PlanNode::HashDeduplicate { child } => is_independent_of(child, variables),
+        // The nearest quads are not the same when the query or the graph change
+        PlanNode::VectorNearest {
+            child,
+            subject,
+            query,
+            graph_name,
+            distance,
+            vector,
+            ..
+        } => {
+            let mut used: BTreeSet<usize> = distance.iter().chain(vector).copied().collect();
+            for value in [subject, query, graph_name].iter() {
+                if let PatternValue::Variable(var) = value {
+                    used.insert(*var);
+                }
+            }
+            used.is_disjoint(variables) && is_independent_of(child, variables)
+        }
+        PlanNode::Join { left, right } | PlanNode::HashJoin { left, right, .. } => {
+            is_independent_of(left, variables) && is_independent_of(right, variables)
+        }
+        PlanNode::Union { children } => children
+            .iter()
+            .all(|child| is_independent_of(child, variables)),
+        PlanNode::Filter { child, expression } => {
+            let mut used = BTreeSet::new();
+            expression.add_maybe_bound_variables(&mut used);
+            used.is_disjoint(variables) && is_independent_of(child, variables)
+        }
+        PlanNode::Extend {
+            child,
+            position,
+            expression,
+        } => {
+            let mut used = BTreeSet::new();
+            expression.add_maybe_bound_variables(&mut used);
+            used.insert(*position);
+            used.is_disjoint(variables) && is_independent_of(child, variables)
+        }
+        PlanNode::Service { .. }
+        | PlanNode::AntiJoin { .. }
+        | PlanNode::LeftJoin { .. }
+        | PlanNode::HashLeftJoin { .. }
+        | PlanNode::Skip { .. }
+        | PlanNode::Limit { .. } => false,
+    }
+}
+
+/// A `text:match` pattern of a basic graph pattern, see `PlanNode::TextMatch`
+struct TextMatchPattern {
+    subject: TermOrVariable,
+    predicate: Option<NamedNode>,
+    query: TextQuery,
+    score: Option<Variable>,
+    literal: Option<Variable>,
+}
+
+/// Splits the `text:match` patterns of a basic graph pattern, with the patterns of their lists, from the other
+/// patterns. Their arguments are `(subject ?score ?literal) text:match (predicate "query")`, the lists are optional
+/// and so are their items but the subject and the query.
+fn split_text_matches(
+    p: &[TriplePattern],
+) -> Result<(Vec<TextMatchPattern>, Vec<TriplePattern>), EvaluationError> {
+    let mut used = vec![false; p.len()];
+    let mut text_matches = Vec::new();
+    for (i, pattern) in p.iter().enumerate() {
+        match &pattern.predicate {
+            NamedNodeOrVariable::NamedNode(predicate) if predicate.as_str() == text::MATCH => (),
+            _ => continue,
+        }
+        used[i] = true;
+        let mut subjects = list_items(p, &pattern.subject, &mut used)
+            .unwrap_or_else(|| vec![pattern.subject.clone()])
+            .into_iter();
+        let mut arguments = list_items(p, &pattern.object, &mut used)
+            .unwrap_or_else(|| vec![pattern.object.clone()])
+            .into_iter()
+            .peekable();
+        let subject = subjects
+            .next()
+            .ok_or_else(|| EvaluationError::msg("text:match has no subject"))?;
+        let score = subjects.next().map(text_match_variable).transpose()?;
+        let literal = subjects.next().map(text_match_variable).transpose()?;
+        let predicate = match arguments.peek() {
+            Some(TermOrVariable::Term(Term::NamedNode(predicate))) => Some(predicate.clone()),
+            _ => None,
+        };
+        if predicate.is_some() {
+            arguments.next();
+        }
+        let query = match arguments.next() {
+            Some(TermOrVariable::Term(Term::Literal(query))) => TextQuery::parse(query.value())
+                .ok_or_else(|| EvaluationError::msg("The query of text:match has no token or a too short prefix"))?,
+            _ => return Err(EvaluationError::msg("text:match expects a literal query")),
+        };
+        if subjects.next().is_some() || arguments.next().is_some() {
+            return Err(EvaluationError::msg("text:match has too many arguments"));
+        }
+        text_matches.push(TextMatchPattern {
+            subject,
+            predicate,
+            query,
+            score,
+            literal,
+        });
+    }
+    let rest = p
+        .iter()
+        .zip(used)
+        .filter(|(_, used)| !used)
+        .map(|(pattern, _)| pattern.clone())
+        .collect();
+    Ok((text_matches, rest))
+}
+
+fn text_match_variable(item: TermOrVariable) -> Result<Variable, EvaluationError> {
+    match item {
+        TermOrVariable::Variable(variable) => Ok(variable),
+        _ => Err(EvaluationError::msg(
+            "The score and the literal of text:match must be variables",
+        )),
+    }
+}
+
+/// A `vec:nearest` pattern of a basic graph pattern, see `PlanNode::VectorNearest`
+struct VectorNearestPattern {
+    subject: TermOrVariable,
+    predicate: NamedNode,
+    query: TermOrVariable,
+    k: usize,
+    distance: Option<Variable>,
+    vector: Option<Variable>,
+}
+
+/// Number of nearest quads of `vec:nearest` when it is not given
+const DEFAULT_NEAREST_COUNT: usize = 10;
+
+/// Splits the `vec:nearest` patterns of a basic graph pattern, with the patterns of their lists, from the other
+/// patterns. Their arguments are `(subject ?distance ?vector) vec:nearest (predicate query k)`, the subject list is
+/// optional and so are its items but the subject, and so is `k`.
+fn split_vector_nearest(
+    p: &[TriplePattern],
+) -> Result<(Vec<VectorNearestPattern>, Vec<TriplePattern>), EvaluationError> {
+    let mut used = vec![false; p.len()];
+    let mut nearest = Vec::new();
+    for (i, pattern) in p.iter().enumerate() {
+        match &pattern.predicate {
+            NamedNodeOrVariable::NamedNode(predicate) if predicate.as_str() == hnsw::NEAREST => (),
+            _ => continue,
+        }
+        used[i] = true;
+        let mut subjects = list_items(p, &pattern.subject, &mut used)
+            .unwrap_or_else(|| vec![pattern.subject.clone()])
+            .into_iter();
+        let mut arguments = list_items(p, &pattern.object, &mut used)
+            .ok_or_else(|| EvaluationError::msg("vec:nearest expects a list of arguments"))?
+            .into_iter();
+        let subject = subjects
+            .next()
+            .ok_or_else(|| EvaluationError::msg("vec:nearest has no subject"))?;
+        let distance = subjects.next().map(vector_nearest_variable).transpose()?;
+        let vector = subjects.next().map(vector_nearest_variable).transpose()?;
+        let predicate = match arguments.next() {
+            Some(TermOrVariable::Term(Term::NamedNode(predicate))) => predicate,
+            _ => return Err(EvaluationError::msg("vec:nearest expects a predicate IRI")),
+        };
+        let query = match arguments.next() {
+            Some(TermOrVariable::Term(Term::Literal(query)))
+                if query.datatype() == vec::FLOAT_VECTOR && query.value().parse::<Vector>().is_ok() =>
+            {
+                TermOrVariable::Term(query.into())
+            }
+            Some(TermOrVariable::Variable(query)) => TermOrVariable::Variable(query),
+            _ => return Err(EvaluationError::msg("vec:nearest expects a vec:floatVector query")),
+        };
+        let k = match arguments.next() {
+            Some(TermOrVariable::Term(Term::Literal(k))) if k.datatype() == xsd::INTEGER => {
+                match k.value().parse::<usize>() {
+                    Ok(k) if k > 0 => k,
+                    _ => return Err(EvaluationError::msg("The count of vec:nearest must be positive")),
+                }
+            }
+            None => DEFAULT_NEAREST_COUNT,
+            _ => return Err(EvaluationError::msg("vec:nearest expects an integer count")),
+        };
+        if subjects.next().is_some() || arguments.next().is_some() {
+            return Err(EvaluationError::msg("vec:nearest has too many arguments"));
+        }
+        nearest.push(VectorNearestPattern {
+            subject,
+            predicate,
+            query,
+            k,
+            distance,
+            vector,
+        });
+    }
+    let rest = p
+        .iter()
+        .zip(used)
+        .filter(|(_, used)| !used)
+        .map(|(pattern, _)| pattern.clone())
+        .collect();
+    Ok((nearest, rest))
+}
+
+fn vector_nearest_variable(item: TermOrVariable) -> Result<Variable, EvaluationError> {
+    match item {
+        TermOrVariable::Variable(variable) => Ok(variable),
+        _ => Err(EvaluationError::msg(
+            "The distance and the vector of vec:nearest must be variables",
+        )),
+    }
+}
+
+/// The items of the list `head`, written with `rdf:first` and `rdf:rest` patterns of `p`, which are marked as
+/// `used`. `None` if `head` is not a list of the basic graph pattern.
+fn list_items(
+    p: &[TriplePattern],
+    head: &TermOrVariable,
+    used: &mut [bool],
+) -> Option<Vec<TermOrVariable>> {
+    let position = |node: &TermOrVariable, predicate: NamedNodeRef<'_>| {
+        p.iter().position(|pattern| {
+            pattern.subject == *node
+                && matches!(&pattern.predicate, NamedNodeOrVariable::NamedNode(n) if n.as_ref() == predicate)
+        })
+    };
+    let mut items = Vec::new();
+    let mut positions = Vec::new();
+    let mut node = head;
+    while let TermOrVariable::Term(Term::BlankNode(_)) = node {
+        let first = position(node, rdf::FIRST)?;
+        let rest = position(node, rdf::REST)?;
+        if positions.contains(&first) {
+            return None;
+        }
+        items.push(p[first].object.clone());
+        positions.push(first);
+        positions.push(rest);
+        node = &p[rest].object;
+    }
+    if items.is_empty() || *node != TermOrVariable::from(rdf::NIL.into_owned()) {
+        return None;
+    }
+    for position in positions {
+        used[position] = true;
+    }
+    Some(items)
+}
+
+fn check_arity(
+    parameters: &[Expression],
+    arity: usize,
+    name: &'static str,
+) -> Result<(), EvaluationError> {
+    if parameters.len() == arity {
+        Ok(())
+    } else {
+        Err(EvaluationError::msg(format!(
+            "The {} function takes {} parameters",
+            name, arity
+        )))
+    }
+}
+
 fn sort_bgp(p: &[TriplePattern]) -> Vec<&TriplePattern> {
     let mut assigned_variables = HashSet::default();
     let mut assigned_blank_nodes = HashSet::default();
@@ -1098,6 +2122,48 @@ fn sort_bgp(p: &[TriplePattern]) -> Vec<&TriplePattern> {
     new_p
 }
 
+struct PatternCardinality {
+    /// Number of quads matching the pattern constants
+    rows: f64,
+    /// If the object constant is already taken into account by `rows`
+    object_included: bool,
+}
+
+/// Estimates the number of rows of one evaluation of the pattern once the assigned variables are bound.
+fn estimate_pattern_rows(
+    pattern: &TriplePattern,
+    cardinality: &PatternCardinality,
+    assigned_variables: &HashSet<&Variable>,
+    assigned_blank_nodes: &HashSet<&BlankNode>,
+) -> f64 {
+    let subject_bound =
+        is_pattern_term_bound(&pattern.subject, assigned_variables, assigned_blank_nodes);
+    let object_bound =
+        is_pattern_term_bound(&pattern.object, assigned_variables, assigned_blank_nodes);
+    if cardinality.rows == 0. {
+        0.
+    } else if subject_bound && object_bound {
+        1.
+    } else if subject_bound || (object_bound && !cardinality.object_included) {
+        // We have no distinct counts: a bound subject or object is assumed to select sqrt(rows) quads
+        cardinality.rows.sqrt().max(1.)
+    } else {
+        cardinality.rows
+    }
+}
+
+fn is_pattern_term_bound(
+    term: &TermOrVariable,
+    assigned_variables: &HashSet<&Variable>,
+    assigned_blank_nodes: &HashSet<&BlankNode>,
+) -> bool {
+    match term {
+        TermOrVariable::Variable(v) => assigned_variables.contains(v),
+        TermOrVariable::Term(Term::BlankNode(bnode)) => assigned_blank_nodes.contains(bnode),
+        TermOrVariable::Term(_) => true,
+    }
+}
+
 fn count_pattern_binds(
     pattern: &TriplePattern,
     assigned_variables: &HashSet<&Variable>,
@@ -1155,3 +2221,88 @@ fn add_pattern_variables<'a>(
         blank_nodes.insert(bnode);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{new_test_ext, Test};
+    use crate::Pallet;
+
+    const DATA: &str = "INSERT DATA { :alice :knows :bob ; :likes :carol . :bob :age 30 . :carol :age 40 . \
+        :dave :height 50 }";
+
+    /// The line of the first node `node` of the plan of a query
+    fn plan_line(query: &str, node: &str) -> Option<String> {
+        let plan = Pallet::<Test>::explain_query(query).unwrap();
+        plan.lines().map(str::trim_start).find(|line| line.starts_with(node)).map(str::to_owned)
+    }
+
+    /// The solutions of a query, as the local names of their values separated by spaces
+    fn solutions(query: &str) -> Vec<String> {
+        let results: serde_json::Value = serde_json::from_str(&Pallet::<Test>::execute_query(query)).unwrap();
+        let variables = results["head"]["vars"].as_array().unwrap().clone();
+        let mut solutions: Vec<String> = results["results"]["bindings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|solution| {
+                let values: Vec<&str> = variables
+                    .iter()
+                    .map(|variable| match solution[variable.as_str().unwrap()]["value"].as_str() {
+                        Some(value) => value.rsplit('/').next().unwrap(),
+                        None => "-",
+                    })
+                    .collect();
+                values.join(" ")
+            })
+            .collect();
+        solutions.sort();
+        solutions
+    }
+
+    #[test]
+    fn the_plans_sharing_variables_are_hash_joined() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(DATA).unwrap();
+            let query = "SELECT ?b ?age WHERE { { :alice :knows ?b } UNION { :alice :likes ?b } { ?b :age ?age } }";
+            assert_eq!(plan_line(query, "HashJoin").unwrap(), "HashJoin ON ?b");
+            assert_eq!(solutions(query), ["bob 30", "carol 40"]);
+
+            // without a shared variable, every pair of tuples is joined
+            let query = "SELECT ?b ?n WHERE { { :alice :knows ?b } UNION { :alice :likes ?b } \
+                { SELECT (COUNT(*) AS ?n) WHERE { ?x :age ?y } } }";
+            assert!(plan_line(query, "HashJoin").is_none());
+            assert!(plan_line(query, "Join").is_some());
+            assert_eq!(solutions(query), ["bob 2", "carol 2"]);
+        });
+    }
+
+    #[test]
+    fn an_optional_pattern_independent_of_the_left_side_is_hash_joined() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(DATA).unwrap();
+            // the second left tuple does not bind ?b: it is compatible with every right tuple
+            let query = "SELECT ?x ?b ?age WHERE { { :alice :knows ?b } UNION { BIND(1 AS ?x) } \
+                OPTIONAL { { ?b :age ?age } UNION { ?b :height ?age } } }";
+            assert!(plan_line(query, "HashLeftJoin ON ?b").is_some());
+            assert_eq!(solutions(query), ["- bob 30", "1 bob 30", "1 carol 40", "1 dave 50"]);
+
+            // a filter on the right variables is evaluated on the joined tuples
+            let query = "SELECT ?b ?age WHERE { :alice :knows ?b \
+                OPTIONAL { { ?b :age ?age } UNION { ?b :height ?age } FILTER(?age > 35) } }";
+            assert!(plan_line(query, "HashLeftJoin").is_some());
+            assert_eq!(solutions(query), ["bob -"]);
+        });
+    }
+
+    #[test]
+    fn an_optional_filter_reading_the_left_side_is_not_hash_joined() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(DATA).unwrap();
+            let query = "SELECT ?b ?c ?age WHERE { :alice :knows ?b \
+                OPTIONAL { { ?c :age ?age } UNION { ?c :height ?age } FILTER(?c != ?b) } }";
+            assert!(plan_line(query, "HashLeftJoin").is_none());
+            assert!(plan_line(query, "LeftJoin").is_some());
+            assert_eq!(solutions(query), ["bob carol 40", "bob dave 50"]);
+        });
+    }
+}
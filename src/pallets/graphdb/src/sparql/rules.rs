@@ -0,0 +1,230 @@
+//! Evaluation of the inference rules written as SPARQL CONSTRUCT queries (see `crate::rules`).
+
+use crate::model::{GraphName, Term, Triple};
+use crate::sparql::algebra::{GraphPattern, NamedNodeOrVariable, Query, QueryDataset, TermOrVariable, TriplePattern};
+use crate::sparql::dataset::DatasetView;
+use crate::sparql::eval::SimpleEvaluator;
+use crate::sparql::model::{QueryResults, Variable};
+use crate::sparql::plan_builder::PlanBuilder;
+use crate::sparql::service::EmptyServiceHandler;
+use crate::sparql::{CustomFunctions, EvaluationError};
+use crate::store::ReadableEncodedStore;
+use oxiri::Iri;
+use std::rc::Rc;
+
+/// A rule: the triples of its template are entailed by each solution of its pattern
+pub(crate) struct Rule {
+    template: Vec<TriplePattern>,
+    pattern: GraphPattern,
+    dataset: QueryDataset,
+    base_iri: Option<Iri<String>>,
+}
+
+impl Rule {
+    /// Parses a rule from a CONSTRUCT query, its template must not have blank nodes as they would be
+    /// new ones at each evaluation
+    pub fn parse(query: &str) -> Result<Self, EvaluationError> {
+        match Query::parse(query, None)? {
+            Query::Construct {
+                template,
+                dataset,
+                pattern,
+                base_iri,
+            } => {
+                let has_blank_node = template.iter().any(|triple| {
+                    matches!(triple.subject, TermOrVariable::Term(Term::BlankNode(_)))
+                        || matches!(triple.object, TermOrVariable::Term(Term::BlankNode(_)))
+                });
+                if has_blank_node {
+                    return Err(EvaluationError::msg("The template of a rule must not have blank nodes"));
+                }
+                Ok(Self {
+                    template,
+                    pattern,
+                    dataset,
+                    base_iri,
+                })
+            }
+            _ => Err(EvaluationError::msg("A rule must be a CONSTRUCT query")),
+        }
+    }
+
+    /// The triples constructed by the rule, `default_graph` is its default graph if its query has no dataset.
+    ///
+    /// With `delta` only the solutions using at least one of its triples are constructed (semi-naive
+    /// evaluation), if the pattern of the rule is a join of basic graph patterns under filters, unions,
+    /// extensions and projections. The other patterns are evaluated in full.
+    pub fn evaluate<S: ReadableEncodedStore + 'static>(
+        &self,
+        store: S,
+        default_graph: &[GraphName],
+        delta: Option<&[Triple]>,
+    ) -> Result<Vec<Triple>, EvaluationError> {
+        let patterns = match delta.map(|delta| delta_patterns(&self.pattern, delta)) {
+            Some(Some(patterns)) => patterns,
+            _ => vec![self.pattern.clone()],
+        };
+        let mut dataset = self.dataset.clone();
+        if dataset.is_default_dataset() {
+            dataset.set_default_graph(default_graph.to_vec());
+        }
+        let dataset = Rc::new(DatasetView::new(store, &dataset)?);
+        let base_iri = self.base_iri.clone().map(Rc::new);
+        // the rules are evaluated after the updates, whatever their options
+        let custom_functions = Rc::new(CustomFunctions::default());
+        let mut triples = Vec::new();
+        for pattern in patterns {
+            let (plan, variables) = PlanBuilder::build(dataset.as_ref(), &pattern, None, &custom_functions)?;
+            let construct = PlanBuilder::build_graph_template(dataset.as_ref(), &self.template, variables)?;
+            let results = SimpleEvaluator::new(
+                dataset.clone(),
+                base_iri.clone(),
+                Rc::new(EmptyServiceHandler),
+                custom_functions.clone(),
+            )
+                .evaluate_construct_plan(&plan, construct)?;
+            if let QueryResults::Graph(iter) = results {
+                for triple in iter {
+                    triples.push(triple?);
+                }
+            }
+        }
+        Ok(triples)
+    }
+}
+
+/// The patterns whose solutions are the solutions of `pattern` with a triple of `delta`: one per triple pattern,
+/// matched against `delta` only. `None` if `pattern` is not monotonic in its triple patterns.
+fn delta_patterns(pattern: &GraphPattern, delta: &[Triple]) -> Option<Vec<GraphPattern>> {
+    Some(match pattern {
+        GraphPattern::BGP(triples) => triples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, triple)| {
+                let table = delta_table(triple, delta)?;
+                let rest = triples
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, triple)| triple.clone())
+                    .collect();
+                Some(GraphPattern::Join {
+                    left: Box::new(table),
+                    right: Box::new(GraphPattern::BGP(rest)),
+                })
+            })
+            .collect(),
+        GraphPattern::Join { left, right } => {
+            let mut patterns = Vec::new();
+            for left in delta_patterns(left, delta)? {
+                patterns.push(GraphPattern::Join {
+                    left: Box::new(left),
+                    right: right.clone(),
+                });
+            }
+            for right in delta_patterns(right, delta)? {
+                patterns.push(GraphPattern::Join {
+                    left: left.clone(),
+                    right: Box::new(right),
+                });
+            }
+            patterns
+        }
+        GraphPattern::Union { left, right } => {
+            let mut patterns = delta_patterns(left, delta)?;
+            patterns.extend(delta_patterns(right, delta)?);
+            patterns
+        }
+        GraphPattern::Filter { expr, inner } => delta_patterns(inner, delta)?
+            .into_iter()
+            .map(|inner| GraphPattern::Filter {
+                expr: expr.clone(),
+                inner: Box::new(inner),
+            })
+            .collect(),
+        GraphPattern::Extend { inner, var, expr } => delta_patterns(inner, delta)?
+            .into_iter()
+            .map(|inner| GraphPattern::Extend {
+                inner: Box::new(inner),
+                var: var.clone(),
+                expr: expr.clone(),
+            })
+            .collect(),
+        GraphPattern::Project { inner, projection } => delta_patterns(inner, delta)?
+            .into_iter()
+            .map(|inner| GraphPattern::Project {
+                inner: Box::new(inner),
+                projection: projection.clone(),
+            })
+            .collect(),
+        GraphPattern::Distinct { inner } => delta_patterns(inner, delta)?
+            .into_iter()
+            .map(|inner| GraphPattern::Distinct { inner: Box::new(inner) })
+            .collect(),
+        GraphPattern::Reduced { inner } => delta_patterns(inner, delta)?
+            .into_iter()
+            .map(|inner| GraphPattern::Reduced { inner: Box::new(inner) })
+            .collect(),
+        GraphPattern::Table { .. } => Vec::new(),
+        _ => return None,
+    })
+}
+
+/// The solutions of a triple pattern in `delta` as a table, `None` if there are none
+fn delta_table(pattern: &TriplePattern, delta: &[Triple]) -> Option<GraphPattern> {
+    let positions = [
+        term_or_variable(&pattern.subject),
+        match &pattern.predicate {
+            NamedNodeOrVariable::NamedNode(node) => Ok(node.clone().into()),
+            NamedNodeOrVariable::Variable(v) => Err(v.clone()),
+        },
+        term_or_variable(&pattern.object),
+    ];
+    let mut variables = Vec::<Variable>::new();
+    for position in positions.iter() {
+        if let Err(v) = position {
+            if !variables.contains(v) {
+                variables.push(v.clone());
+            }
+        }
+    }
+    let rows = delta
+        .iter()
+        .filter_map(|triple| {
+            let terms = [
+                Term::from(triple.subject.clone()),
+                triple.predicate.clone().into(),
+                triple.object.clone(),
+            ];
+            let mut row = vec![None; variables.len()];
+            for (position, term) in positions.iter().zip(terms.iter()) {
+                match position {
+                    Ok(constant) if constant != term => return None,
+                    Ok(_) => (),
+                    Err(v) => {
+                        let value = &mut row[variables.iter().position(|variable| variable == v)?];
+                        match value {
+                            Some(bound) if bound != term => return None,
+                            _ => *value = Some(term.clone()),
+                        }
+                    }
+                }
+            }
+            Some(row)
+        })
+        .collect::<Vec<_>>();
+    if rows.is_empty() {
+        None
+    } else {
+        Some(GraphPattern::Table { variables, rows })
+    }
+}
+
+fn term_or_variable(term: &TermOrVariable) -> Result<Term, Variable> {
+    match term {
+        TermOrVariable::Variable(v) => Err(v.clone()),
+        // the plan builder matches the blank nodes of the patterns as variables with the same name
+        TermOrVariable::Term(Term::BlankNode(bnode)) => Err(Variable::new_unchecked(bnode.as_str())),
+        TermOrVariable::Term(term) => Ok(term.clone()),
+    }
+}
@@ -6,6 +6,7 @@ use crate::sparql::algebra::{
     QueryDataset, TermOrVariable,
 };
 use crate::sparql::dataset::{DatasetStrId, DatasetView};
+use crate::sparql::entailment::RdfsSchema;
 use crate::sparql::eval::SimpleEvaluator;
 use crate::sparql::http::Client;
 use crate::sparql::plan::EncodedTuple;
@@ -114,16 +115,27 @@ where
         algebra: &GraphPattern,
     ) -> Result<(), EvaluationError> {
         let dataset = Rc::new(DatasetView::new(self.read.clone(), using)?);
-        let (plan, variables) = PlanBuilder::build(dataset.as_ref(), algebra)?;
+        let schema = RdfsSchema::load(self.options.query_options.entailment, dataset.as_ref())?;
+        let (plan, variables) = PlanBuilder::build(
+            dataset.as_ref(),
+            algebra,
+            schema,
+            &self.options.query_options.custom_functions,
+        )?;
         let evaluator = SimpleEvaluator::<DatasetView<R>>::new(
             dataset.clone(),
             self.base_iri.clone(),
             self.options.query_options.service_handler.clone(),
+            self.options.query_options.custom_functions.clone(),
         );
         let mut bnodes = HashMap::new();
-        for tuple in evaluator.eval_plan(&plan, EncodedTuple::with_capacity(variables.len())) {
+        // The store is read lazily: all the solutions are computed before it is modified
+        let tuples = evaluator
+            .eval_plan(&plan, EncodedTuple::with_capacity(variables.len()))
+            .collect::<Result<Vec<_>, _>>()?;
+        for tuple in tuples {
             // We map the tuple to only get store strings
-            let tuple = tuple?
+            let tuple = tuple
                 .into_iter()
                 .map(|t| {
                     Ok(if let Some(t) = t {
@@ -286,18 +298,18 @@ where
                 .map_err(to_eval_error),
             GraphTarget::NamedGraphs => {
                 // TODO: optimize?
-                for graph in self.read.encoded_named_graphs() {
+                for graph in self.named_graphs()? {
                     self.write
-                        .clear_encoded_graph(graph.map_err(to_eval_error)?)
+                        .clear_encoded_graph(graph)
                         .map_err(to_eval_error)?;
                 }
                 Ok(())
             }
             GraphTarget::AllGraphs => {
                 // TODO: optimize?
-                for graph in self.read.encoded_named_graphs() {
+                for graph in self.named_graphs()? {
                     self.write
-                        .clear_encoded_graph(graph.map_err(to_eval_error)?)
+                        .clear_encoded_graph(graph)
                         .map_err(to_eval_error)?;
                 }
                 self.write
@@ -307,6 +319,14 @@ where
         }
     }
 
+    /// The named graphs of the store, collected before they are modified
+    fn named_graphs(&self) -> Result<Vec<EncodedTerm<R::StrId>>, EvaluationError> {
+        self.read
+            .encoded_named_graphs()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(to_eval_error)
+    }
+
     fn eval_drop(&mut self, graph: &GraphTarget, silent: bool) -> Result<(), EvaluationError> {
         match graph {
             GraphTarget::NamedNode(graph_name) => {
@@ -341,9 +361,9 @@ where
                 .map_err(to_eval_error),
             GraphTarget::NamedGraphs => {
                 // TODO: optimize?
-                for graph in self.read.encoded_named_graphs() {
+                for graph in self.named_graphs()? {
                     self.write
-                        .remove_encoded_named_graph(graph.map_err(to_eval_error)?)
+                        .remove_encoded_named_graph(graph)
                         .map_err(to_eval_error)?;
                 }
                 Ok(())
@@ -0,0 +1,263 @@
+//! Spatial index of the `geo:wktLiteral` geometries of the default graph.
+//!
+//! The index is a quadtree over the longitudes and latitudes: the cell of level `l` is one of the `4^l` tiles
+//! of the world, and each geometry is indexed in the smallest cell containing its envelope. A cell is keyed by the
+//! Morton code of its lower corner at `MAX_LEVEL` then by its level, so that the cells inside a cell are stored
+//! right after it and are read with a single range scan. `SpatialIndexStore` maps the cell of each geometry
+//! object of the default graph to its quads, it is kept up to date with the index entries of the quads when
+//! `QuadIndexes::SPATIAL` is maintained.
+//!
+//! The queries read it for the filters on the GeoSPARQL functions (see `crate::sparql::plan_builder`): with
+//! `FILTER(geof:distance(?location, "POINT(2.35 48.85)"^^geo:wktLiteral, uom:metre) < 5000)`, only the geometries
+//! in the cells intersecting the envelope of the 5 km around the point are read, the filter then checks them.
+//! The geometries of the named graphs are not indexed, they are scanned.
+
+use std::collections::BTreeSet;
+
+use frame_support::storage::StoragePrefixedMap;
+
+use crate::{decode_term_id, Config, Id2StrStore, SpatialIndexStore};
+use crate::error::StoreError;
+use crate::meter::Accesses;
+use crate::model::geo::{Envelope, Geometry};
+use crate::store::codec::{EncodedQuad, EncodedTerm};
+
+/// Depth of the quadtree: the cells of the last level are about 2.4 m wide
+pub const MAX_LEVEL: u8 = 24;
+
+/// Most cells of a level read by a lookup, the lookups of large areas read shallower levels
+const MAX_LOOKUP_CELLS: u64 = 16;
+
+/// The Morton code (big endian) of the lower corner of a cell at `MAX_LEVEL`, then the level of the cell
+pub type CellKey = [u8; 9];
+
+/// A cell of the quadtree: its level and its column and row at this level
+#[derive(Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy)]
+struct Cell {
+    level: u8,
+    x: u32,
+    y: u32,
+}
+
+impl Cell {
+    fn parent(self) -> Self {
+        Self { level: self.level - 1, x: self.x >> 1, y: self.y >> 1 }
+    }
+
+    /// The first Morton code at `MAX_LEVEL` inside the cell
+    fn start(self) -> u64 {
+        let shift = MAX_LEVEL - self.level;
+        morton(self.x << shift, self.y << shift)
+    }
+
+    /// The last Morton code at `MAX_LEVEL` inside the cell
+    fn end(self) -> u64 {
+        self.start() + (1 << (2 * u32::from(MAX_LEVEL - self.level))) - 1
+    }
+
+    fn key(self) -> CellKey {
+        let mut key = [0; 9];
+        key[..8].copy_from_slice(&self.start().to_be_bytes());
+        key[8] = self.level;
+        key
+    }
+}
+
+/// The column of a longitude or the row of a latitude at `MAX_LEVEL`
+fn grid(value: f64, min: f64, span: f64) -> u32 {
+    let size = f64::from(1_u32 << MAX_LEVEL);
+    ((value - min) / span * size).clamp(0., size - 1.) as u32
+}
+
+/// The columns and rows of an envelope at `MAX_LEVEL`: `(min x, min y, max x, max y)`
+fn grid_bounds(envelope: &Envelope) -> (u32, u32, u32, u32) {
+    (
+        grid(envelope.min_x, -180., 360.),
+        grid(envelope.min_y, -90., 180.),
+        grid(envelope.max_x, -180., 360.),
+        grid(envelope.max_y, -90., 180.),
+    )
+}
+
+/// Interleaves the bits of a column (even bits) and of a row (odd bits)
+fn morton(x: u32, y: u32) -> u64 {
+    (0..MAX_LEVEL).fold(0, |code, bit| {
+        code | (u64::from(x >> bit & 1) << (2 * bit)) | (u64::from(y >> bit & 1) << (2 * bit + 1))
+    })
+}
+
+/// The smallest cell containing an envelope
+fn covering_cell(envelope: &Envelope) -> Cell {
+    let (min_x, min_y, max_x, max_y) = grid_bounds(envelope);
+    let mut level = MAX_LEVEL;
+    let shift = |level: u8| MAX_LEVEL - level;
+    while level > 0
+        && (min_x >> shift(level) != max_x >> shift(level) || min_y >> shift(level) != max_y >> shift(level))
+    {
+        level -= 1;
+    }
+    Cell { level, x: min_x >> shift(level), y: min_y >> shift(level) }
+}
+
+/// The cells intersecting an envelope at the deepest level where there are at most `MAX_LOOKUP_CELLS` of them
+fn lookup_cells(envelope: &Envelope) -> Vec<Cell> {
+    let (min_x, min_y, max_x, max_y) = grid_bounds(envelope);
+    let mut level = MAX_LEVEL;
+    loop {
+        let shift = MAX_LEVEL - level;
+        let (x0, y0, x1, y1) = (min_x >> shift, min_y >> shift, max_x >> shift, max_y >> shift);
+        if level == 0 || u64::from(x1 - x0 + 1) * u64::from(y1 - y0 + 1) <= MAX_LOOKUP_CELLS {
+            return (x0..=x1).flat_map(|x| (y0..=y1).map(move |y| Cell { level, x, y })).collect();
+        }
+        level -= 1;
+    }
+}
+
+/// Writes the entry of the geometry object of a quad inserted in the default graph, returns the reads and writes
+/// it did
+pub(crate) fn index<T: Config>(quad: &EncodedQuad) -> Result<Accesses, StoreError> {
+    if let Some(key) = cell_key::<T>(quad)? {
+        let (o, s, p) = (quad.object.term_id(), quad.subject.term_id(), quad.predicate.term_id());
+        <SpatialIndexStore<T>>::insert((key, o, s, p), ());
+        return Ok((object_reads(quad.object), 1));
+    }
+    Ok((0, 0))
+}
+
+/// Removes the entry of the geometry object of a quad removed from the default graph, returns the reads and
+/// writes it did
+pub(crate) fn unindex<T: Config>(quad: &EncodedQuad) -> Result<Accesses, StoreError> {
+    if let Some(key) = cell_key::<T>(quad)? {
+        let (o, s, p) = (quad.object.term_id(), quad.subject.term_id(), quad.predicate.term_id());
+        <SpatialIndexStore<T>>::remove((key, o, s, p));
+        return Ok((object_reads(quad.object), 1));
+    }
+    Ok((0, 0))
+}
+
+/// The reads of the text of a geometry: its value when it is not inlined in the term
+pub(crate) fn object_reads(object: EncodedTerm) -> u64 {
+    match object {
+        EncodedTerm::BigWktLiteral { .. } => 1,
+        _ => 0,
+    }
+}
+
+/// The key of the cell of the object of a quad, `None` if it is not a geometry of the default graph
+fn cell_key<T: Config>(quad: &EncodedQuad) -> Result<Option<CellKey>, StoreError> {
+    if quad.graph_name.is_default_graph() {
+        object_cell_key::<T>(quad.object)
+    } else {
+        Ok(None)
+    }
+}
+
+/// The key of the cell of a geometry, `None` if the term is not a geometry
+pub(crate) fn object_cell_key<T: Config>(object: EncodedTerm) -> Result<Option<CellKey>, StoreError> {
+    let text = match object {
+        EncodedTerm::SmallWktLiteral(value) => String::from(value),
+        EncodedTerm::BigWktLiteral { value_id } => {
+            let value = <Id2StrStore<T>>::get(*value_id).ok_or(StoreError::MissingStr { hash: *value_id })?;
+            String::from_utf8(value.into_inner()).map_err(|_| StoreError::InvalidUtf8 { hash: *value_id })?
+        }
+        _ => return Ok(None),
+    };
+    // the literals are encoded as geometries only when they are valid
+    Ok(text.parse::<Geometry>().ok().map(|geometry| covering_cell(&geometry.envelope()).key()))
+}
+
+/// The quads of the default graph, with `predicate` if set, whose geometry object is in a cell intersecting `area`.
+/// The cells are larger than the geometries: the geometries are to be checked by the filter.
+pub(crate) fn quads<T: Config>(
+    area: &Envelope,
+    predicate: Option<EncodedTerm>,
+) -> impl Iterator<Item = Result<EncodedQuad, StoreError>> {
+    let p = predicate.map(|predicate| predicate.term_id());
+    let cells = lookup_cells(area);
+    let mut found = BTreeSet::new();
+    let mut ancestors = BTreeSet::new();
+    for cell in &cells {
+        // the cell and the cells inside it
+        let end = cell.end();
+        let mut start_key = <SpatialIndexStore<T>>::final_prefix().to_vec();
+        start_key.extend_from_slice(&cell.start().to_be_bytes());
+        found.extend(
+            <SpatialIndexStore<T>>::iter_from(start_key)
+                .map(|(key, _)| key)
+                .take_while(|(key, ..)| u64::from_be_bytes(code(key)) <= end)
+                .map(|(_, o, s, key_p)| (o, s, key_p)),
+        );
+        let mut ancestor = *cell;
+        while ancestor.level > 0 {
+            ancestor = ancestor.parent();
+            ancestors.insert(ancestor);
+        }
+    }
+    // the cells containing them
+    for ancestor in ancestors {
+        found.extend(<SpatialIndexStore<T>>::iter_key_prefix((ancestor.key(),)));
+    }
+    found
+        .into_iter()
+        .filter(move |(_, _, key_p)| match &p {
+            Some(p) => key_p == p,
+            None => true,
+        })
+        .map(|(o, s, p)| {
+            Ok(EncodedQuad::new(
+                decode_term_id::<T>(&s)?,
+                decode_term_id::<T>(&p)?,
+                decode_term_id::<T>(&o)?,
+                EncodedTerm::DefaultGraph,
+            ))
+        })
+}
+
+fn code(key: &CellKey) -> [u8; 8] {
+    let mut code = [0; 8];
+    code.copy_from_slice(&key[..8]);
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{new_test_ext, Graphdb, Origin, Test};
+    use crate::Pallet;
+
+    fn envelope(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Envelope {
+        Envelope { min_x, min_y, max_x, max_y }
+    }
+
+    #[test]
+    fn cells_nest() {
+        let point = covering_cell(&envelope(2.35, 48.85, 2.35, 48.85));
+        assert_eq!(point.level, MAX_LEVEL);
+        let city = covering_cell(&envelope(2.2, 48.8, 2.5, 48.9));
+        assert!(city.level < MAX_LEVEL);
+        assert!(city.start() <= point.start() && point.end() <= city.end());
+        assert!(city.key() < point.key());
+        // an envelope across the equator and the prime meridian is only in the world cell
+        assert_eq!(covering_cell(&envelope(-1., -1., 1., 1.)).level, 0);
+
+        let cells = lookup_cells(&envelope(2.2, 48.8, 2.5, 48.9));
+        assert!(cells.len() as u64 <= MAX_LOOKUP_CELLS);
+        assert!(cells.iter().any(|cell| cell.start() <= point.start() && point.end() <= cell.end()));
+        assert_eq!(lookup_cells(&envelope(-180., -90., 180., 90.)).len() as u64, MAX_LOOKUP_CELLS);
+    }
+
+    #[test]
+    fn an_insertion_is_charged_for_its_cell_entry() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :z :at :y }").unwrap();
+            let update = |update: &str| {
+                let update = update.as_bytes().to_vec();
+                Graphdb::sparql_update(Origin::signed(1), update).unwrap().actual_weight.unwrap()
+            };
+            let unindexed = update("INSERT DATA { :a :at :x }");
+            let indexed = update("INSERT DATA { :b :at \"POINT(2.35 48.85)\"^^geo:wktLiteral }");
+            let write = <Test as frame_system::Config>::DbWeight::get().writes(1);
+            assert!(indexed >= unindexed + write, "{} < {} + {}", indexed, unindexed, write);
+        });
+    }
+}
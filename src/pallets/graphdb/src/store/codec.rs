@@ -2,7 +2,7 @@ use std::io;
 use std::io::{Cursor, Read};
 use std::mem::size_of;
 
-use crate::error::invalid_data_error;
+use crate::error::{invalid_data_error, StoreError};
 use crate::model::xsd::*;
 use crate::store::small_string::SmallString;
 use crate::StrHash;
@@ -12,11 +12,25 @@ pub(crate) type EncodedQuad = crate::store::numeric_encoder::EncodedQuad<StrHash
 
 const WRITTEN_TERM_MAX_SIZE: usize = size_of::<u8>() + 2 * size_of::<StrHash>();
 
+/// Version of the term encoding, stored with the data (see `CodecVersionStore`).
+///
+/// 1: named nodes referenced by the hash of their IRI
+/// 2: named nodes starting with a namespace encoded as a prefix id and a local name
+/// 3: `geo:wktLiteral` literals encoded with the canonical text of their geometry instead of as typed literals
+/// 4: `vec:floatVector` literals encoded with the canonical text of their components instead of as typed literals
+/// 5: strings hashed with `blake2_128` instead of the `SipHash` keyed by the runtime
+///
+/// The terms of all the versions are still decoded: a change of the encoding bumps the version and
+/// the keys written with an older one are re-encoded by `migrations::ReEncodeTerms`.
+/// The type bytes of a version are not reused by the following ones.
+pub const CODEC_VERSION: u8 = 5;
+
 // Encoded term type blocks
 // 1-7: usual named nodes (except prefixes c.f. later)
 // 8-15: blank nodes
 // 16-47: literals
-// 48-64: future use
+// 48: terms referenced by hash in term ids (c.f. `TermId`)
+// 49-63: literals
 // 64-127: default named node prefixes
 // 128-255: custom named node prefixes
 const TYPE_NAMED_NODE_ID: u8 = 1;
@@ -48,6 +62,26 @@ const TYPE_G_MONTH_LITERAL: u8 = 41;
 const TYPE_DURATION_LITERAL: u8 = 42;
 const TYPE_YEAR_MONTH_DURATION_LITERAL: u8 = 43;
 const TYPE_DAY_TIME_DURATION_LITERAL: u8 = 44;
+const TYPE_SMALL_WKT_LITERAL: u8 = 45;
+const TYPE_BIG_WKT_LITERAL: u8 = 46;
+const TYPE_SMALL_VECTOR_LITERAL: u8 = 47;
+const TYPE_BIG_VECTOR_LITERAL: u8 = 49;
+// Term ids: terms too long to be inlined are referenced by the hash of their encoding
+const TYPE_TERM_REF: u8 = 48;
+// Prefixed named nodes: the type is the prefix id (c.f. `Namespaces`), followed by the kind of local name
+const FIRST_PREFIX_ID: u8 = 64;
+const LOCAL_NAME_SMALL: u8 = 0;
+const LOCAL_NAME_BIG: u8 = 1;
+
+/// Size of the term ids used as storage keys: the largest inline literal (dates and times)
+pub const TERM_ID_SIZE: usize = 19;
+
+/// Fixed-width id of a term in the storage indexes.
+///
+/// The encoding of the term padded with zeros, or `TYPE_TERM_REF` followed by the hash of the
+/// encoding when it does not fit (language-tagged strings, custom datatypes, durations).
+/// The default graph is all zeros.
+pub type TermId = [u8; TERM_ID_SIZE];
 
 
 impl EncodedTerm {
@@ -55,10 +89,126 @@ impl EncodedTerm {
         encode_term(self)
     }
 
-    pub fn from_bytes(data: &[u8]) -> Self {
-        // TODO: handle unwrap
-        decode_term(data).unwrap()
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StoreError> {
+        decode_term(data).map_err(|error| StoreError::CorruptedTerm { msg: error.to_string() })
+    }
+
+    /// Returns the id of the term and, if the id is a reference, the encoding it references.
+    pub fn to_term_id(self) -> (TermId, Option<(StrHash, Vec<u8>)>) {
+        let bytes = self.to_bytes();
+        let mut id = [0; TERM_ID_SIZE];
+        if bytes.len() <= TERM_ID_SIZE {
+            id[..bytes.len()].copy_from_slice(&bytes);
+            (id, None)
+        } else {
+            let hash = StrHash::from_bytes(&bytes);
+            id[0] = TYPE_TERM_REF;
+            id[1..17].copy_from_slice(&hash.to_be_bytes());
+            (id, Some((hash, bytes)))
+        }
+    }
+
+    /// Decodes a term id, the referenced encoding is given by `resolve`.
+    pub fn from_term_id(
+        id: &TermId,
+        resolve: impl FnOnce(StrHash) -> Result<Vec<u8>, StoreError>,
+    ) -> Result<Self, StoreError> {
+        if id[0] == TYPE_TERM_REF {
+            let mut hash = [0; 16];
+            hash.copy_from_slice(&id[1..17]);
+            Self::from_bytes(&resolve(StrHash::from_be_bytes(hash))?)
+        } else if id[0] == 0 {
+            Ok(EncodedTerm::DefaultGraph)
+        } else {
+            Self::from_bytes(id)
+        }
+    }
+
+    /// Key of the ordered literal index: a type tag followed by the value as a sortable `f64`.
+    ///
+    /// All numeric types share the same tag so that they are compared together like in SPARQL.
+    /// Returns `None` for terms that are not numeric or date/time literals, and for NaN.
+    pub fn to_ordered_key(self) -> Option<OrderedKey> {
+        match self {
+            EncodedTerm::FloatLiteral(value) => ordered_key(TYPE_DOUBLE_LITERAL, value.into()),
+            EncodedTerm::DoubleLiteral(value) => ordered_key(TYPE_DOUBLE_LITERAL, value),
+            EncodedTerm::IntegerLiteral(value) => ordered_key(TYPE_DOUBLE_LITERAL, value as f64),
+            EncodedTerm::DecimalLiteral(value) => ordered_key(TYPE_DOUBLE_LITERAL, value.to_f64()),
+            EncodedTerm::DateTimeLiteral(value) => {
+                ordered_key(TYPE_DATE_TIME_LITERAL, value.timestamp_seconds().to_f64())
+            }
+            EncodedTerm::TimeLiteral(value) => {
+                ordered_key(TYPE_TIME_LITERAL, value.timestamp_seconds().to_f64())
+            }
+            EncodedTerm::DateLiteral(value) => {
+                ordered_key(TYPE_DATE_LITERAL, value.timestamp_seconds().to_f64())
+            }
+            _ => None,
+        }
+    }
+
+    /// Same as `to_ordered_key` but widened so that the key range covers every value
+    /// that may compare lower (`upper == false`) or greater (`upper == true`) than this term.
+    ///
+    /// Date/time values without timezone are only ordered against the others up to 14 hours.
+    pub fn to_ordered_key_bound(self, upper: bool) -> Option<OrderedKey> {
+        let key = self.to_ordered_key()?;
+        if key[0] == TYPE_DOUBLE_LITERAL {
+            return Some(key);
+        }
+        let value = ordered_value(key);
+        let fuzz = if upper {
+            TIMEZONE_FUZZ_SECONDS
+        } else {
+            -TIMEZONE_FUZZ_SECONDS
+        };
+        ordered_key(key[0], value + fuzz)
+    }
+}
+
+/// Size of the keys of the ordered literal index
+pub const ORDERED_KEY_SIZE: usize = 9;
+
+pub type OrderedKey = [u8; ORDERED_KEY_SIZE];
+
+const TIMEZONE_FUZZ_SECONDS: f64 = 14. * 3600.;
+
+/// Lowest and highest keys sharing the type tag of `key`
+pub fn ordered_key_type_range(key: OrderedKey) -> (OrderedKey, OrderedKey) {
+    let mut lower = [0; ORDERED_KEY_SIZE];
+    let mut upper = [u8::MAX; ORDERED_KEY_SIZE];
+    lower[0] = key[0];
+    upper[0] = key[0];
+    (lower, upper)
+}
+
+fn ordered_key(tag: u8, value: f64) -> Option<OrderedKey> {
+    if value.is_nan() {
+        return None;
     }
+    // -0 and 0 are equal
+    let value = if value == 0. { 0. } else { value };
+    let bits = value.to_bits();
+    let bits = if value.is_sign_negative() {
+        !bits
+    } else {
+        bits ^ (1 << 63)
+    };
+    let mut key = [0; ORDERED_KEY_SIZE];
+    key[0] = tag;
+    key[1..].copy_from_slice(&bits.to_be_bytes());
+    Some(key)
+}
+
+fn ordered_value(key: OrderedKey) -> f64 {
+    let mut bits = [0; 8];
+    bits.copy_from_slice(&key[1..]);
+    let bits = u64::from_be_bytes(bits);
+    f64::from_bits(if bits >> 63 == 1 {
+        bits ^ (1 << 63)
+    } else {
+        !bits
+    })
 }
 
 pub fn encode_term(t: EncodedTerm) -> Vec<u8> {
@@ -98,6 +248,16 @@ fn write_term(sink: &mut Vec<u8>, term: EncodedTerm) {
             sink.push(TYPE_NAMED_NODE_ID);
             sink.extend_from_slice(&iri_id.to_be_bytes());
         }
+        EncodedTerm::SmallPrefixedNamedNode { prefix, local } => {
+            sink.push(prefix);
+            sink.push(LOCAL_NAME_SMALL);
+            sink.extend_from_slice(&local.to_be_bytes());
+        }
+        EncodedTerm::BigPrefixedNamedNode { prefix, local_id } => {
+            sink.push(prefix);
+            sink.push(LOCAL_NAME_BIG);
+            sink.extend_from_slice(&local_id.to_be_bytes());
+        }
         EncodedTerm::NumericalBlankNode { id } => {
             sink.push(TYPE_NUMERICAL_BLANK_NODE_ID);
             sink.extend_from_slice(&id.to_be_bytes())
@@ -216,6 +376,22 @@ fn write_term(sink: &mut Vec<u8>, term: EncodedTerm) {
             sink.push(TYPE_DAY_TIME_DURATION_LITERAL);
             sink.extend_from_slice(&value.to_be_bytes())
         }
+        EncodedTerm::SmallWktLiteral(value) => {
+            sink.push(TYPE_SMALL_WKT_LITERAL);
+            sink.extend_from_slice(&value.to_be_bytes())
+        }
+        EncodedTerm::BigWktLiteral { value_id } => {
+            sink.push(TYPE_BIG_WKT_LITERAL);
+            sink.extend_from_slice(&value_id.to_be_bytes());
+        }
+        EncodedTerm::SmallVectorLiteral(value) => {
+            sink.push(TYPE_SMALL_VECTOR_LITERAL);
+            sink.extend_from_slice(&value.to_be_bytes())
+        }
+        EncodedTerm::BigVectorLiteral { value_id } => {
+            sink.push(TYPE_BIG_VECTOR_LITERAL);
+            sink.extend_from_slice(&value_id.to_be_bytes());
+        }
     }
 }
 
@@ -425,7 +601,126 @@ impl<R: Read> TermReader for R {
                     DayTimeDuration::from_be_bytes(buffer),
                 ))
             }
+            TYPE_SMALL_WKT_LITERAL => {
+                let mut buffer = [0; 16];
+                self.read_exact(&mut buffer)?;
+                Ok(EncodedTerm::SmallWktLiteral(
+                    SmallString::from_be_bytes(buffer).map_err(invalid_data_error)?,
+                ))
+            }
+            TYPE_BIG_WKT_LITERAL => {
+                let mut buffer = [0; 16];
+                self.read_exact(&mut buffer)?;
+                Ok(EncodedTerm::BigWktLiteral {
+                    value_id: StrHash::from_be_bytes(buffer),
+                })
+            }
+            TYPE_SMALL_VECTOR_LITERAL => {
+                let mut buffer = [0; 16];
+                self.read_exact(&mut buffer)?;
+                Ok(EncodedTerm::SmallVectorLiteral(
+                    SmallString::from_be_bytes(buffer).map_err(invalid_data_error)?,
+                ))
+            }
+            TYPE_BIG_VECTOR_LITERAL => {
+                let mut buffer = [0; 16];
+                self.read_exact(&mut buffer)?;
+                Ok(EncodedTerm::BigVectorLiteral {
+                    value_id: StrHash::from_be_bytes(buffer),
+                })
+            }
+            prefix if prefix >= FIRST_PREFIX_ID => {
+                let mut kind_buffer = [0];
+                self.read_exact(&mut kind_buffer)?;
+                let mut buffer = [0; 16];
+                self.read_exact(&mut buffer)?;
+                match kind_buffer[0] {
+                    LOCAL_NAME_SMALL => Ok(EncodedTerm::SmallPrefixedNamedNode {
+                        prefix,
+                        local: SmallString::from_be_bytes(buffer).map_err(invalid_data_error)?,
+                    }),
+                    LOCAL_NAME_BIG => Ok(EncodedTerm::BigPrefixedNamedNode {
+                        prefix,
+                        local_id: StrHash::from_be_bytes(buffer),
+                    }),
+                    _ => Err(invalid_data_error("the term buffer has an invalid local name kind")),
+                }
+            }
             _ => Err(invalid_data_error("the term buffer has an invalid type id")),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn ordered_keys_follow_values() {
+        let values = [
+            EncodedTerm::DoubleLiteral(f64::NEG_INFINITY),
+            EncodedTerm::IntegerLiteral(-10),
+            EncodedTerm::DecimalLiteral(Decimal::from(-1)),
+            EncodedTerm::FloatLiteral(-0.5),
+            EncodedTerm::DoubleLiteral(-0.),
+            EncodedTerm::IntegerLiteral(0),
+            EncodedTerm::DecimalLiteral("0.5".parse().unwrap()),
+            EncodedTerm::IntegerLiteral(1),
+            EncodedTerm::DoubleLiteral(1e10),
+        ];
+        let keys = values
+            .iter()
+            .map(|t| t.to_ordered_key().unwrap())
+            .collect::<Vec<_>>();
+        for (a, b) in keys.iter().zip(&keys[1..]) {
+            assert!(a <= b);
+        }
+        assert_eq!(keys[4], keys[5]);
+        assert_eq!(EncodedTerm::DoubleLiteral(f64::NAN).to_ordered_key(), None);
+        assert_eq!(EncodedTerm::BooleanLiteral(true).to_ordered_key(), None);
+
+        let date = EncodedTerm::DateLiteral("2020-01-01".parse().unwrap());
+        let key = date.to_ordered_key().unwrap();
+        assert!(date.to_ordered_key_bound(false).unwrap() < key);
+        assert!(date.to_ordered_key_bound(true).unwrap() > key);
+        assert!(key > keys[8]);
+    }
+
+    #[test]
+    fn term_ids_round_trip() {
+        let inline = [
+            EncodedTerm::DefaultGraph,
+            EncodedTerm::NamedNode { iri_id: StrHash::from_bytes(b"http://example.com/s") },
+            EncodedTerm::SmallPrefixedNamedNode { prefix: 128, local: "s".try_into().unwrap() },
+            EncodedTerm::BigPrefixedNamedNode { prefix: 255, local_id: StrHash::from_bytes(b"a long local name") },
+            EncodedTerm::IntegerLiteral(-3),
+            EncodedTerm::SmallWktLiteral("POINT(1 2)".try_into().unwrap()),
+            EncodedTerm::SmallVectorLiteral("[1, 2.5]".try_into().unwrap()),
+            EncodedTerm::BigVectorLiteral { value_id: StrHash::from_bytes(b"[0.1, 0.2, 0.3, 0.4]") },
+            EncodedTerm::DateTimeLiteral("2020-01-01T00:00:00Z".parse().unwrap()),
+        ];
+        for term in inline.iter() {
+            let (id, reference) = term.to_term_id();
+            assert_eq!(reference, None);
+            assert_eq!(EncodedTerm::from_term_id(&id, |_| unreachable!()).unwrap(), *term);
+        }
+
+        let lang_string = EncodedTerm::BigBigLangStringLiteral {
+            value_id: StrHash::from_bytes(b"value"),
+            language_id: StrHash::from_bytes(b"en"),
+        };
+        let (id, reference) = lang_string.to_term_id();
+        let (hash, bytes) = reference.unwrap();
+        assert_eq!(id[0], TYPE_TERM_REF);
+        assert_eq!(
+            EncodedTerm::from_term_id(&id, |h| {
+                assert_eq!(h, hash);
+                Ok(bytes)
+            })
+            .unwrap(),
+            lang_string
+        );
+        assert!(EncodedTerm::from_term_id(&id, |_| Ok(vec![TYPE_TERM_REF])).is_err());
+    }
+}
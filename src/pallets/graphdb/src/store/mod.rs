@@ -7,16 +7,20 @@ pub(crate) mod numeric_encoder;
 pub(crate) mod small_string;
 pub(crate) mod model;
 pub(crate) mod codec;
+pub(crate) mod namespace;
 #[cfg(feature = "sophia")]
 mod sophia;
 
 // pub use crate::store::memory::MemoryStore;
 // pub use crate::store::substrate::MemoryStore;
 
-use crate::error::invalid_input_error;
+use crate::error::{invalid_input_error, StoreError};
 use crate::io::{DatasetFormat, DatasetSerializer, GraphFormat, GraphSerializer};
+use crate::model::geo::Envelope;
+use crate::model::vector::Vector;
 use crate::model::*;
 use crate::store::numeric_encoder::*;
+use crate::text::TextQuery;
 use oxiri::Iri;
 use rio_api::parser::{QuadsParser, TriplesParser};
 use rio_turtle::{NQuadsParser, NTriplesParser, TriGParser, TurtleError, TurtleParser};
@@ -45,6 +49,151 @@ pub(crate) trait ReadableEncodedStore: StrLookup {
         &self,
         graph_name: EncodedTerm<Self::StrId>,
     ) -> Result<bool, Self::Error>;
+
+    /// Quads matching the pattern when the nodes linked by `owl:sameAs` are equal: a term of the pattern matches
+    /// the quads of the nodes equal to it, and the quads found use the canonical node of each class of equal
+    /// nodes, without duplicates. Stores without such classes only match the stored terms.
+    fn encoded_quads_for_equal_pattern(
+        &self,
+        subject: Option<EncodedTerm<Self::StrId>>,
+        predicate: Option<EncodedTerm<Self::StrId>>,
+        object: Option<EncodedTerm<Self::StrId>>,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Self::QuadsIter {
+        self.encoded_quads_for_pattern(subject, predicate, object, graph_name)
+    }
+
+    /// Names the indexes `encoded_quads_for_pattern` reads for a lookup of the given shape.
+    ///
+    /// Only used to explain query plans, stores without named indexes may keep the default.
+    fn encoded_quads_indexes_for_pattern(&self, _shape: QuadPatternShape) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Number of quads with the given predicate, `None` if the store keeps no statistics.
+    fn encoded_predicate_cardinality(&self, _predicate: EncodedTerm<Self::StrId>) -> Option<u64> {
+        None
+    }
+
+    /// Number of quads with the given predicate (any if `None`) in the given graph (any graph if `None`, as for
+    /// `encoded_quads_for_pattern`), `None` if the store does not count them.
+    fn encoded_quad_count(
+        &self,
+        _predicate: Option<EncodedTerm<Self::StrId>>,
+        _graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Option<u64> {
+        None
+    }
+
+    /// Number of quads with the given predicate whose subject is an instance of `class`.
+    ///
+    /// For `rdf:type` this is the number of instances of `class`, `None` if the store keeps no statistics.
+    fn encoded_predicate_class_cardinality(
+        &self,
+        _predicate: EncodedTerm<Self::StrId>,
+        _class: EncodedTerm<Self::StrId>,
+    ) -> Option<u64> {
+        None
+    }
+
+    /// Quads with the given predicate whose object is a numeric or date/time literal in `range`.
+    ///
+    /// The result may contain quads out of the range: the default implementation ignores it.
+    fn encoded_quads_for_object_range(
+        &self,
+        predicate: EncodedTerm<Self::StrId>,
+        _range: &EncodedObjectRange<Self::StrId>,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Self::QuadsIter {
+        self.encoded_quads_for_pattern(None, Some(predicate), None, graph_name)
+    }
+
+    /// Names the indexes `encoded_quads_for_object_range` reads, see `encoded_quads_indexes_for_pattern`.
+    fn encoded_object_range_indexes(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Quads with the given predicate (any if `None`) whose object is a string literal matching the full-text
+    /// `query` (see `crate::text`).
+    ///
+    /// The result may contain quads not matching the query: the default implementation ignores it.
+    fn encoded_quads_for_text(
+        &self,
+        predicate: Option<EncodedTerm<Self::StrId>>,
+        _query: &TextQuery,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Self::QuadsIter {
+        self.encoded_quads_for_pattern(None, predicate, None, graph_name)
+    }
+
+    /// Names the indexes `encoded_quads_for_text` reads, see `encoded_quads_indexes_for_pattern`.
+    fn encoded_text_indexes(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Quads with the given predicate (any if `None`) whose object is a `geo:wktLiteral` geometry intersecting
+    /// `area` (see `crate::spatial`).
+    ///
+    /// The result may contain quads outside of the area: the default implementation ignores it.
+    fn encoded_quads_for_area(
+        &self,
+        predicate: Option<EncodedTerm<Self::StrId>>,
+        _area: &Envelope,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Self::QuadsIter {
+        self.encoded_quads_for_pattern(None, predicate, None, graph_name)
+    }
+
+    /// Names the indexes `encoded_quads_for_area` reads, see `encoded_quads_indexes_for_pattern`.
+    fn encoded_spatial_indexes(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Quads with the given predicate whose object is one of the `k` `vec:floatVector` vectors the closest to
+    /// `query` (see `crate::hnsw`).
+    ///
+    /// The result may contain other quads and miss some of the closest ones: the default implementation ignores
+    /// the query.
+    fn encoded_quads_for_vector(
+        &self,
+        predicate: EncodedTerm<Self::StrId>,
+        _query: &Vector,
+        _k: usize,
+        graph_name: Option<EncodedTerm<Self::StrId>>,
+    ) -> Self::QuadsIter {
+        self.encoded_quads_for_pattern(None, Some(predicate), None, graph_name)
+    }
+
+    /// Names the indexes `encoded_quads_for_vector` reads, see `encoded_quads_indexes_for_pattern`.
+    fn encoded_vector_indexes(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+}
+
+/// Inclusive bounds on the value of a literal, at least one of them is set.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub(crate) struct EncodedObjectRange<I: StrId> {
+    pub lower: Option<EncodedTerm<I>>,
+    pub upper: Option<EncodedTerm<I>>,
+}
+
+/// The components of a quad pattern known when the store is probed.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub(crate) struct QuadPatternShape {
+    pub subject: bool,
+    pub predicate: bool,
+    pub object: bool,
+    pub graph_name: GraphNameShape,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub(crate) enum GraphNameShape {
+    /// The lookup is done on all the graphs
+    Any,
+    DefaultGraph,
+    NamedGraph,
+    /// The graph name is only known during evaluation: either the default graph or a named graph
+    Bound,
 }
 
 pub(crate) trait WritableEncodedStore: StrEncodingAware {
@@ -210,6 +359,15 @@ impl From<StoreOrParseError<io::Error>> for io::Error {
     }
 }
 
+impl From<StoreOrParseError<StoreError>> for io::Error {
+    fn from(error: StoreOrParseError<StoreError>) -> Self {
+        match error {
+            StoreOrParseError::Store(error) => error.into(),
+            StoreOrParseError::Parse(error) => error,
+        }
+    }
+}
+
 impl From<StoreOrParseError<Infallible>> for io::Error {
     fn from(error: StoreOrParseError<Infallible>) -> Self {
         match error {
@@ -12,9 +12,16 @@ pub struct StrHash {
 }
 
 impl StrHash {
+    /// Hash of a string of the store, with `blake2_128` so that colliding strings cannot be computed
     pub fn new(value: &str) -> Self {
+        Self {
+            hash: u128::from_be_bytes(sp_io::hashing::blake2_128(value.as_bytes())),
+        }
+    }
+
+    pub fn from_bytes(value: &[u8]) -> Self {
         let mut hasher = SipHasher24::new();
-        hasher.write(value.as_bytes());
+        hasher.write(value);
         Self {
             hash: hasher.finish128().into(),
         }
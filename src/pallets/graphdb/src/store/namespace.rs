@@ -0,0 +1,78 @@
+//! Namespaces of the named nodes encoded as a prefix id followed by their local name.
+//!
+//! The prefix ids 64-127 are the built-in namespaces and 128-255 the namespaces of the runtime
+//! (see `Config::Namespaces`), in their order. These are the type bytes of the prefixed named nodes in `codec`.
+
+/// The built-in namespaces, from the prefix id `BUILT_IN_FIRST_ID`
+const BUILT_IN: [&str; 4] = [
+    "http://www.w3.org/1999/02/22-rdf-syntax-ns#",
+    "http://www.w3.org/2000/01/rdf-schema#",
+    "http://www.w3.org/2001/XMLSchema#",
+    "http://www.w3.org/2002/07/owl#",
+];
+const BUILT_IN_FIRST_ID: u8 = 64;
+const CUSTOM_FIRST_ID: u8 = 128;
+
+/// Number of namespaces a runtime may register, the following ones are ignored
+pub const MAX_CUSTOM_NAMESPACES: usize = 128;
+
+/// The prefix table: the built-in namespaces and the namespaces of the runtime.
+///
+/// An IRI is encoded with the longest namespace it starts with, so the table must not change once
+/// named nodes are stored with it.
+#[derive(Debug, Clone, Copy)]
+pub struct Namespaces {
+    custom: &'static [&'static str],
+}
+
+impl Namespaces {
+    pub const fn new(custom: &'static [&'static str]) -> Self {
+        Self { custom }
+    }
+
+    /// The namespace of a prefix id
+    pub fn namespace(self, prefix: u8) -> Option<&'static str> {
+        if prefix >= CUSTOM_FIRST_ID {
+            self.custom().get(usize::from(prefix - CUSTOM_FIRST_ID)).copied()
+        } else if prefix >= BUILT_IN_FIRST_ID {
+            BUILT_IN.get(usize::from(prefix - BUILT_IN_FIRST_ID)).copied()
+        } else {
+            None
+        }
+    }
+
+    /// Splits `iri` into the prefix id of its longest namespace and its local name
+    pub fn split(self, iri: &str) -> Option<(u8, &str)> {
+        let built_in = (BUILT_IN_FIRST_ID..).zip(BUILT_IN.iter());
+        let custom = (CUSTOM_FIRST_ID..=u8::MAX).zip(self.custom().iter());
+        built_in
+            .chain(custom)
+            .filter(|(_, namespace)| iri.starts_with(*namespace))
+            .max_by_key(|(_, namespace)| namespace.len())
+            .map(|(prefix, namespace)| (prefix, &iri[namespace.len()..]))
+    }
+
+    fn custom(self) -> &'static [&'static str] {
+        &self.custom[..self.custom.len().min(MAX_CUSTOM_NAMESPACES)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_namespace() {
+        let namespaces = Namespaces::new(&["http://example.com/", "http://example.com/ns/"]);
+        assert_eq!(namespaces.split("http://example.com/ns/a"), Some((129, "a")));
+        assert_eq!(namespaces.split("http://example.com/a"), Some((128, "a")));
+        assert_eq!(
+            namespaces.split("http://www.w3.org/2001/XMLSchema#integer"),
+            Some((66, "integer"))
+        );
+        assert_eq!(namespaces.split("http://example.org/a"), None);
+        assert_eq!(namespaces.namespace(129), Some("http://example.com/ns/"));
+        assert_eq!(namespaces.namespace(130), None);
+        assert_eq!(namespaces.namespace(48), None);
+    }
+}
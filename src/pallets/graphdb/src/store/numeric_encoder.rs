@@ -1,14 +1,18 @@
 #![allow(clippy::unreadable_literal)]
 
-use crate::error::invalid_data_error;
+use crate::error::{invalid_data_error, StoreError};
+use crate::model::geo::Geometry;
+use crate::model::vector::Vector;
+use crate::model::vocab::{geo, vec};
 use crate::model::xsd::*;
 use crate::model::*;
 use crate::sparql::EvaluationError;
+use crate::store::namespace::Namespaces;
 use crate::store::small_string::SmallString;
 use rand::random;
 use rio_api::model as rio;
 use std::collections::HashMap;
-use std::convert::{TryFrom, TryInto};
+use std::convert::{Infallible, TryFrom, TryInto};
 use std::error::Error;
 use std::fmt::Debug;
 use std::hash::Hash;
@@ -23,6 +27,14 @@ pub enum EncodedTerm<I: StrId> {
     NamedNode {
         iri_id: I,
     },
+    SmallPrefixedNamedNode {
+        prefix: u8,
+        local: SmallString,
+    },
+    BigPrefixedNamedNode {
+        prefix: u8,
+        local_id: I,
+    },
     NumericalBlankNode {
         id: u128,
     },
@@ -74,6 +86,16 @@ pub enum EncodedTerm<I: StrId> {
     DurationLiteral(Duration),
     YearMonthDurationLiteral(YearMonthDuration),
     DayTimeDurationLiteral(DayTimeDuration),
+    /// A `geo:wktLiteral`, with the canonical text of its geometry
+    SmallWktLiteral(SmallString),
+    BigWktLiteral {
+        value_id: I,
+    },
+    /// A `vec:floatVector`, with the canonical text of its components
+    SmallVectorLiteral(SmallString),
+    BigVectorLiteral {
+        value_id: I,
+    },
 }
 
 impl<I: StrId> PartialEq for EncodedTerm<I> {
@@ -83,6 +105,26 @@ impl<I: StrId> PartialEq for EncodedTerm<I> {
             (Self::NamedNode { iri_id: iri_id_a }, Self::NamedNode { iri_id: iri_id_b }) => {
                 iri_id_a == iri_id_b
             }
+            (
+                Self::SmallPrefixedNamedNode {
+                    prefix: prefix_a,
+                    local: local_a,
+                },
+                Self::SmallPrefixedNamedNode {
+                    prefix: prefix_b,
+                    local: local_b,
+                },
+            ) => prefix_a == prefix_b && local_a == local_b,
+            (
+                Self::BigPrefixedNamedNode {
+                    prefix: prefix_a,
+                    local_id: local_id_a,
+                },
+                Self::BigPrefixedNamedNode {
+                    prefix: prefix_b,
+                    local_id: local_id_b,
+                },
+            ) => prefix_a == prefix_b && local_id_a == local_id_b,
             (Self::NumericalBlankNode { id: id_a }, Self::NumericalBlankNode { id: id_b }) => {
                 id_a == id_b
             }
@@ -187,6 +229,24 @@ impl<I: StrId> PartialEq for EncodedTerm<I> {
             (Self::DurationLiteral(a), Self::DurationLiteral(b)) => a == b,
             (Self::YearMonthDurationLiteral(a), Self::YearMonthDurationLiteral(b)) => a == b,
             (Self::DayTimeDurationLiteral(a), Self::DayTimeDurationLiteral(b)) => a == b,
+            (Self::SmallWktLiteral(a), Self::SmallWktLiteral(b)) => a == b,
+            (
+                Self::BigWktLiteral {
+                    value_id: value_id_a,
+                },
+                Self::BigWktLiteral {
+                    value_id: value_id_b,
+                },
+            ) => value_id_a == value_id_b,
+            (Self::SmallVectorLiteral(a), Self::SmallVectorLiteral(b)) => a == b,
+            (
+                Self::BigVectorLiteral {
+                    value_id: value_id_a,
+                },
+                Self::BigVectorLiteral {
+                    value_id: value_id_b,
+                },
+            ) => value_id_a == value_id_b,
             (_, _) => false,
         }
     }
@@ -198,6 +258,14 @@ impl<I: StrId> Hash for EncodedTerm<I> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
             Self::NamedNode { iri_id } => iri_id.hash(state),
+            Self::SmallPrefixedNamedNode { prefix, local } => {
+                prefix.hash(state);
+                local.hash(state);
+            }
+            Self::BigPrefixedNamedNode { prefix, local_id } => {
+                prefix.hash(state);
+                local_id.hash(state);
+            }
             Self::NumericalBlankNode { id } => id.hash(state),
             Self::SmallBlankNode(id) => id.hash(state),
             Self::BigBlankNode { id_id } => id_id.hash(state),
@@ -250,13 +318,22 @@ impl<I: StrId> Hash for EncodedTerm<I> {
             Self::DurationLiteral(value) => value.hash(state),
             Self::YearMonthDurationLiteral(value) => value.hash(state),
             Self::DayTimeDurationLiteral(value) => value.hash(state),
+            Self::SmallWktLiteral(value) => value.hash(state),
+            Self::BigWktLiteral { value_id } => value_id.hash(state),
+            Self::SmallVectorLiteral(value) => value.hash(state),
+            Self::BigVectorLiteral { value_id } => value_id.hash(state),
         }
     }
 }
 
 impl<I: StrId> EncodedTerm<I> {
     pub fn is_named_node(&self) -> bool {
-        matches!(self, Self::NamedNode { .. })
+        matches!(
+            self,
+            Self::NamedNode { .. }
+                | Self::SmallPrefixedNamedNode { .. }
+                | Self::BigPrefixedNamedNode { .. }
+        )
     }
 
     pub fn is_blank_node(&self) -> bool {
@@ -295,6 +372,10 @@ impl<I: StrId> EncodedTerm<I> {
                 | Self::DurationLiteral(_)
                 | Self::YearMonthDurationLiteral(_)
                 | Self::DayTimeDurationLiteral(_)
+                | Self::SmallWktLiteral(_)
+                | Self::BigWktLiteral { .. }
+                | Self::SmallVectorLiteral(_)
+                | Self::BigVectorLiteral { .. }
         )
     }
 
@@ -315,6 +396,13 @@ impl<I: StrId> EncodedTerm<I> {
             Self::NamedNode { iri_id } => EncodedTerm::NamedNode {
                 iri_id: mapping(iri_id),
             },
+            Self::SmallPrefixedNamedNode { prefix, local } => {
+                EncodedTerm::SmallPrefixedNamedNode { prefix, local }
+            }
+            Self::BigPrefixedNamedNode { prefix, local_id } => EncodedTerm::BigPrefixedNamedNode {
+                prefix,
+                local_id: mapping(local_id),
+            },
             Self::NumericalBlankNode { id } => EncodedTerm::NumericalBlankNode { id },
             Self::SmallBlankNode(id) => EncodedTerm::SmallBlankNode(id),
             Self::BigBlankNode { id_id } => EncodedTerm::BigBlankNode {
@@ -373,9 +461,25 @@ impl<I: StrId> EncodedTerm<I> {
             Self::DurationLiteral(value) => EncodedTerm::DurationLiteral(value),
             Self::YearMonthDurationLiteral(value) => EncodedTerm::YearMonthDurationLiteral(value),
             Self::DayTimeDurationLiteral(value) => EncodedTerm::DayTimeDurationLiteral(value),
+            Self::SmallWktLiteral(value) => EncodedTerm::SmallWktLiteral(value),
+            Self::BigWktLiteral { value_id } => EncodedTerm::BigWktLiteral {
+                value_id: mapping(value_id),
+            },
+            Self::SmallVectorLiteral(value) => EncodedTerm::SmallVectorLiteral(value),
+            Self::BigVectorLiteral { value_id } => EncodedTerm::BigVectorLiteral {
+                value_id: mapping(value_id),
+            },
         }
     }
 
+    /// Calls `callback` with each string id of the term, an id referenced twice is given twice
+    pub fn on_each_id(self, mut callback: impl FnMut(I)) {
+        let _ = self.try_map_id::<I, Infallible>(|id| {
+            callback(id);
+            Ok(id)
+        });
+    }
+
     pub fn try_map_id<J: StrId, E>(
         self,
         mut mapping: impl FnMut(I) -> Result<J, E>,
@@ -385,6 +489,13 @@ impl<I: StrId> EncodedTerm<I> {
             Self::NamedNode { iri_id } => EncodedTerm::NamedNode {
                 iri_id: mapping(iri_id)?,
             },
+            Self::SmallPrefixedNamedNode { prefix, local } => {
+                EncodedTerm::SmallPrefixedNamedNode { prefix, local }
+            }
+            Self::BigPrefixedNamedNode { prefix, local_id } => EncodedTerm::BigPrefixedNamedNode {
+                prefix,
+                local_id: mapping(local_id)?,
+            },
             Self::NumericalBlankNode { id } => EncodedTerm::NumericalBlankNode { id },
             Self::SmallBlankNode(id) => EncodedTerm::SmallBlankNode(id),
             Self::BigBlankNode { id_id } => EncodedTerm::BigBlankNode {
@@ -443,6 +554,14 @@ impl<I: StrId> EncodedTerm<I> {
             Self::DurationLiteral(value) => EncodedTerm::DurationLiteral(value),
             Self::YearMonthDurationLiteral(value) => EncodedTerm::YearMonthDurationLiteral(value),
             Self::DayTimeDurationLiteral(value) => EncodedTerm::DayTimeDurationLiteral(value),
+            Self::SmallWktLiteral(value) => EncodedTerm::SmallWktLiteral(value),
+            Self::BigWktLiteral { value_id } => EncodedTerm::BigWktLiteral {
+                value_id: mapping(value_id)?,
+            },
+            Self::SmallVectorLiteral(value) => EncodedTerm::SmallVectorLiteral(value),
+            Self::BigVectorLiteral { value_id } => EncodedTerm::BigVectorLiteral {
+                value_id: mapping(value_id)?,
+            },
         })
     }
 }
@@ -558,11 +677,18 @@ pub(crate) trait StrEncodingAware {
     //TODO: rename
     type Error: Error + Into<EvaluationError> + 'static;
     type StrId: StrId + 'static;
+
+    /// The namespaces of the prefixed named nodes
+    fn namespaces(&self) -> Namespaces;
 }
 
 impl<'a, T: StrEncodingAware> StrEncodingAware for &'a T {
     type Error = T::Error;
     type StrId = T::StrId;
+
+    fn namespaces(&self) -> Namespaces {
+        (*self).namespaces()
+    }
 }
 
 pub(crate) trait StrLookup: StrEncodingAware {
@@ -581,12 +707,28 @@ pub(crate) trait ReadEncoder: StrEncodingAware {
         &self,
         named_node: NamedNodeRef<'_>,
     ) -> Result<Option<EncodedTerm<Self::StrId>>, Self::Error> {
-        Ok(Some(EncodedTerm::NamedNode {
-            iri_id: if let Some(iri_id) = self.get_encoded_str(named_node.as_str())? {
-                iri_id
+        let iri = named_node.as_str();
+        Ok(Some(if let Some((prefix, local)) = self.namespaces().split(iri) {
+            if let Ok(local) = SmallString::try_from(local) {
+                EncodedTerm::SmallPrefixedNamedNode { prefix, local }
             } else {
-                return Ok(None);
-            },
+                EncodedTerm::BigPrefixedNamedNode {
+                    prefix,
+                    local_id: if let Some(local_id) = self.get_encoded_str(local)? {
+                        local_id
+                    } else {
+                        return Ok(None);
+                    },
+                }
+            }
+        } else {
+            EncodedTerm::NamedNode {
+                iri_id: if let Some(iri_id) = self.get_encoded_str(iri)? {
+                    iri_id
+                } else {
+                    return Ok(None);
+                },
+            }
         }))
     }
 
@@ -713,6 +855,42 @@ pub(crate) trait ReadEncoder: StrEncodingAware {
                 "http://www.w3.org/2001/XMLSchema#dayTimeDuration" => {
                     parse_day_time_duration_str(value)
                 }
+                "http://www.opengis.net/ont/geosparql#wktLiteral" => {
+                    if let Ok(geometry) = value.parse::<Geometry>() {
+                        let value = geometry.to_string();
+                        Some(if let Ok(value) = SmallString::try_from(value.as_str()) {
+                            EncodedTerm::SmallWktLiteral(value)
+                        } else {
+                            EncodedTerm::BigWktLiteral {
+                                value_id: if let Some(value_id) = self.get_encoded_str(&value)? {
+                                    value_id
+                                } else {
+                                    return Ok(None);
+                                },
+                            }
+                        })
+                    } else {
+                        None
+                    }
+                }
+                "http://relationlabs.ai/vector/floatVector" => {
+                    if let Ok(vector) = value.parse::<Vector>() {
+                        let value = vector.to_string();
+                        Some(if let Ok(value) = SmallString::try_from(value.as_str()) {
+                            EncodedTerm::SmallVectorLiteral(value)
+                        } else {
+                            EncodedTerm::BigVectorLiteral {
+                                value_id: if let Some(value_id) = self.get_encoded_str(&value)? {
+                                    value_id
+                                } else {
+                                    return Ok(None);
+                                },
+                            }
+                        })
+                    } else {
+                        None
+                    }
+                }
                 _ => None,
             } {
                 Some(term) => term,
@@ -907,8 +1085,19 @@ pub(crate) trait WriteEncoder: StrEncodingAware {
         &mut self,
         named_node: rio::NamedNode<'_>,
     ) -> Result<EncodedTerm<Self::StrId>, Self::Error> {
-        Ok(EncodedTerm::NamedNode {
-            iri_id: self.encode_str(named_node.iri)?,
+        Ok(if let Some((prefix, local)) = self.namespaces().split(named_node.iri) {
+            if let Ok(local) = SmallString::try_from(local) {
+                EncodedTerm::SmallPrefixedNamedNode { prefix, local }
+            } else {
+                EncodedTerm::BigPrefixedNamedNode {
+                    prefix,
+                    local_id: self.encode_str(local)?,
+                }
+            }
+        } else {
+            EncodedTerm::NamedNode {
+                iri_id: self.encode_str(named_node.iri)?,
+            }
         })
     }
 
@@ -1009,6 +1198,34 @@ pub(crate) trait WriteEncoder: StrEncodingAware {
                     "http://www.w3.org/2001/XMLSchema#dayTimeDuration" => {
                         parse_day_time_duration_str(value)
                     }
+                    "http://www.opengis.net/ont/geosparql#wktLiteral" => {
+                        if let Ok(geometry) = value.parse::<Geometry>() {
+                            let value = geometry.to_string();
+                            Some(if let Ok(value) = SmallString::try_from(value.as_str()) {
+                                EncodedTerm::SmallWktLiteral(value)
+                            } else {
+                                EncodedTerm::BigWktLiteral {
+                                    value_id: self.encode_str(&value)?,
+                                }
+                            })
+                        } else {
+                            None
+                        }
+                    }
+                    "http://relationlabs.ai/vector/floatVector" => {
+                        if let Ok(vector) = value.parse::<Vector>() {
+                            let value = vector.to_string();
+                            Some(if let Ok(value) = SmallString::try_from(value.as_str()) {
+                                EncodedTerm::SmallVectorLiteral(value)
+                            } else {
+                                EncodedTerm::BigVectorLiteral {
+                                    value_id: self.encode_str(&value)?,
+                                }
+                            })
+                        } else {
+                            None
+                        }
+                    }
                     _ => None,
                 } {
                     Some(v) => v,
@@ -1238,6 +1455,13 @@ impl<S: StrLookup> Decoder for S {
             EncodedTerm::NamedNode { iri_id } => {
                 Ok(NamedNode::new_unchecked(get_required_str(self, iri_id)?).into())
             }
+            EncodedTerm::SmallPrefixedNamedNode { prefix, local } => {
+                Ok(NamedNode::new_unchecked(get_namespace(self, prefix)? + local.as_str()).into())
+            }
+            EncodedTerm::BigPrefixedNamedNode { prefix, local_id } => Ok(NamedNode::new_unchecked(
+                get_namespace(self, prefix)? + &get_required_str(self, local_id)?,
+            )
+            .into()),
             EncodedTerm::NumericalBlankNode { id } => Ok(BlankNode::new_from_unique_id(id).into()),
             EncodedTerm::SmallBlankNode(id) => Ok(BlankNode::new_unchecked(id.as_str()).into()),
             EncodedTerm::BigBlankNode { id_id } => {
@@ -1303,10 +1527,36 @@ impl<S: StrLookup> Decoder for S {
             EncodedTerm::DurationLiteral(value) => Ok(Literal::from(value).into()),
             EncodedTerm::YearMonthDurationLiteral(value) => Ok(Literal::from(value).into()),
             EncodedTerm::DayTimeDurationLiteral(value) => Ok(Literal::from(value).into()),
+            EncodedTerm::SmallWktLiteral(value) => {
+                Ok(Literal::new_typed_literal(value, geo::WKT_LITERAL).into())
+            }
+            EncodedTerm::BigWktLiteral { value_id } => Ok(Literal::new_typed_literal(
+                get_required_str(self, value_id)?,
+                geo::WKT_LITERAL,
+            )
+            .into()),
+            EncodedTerm::SmallVectorLiteral(value) => {
+                Ok(Literal::new_typed_literal(value, vec::FLOAT_VECTOR).into())
+            }
+            EncodedTerm::BigVectorLiteral { value_id } => Ok(Literal::new_typed_literal(
+                get_required_str(self, value_id)?,
+                vec::FLOAT_VECTOR,
+            )
+            .into()),
         }
     }
 }
 
+fn get_namespace<L: StrLookup>(lookup: &L, prefix: u8) -> Result<String, DecoderError<L::Error>> {
+    lookup
+        .namespaces()
+        .namespace(prefix)
+        .map(str::to_owned)
+        .ok_or_else(|| DecoderError::Decoder {
+            msg: format!("Not able to find the namespace with prefix id {}", prefix),
+        })
+}
+
 fn get_required_str<L: StrLookup>(
     lookup: &L,
     id: L::StrId,
@@ -1354,3 +1604,12 @@ impl<E: Into<io::Error>> From<DecoderError<E>> for io::Error {
         }
     }
 }
+
+impl From<DecoderError<StoreError>> for StoreError {
+    fn from(e: DecoderError<StoreError>) -> Self {
+        match e {
+            DecoderError::Store(e) => e,
+            DecoderError::Decoder { msg } => StoreError::CorruptedTerm { msg },
+        }
+    }
+}
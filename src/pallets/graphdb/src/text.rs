@@ -0,0 +1,345 @@
+//! Full-text search of the string literals of the default graph.
+//!
+//! The text of a string literal (simple or with a language tag) is split in tokens, the runs of alphanumeric
+//! characters, lowercased. `TextIndexStore` maps each token of the objects of the default graph to their quads,
+//! under the key of the token, its first `TOKEN_KEY_SIZE` bytes, and under the key of its first `PREFIX_LENGTH`
+//! characters followed by `*`, which never ends a token. The keys are hashed so that the tokens chosen by the
+//! writers cannot unbalance the trie. It is kept up to date with the index entries of the quads when
+//! `QuadIndexes::TEXT` is enabled.
+//!
+//! The queries read it with the `text:match` property function (see `crate::sparql::plan_builder`):
+//! `?person text:match "luna"` binds the subjects of the quads whose object has the token `luna`, and
+//! `(?person ?score ?name) text:match (:name "lun*")` only matches the `:name` quads, matches the tokens starting
+//! with `lun`, and binds the relevance of the literal (see `TextQuery::score`) and the literal itself. A prefix
+//! reads the entries of its first `PREFIX_LENGTH` characters, it has at least as many. The literals of the named
+//! graphs, and of the default graph if the index is not maintained, are scanned.
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::{decode_term_id, Config, Id2StrStore, TextIndexStore};
+use crate::error::StoreError;
+use crate::meter::Accesses;
+use crate::store::codec::{EncodedQuad, EncodedTerm, TermId};
+
+/// The property function of the full-text search
+pub(crate) const MATCH: &str = "http://relationlabs.ai/text/match";
+
+/// Number of bytes of a token kept in the keys of `TextIndexStore`, the longer tokens share their key
+pub const TOKEN_KEY_SIZE: usize = 16;
+
+/// Number of characters of the prefixes of the tokens indexed, and minimum length of a prefix in a query
+pub const PREFIX_LENGTH: usize = 3;
+
+/// The first bytes of a token, padded with zeros
+pub type TokenKey = [u8; TOKEN_KEY_SIZE];
+
+/// The tokens of a text: its runs of alphanumeric characters, lowercased
+pub(crate) fn tokens(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn token_key(token: &str) -> TokenKey {
+    let mut key = [0; TOKEN_KEY_SIZE];
+    let length = token.len().min(TOKEN_KEY_SIZE);
+    key[..length].copy_from_slice(&token.as_bytes()[..length]);
+    key
+}
+
+/// The key of the tokens starting with the first `PREFIX_LENGTH` characters of `token`, `None` if it is shorter
+fn prefix_key(token: &str) -> Option<TokenKey> {
+    let (end, _) = token.char_indices().nth(PREFIX_LENGTH - 1)?;
+    let end = end + token[end..].chars().next()?.len_utf8();
+    Some(token_key(&format!("{}*", &token[..end])))
+}
+
+/// A full-text query: the literals matching it have a token matching each of its terms
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub(crate) struct TextQuery {
+    terms: Vec<TextTerm>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+struct TextTerm {
+    token: String,
+    /// Whether the term matches the tokens it is a prefix of, written with a trailing `*`
+    prefix: bool,
+}
+
+impl TextTerm {
+    fn matches(&self, token: &str) -> bool {
+        if self.prefix {
+            token.starts_with(&self.token)
+        } else {
+            token == self.token
+        }
+    }
+
+    /// The key of the tokens the term matches, and of other ones sharing their key
+    fn key(&self) -> TokenKey {
+        if self.prefix {
+            prefix_key(&self.token).unwrap_or_else(|| token_key(&self.token))
+        } else {
+            token_key(&self.token)
+        }
+    }
+}
+
+impl TextQuery {
+    /// Parses a query, its terms are the tokens of its words (e.g. `"luna lov*"`). `None` if it has no token,
+    /// or a prefix shorter than `PREFIX_LENGTH` characters.
+    pub fn parse(query: &str) -> Option<Self> {
+        let mut terms = Vec::new();
+        for word in query.split_whitespace() {
+            let mut word_terms = tokens(word)
+                .map(|token| TextTerm { token, prefix: false })
+                .collect::<Vec<_>>();
+            if let Some(last) = word_terms.last_mut() {
+                last.prefix = word.ends_with('*');
+            }
+            terms.extend(word_terms);
+        }
+        if terms.is_empty() || terms.iter().any(|term| term.prefix && term.token.chars().count() < PREFIX_LENGTH) {
+            None
+        } else {
+            Some(Self { terms })
+        }
+    }
+
+    /// The relevance of a text for the query: the share of its tokens matching a term.
+    /// `None` if a term matches none of them.
+    pub fn score(&self, text: &str) -> Option<f64> {
+        let tokens = tokens(text).collect::<Vec<_>>();
+        if !self.terms.iter().all(|term| tokens.iter().any(|token| term.matches(token))) {
+            return None;
+        }
+        let matched = tokens
+            .iter()
+            .filter(|token| self.terms.iter().any(|term| term.matches(token)))
+            .count();
+        Some(matched as f64 / tokens.len() as f64)
+    }
+}
+
+impl fmt::Display for TextQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, term) in self.terms.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(&term.token)?;
+            if term.prefix {
+                f.write_str("*")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the entries of the tokens of the object of a quad inserted in the default graph, returns the reads and
+/// writes it did
+pub(crate) fn index<T: Config>(quad: &EncodedQuad) -> Result<Accesses, StoreError> {
+    let (o, s, p) = (quad.object.term_id(), quad.subject.term_id(), quad.predicate.term_id());
+    let keys = token_keys::<T>(quad)?;
+    for key in keys.iter() {
+        <TextIndexStore<T>>::insert((*key, o, s, p), ());
+    }
+    Ok((quad_reads(quad), keys.len() as u64))
+}
+
+/// Removes the entries of the tokens of the object of a quad removed from the default graph, returns the reads
+/// and writes it did
+pub(crate) fn unindex<T: Config>(quad: &EncodedQuad) -> Result<Accesses, StoreError> {
+    let (o, s, p) = (quad.object.term_id(), quad.subject.term_id(), quad.predicate.term_id());
+    let keys = token_keys::<T>(quad)?;
+    for key in keys.iter() {
+        <TextIndexStore<T>>::remove((*key, o, s, p));
+    }
+    Ok((quad_reads(quad), keys.len() as u64))
+}
+
+/// The reads of the text of the object of a quad of the default graph
+fn quad_reads(quad: &EncodedQuad) -> u64 {
+    if quad.graph_name.is_default_graph() {
+        object_reads(quad.object)
+    } else {
+        0
+    }
+}
+
+/// The reads of the text of a string literal: its value when it is not inlined in the term
+pub(crate) fn object_reads(object: EncodedTerm) -> u64 {
+    match object {
+        EncodedTerm::BigStringLiteral { .. }
+        | EncodedTerm::BigSmallLangStringLiteral { .. }
+        | EncodedTerm::BigBigLangStringLiteral { .. } => 1,
+        _ => 0,
+    }
+}
+
+/// The keys of the tokens of the object of a quad and of their prefixes, none if it is not a string literal of
+/// the default graph
+fn token_keys<T: Config>(quad: &EncodedQuad) -> Result<BTreeSet<TokenKey>, StoreError> {
+    if !quad.graph_name.is_default_graph() {
+        return Ok(BTreeSet::new());
+    }
+    object_token_keys::<T>(quad.object)
+}
+
+/// The keys of the tokens of a string literal and of their prefixes, none for the other terms
+pub(crate) fn object_token_keys<T: Config>(object: EncodedTerm) -> Result<BTreeSet<TokenKey>, StoreError> {
+    Ok(match literal_text::<T>(object)? {
+        Some(text) => tokens(&text)
+            .flat_map(|token| [Some(token_key(&token)), prefix_key(&token)])
+            .flatten()
+            .collect(),
+        None => BTreeSet::new(),
+    })
+}
+
+/// The text of a string literal, `None` for the other terms
+fn literal_text<T: Config>(term: EncodedTerm) -> Result<Option<String>, StoreError> {
+    Ok(match term {
+        EncodedTerm::SmallStringLiteral(value)
+        | EncodedTerm::SmallSmallLangStringLiteral { value, .. }
+        | EncodedTerm::SmallBigLangStringLiteral { value, .. } => Some(value.into()),
+        EncodedTerm::BigStringLiteral { value_id }
+        | EncodedTerm::BigSmallLangStringLiteral { value_id, .. }
+        | EncodedTerm::BigBigLangStringLiteral { value_id, .. } => {
+            let value = <Id2StrStore<T>>::get(*value_id).ok_or(StoreError::MissingStr { hash: *value_id })?;
+            Some(String::from_utf8(value.into_inner()).map_err(|_| StoreError::InvalidUtf8 { hash: *value_id })?)
+        }
+        _ => None,
+    })
+}
+
+/// The quads of the default graph, with `predicate` if set, whose object has a token key matching each term
+/// of `query`. The keys of the long tokens and of the prefixes are shared: the literals are to be checked with
+/// `TextQuery::score`.
+pub(crate) fn quads<T: Config>(
+    query: &TextQuery,
+    predicate: Option<EncodedTerm>,
+) -> impl Iterator<Item = Result<EncodedQuad, StoreError>> {
+    let p = predicate.map(|predicate| predicate.term_id());
+    let mut found: Option<BTreeSet<(TermId, TermId, TermId)>> = None;
+    for term in &query.terms {
+        let entries = <TextIndexStore<T>>::iter_key_prefix((term.key(),))
+            .filter(|(_, _, key_p)| match &p {
+                Some(p) => key_p == p,
+                None => true,
+            })
+            .filter(|entry| match &found {
+                Some(found) => found.contains(entry),
+                None => true,
+            })
+            .collect();
+        found = Some(entries);
+    }
+    found.unwrap_or_default().into_iter().map(|(o, s, p)| {
+        Ok(EncodedQuad::new(
+            decode_term_id::<T>(&s)?,
+            decode_term_id::<T>(&p)?,
+            decode_term_id::<T>(&o)?,
+            EncodedTerm::DefaultGraph,
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consistency::Inconsistency;
+    use crate::mock::{new_test_ext, Graphdb, Origin, Test};
+    use crate::Pallet;
+
+    /// The subjects matching a `text:match` query, in the order of the results
+    fn matches(query: &str) -> String {
+        Pallet::<Test>::execute_query(format!("SELECT ?s WHERE {{ ?s text:match \"{}\" }} ORDER BY ?s", query))
+    }
+
+    #[test]
+    fn a_text_is_split_in_lowercase_tokens() {
+        assert_eq!(tokens("Luna Lovegood, 2nd-year!").collect::<Vec<_>>(), vec!["luna", "lovegood", "2nd", "year"]);
+        assert_eq!(tokens("Été à Paris").collect::<Vec<_>>(), vec!["été", "à", "paris"]);
+        assert_eq!(tokens(" -- ").count(), 0);
+    }
+
+    #[test]
+    fn a_query_is_parsed_in_terms() {
+        let query = TextQuery::parse("Luna-Lovegood*  year").unwrap();
+        assert_eq!(query.to_string(), "luna lovegood* year");
+        assert_eq!(TextQuery::parse(" * -- "), None);
+        // a prefix reads the entries of its first characters
+        assert_eq!(TextQuery::parse("lo*"), None);
+        assert_eq!(TextQuery::parse("été*").unwrap().to_string(), "été*");
+        assert_eq!(prefix_key("lovegood"), prefix_key("lov"));
+        assert_ne!(prefix_key("lov"), Some(token_key("lov")));
+        assert_eq!(prefix_key("lo"), None);
+    }
+
+    #[test]
+    fn the_score_is_the_share_of_the_tokens_matching_a_term() {
+        let query = TextQuery::parse("lov*").unwrap();
+        assert_eq!(query.score("Luna Lovegood"), Some(0.5));
+        assert_eq!(query.score("Love, love me do"), Some(0.5));
+        assert_eq!(query.score("Luna"), None);
+        let query = TextQuery::parse("luna lovegood").unwrap();
+        assert_eq!(query.score("Luna Lovegood"), Some(1.));
+        assert_eq!(query.score("Luna"), None);
+    }
+
+    #[test]
+    fn the_literals_are_matched_through_the_index() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update(
+                "INSERT DATA { :a :name \"Luna Lovegood\" . :b :name \"Lovecraft\" . \
+                    :c :name \"internationalization\" . :d :name \"internationalizations\"@en }",
+            )
+            .unwrap();
+            let a = "http://relationlabs.ai/entity/a";
+            let b = "http://relationlabs.ai/entity/b";
+            let c = "http://relationlabs.ai/entity/c";
+            let d = "http://relationlabs.ai/entity/d";
+
+            let found = matches("luna");
+            assert!(found.contains(a) && !found.contains(b));
+            let found = matches("lov*");
+            assert!(found.contains(a) && found.contains(b));
+            assert!(!matches("lovecraftian*").contains(b));
+            // the tokens sharing their key are told apart by their literal
+            let found = matches("internationalization");
+            assert!(found.contains(c) && !found.contains(d));
+
+            Pallet::<Test>::execute_update("DELETE DATA { :a :name \"Luna Lovegood\" }").unwrap();
+            assert!(!matches("lov*").contains(a));
+            assert!(Pallet::<Test>::check_indexes().is_empty());
+        });
+    }
+
+    #[test]
+    fn a_missing_token_is_found_by_the_check() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :a :name \"Luna\" }").unwrap();
+            let (key, o, s, p) = <TextIndexStore<Test>>::iter_keys().next().unwrap();
+            <TextIndexStore<Test>>::remove((key, o, s, p));
+            let found = crate::consistency::check_indexes::<Test>();
+            assert_eq!(found, vec![Inconsistency::MissingEntry { index: "Text", graph: None, quad: [s, p, o] }]);
+        });
+    }
+
+    #[test]
+    fn an_insertion_is_charged_for_its_token_entries() {
+        new_test_ext().execute_with(|| {
+            Pallet::<Test>::execute_update("INSERT DATA { :z :name 1 }").unwrap();
+            let update = |update: &str| {
+                let update = update.as_bytes().to_vec();
+                Graphdb::sparql_update(Origin::signed(1), update).unwrap().actual_weight.unwrap()
+            };
+            let one = update("INSERT DATA { :a :name \"ab\" }");
+            let three = update("INSERT DATA { :b :name \"ab cd ef\" }");
+            let writes = <Test as frame_system::Config>::DbWeight::get().writes(2);
+            assert!(three >= one + writes, "{} < {} + {}", three, one, writes);
+        });
+    }
+}
@@ -99,7 +99,7 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	//   `spec_version`, and `authoring_version` are the same between Wasm and native.
 	// This value is set to 100 to notify Polkadot-JS App (https://polkadot.js.org/apps) to use
 	//   the compatible custom types.
-	spec_version: 100,
+	spec_version: 117,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
 	transaction_version: 1,
@@ -276,10 +276,61 @@ impl pallet_template::Config for Runtime {
 parameter_types! {
       //Maximum bounds on storage are important to secure your chain.
       pub const MaxValueLength: u32 = u32::MAX;
+      // All the quad indexes: any lookup reads a single index, and the spatial, vector and full-text
+      // indexes of the GeoSPARQL filters, the `vec:nearest` and the `text:match` property functions
+      pub const QuadIndexes: pallet_graphdb::QuadIndexes = pallet_graphdb::QuadIndexes::ALL
+            .with(pallet_graphdb::QuadIndexes::SPATIAL)
+            .with(pallet_graphdb::QuadIndexes::VECTOR)
+            .with(pallet_graphdb::QuadIndexes::TEXT);
+      // Namespaces of the named nodes stored as a prefix id and a local name, changing the list
+      // requires a migration rebuilding the store
+      pub const Namespaces: &'static [&'static str] = &[
+            "http://relationlabs.ai/entity/",
+            "http://relationlabs.ai/acl/",
+      ];
+      // Index keys re-encoded per block while the keys of an older term encoding are upgraded
+      pub const ReEncodingBatchSize: u32 = 200;
+      // Index entries removed or written per block while the secondary indexes are rebuilt
+      pub const IndexRepairBatchSize: u32 = 500;
+      // Keys of the dropped named graphs removed per block
+      pub const GraphPurgeBatchSize: u32 = 500;
+      // Named graph the OWL 2 RL consequences of the default graph are materialized into
+      pub const InferredGraph: Option<&'static str> = Some("http://relationlabs.ai/inferred/");
+      // Named graph of the user-defined inference rules, and the one the triples they construct are written to
+      pub const RulesGraph: Option<&'static str> = Some("http://relationlabs.ai/rules/");
+      pub const DerivedGraph: &'static str = "http://relationlabs.ai/derived/";
+      // Rounds of rule evaluation after an update before it fails
+      pub const MaxRuleRounds: u32 = 32;
+      // Named graph of the SHACL shapes the updates are validated against
+      pub const ShapesGraph: Option<&'static str> = Some("http://relationlabs.ai/shapes/");
+      // Largest WebAssembly module of a user-defined function, and the fuel of the calls of a query or an update
+      pub const MaxFunctionSize: u32 = 64 * 1024;
+      pub const MaxFunctionFuel: u64 = 10_000_000;
+      // Deposit reserved for a user-defined function, and for each byte of its module
+      pub const FunctionDeposit: Balance = 100_000;
+      pub const FunctionByteDeposit: Balance = 100;
+      // Weight declared by an update, the unused part is refunded: half of a block
+      pub const MaxUpdateWeight: Weight = WEIGHT_PER_SECOND;
 }
 impl pallet_graphdb::Config for Runtime {
 	type Event = Event;
 	type MaxValueLength = MaxValueLength;
+	type QuadIndexes = QuadIndexes;
+	type Namespaces = Namespaces;
+	type ReEncodingBatchSize = ReEncodingBatchSize;
+	type IndexRepairBatchSize = IndexRepairBatchSize;
+	type GraphPurgeBatchSize = GraphPurgeBatchSize;
+	type InferredGraph = InferredGraph;
+	type RulesGraph = RulesGraph;
+	type DerivedGraph = DerivedGraph;
+	type MaxRuleRounds = MaxRuleRounds;
+	type ShapesGraph = ShapesGraph;
+	type MaxFunctionSize = MaxFunctionSize;
+	type MaxFunctionFuel = MaxFunctionFuel;
+	type Currency = Balances;
+	type FunctionDeposit = FunctionDeposit;
+	type FunctionByteDeposit = FunctionByteDeposit;
+	type MaxUpdateWeight = MaxUpdateWeight;
 }
 
 // Create the runtime by composing the FRAME pallets that were previously configured.
@@ -330,6 +381,18 @@ pub type Executive = frame_executive::Executive<
 	frame_system::ChainContext<Runtime>,
 	Runtime,
 	AllPalletsWithSystem,
+	(
+		pallet_graphdb::migrations::v1::CompactKeys<Runtime>,
+		pallet_graphdb::migrations::v2::GraphTries<Runtime>,
+		pallet_graphdb::migrations::v3::StrRefCounts<Runtime>,
+		pallet_graphdb::migrations::v4::KeyedStrHashes<Runtime>,
+		pallet_graphdb::migrations::v5::PrefixedNamedNodes<Runtime>,
+		pallet_graphdb::migrations::v6::CodecVersionTag<Runtime>,
+		pallet_graphdb::migrations::v7::QuadCounts<Runtime>,
+		pallet_graphdb::migrations::v8::SameAsClasses<Runtime>,
+		pallet_graphdb::migrations::v9::TextIndex<Runtime>,
+		pallet_graphdb::migrations::ReEncodeTerms<Runtime>,
+	),
 >;
 
 #[cfg(feature = "runtime-benchmarks")]
@@ -352,6 +415,27 @@ impl_runtime_apis! {
 		fn query(query: String) -> String {
 			Graphdb::execute_query(query)
 		}
+		fn query_rdfs(query: String) -> String {
+			Graphdb::execute_query_with_entailment(query, pallet_graphdb::EntailmentRegime::Rdfs)
+		}
+		fn query_same_as(query: String) -> String {
+			Graphdb::execute_query_with_equality(query, pallet_graphdb::EqualityMode::SameAs)
+		}
+		fn explain(query: String) -> Result<String, String> {
+			Graphdb::explain_query(query)
+		}
+		fn profile(query: String) -> Result<String, String> {
+			Graphdb::profile_query(query)
+		}
+		fn validate_shapes() -> String {
+			Graphdb::validate_shapes()
+		}
+		fn graph_root(graph_name: String) -> Option<Vec<u8>> {
+			Graphdb::graph_root(graph_name)
+		}
+		fn check_indexes() -> Vec<String> {
+			Graphdb::check_indexes()
+		}
 	}
 	impl sp_api::Core<Block> for Runtime {
 		fn version() -> RuntimeVersion {